@@ -0,0 +1,335 @@
+//! Types implemented in Rust whose values programs can hold. Programs call
+//! their methods and read and assign their properties like those of amber
+//! values, but cannot look inside them.
+
+use crate::engine::native::{native_fn, FromValue, IntoValue};
+use crate::engine::value::Value;
+use crate::syntax::symbol::Symbol;
+use crate::types::host::{Property, TypeDeclaration};
+use crate::types::ty::Type;
+use crate::vm::value::{HostObject, NativeFn};
+use crate::vm::Vm;
+use std::any::Any;
+use std::cell::{Ref, RefMut};
+use std::marker::PhantomData;
+
+/// A Rust type programs can be handed values of, as `Handle`s.
+///
+/// ```
+/// use amber::engine::{Handle, HostType, Methods};
+///
+/// struct Counter(f64);
+///
+/// impl HostType for Counter {
+///     const NAME: &'static str = "counter";
+///
+///     fn declare(methods: &mut Methods<Self>) {
+///         methods
+///             .method("add", |counter: &mut Counter, n: f64| counter.0 += n)
+///             .getter("count", |counter: &Counter| counter.0);
+///     }
+/// }
+///
+/// let mut engine = amber::Engine::new();
+/// engine.register_type::<Counter>();
+/// engine.register_fn("counter", || Handle::new(Counter(0.0)));
+/// let count = engine.eval("let c = counter()\nc.add(2)\nc.count\n").unwrap();
+/// assert_eq!(amber::Value::Number(2.0), count);
+/// ```
+pub trait HostType: Any + Sized {
+    /// The name programs know the type by, in annotations and errors.
+    const NAME: &'static str;
+
+    /// Declares the methods and properties of the type.
+    fn declare(methods: &mut Methods<Self>);
+}
+
+/// A value of a type of the host, shared with the programs it is handed to.
+pub struct Handle<T> {
+    object: HostObject,
+    marker: PhantomData<T>,
+}
+
+impl<T: HostType> Handle<T> {
+    pub fn new(value: T) -> Self {
+        Handle {
+            object: HostObject::new(Symbol::intern(T::NAME), value),
+            marker: PhantomData,
+        }
+    }
+
+    /// Borrows the value, panicking if it is borrowed mutably, like
+    /// `RefCell::borrow`.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.object.borrow().expect("value is borrowed mutably")
+    }
+
+    /// Borrows the value mutably, panicking if it is borrowed.
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.object.borrow_mut().expect("value is borrowed")
+    }
+
+    /// Borrows the value, failing if it is borrowed mutably. The value of
+    /// the receiver is, while a method taking `&mut T` runs, so this is
+    /// the borrow for such methods to make of their arguments.
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, String> {
+        self.object
+            .borrow()
+            .ok_or_else(|| format!("{} is in use", T::NAME))
+    }
+
+    /// Borrows the value mutably, failing if it is borrowed.
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, String> {
+        self.object
+            .borrow_mut()
+            .ok_or_else(|| format!("{} is in use", T::NAME))
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle {
+            object: self.object.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> From<Handle<T>> for Value {
+    fn from(handle: Handle<T>) -> Self {
+        Value::Host(handle.object)
+    }
+}
+
+impl<T: HostType> FromValue for Handle<T> {
+    fn from_value(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Host(object) if object.borrow::<T>().is_some() => Ok(Handle {
+                object,
+                marker: PhantomData,
+            }),
+            value => Err(format!("expected {}, found {}", T::NAME, value.type_name())),
+        }
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Named(Symbol::intern(T::NAME), vec![])
+    }
+}
+
+impl<T: HostType> IntoValue for Handle<T> {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(self.into())
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Named(Symbol::intern(T::NAME), vec![])
+    }
+}
+
+/// The methods and properties of a type of the host, by name.
+pub struct Methods<T> {
+    /// The arity without the receiver.
+    methods: Vec<(Symbol, usize, Type, NativeFn)>,
+    getters: Vec<(Symbol, Type, NativeFn)>,
+    setters: Vec<(Symbol, Type, NativeFn)>,
+    marker: PhantomData<T>,
+}
+
+impl<T: HostType> Default for Methods<T> {
+    fn default() -> Self {
+        Methods {
+            methods: vec![],
+            getters: vec![],
+            setters: vec![],
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: HostType> Methods<T> {
+    /// Declares a method, called as `value.name(arguments)`, which takes the
+    /// value by `&T` or `&mut T` and then arguments like a native function.
+    pub fn method<Args>(
+        &mut self,
+        name: &str,
+        method: impl HostMethod<T, Args> + 'static,
+    ) -> &mut Self {
+        let arity = method.arity();
+        let ty = method.ty();
+        let function = native_fn(move |mut args| {
+            let receiver = receiver::<T>(args.remove(0))?;
+            method.invoke(&receiver, args)
+        });
+        self.methods
+            .push((Symbol::intern(name), arity, ty, function));
+        self
+    }
+
+    /// Declares a property, read as `value.name`.
+    pub fn getter<R: IntoValue>(
+        &mut self,
+        name: &str,
+        getter: impl Fn(&T) -> R + 'static,
+    ) -> &mut Self {
+        let function = native_fn(move |mut args| {
+            let receiver = receiver::<T>(args.remove(0))?;
+            with_ref(&receiver, |value: &T| getter(value))?.into_value()
+        });
+        let ty = R::ty(&mut vec![]);
+        self.getters.push((Symbol::intern(name), ty, function));
+        self
+    }
+
+    /// Lets a property be assigned, as `value.name = new_value`. An `Err`
+    /// returned by the setter stops the program.
+    pub fn setter<A: FromValue, R: IntoValue>(
+        &mut self,
+        name: &str,
+        setter: impl Fn(&mut T, A) -> R + 'static,
+    ) -> &mut Self {
+        let function = native_fn(move |mut args| {
+            let receiver = receiver::<T>(args.remove(0))?;
+            exclusive_arguments(&receiver, &args)?;
+            let value = A::from_value(args.remove(0))?;
+            with_mut(&receiver, |object: &mut T| setter(object, value))?.into_value()
+        });
+        let ty = A::ty(&mut vec![]);
+        self.setters.push((Symbol::intern(name), ty, function));
+        self
+    }
+
+    /// Defines the methods and properties in a VM, returning their types
+    /// for the checker.
+    pub fn define(self, vm: &mut Vm) -> TypeDeclaration {
+        let type_name = Symbol::intern(T::NAME);
+        let mut declaration = TypeDeclaration::default();
+        for (name, arity, ty, function) in self.methods {
+            declaration.methods.insert(name, ty);
+            vm.define_method(type_name, name, arity + 1, function);
+        }
+        for (name, ty, function) in self.getters {
+            let property = Property {
+                ty,
+                settable: false,
+            };
+            declaration.properties.insert(name, property);
+            vm.define_getter(type_name, name, function);
+        }
+        for (name, ty, function) in self.setters {
+            let property = declaration.properties.entry(name).or_insert(Property {
+                ty,
+                settable: false,
+            });
+            property.settable = true;
+            vm.define_setter(type_name, name, function);
+        }
+        declaration
+    }
+}
+
+fn receiver<T: HostType>(value: Value) -> Result<HostObject, String> {
+    match value {
+        Value::Host(object) if object.borrow::<T>().is_some() => Ok(object),
+        value => Err(format!("expected {}, found {}", T::NAME, value.type_name())),
+    }
+}
+
+/// Calls `f` with the value of the object, which is borrowed until it
+/// returns.
+fn with_ref<T: HostType, R>(object: &HostObject, f: impl FnOnce(&T) -> R) -> Result<R, String> {
+    match object.borrow() {
+        Some(value) => Ok(f(&value)),
+        None => Err(format!("{} is in use", T::NAME)),
+    }
+}
+
+fn with_mut<T: HostType, R>(object: &HostObject, f: impl FnOnce(&mut T) -> R) -> Result<R, String> {
+    match object.borrow_mut() {
+        Some(mut value) => Ok(f(&mut value)),
+        None => Err(format!("{} is in use", T::NAME)),
+    }
+}
+
+/// A method taking `&mut T` cannot be passed its receiver as well, which it
+/// could not borrow while the receiver is borrowed mutably.
+fn exclusive_arguments(receiver: &HostObject, args: &[Value]) -> Result<(), String> {
+    match args.iter().position(|arg| contains(arg, receiver)) {
+        Some(i) => Err(format!(
+            "argument {}: {} is in use",
+            i + 1,
+            receiver.type_name
+        )),
+        None => Ok(()),
+    }
+}
+
+fn contains(value: &Value, object: &HostObject) -> bool {
+    match value {
+        Value::Host(host) => host == object,
+        Value::Tuple(values) | Value::Variant(_, _, values) => {
+            values.iter().any(|value| contains(value, object))
+        }
+        Value::Record(_, fields) => fields.iter().any(|(_, value)| contains(value, object)),
+        _ => false,
+    }
+}
+
+/// A Rust function which can be a method of `T`, taking a `&T` or `&mut T`
+/// and then arguments like a `NativeFunction`.
+pub trait HostMethod<T, Args> {
+    /// The number of arguments after the receiver.
+    fn arity(&self) -> usize;
+
+    /// The type of the method, whose first parameter is the receiver.
+    fn ty(&self) -> Type;
+
+    fn invoke(&self, receiver: &HostObject, args: Vec<Value>) -> Result<Value, String>;
+}
+
+macro_rules! host_method {
+    ($receiver:ty, $with:ident $(, $check:ident)?; $($arg:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<T, F, R, $($arg),*> HostMethod<T, ($receiver, $($arg,)*)> for F
+        where
+            T: HostType,
+            F: Fn($receiver, $($arg),*) -> R,
+            R: IntoValue,
+            $($arg: FromValue,)*
+        {
+            fn arity(&self) -> usize {
+                <[&str]>::len(&[$(stringify!($arg)),*])
+            }
+
+            #[allow(unused_mut)]
+            fn ty(&self) -> Type {
+                let mut params = vec![];
+                let mut args = vec![Type::Named(Symbol::intern(T::NAME), vec![])];
+                $(args.push($arg::ty(&mut params));)*
+                Type::Function(args, Box::new(R::ty(&mut params)))
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn invoke(&self, receiver: &HostObject, args: Vec<Value>) -> Result<Value, String> {
+                $($check(receiver, &args)?;)?
+                let mut args = args.into_iter().enumerate();
+                $(
+                    let (i, value) = args.next().unwrap();
+                    let $arg = $arg::from_value(value)
+                        .map_err(|err| format!("argument {}: {}", i + 1, err))?;
+                )*
+                $with(receiver, |receiver: $receiver| self(receiver, $($arg),*))?.into_value()
+            }
+        }
+    };
+}
+
+// Any argument can be passed to a method taking `&T`, even its receiver.
+host_method!(&T, with_ref;);
+host_method!(&T, with_ref; A);
+host_method!(&T, with_ref; A, B);
+host_method!(&T, with_ref; A, B, C);
+host_method!(&mut T, with_mut, exclusive_arguments;);
+host_method!(&mut T, with_mut, exclusive_arguments; A);
+host_method!(&mut T, with_mut, exclusive_arguments; A, B);
+host_method!(&mut T, with_mut, exclusive_arguments; A, B, C);
@@ -0,0 +1,726 @@
+//! Running amber from Rust: an `Engine` checks, compiles and runs sources,
+//! handing back what they evaluate to as a `Value` the host owns.
+
+pub mod host;
+pub mod native;
+pub mod value;
+
+pub use crate::vm::limits::{CancelToken, Limit, Limits};
+pub use host::{Handle, HostMethod, HostType, Methods};
+pub use native::{FromValue, IntoValue, NativeFunction};
+pub use value::Value;
+
+use crate::resolver::{self, ResolveError, Resolver};
+use crate::syntax::ast::ModuleAst;
+use crate::syntax::error::{ParserError, SyntaxError};
+use crate::syntax::lexer::Lexer;
+use crate::syntax::parser::AstParser;
+use crate::syntax::symbol::Symbol;
+use crate::types::checker::{self, TypeChecker, TypeError};
+use crate::types::host::Host;
+use crate::vm::chunk::Function;
+use crate::vm::compiler::Compiler;
+use crate::vm::error::{Panic, RuntimeError};
+use crate::vm::value::GcConfig;
+use crate::vm::Vm;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Why a source could not be run, by the stage which rejected it.
+#[derive(Debug)]
+pub enum Error {
+    Syntax(SyntaxError),
+    Parser(ParserError),
+    /// Every name which could not be resolved, and other scoping errors.
+    Resolve(Vec<ResolveError>),
+    Type(Vec<TypeError>),
+    /// A runtime error, and the calls which led to it.
+    Runtime(Panic),
+    /// The value a run returned, which could not be handed to the host.
+    Value(String),
+}
+
+impl Error {
+    /// The line of the (first) error, if it is known.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::Syntax(err) => err.line(),
+            Error::Parser(err) => err.line(),
+            Error::Resolve(errors) => errors.first().map(ResolveError::line),
+            Error::Type(errors) => errors.first().map(TypeError::line),
+            Error::Runtime(panic) => Some(panic.line()),
+            Error::Value(_) => None,
+        }
+    }
+
+    /// The limit a run went past, if that is what stopped it.
+    pub fn limit(&self) -> Option<&Limit> {
+        match self {
+            Error::Runtime(Panic {
+                error: RuntimeError::LimitExceeded(limit, _),
+                ..
+            }) => Some(limit),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = |f: &mut fmt::Formatter<'_>, errors: Vec<(usize, String)>| {
+            let errors: Vec<String> = errors
+                .into_iter()
+                .map(|(line, message)| format!("line {}: {}", line, message))
+                .collect();
+            write!(f, "{}", errors.join("\n"))
+        };
+
+        match self {
+            Error::Syntax(err) => match err.line() {
+                Some(line) => write!(f, "line {}: {}", line, err),
+                None => write!(f, "{}", err),
+            },
+            Error::Parser(err) => match err.line() {
+                Some(line) => write!(f, "line {}: {}", line, err),
+                None => write!(f, "{}", err),
+            },
+            Error::Resolve(errors) => lines(
+                f,
+                errors
+                    .iter()
+                    .map(|err| (err.line(), err.to_string()))
+                    .collect(),
+            ),
+            Error::Type(errors) => lines(
+                f,
+                errors
+                    .iter()
+                    .map(|err| (err.line(), err.to_string()))
+                    .collect(),
+            ),
+            Error::Runtime(panic) => write!(f, "line {}: {}", panic.line(), panic),
+            Error::Value(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A checked and compiled source, which can be run any number of times.
+#[derive(Debug)]
+pub struct Program {
+    module: ModuleAst,
+    compiled: RefCell<Compiled>,
+}
+
+/// A program as checked against the globals of one generation.
+#[derive(Debug)]
+struct Compiled {
+    function: Rc<Function>,
+    /// The globals once the program has run: those it was checked against,
+    /// and its own.
+    globals: Globals,
+    generation: usize,
+}
+
+/// What the programs run so far declared at their top level, for name
+/// resolution and for the checker.
+#[derive(Debug, Clone, Default)]
+struct Globals {
+    names: resolver::Globals,
+    types: checker::Globals,
+}
+
+/// Runs amber sources. Globals are kept between runs, so the functions,
+/// types and interfaces a program declares can be used by the programs
+/// compiled after it has run, and they are checked against their types.
+/// Functions the host registers are globals of every program.
+///
+/// A program compiled before others have run, or before the host declared
+/// more, is checked again when it is run, against the globals as they are
+/// then.
+///
+/// ```
+/// let mut engine = amber::Engine::new();
+/// engine.eval("def twice(x)\n    x * 2\nend\n").unwrap();
+/// assert_eq!(amber::Value::Number(4.0), engine.eval("twice(2)\n").unwrap());
+/// ```
+pub struct Engine {
+    vm: Vm,
+    /// The globals and types the host has declared, for the checker.
+    host: Host,
+    globals: Globals,
+    /// Counts the changes to the globals and the host, to tell which
+    /// programs were checked against older ones.
+    generation: usize,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine::with_gc(GcConfig::default())
+    }
+
+    pub fn with_gc(config: GcConfig) -> Self {
+        Engine {
+            vm: Vm::with_gc(config),
+            host: Host::default(),
+            globals: Globals::default(),
+            generation: 0,
+        }
+    }
+
+    /// Bounds the runs after, for programs which cannot be trusted. A run
+    /// which goes past a limit fails with an error whose `limit` it is.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.vm.set_limits(limits);
+    }
+
+    /// A token which cancels runs from another thread. It stays cancelled
+    /// until it is reset.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.vm.cancel_token()
+    }
+
+    /// Declares a global function implemented in Rust, which programs call
+    /// like any other. Its arguments are converted from the values it is
+    /// called with, and its type follows from theirs and its result's: a
+    /// `Value` can be anything. A conversion error, or an `Err` returned by
+    /// the function, stops the program with a runtime error.
+    ///
+    /// ```
+    /// let mut engine = amber::Engine::new();
+    /// engine.register_fn("hypot", |a: f64, b: f64| a.hypot(b));
+    /// assert_eq!(amber::Value::Number(5.0), engine.eval("hypot(3, 4)\n").unwrap());
+    /// ```
+    pub fn register_fn<Args>(&mut self, name: &str, function: impl NativeFunction<Args> + 'static) {
+        let name = Symbol::intern(name);
+        self.host.globals.insert(name, function.ty());
+        self.generation += 1;
+        let arity = function.arity();
+        let native = native::native_fn(move |args| function.invoke(args));
+        self.vm.define_native(name, arity, native);
+    }
+
+    /// Declares a type implemented in Rust, whose values natives can hand
+    /// to programs as `Handle`s.
+    pub fn register_type<T: HostType>(&mut self) {
+        let mut methods = Methods::default();
+        T::declare(&mut methods);
+        let declaration = methods.define(&mut self.vm);
+        self.host.types.insert(Symbol::intern(T::NAME), declaration);
+        self.generation += 1;
+    }
+
+    /// The syntax tree of a source.
+    pub fn parse(&self, source: &str) -> Result<ModuleAst, Error> {
+        let mut tokens = Lexer::tokenize(source).map_err(Error::Syntax)?;
+        AstParser::parse(&mut tokens).map_err(Error::Parser)
+    }
+
+    /// Parses, resolves, type checks and compiles a source.
+    pub fn compile(&self, source: &str) -> Result<Program, Error> {
+        let module = self.parse(source)?;
+        let compiled = self.check(&module)?;
+        Ok(Program {
+            module,
+            compiled: RefCell::new(compiled),
+        })
+    }
+
+    /// Resolves, type checks and compiles a module against the globals as
+    /// they are.
+    fn check(&self, module: &ModuleAst) -> Result<Compiled, Error> {
+        let resolution = Resolver::resolve_with(module, &self.host, &self.globals.names);
+        if !resolution.errors().is_empty() {
+            return Err(Error::Resolve(resolution.errors().to_vec()));
+        }
+        let types = TypeChecker::check_with(module, &self.host, &self.globals.types);
+        if !types.errors().is_empty() {
+            return Err(Error::Type(types.errors().to_vec()));
+        }
+
+        let function = Compiler::compile(module, &resolution, &types);
+        Ok(Compiled {
+            function: Rc::new(function),
+            globals: Globals {
+                names: resolution.globals().clone(),
+                types: types.globals().clone(),
+            },
+            generation: self.generation,
+        })
+    }
+
+    /// Runs a program, returning the value of its last expression. Once it
+    /// has run, the programs compiled after can use what it declares; a
+    /// run which fails declares nothing.
+    pub fn run(&mut self, program: &Program) -> Result<Value, Error> {
+        if program.compiled.borrow().generation != self.generation {
+            let compiled = self.check(&program.module)?;
+            program.compiled.replace(compiled);
+        }
+        let function = program.compiled.borrow().function.clone();
+        let value = self.vm.run(function).map_err(Error::Runtime)?;
+
+        self.generation += 1;
+        let mut compiled = program.compiled.borrow_mut();
+        self.globals = compiled.globals.clone();
+        compiled.generation = self.generation;
+        Value::from_vm(self.vm.heap(), value).map_err(Error::Value)
+    }
+
+    /// Compiles and runs a source.
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        let program = self.compile(source)?;
+        self.run(&program)
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval() {
+        let mut engine = Engine::new();
+        let value = engine
+            .eval("let x = 2\n(x * 3, \"six\", Some(x))\n")
+            .unwrap();
+        assert_eq!(
+            Value::Tuple(vec![
+                Value::Number(6.0),
+                Value::String("six".to_string()),
+                Value::Variant(
+                    "option".to_string(),
+                    "Some".to_string(),
+                    vec![Value::Number(2.0)]
+                ),
+            ]),
+            value
+        );
+        assert_eq!("(6, \"six\", Some(2))", value.to_string());
+    }
+
+    #[test]
+    fn run_programs_again() {
+        let mut engine = Engine::new();
+        let program = engine
+            .compile("type point = { x, y }\npoint { x: 1, y: (2,) }\n")
+            .unwrap();
+        for _ in 0..2 {
+            let value = engine.run(&program).unwrap();
+            assert_eq!("point { x: 1, y: (2,) }", value.to_string());
+        }
+    }
+
+    #[test]
+    fn globals_of_earlier_runs() {
+        let mut engine = Engine::new();
+        engine.eval("def f(x)\n    x\nend\n").unwrap();
+        assert_eq!(Value::Number(1.0), engine.eval("f(1)\n").unwrap());
+        // Its type is what the earlier program left unknown.
+        assert!(matches!(engine.eval("f(1) + \"a\"\n"), Err(Error::Type(_))));
+
+        let source = r#"type point = { x, y }
+interface Show
+    def show(self)
+end
+impl Show for point
+    def show(p)
+        "point"
+    end
+end
+def describe(value: a) where a: Show
+    show(value)
+end
+let mut count = 0
+"#;
+        engine.eval(source).unwrap();
+        let value = engine
+            .eval(
+                "count = count + 1
+let p = point { x: 1, y: 2 }
+(p.x, describe(p), count)
+",
+            )
+            .unwrap();
+        assert_eq!("(1, \"point\", 1)", value.to_string());
+        assert!(matches!(engine.eval("describe(1)\n"), Err(Error::Type(_))));
+        assert!(matches!(engine.eval("f = 1\n"), Err(Error::Resolve(_))));
+
+        // A later program may declare a name again.
+        engine.eval("let f = 2\n").unwrap();
+        assert_eq!(Value::Number(3.0), engine.eval("f + 1\n").unwrap());
+    }
+
+    #[test]
+    fn programs_compiled_before_others_run() {
+        let mut engine = Engine::new();
+        let a = engine.compile("def f(x)\n    x\nend\n").unwrap();
+        let b = engine.compile("def g(x)\n    x + 1\nend\n").unwrap();
+        engine.run(&a).unwrap();
+        engine.run(&b).unwrap();
+        assert_eq!(Value::Number(3.0), engine.eval("f(g(2))\n").unwrap());
+
+        // Running an older program again keeps what was declared since.
+        engine.eval("def h(x)\n    x * 2\nend\n").unwrap();
+        engine.run(&a).unwrap();
+        assert_eq!(Value::Number(4.0), engine.eval("h(f(2))\n").unwrap());
+
+        // So does running one which was compiled before a type was
+        // declared again.
+        engine.eval("type p = { a }\n").unwrap();
+        let c = engine.compile("1\n").unwrap();
+        engine.eval("type p = { b }\n").unwrap();
+        engine.run(&c).unwrap();
+        assert_eq!(
+            "p { b: 1 }",
+            engine.eval("p { b: 1 }\n").unwrap().to_string()
+        );
+
+        // A program is checked again against what it is run after.
+        let d = engine.compile("let k = 1\nk + 1\n").unwrap();
+        engine.eval("def k(x)\n    x\nend\n").unwrap();
+        let e = engine.compile("k(1)\n").unwrap();
+        assert_eq!(Value::Number(2.0), engine.run(&d).unwrap());
+        assert!(matches!(engine.run(&e), Err(Error::Type(_))));
+    }
+
+    #[test]
+    fn failed_runs_declare_nothing() {
+        let mut engine = Engine::new();
+        let source = "def f()\n    1\nend\npanic(\"no\")\ndef g()\n    2\nend\n";
+        assert!(matches!(engine.eval(source), Err(Error::Runtime(_))));
+        assert!(matches!(engine.eval("g()\n"), Err(Error::Resolve(_))));
+        assert!(matches!(engine.eval("f()\n"), Err(Error::Resolve(_))));
+    }
+
+    #[test]
+    fn errors() {
+        let mut engine = Engine::new();
+        let error = |engine: &mut Engine, source| engine.eval(source).unwrap_err();
+
+        assert!(matches!(error(&mut engine, "\"x\n"), Error::Syntax(_)));
+        assert!(matches!(error(&mut engine, "let = 1\n"), Error::Parser(_)));
+        let err = error(&mut engine, "x\ny\n");
+        assert!(matches!(&err, Error::Resolve(errors) if errors.len() == 2));
+        assert_eq!(
+            "line 1: undefined variable `x`\nline 2: undefined variable `y`",
+            err.to_string()
+        );
+        assert!(matches!(
+            error(&mut engine, "1 + \"one\"\n"),
+            Error::Type(_)
+        ));
+
+        let err = error(&mut engine, "\n\npanic(\"no\")\n");
+        assert_eq!(Some(3), err.line());
+        assert_eq!("line 3: no", err.to_string());
+        // A failed run leaves the engine usable.
+        assert_eq!(Value::Number(3.0), engine.eval("1 + 2\n").unwrap());
+    }
+
+    #[test]
+    fn natives() {
+        let mut engine = Engine::new();
+        engine.register_fn("add", |a: f64, b: f64| a + b);
+        engine.register_fn("greet", |name: String| format!("hello {}", name));
+        engine.register_fn("first", |elements: Vec<Value>| elements.into_iter().next());
+        engine.register_fn("half", |n: i64| n / 2);
+        engine.register_fn("fail", || -> Result<f64, String> {
+            Err("broken".to_string())
+        });
+
+        let value = engine
+            .eval("(add(1, 2) * 2, greet(\"you\"), first((Some(1), 2)), first(()))\n")
+            .unwrap();
+        assert_eq!("(6, \"hello you\", Some(Some(1)), None)", value.to_string());
+        assert_eq!("<def add>", engine.eval("add\n").unwrap().to_string());
+
+        // The types of the arguments are checked, and so is their number.
+        assert!(matches!(
+            engine.eval("add(\"a\", 1)\n"),
+            Err(Error::Type(_))
+        ));
+        assert!(matches!(engine.eval("add(1)\n"), Err(Error::Type(_))));
+        // `greet(...)` is a string.
+        assert!(matches!(
+            engine.eval("greet(\"a\") + 1\n"),
+            Err(Error::Type(_))
+        ));
+
+        let err = engine.eval("half(4)\nhalf(1.5)\n").unwrap_err();
+        assert_eq!(
+            "line 2: half: argument 1: expected an integer, found 1.5",
+            err.to_string()
+        );
+
+        let err = engine
+            .eval("def f()\n    fail()\nend\nf() + 1\n")
+            .unwrap_err();
+        match err {
+            Error::Runtime(panic) => {
+                assert_eq!("fail: broken", panic.to_string());
+                let functions: Vec<&str> = panic
+                    .trace
+                    .iter()
+                    .map(|frame| frame.function.as_str())
+                    .collect();
+                assert_eq!(vec!["f", "<module>"], functions);
+            }
+            err => panic!("{:?}", err),
+        }
+    }
+
+    #[test]
+    fn functions_handed_to_the_host() {
+        use std::cell::RefCell;
+
+        // Collects before every instruction, so a function the host holds
+        // would be freed if it were not rooted.
+        let mut engine = Engine::with_gc(GcConfig {
+            stress: true,
+            ..GcConfig::default()
+        });
+        let kept = Rc::new(RefCell::new(None));
+        engine.register_fn("keep", {
+            let kept = kept.clone();
+            move |function: Value| *kept.borrow_mut() = Some(function)
+        });
+        engine.register_fn("kept", {
+            let kept = kept.clone();
+            move || kept.borrow().clone().unwrap()
+        });
+
+        let source =
+            "def adder(n)\n    def add(x)\n        x + n\n    end\n    keep(add)\nend\nadder(10)\n";
+        engine.eval(source).unwrap();
+        // The closure is not found again by its name, which is a global's.
+        let source = "def add(x)\n    x\nend\nlet f = kept()\nf(1)\n";
+        assert_eq!(Value::Number(11.0), engine.eval(source).unwrap());
+
+        let function = engine.eval("add\n").unwrap();
+        assert_eq!("<def add>", function.to_string());
+        assert_ne!(Some(&function), kept.borrow().as_ref());
+        assert_eq!(function, engine.eval("add\n").unwrap());
+
+        // It cannot be handed to another engine.
+        let mut other = Engine::new();
+        other.register_fn("kept", move || kept.borrow().clone().unwrap());
+        let err = other.eval("kept()\n").unwrap_err();
+        assert_eq!(
+            "line 1: kept: `add` is a function of another engine",
+            err.to_string()
+        );
+    }
+
+    struct Request {
+        path: String,
+        headers: Vec<(String, String)>,
+        status: f64,
+    }
+
+    impl HostType for Request {
+        const NAME: &'static str = "request";
+
+        fn declare(methods: &mut Methods<Self>) {
+            methods
+                .method("header", |req: &Request, name: String| {
+                    req.headers
+                        .iter()
+                        .find(|(header, _)| *header == name)
+                        .map(|(_, value)| value.clone())
+                })
+                .method("set_header", |req: &mut Request, name, value| {
+                    req.headers.push((name, value))
+                })
+                .method("merge", |req: &mut Request, other: Handle<Request>| {
+                    let other = other.borrow();
+                    req.headers.extend(other.headers.iter().cloned())
+                })
+                .method("copy_to", |req: &Request, other: Handle<Request>| {
+                    let mut other = other.try_borrow_mut()?;
+                    other.headers = req.headers.clone();
+                    Ok::<_, String>(())
+                })
+                .getter("path", |req: &Request| req.path.clone())
+                .getter("status", |req: &Request| req.status)
+                .setter("status", |req: &mut Request, status: f64| {
+                    if !(100.0..600.0).contains(&status) {
+                        return Err(format!("{} is not a status", status));
+                    }
+                    req.status = status;
+                    Ok(())
+                });
+        }
+    }
+
+    #[test]
+    fn host_types() {
+        let req = Handle::new(Request {
+            path: "/".to_string(),
+            headers: vec![("x".to_string(), "1".to_string())],
+            status: 200.0,
+        });
+        let mut engine = Engine::new();
+        engine.register_type::<Request>();
+        let handle = req.clone();
+        engine.register_fn("request", move || handle.clone());
+
+        let source = r#"let req = request()
+req.set_header("y", req.path)
+req.status = 404
+(req.header("x"), req.header("y"), req.header("z"), req.status, req)
+"#;
+        let value = engine.eval(source).unwrap();
+        assert_eq!(
+            r#"(Some("1"), Some("/"), None, 404, <request>)"#,
+            value.to_string()
+        );
+        // The program and the host share the request.
+        assert_eq!(404.0, req.borrow().status);
+        assert_eq!(2, req.borrow().headers.len());
+
+        // Methods and properties are typed by their Rust types.
+        for source in [
+            "request().header(1)\n",
+            "request().path + 1\n",
+            "request().nothing\n",
+            "request().path = \"/\"\n",
+            "request { path: \"/\" }\n",
+        ] {
+            assert!(
+                matches!(engine.eval(source), Err(Error::Type(_))),
+                "{}",
+                source
+            );
+        }
+        let err = engine.eval("request().status = 1\n").unwrap_err();
+        assert_eq!("line 1: status: 1 is not a status", err.to_string());
+
+        // A request cannot be borrowed as an argument while it is the
+        // receiver of a method which changes it.
+        let err = engine.eval("let r = request()\nr.merge(r)\n").unwrap_err();
+        assert_eq!(
+            "line 2: merge: argument 1: request is in use",
+            err.to_string()
+        );
+        let err = engine
+            .eval("let r = request()\nr.copy_to(r)\n")
+            .unwrap_err();
+        assert_eq!("line 2: copy_to: request is in use", err.to_string());
+        engine.register_fn("other", || {
+            Handle::new(Request {
+                path: "/other".to_string(),
+                headers: vec![],
+                status: 200.0,
+            })
+        });
+        let source = "let o = other()\nrequest().copy_to(o)\no.merge(request())\no.header(\"x\")\n";
+        let value = engine.eval(source).unwrap();
+        assert_eq!(r#"Some("1")"#, value.to_string());
+    }
+
+    #[test]
+    fn limits() {
+        let mut engine = Engine::new();
+        let burn = "def burn(n)\n    match n {\n        | 0 => 1\n        | _ => burn(n - 1) + burn(n - 1)\n    }\nend\n";
+        let program = engine.compile(&format!("{}burn(60)\n", burn)).unwrap();
+
+        engine.set_limits(Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        });
+        let err = engine.run(&program).unwrap_err();
+        assert_eq!(Some(&Limit::Fuel(1000)), err.limit());
+        // Every run gets its fuel anew.
+        assert_eq!(
+            Value::Number(8.0),
+            engine.eval(&format!("{}burn(3)\n", burn)).unwrap()
+        );
+
+        engine.set_limits(Limits {
+            max_call_depth: Some(10),
+            ..Limits::default()
+        });
+        let err = engine.run(&program).unwrap_err();
+        assert_eq!(Some(&Limit::CallDepth(10)), err.limit());
+        assert_eq!("line 4: calls nested deeper than 10", err.to_string());
+        assert!(engine.eval(&format!("{}burn(9)\n", burn)).is_ok());
+
+        engine.set_limits(Limits {
+            max_heap_bytes: Some(64 * 1024),
+            ..Limits::default()
+        });
+        engine.register_fn("repeat", |s: String, n: i64| s.repeat(n as usize));
+        let err = engine.eval("repeat(\"ab\", 40000)\n").unwrap_err();
+        assert_eq!(Some(&Limit::HeapBytes(64 * 1024)), err.limit());
+        // What can be collected does not count.
+        let source = "repeat(\"a\", 40000)\nrepeat(\"a\", 40000)\nrepeat(\"a\", 40000)\n1\n";
+        assert_eq!(Value::Number(1.0), engine.eval(source).unwrap());
+
+        engine.set_limits(Limits::default());
+        let token = engine.cancel_token();
+        let cancel = std::thread::spawn({
+            let token = token.clone();
+            move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                token.cancel();
+            }
+        });
+        let err = engine.run(&program).unwrap_err();
+        cancel.join().unwrap();
+        assert_eq!(Some(&Limit::Cancelled), err.limit());
+        // The engine is still usable once the token is reset.
+        assert!(engine.eval("1\n").unwrap_err().limit().is_some());
+        token.reset();
+        assert_eq!(Value::Number(1.0), engine.eval("1\n").unwrap());
+    }
+
+    #[test]
+    fn deep_values() {
+        let mut engine = Engine::new();
+        engine.set_limits(Limits {
+            fuel: Some(10_000_000),
+            max_heap_bytes: Some(64 * 1024 * 1024),
+            max_call_depth: Some(64),
+        });
+        let source = "type list =\n    | Cons(head, tail)\n    | Empty\ndef grow(k, l)\n    match k {\n        | 0 => Cons(0, l)\n        | _ => grow(k - 1, grow(k - 1, l))\n    }\nend\n";
+
+        let err = engine
+            .eval(&format!("{}grow(15, Empty)\n", source))
+            .unwrap_err();
+        assert_eq!(
+            format!("value nested deeper than {}", value::MAX_DEPTH),
+            err.to_string()
+        );
+        // The list is fine as long as it stays in the program.
+        engine.register_fn("length", |l: Value| match l {
+            Value::Variant(_, _, fields) => fields.len() as f64,
+            _ => 0.0,
+        });
+        let err = engine.eval("length(grow(15, Empty))\n").unwrap_err();
+        assert_eq!(
+            format!(
+                "line 1: length: argument 1: value nested deeper than {}",
+                value::MAX_DEPTH
+            ),
+            err.to_string()
+        );
+        assert_eq!(
+            Value::Bool(true),
+            engine.eval("grow(15, Empty) == grow(15, Empty)\n").unwrap()
+        );
+        assert!(engine.eval("grow(8, Empty)\n").is_ok());
+    }
+}
@@ -0,0 +1,351 @@
+//! Functions the host defines for amber to call. Their arguments and result
+//! are converted between Rust types and `Value`s, and the Rust types decide
+//! the type the checker gives the function.
+
+use crate::engine::value::Value;
+use crate::syntax::symbol::Symbol;
+use crate::types::ty::Type;
+use crate::vm::value::{self as vm, NativeFn};
+use crate::vm::Vm;
+use std::fmt;
+use std::rc::Rc;
+
+/// A Rust type a native function can take as an argument.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, String>;
+
+    /// The amber type of the values which convert. A `Value` could be
+    /// anything, so by default it is a new type parameter, added to
+    /// `params`.
+    fn ty(params: &mut Vec<Symbol>) -> Type {
+        fresh(params)
+    }
+}
+
+/// A Rust type a native function can return.
+pub trait IntoValue {
+    fn into_value(self) -> Result<Value, String>;
+
+    /// The amber type of the converted values, like `FromValue::ty`.
+    fn ty(params: &mut Vec<Symbol>) -> Type {
+        fresh(params)
+    }
+}
+
+fn fresh(params: &mut Vec<Symbol>) -> Type {
+    let param = Symbol::intern(&format!("t{}", params.len()));
+    params.push(param);
+    Type::Param(param)
+}
+
+fn expected<T>(ty: &str, value: &Value) -> Result<T, String> {
+    Err(format!("expected {}, found {}", ty, value.type_name()))
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, String> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(self)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Number(n) => Ok(n),
+            value => expected("number", &value),
+        }
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Number
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::Number(self))
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Number
+    }
+}
+
+/// Numbers without a fractional part.
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Number(n) if n.fract() == 0.0 => Ok(n as i64),
+            Value::Number(n) => Err(format!("expected an integer, found {}", n)),
+            value => expected("number", &value),
+        }
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Number
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::Number(self as f64))
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Number
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            value => expected("bool", &value),
+        }
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Bool
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::Bool(self))
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Bool
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, String> {
+        match value {
+            Value::String(s) => Ok(s),
+            value => expected("string", &value),
+        }
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::String
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::String(self))
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::String
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::String(self.to_string()))
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::String
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Nil => Ok(()),
+            value => expected("nil", &value),
+        }
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Nil
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::Nil)
+    }
+
+    fn ty(_: &mut Vec<Symbol>) -> Type {
+        Type::Nil
+    }
+}
+
+/// `Some` and `None` of the prelude's `option`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Variant(ty, name, mut fields) if ty == "option" => match name.as_str() {
+                "Some" if fields.len() == 1 => T::from_value(fields.remove(0)).map(Some),
+                _ => Ok(None),
+            },
+            value => expected("option", &value),
+        }
+    }
+
+    fn ty(params: &mut Vec<Symbol>) -> Type {
+        Type::Named(Symbol::intern("option"), vec![T::ty(params)])
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Result<Value, String> {
+        let option = "option".to_string();
+        Ok(match self {
+            Some(value) => Value::Variant(option, "Some".to_string(), vec![value.into_value()?]),
+            None => Value::Variant(option, "None".to_string(), vec![]),
+        })
+    }
+
+    fn ty(params: &mut Vec<Symbol>) -> Type {
+        Type::Named(Symbol::intern("option"), vec![T::ty(params)])
+    }
+}
+
+/// An error stops the program, with the error as its message.
+impl<T: IntoValue, E: fmt::Display> IntoValue for Result<T, E> {
+    fn into_value(self) -> Result<Value, String> {
+        self.map_err(|err| err.to_string())?.into_value()
+    }
+
+    fn ty(params: &mut Vec<Symbol>) -> Type {
+        T::ty(params)
+    }
+}
+
+/// The elements of a tuple of any length.
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, String> {
+        match value {
+            Value::Tuple(elements) => elements.into_iter().map(T::from_value).collect(),
+            value => expected("tuple", &value),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Result<Value, String> {
+        let elements = self
+            .into_iter()
+            .map(T::into_value)
+            .collect::<Result<_, _>>()?;
+        Ok(Value::Tuple(elements))
+    }
+}
+
+macro_rules! tuple {
+    ($len:literal, $($element:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($element: FromValue),*> FromValue for ($($element,)*) {
+            fn from_value(value: Value) -> Result<Self, String> {
+                match value {
+                    Value::Tuple(elements) if elements.len() == $len => {
+                        let mut elements = elements.into_iter();
+                        $(let $element = $element::from_value(elements.next().unwrap())?;)*
+                        Ok(($($element,)*))
+                    }
+                    value => expected(concat!("a tuple of ", $len), &value),
+                }
+            }
+
+            fn ty(params: &mut Vec<Symbol>) -> Type {
+                Type::Tuple(vec![$($element::ty(params)),*])
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl<$($element: IntoValue),*> IntoValue for ($($element,)*) {
+            fn into_value(self) -> Result<Value, String> {
+                let ($($element,)*) = self;
+                Ok(Value::Tuple(vec![$($element.into_value()?),*]))
+            }
+
+            fn ty(params: &mut Vec<Symbol>) -> Type {
+                Type::Tuple(vec![$($element::ty(params)),*])
+            }
+        }
+    };
+}
+
+tuple!(2, A, B);
+tuple!(3, A, B, C);
+tuple!(4, A, B, C, D);
+
+/// A Rust function amber can call, taking arguments which convert from
+/// values and returning one which converts into a value. `Args` are the
+/// types of the arguments, which tell apart the implementations for
+/// functions of different arity.
+pub trait NativeFunction<Args> {
+    fn arity(&self) -> usize;
+
+    /// The type of the function, for the checker.
+    fn ty(&self) -> Type;
+
+    /// Calls the function with `arity` arguments.
+    fn invoke(&self, args: Vec<Value>) -> Result<Value, String>;
+}
+
+macro_rules! native_function {
+    ($($arg:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<F, R, $($arg),*> NativeFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R,
+            R: IntoValue,
+            $($arg: FromValue,)*
+        {
+            fn arity(&self) -> usize {
+                <[&str]>::len(&[$(stringify!($arg)),*])
+            }
+
+            fn ty(&self) -> Type {
+                let mut params = vec![];
+                let args = vec![$($arg::ty(&mut params)),*];
+                Type::Function(args, Box::new(R::ty(&mut params)))
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn invoke(&self, args: Vec<Value>) -> Result<Value, String> {
+                let mut args = args.into_iter().enumerate();
+                $(
+                    let (i, value) = args.next().unwrap();
+                    let $arg = $arg::from_value(value)
+                        .map_err(|err| format!("argument {}: {}", i + 1, err))?;
+                )*
+                self($($arg),*).into_value()
+            }
+        }
+    };
+}
+
+native_function!();
+native_function!(A);
+native_function!(A, B);
+native_function!(A, B, C);
+native_function!(A, B, C, D);
+
+/// Wraps a function of values the host owns as one the VM can call, which
+/// copies the arguments out of the heap and the result into it.
+pub fn native_fn(function: impl Fn(Vec<Value>) -> Result<Value, String> + 'static) -> NativeFn {
+    Rc::new(move |vm: &mut Vm, args: &[vm::Value]| {
+        let args = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                Value::from_vm(vm.heap(), *arg)
+                    .map_err(|err| format!("argument {}: {}", i + 1, err))
+            })
+            .collect::<Result<_, _>>()?;
+        function(args)?.into_vm(vm)
+    })
+}
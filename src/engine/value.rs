@@ -0,0 +1,246 @@
+use crate::syntax::symbol::Symbol;
+use crate::vm::value::{self as vm, Heap, HostObject, Object, Record, Root, Variant};
+use crate::vm::Vm;
+use std::fmt;
+use std::rc::Rc;
+
+/// How deeply the values handed to the host may nest.
+pub const MAX_DEPTH: usize = 512;
+
+/// A value handed to the host. Unlike the values of the VM it owns its
+/// contents, so it stays valid however long the host keeps it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Tuple(Vec<Value>),
+    /// The name of the type and the fields.
+    Record(String, Vec<(String, Value)>),
+    /// The name of the type, of the constructor and the fields.
+    Variant(String, String, Vec<Value>),
+    /// A function. It can only be called from amber.
+    Function(Function),
+    /// A value of a type the host implements, shared with the program.
+    Host(HostObject),
+}
+
+impl Value {
+    /// The name of the type of the value, for error messages.
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Tuple(_) => "tuple",
+            Value::Record(type_name, _) | Value::Variant(type_name, _, _) => type_name,
+            Value::Function(_) => "function",
+            Value::Host(host) => host.type_name.as_str(),
+        }
+    }
+
+    /// Copies a value onto the heap of a VM. Variants refer to the globals
+    /// of their constructors, which have to be declared, and functions
+    /// have to come from the same VM.
+    pub fn into_vm(self, vm: &mut Vm) -> Result<vm::Value, String> {
+        let object = match self {
+            Value::Nil => return Ok(vm::Value::Nil),
+            Value::Bool(b) => return Ok(vm::Value::Bool(b)),
+            Value::Number(n) => return Ok(vm::Value::Number(n)),
+            Value::String(s) => Object::String(s),
+            Value::Tuple(elements) => Object::Tuple(
+                elements
+                    .into_iter()
+                    .map(|element| element.into_vm(vm))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Record(type_name, fields) => Object::Record(Record {
+                type_name: Symbol::intern(&type_name),
+                fields: fields
+                    .into_iter()
+                    .map(|(name, value)| Ok((Symbol::intern(&name), value.into_vm(vm)?)))
+                    .collect::<Result<_, String>>()?,
+            }),
+            Value::Variant(type_name, name, fields) => {
+                let constructor = vm
+                    .global(Symbol::intern(&name))
+                    .and_then(|value| constructor(vm.heap(), value))
+                    .filter(|&(ty, arity, _)| ty == type_name && arity == fields.len());
+                let constructor = match constructor {
+                    Some((_, _, constructor)) => constructor,
+                    None => {
+                        return Err(format!("`{}` is not a constructor of {}", name, type_name))
+                    }
+                };
+                Object::Variant(Variant {
+                    constructor,
+                    fields: fields
+                        .into_iter()
+                        .map(|field| field.into_vm(vm))
+                        .collect::<Result<_, _>>()?,
+                })
+            }
+            Value::Host(host) => Object::Host(host),
+            Value::Function(function) => {
+                return vm
+                    .heap()
+                    .rooted(&function.root)
+                    .map(vm::Value::Object)
+                    .ok_or_else(|| format!("`{}` is a function of another engine", function.name))
+            }
+        };
+        Ok(vm::Value::Object(vm.heap_mut().alloc(object)))
+    }
+
+    /// Copies a value out of the heap of a VM. Values nested deeper than
+    /// [`MAX_DEPTH`] are refused, so the host never holds one too deep to
+    /// compare, display or drop.
+    pub fn from_vm(heap: &Heap, value: vm::Value) -> Result<Self, String> {
+        Value::copy(heap, value, 0)
+    }
+
+    fn copy(heap: &Heap, value: vm::Value, depth: usize) -> Result<Self, String> {
+        let values = |values: &[vm::Value]| {
+            values
+                .iter()
+                .map(|value| Value::copy(heap, *value, depth + 1))
+                .collect::<Result<_, _>>()
+        };
+
+        let object = match value {
+            vm::Value::Nil => return Ok(Value::Nil),
+            vm::Value::Bool(b) => return Ok(Value::Bool(b)),
+            vm::Value::Number(n) => return Ok(Value::Number(n)),
+            vm::Value::Object(object) => object,
+        };
+        if depth == MAX_DEPTH {
+            return Err(format!("value nested deeper than {}", MAX_DEPTH));
+        }
+        Ok(match heap.get(object) {
+            Object::String(s) => Value::String(s.clone()),
+            Object::Tuple(elements) => Value::Tuple(values(elements)?),
+            Object::Record(record) => Value::Record(
+                record.type_name.to_string(),
+                record
+                    .fields
+                    .iter()
+                    .map(|(name, value)| {
+                        Ok((name.to_string(), Value::copy(heap, *value, depth + 1)?))
+                    })
+                    .collect::<Result<_, String>>()?,
+            ),
+            Object::Variant(variant) => match heap.get(variant.constructor) {
+                Object::Constructor(constructor) => Value::Variant(
+                    constructor.type_name.to_string(),
+                    constructor.name.to_string(),
+                    values(&variant.fields)?,
+                ),
+                _ => unreachable!(),
+            },
+            Object::Constructor(_)
+            | Object::Partial(_)
+            | Object::Builtin(_)
+            | Object::Native(_)
+            | Object::Closure(_) => Value::Function(Function {
+                name: function_name(heap, object),
+                root: heap.root(object),
+            }),
+            Object::Host(host) => Value::Host(host.clone()),
+            Object::Upvalue(_) => unreachable!("upvalues are not values"),
+        })
+    }
+}
+
+/// A function of a program, or one it was handed, which the host can pass
+/// back to it. It is the same function, with the variables it captured,
+/// and stays alive as long as the host holds it.
+#[derive(Clone)]
+pub struct Function {
+    name: String,
+    root: Rc<Root>,
+}
+
+impl Function {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Functions are equal if they are the same object.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.root == other.root
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Function").field(&self.name).finish()
+    }
+}
+
+fn function_name(heap: &Heap, object: vm::ObjectRef) -> String {
+    match heap.get(object) {
+        Object::Constructor(constructor) => constructor.name.to_string(),
+        Object::Partial(partial) => function_name(heap, partial.function),
+        Object::Builtin(builtin) => builtin.name().to_string(),
+        Object::Native(native) => native.name.to_string(),
+        Object::Closure(closure) => closure.function.name.to_string(),
+        _ => unreachable!("not a function"),
+    }
+}
+
+/// The type, arity and object of the constructor a global refers to, which
+/// for a constructor without fields is its only variant.
+fn constructor(heap: &Heap, value: vm::Value) -> Option<(&str, usize, vm::ObjectRef)> {
+    let object = match value {
+        vm::Value::Object(object) => object,
+        _ => return None,
+    };
+    let object = match heap.get(object) {
+        Object::Variant(variant) => variant.constructor,
+        _ => object,
+    };
+    match heap.get(object) {
+        Object::Constructor(constructor) => {
+            Some((constructor.type_name.as_str(), constructor.arity, object))
+        }
+        _ => None,
+    }
+}
+
+/// Renders the value the way it would be written in amber, like
+/// `Heap::display`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |values: &[Value]| {
+            values
+                .iter()
+                .map(Value::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Tuple(elements) if elements.len() == 1 => write!(f, "({},)", elements[0]),
+            Value::Tuple(elements) => write!(f, "({})", list(elements)),
+            Value::Record(type_name, fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect();
+                write!(f, "{} {{ {} }}", type_name, fields.join(", "))
+            }
+            Value::Variant(_, name, fields) if fields.is_empty() => write!(f, "{}", name),
+            Value::Variant(_, name, fields) => write!(f, "{}({})", name, list(fields)),
+            Value::Function(function) => write!(f, "<def {}>", function.name),
+            Value::Host(host) => write!(f, "<{}>", host.type_name),
+        }
+    }
+}
@@ -0,0 +1,653 @@
+use crate::syntax::cst::{SyntaxToken, TriviaKind};
+use crate::syntax::cst_parser::CstParser;
+use crate::syntax::error::SyntaxError;
+use crate::syntax::token::{Keyword, TokenType};
+use std::ops::Range;
+
+const INDENT: &str = "    ";
+const MAX_WIDTH: usize = 100;
+
+/// Formats the source in the canonical amber style. Comments are kept,
+/// indentation and spacing are normalized and argument lists which do not
+/// fit on a line are wrapped, one argument per line. A line with a `//`
+/// comment inside it which cannot end up after an argument is left as it is.
+pub fn format(source: &str) -> Result<String, SyntaxError> {
+    let cst = CstParser::parse(source)?;
+    let lines = collect_lines(&cst.tokens());
+
+    let mut printer = Printer::default();
+    for line in &lines {
+        printer.line(line, &source[line.span.clone()]);
+    }
+    Ok(printer.finish())
+}
+
+/// A token, the comments attached to it and the own-line comments in
+/// front of it. The latter only occur inside of wrapped brackets.
+#[derive(Debug, Clone)]
+struct Piece {
+    token_type: TokenType,
+    text: String,
+    leading: Vec<String>,
+    trailing: Vec<String>,
+}
+
+impl Piece {
+    fn is_opener(&self) -> bool {
+        matches!(
+            self.token_type,
+            TokenType::Keyword(Keyword::Def)
+                | TokenType::Keyword(Keyword::Impl)
+                | TokenType::Keyword(Keyword::Interface)
+                | TokenType::LeftParen
+                | TokenType::LeftBracket
+                | TokenType::LeftBrace
+        )
+    }
+
+    fn is_closer(&self) -> bool {
+        matches!(
+            self.token_type,
+            TokenType::Keyword(Keyword::End)
+                | TokenType::RightParen
+                | TokenType::RightBracket
+                | TokenType::RightBrace
+        )
+    }
+
+    fn is_operator(&self) -> bool {
+        matches!(
+            self.token_type,
+            TokenType::Minus
+                | TokenType::Plus
+                | TokenType::Star
+                | TokenType::Slash
+                | TokenType::Bang
+                | TokenType::BangEqual
+                | TokenType::Equal
+                | TokenType::EqualEqual
+                | TokenType::LessThan
+                | TokenType::LessThanEqual
+                | TokenType::GreaterThan
+                | TokenType::GreaterThanEqual
+        )
+    }
+
+    fn has_line_comment(&self) -> bool {
+        self.trailing.iter().any(|c| c.starts_with("//"))
+    }
+}
+
+#[derive(Debug)]
+enum Before {
+    Blank,
+    Comment(String),
+}
+
+/// A logical line, that is everything up to the next line break token.
+#[derive(Debug, Default)]
+struct Line {
+    before: Vec<Before>,
+    pieces: Vec<Piece>,
+    /// Where the pieces and their comments are in the source.
+    span: Range<usize>,
+}
+
+fn collect_lines(tokens: &[SyntaxToken]) -> Vec<Line> {
+    let mut lines: Vec<Line> = vec![];
+    let mut line = Line::default();
+
+    let mut at_line_start = true;
+    let mut blank = false;
+    let mut piece_leading = vec![];
+    for token in tokens {
+        let mut offset = token.full_range().start;
+        for trivia in token.green().leading_trivia() {
+            offset += trivia.text().len();
+            match trivia.kind() {
+                TriviaKind::Newline if at_line_start && line.pieces.is_empty() => blank = true,
+                TriviaKind::Newline => at_line_start = true,
+                TriviaKind::Comment => {
+                    let comment = trivia.text().trim_end().to_string();
+                    if !line.pieces.is_empty() {
+                        piece_leading.push(comment);
+                    } else if !at_line_start && !lines.is_empty() {
+                        // A comment following a ";" on the same line.
+                        let previous = lines.last_mut().unwrap();
+                        previous.pieces.last_mut().unwrap().trailing.push(comment);
+                        previous.span.end = offset;
+                    } else {
+                        if blank {
+                            line.before.push(Before::Blank);
+                            blank = false;
+                        }
+                        line.before.push(Before::Comment(comment));
+                        at_line_start = false;
+                    }
+                }
+                TriviaKind::Whitespace | TriviaKind::Semicolon => {}
+            }
+        }
+
+        match token.token_type() {
+            TokenType::Line => {
+                if !line.pieces.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                at_line_start = token.text() != ";";
+                blank = false;
+            }
+            TokenType::EOF => {
+                line.before
+                    .extend(piece_leading.drain(..).map(Before::Comment));
+                if !line.pieces.is_empty() || !line.before.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+            }
+            token_type => {
+                if blank && line.pieces.is_empty() {
+                    line.before.push(Before::Blank);
+                }
+                blank = false;
+                at_line_start = false;
+
+                if line.pieces.is_empty() {
+                    line.span.start = token.text_range().start;
+                }
+                line.span.end = token.full_range().end;
+                line.pieces.push(Piece {
+                    token_type: token_type.clone(),
+                    text: token.text().to_string(),
+                    leading: std::mem::take(&mut piece_leading),
+                    trailing: token
+                        .green()
+                        .trailing_trivia()
+                        .iter()
+                        .filter(|t| t.kind() == TriviaKind::Comment)
+                        .map(|t| t.text().trim_end().to_string())
+                        .collect(),
+                });
+            }
+        }
+    }
+
+    lines
+}
+
+#[derive(Default)]
+struct Printer {
+    output: Vec<String>,
+    indent: usize,
+    opened_block: bool,
+    /// Whether the last line belongs to a `type`, whose cases are
+    /// indented when they are on lines of their own.
+    in_type: bool,
+    /// Whether the line is inside an `interface`, whose `def`s have no
+    /// body to indent.
+    in_interface: bool,
+}
+
+impl Printer {
+    /// Prints a line, or `source`, the line as it is, if it has a line
+    /// comment where it cannot be broken.
+    fn line(&mut self, line: &Line, source: &str) {
+        let starts_with_closer = line.pieces.first().is_some_and(Piece::is_closer);
+
+        for before in &line.before {
+            match before {
+                Before::Blank => {
+                    let skip = self.output.is_empty()
+                        || self.opened_block
+                        || starts_with_closer
+                        || self.output.last().is_some_and(|l| l.is_empty());
+                    if !skip {
+                        self.output.push(String::new());
+                    }
+                }
+                Before::Comment(comment) => self.push(self.indent, comment),
+            }
+            self.opened_block = false;
+        }
+
+        if line.pieces.is_empty() {
+            return;
+        }
+
+        let first = &line.pieces[0].token_type;
+        if self.in_interface && first == &TokenType::Keyword(Keyword::End) {
+            self.in_interface = false;
+        }
+        let is_signature = self.in_interface && first == &TokenType::Keyword(Keyword::Def);
+        self.in_interface |= first == &TokenType::Keyword(Keyword::Interface);
+
+        let mut depth: isize = 0;
+        for (i, piece) in line.pieces.iter().enumerate() {
+            if piece.is_opener() && !(i == 0 && is_signature) {
+                depth += 1;
+            } else if piece.is_closer() && !(i == 0 && starts_with_closer) {
+                depth -= 1;
+            }
+        }
+        if starts_with_closer {
+            self.indent = self.indent.saturating_sub(1);
+        }
+
+        let is_case = self.in_type && first == &TokenType::Pipe;
+        self.in_type = is_case || first == &TokenType::Keyword(Keyword::Type);
+
+        let indent = self.indent + is_case as usize;
+        match render(&line.pieces, indent) {
+            Some(lines) => {
+                for text in lines {
+                    self.push(0, &text);
+                }
+            }
+            None => {
+                let mut lines = source.trim_end().lines();
+                self.push(indent, lines.next().unwrap_or_default());
+                for text in lines {
+                    self.push(0, text);
+                }
+            }
+        }
+
+        self.indent = (self.indent as isize + depth).max(0) as usize;
+        self.opened_block = depth > 0;
+    }
+
+    fn push(&mut self, indent: usize, text: &str) {
+        self.output
+            .push(format!("{}{}", INDENT.repeat(indent), text));
+    }
+
+    fn finish(mut self) -> String {
+        while self.output.last().is_some_and(|l| l.is_empty()) {
+            self.output.pop();
+        }
+        if self.output.is_empty() {
+            return String::new();
+        }
+        self.output.join("\n") + "\n"
+    }
+}
+
+/// Renders the pieces on one line if they fit, otherwise breaks the first
+/// bracket up into one line per argument. Lines are returned with their
+/// indentation applied. A line comment has to end a line, if it comes before
+/// another piece and is not after an argument there is no rendering.
+fn render(pieces: &[Piece], indent: usize) -> Option<Vec<String>> {
+    let flat = render_flat(pieces);
+    let line_comment = pieces[..pieces.len() - 1]
+        .iter()
+        .any(Piece::has_line_comment);
+    let must_break = line_comment || pieces.iter().skip(1).any(|p| !p.leading.is_empty());
+    let fits = INDENT.len() * indent + flat.chars().count() <= MAX_WIDTH;
+
+    if must_break || !fits {
+        if let Some(lines) = render_wrapped(pieces, indent) {
+            return Some(lines);
+        }
+    }
+    if line_comment {
+        return None;
+    }
+
+    let mut lines: Vec<String> = pieces
+        .iter()
+        .flat_map(|p| p.leading.iter())
+        .map(|comment| format!("{}{}", INDENT.repeat(indent), comment))
+        .collect();
+    lines.push(format!("{}{}", INDENT.repeat(indent), flat));
+    Some(lines)
+}
+
+fn render_wrapped(pieces: &[Piece], indent: usize) -> Option<Vec<String>> {
+    let open = pieces
+        .iter()
+        .position(|p| matches!(p.token_type, TokenType::LeftParen | TokenType::LeftBracket))?;
+
+    let mut depth = 0;
+    let mut close = None;
+    for (i, piece) in pieces.iter().enumerate().skip(open) {
+        match piece.token_type {
+            TokenType::LeftParen | TokenType::LeftBracket => depth += 1,
+            TokenType::RightParen | TokenType::RightBracket => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let close = close?;
+    if close == open + 1 {
+        return None;
+    }
+
+    let mut lines = render(&pieces[..=open], indent)?;
+
+    let mut start = open + 1;
+    depth = 0;
+    for i in open + 1..close {
+        match pieces[i].token_type {
+            TokenType::LeftParen | TokenType::LeftBracket => depth += 1,
+            TokenType::RightParen | TokenType::RightBracket => depth -= 1,
+            TokenType::Comma if depth == 0 => {
+                lines.extend(render(&pieces[start..=i], indent + 1)?);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < close {
+        lines.extend(render(&pieces[start..close], indent + 1)?);
+    }
+
+    for comment in &pieces[close].leading {
+        lines.push(format!("{}{}", INDENT.repeat(indent + 1), comment));
+    }
+    let mut tail = pieces[close..].to_vec();
+    tail[0].leading.clear();
+    lines.extend(render(&tail, indent)?);
+
+    Some(lines)
+}
+
+fn render_flat(pieces: &[Piece]) -> String {
+    let mut out = String::new();
+    for (i, piece) in pieces.iter().enumerate() {
+        if i > 0 && needs_space(pieces, i) {
+            out.push(' ');
+        }
+        out.push_str(&piece.text);
+        for comment in &piece.trailing {
+            out.push(' ');
+            out.push_str(comment);
+        }
+    }
+    out
+}
+
+fn needs_space(pieces: &[Piece], i: usize) -> bool {
+    let prev = &pieces[i - 1];
+    let cur = &pieces[i];
+
+    if is_unary(pieces, i - 1) || is_type_bracket(pieces, i) {
+        return false;
+    }
+    if prev.token_type == TokenType::LessThan && is_type_bracket(pieces, i - 1) {
+        return false;
+    }
+    match cur.token_type {
+        TokenType::Comma
+        | TokenType::Question
+        | TokenType::Colon
+        | TokenType::RightParen
+        | TokenType::RightBracket
+        | TokenType::Dot => return false,
+        TokenType::LeftParen | TokenType::LeftBracket => {
+            if let TokenType::Identifier | TokenType::RightParen | TokenType::RightBracket =
+                prev.token_type
+            {
+                return false;
+            }
+        }
+        _ => {}
+    }
+    !matches!(
+        prev.token_type,
+        TokenType::LeftParen | TokenType::LeftBracket | TokenType::Dot | TokenType::DotDot
+    )
+}
+
+/// Checks whether the `<` or `>` at `i` encloses type arguments, as in
+/// `def first(x: option<number>)`. The line of a `def` has no comparisons.
+fn is_type_bracket(pieces: &[Piece], i: usize) -> bool {
+    matches!(
+        pieces[i].token_type,
+        TokenType::LessThan | TokenType::GreaterThan
+    ) && pieces[0].token_type == TokenType::Keyword(Keyword::Def)
+}
+
+/// Checks whether the operator at `i` is a prefix operator, based on the
+/// piece before it.
+fn is_unary(pieces: &[Piece], i: usize) -> bool {
+    if !matches!(pieces[i].token_type, TokenType::Minus | TokenType::Bang) {
+        return false;
+    }
+    match i.checked_sub(1).map(|i| &pieces[i]) {
+        None => true,
+        Some(prev) => {
+            prev.is_operator()
+                || matches!(
+                    prev.token_type,
+                    TokenType::LeftParen
+                        | TokenType::LeftBracket
+                        | TokenType::LeftBrace
+                        | TokenType::Comma
+                        | TokenType::Keyword(_)
+                )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::lexer::Lexer;
+    use crate::syntax::parser::AstParser;
+
+    fn assert_format(source: &str, expect: &str) {
+        let actual = format(source).unwrap();
+        assert_eq!(expect, actual);
+
+        // Formatting is idempotent and never changes the meaning.
+        assert_eq!(expect, format(&actual).unwrap());
+        let mut before = Lexer::tokenize(source).unwrap();
+        let mut after = Lexer::tokenize(&actual).unwrap();
+        assert_eq!(
+            AstParser::parse(&mut before).unwrap(),
+            AstParser::parse(&mut after).unwrap()
+        );
+    }
+
+    #[test]
+    fn format_indentation() {
+        let source = r#"
+def double(x)
+return x*2
+    end
+  def   inc( x )
+        return x+1
+  end
+"#;
+        let expect = r#"def double(x)
+    return x * 2
+end
+def inc(x)
+    return x + 1
+end
+"#;
+        assert_format(source, expect);
+    }
+
+    #[test]
+    fn format_operators() {
+        let source = "let x = -(1+2)*-3\nlet y=!x  !=  x>=2;let z = x/ 2\n";
+        let expect = "let x = -(1 + 2) * -3\nlet y = !x != x >= 2\nlet z = x / 2\n";
+        assert_format(source, expect);
+    }
+
+    #[test]
+    fn format_comments() {
+        let source = r#"// Header.
+
+
+/// Doubles x.
+def double(x)   // Trailing.
+
+        // Own line.
+  return x * /* two */ 2
+    // Before end.
+end
+let y = 1; // After a semicolon.
+"#;
+        let expect = r#"// Header.
+
+/// Doubles x.
+def double(x) // Trailing.
+    // Own line.
+    return x * /* two */ 2
+    // Before end.
+end
+let y = 1 // After a semicolon.
+"#;
+        assert_format(source, expect);
+    }
+
+    #[test]
+    fn format_types() {
+        let source = r#"type point = {x,y}
+type shape =
+| Circle(r)
+        | Empty
+impl point
+def add(a,b)
+point {x:a.x+b.x,y:a.y+b.y}
+end
+end
+"#;
+        let expect = r#"type point = { x, y }
+type shape =
+    | Circle(r)
+    | Empty
+impl point
+    def add(a, b)
+        point { x: a.x + b.x, y: a.y + b.y }
+    end
+end
+"#;
+        assert_format(source, expect);
+    }
+
+    #[test]
+    fn format_interfaces() {
+        let source = r#"interface Show
+def show(x)
+def describe(x,prefix:string)
+end
+impl Show for number
+def show(n)
+"number"
+end
+end
+def print(x:a)where a:Show
+show(x)
+end
+def first(pair:(option<number>,result<a,b>))
+pair.0 ?
+end
+"#;
+        let expect = r#"interface Show
+    def show(x)
+    def describe(x, prefix: string)
+end
+impl Show for number
+    def show(n)
+        "number"
+    end
+end
+def print(x: a) where a: Show
+    show(x)
+end
+def first(pair: (option<number>, result<a, b>))
+    pair.0?
+end
+"#;
+        assert_format(source, expect);
+    }
+
+    #[test]
+    fn format_blank_lines() {
+        let source = "\n\nlet x = 1\n\n\n\nlet y = 2\n\n";
+        let expect = "let x = 1\n\nlet y = 2\n";
+        assert_format(source, expect);
+    }
+
+    #[test]
+    fn format_long_parameter_list() {
+        let source = r#"def functionwithaverylongnameindeed(firstparameter, secondparameter, thirdparameter, fourthparameter)
+end
+def short(
+    x,
+    y
+)
+end
+"#;
+        let expect = r#"def functionwithaverylongnameindeed(
+    firstparameter,
+    secondparameter,
+    thirdparameter,
+    fourthparameter
+)
+end
+def short(x, y)
+end
+"#;
+        assert_format(source, expect);
+    }
+
+    #[test]
+    fn format_comments_in_parameter_list() {
+        let source = r#"def f(x, // The x.
+    // The y.
+    y)
+end
+"#;
+        let expect = r#"def f(
+    x, // The x.
+    // The y.
+    y
+)
+end
+"#;
+        assert_format(source, expect);
+    }
+
+    #[test]
+    fn format_line_comments_inside_lines() {
+        // A line comment always ends the line. Where that is not after an
+        // argument, the line is left as it is.
+        let source = r#"let y = (1 // one
+ + 2)
+f(a // c
+, b)
+f(a, // c
+b)
+let x = f(1 + // c
+    2, 3)
+def g(x)
+let z = f(1,  (2 // two
+  + 3)); // After.
+end
+"#;
+        let expect = r#"let y = (1 // one
+ + 2)
+f(a // c
+, b)
+f(
+    a, // c
+    b
+)
+let x = f(1 + // c
+    2, 3)
+def g(x)
+    let z = f(1,  (2 // two
+  + 3)); // After.
+end
+"#;
+        assert_format(source, expect);
+    }
+}
@@ -0,0 +1,24 @@
+//! amber, a small scripting language, and what it takes to embed it.
+//!
+//! ```
+//! # fn main() -> Result<(), amber::Error> {
+//! let mut engine = amber::Engine::new();
+//! let value = engine.eval("let x = 2\nx * 3\n")?;
+//! assert_eq!(amber::Value::Number(6.0), value);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The stages the engine runs are public too, from `syntax` for the tree
+//! of a source to `vm` for running it.
+
+pub mod engine;
+pub mod formatter;
+pub mod lsp;
+mod prelude;
+pub mod resolver;
+pub mod syntax;
+pub mod types;
+pub mod vm;
+
+pub use engine::{Engine, Error, Program, Value};
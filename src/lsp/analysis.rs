@@ -0,0 +1,561 @@
+use crate::resolver::Resolver;
+use crate::syntax::ast::*;
+use crate::syntax::cst::SyntaxNode;
+use crate::syntax::cst_parser::CstParser;
+use crate::syntax::symbol::Symbol;
+use crate::types::checker::{TypeChecker, TypeInfo};
+use crate::types::ty::Type;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+    Parameter,
+    Constructor,
+    /// A record type, whose fields are its children.
+    Struct,
+    /// A variant type, whose constructors are its children.
+    Enum,
+    Field,
+}
+
+impl SymbolKind {
+    /// Whether the definition names a value, which expressions can refer
+    /// to, rather than a type or a field.
+    pub fn is_value(self) -> bool {
+        !matches!(
+            self,
+            SymbolKind::Struct | SymbolKind::Enum | SymbolKind::Field
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Definition {
+    pub name: Symbol,
+    pub kind: SymbolKind,
+    /// The range of the name.
+    pub range: Range<usize>,
+    /// The range of the whole declaration, for functions up to their `end`.
+    pub full_range: Range<usize>,
+    /// Where the name can be referred to.
+    pub visible: Range<usize>,
+    /// The function or type the definition is nested in.
+    pub parent: Option<usize>,
+    pub doc: Option<String>,
+    pub ty: Option<Type>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Reference {
+    pub range: Range<usize>,
+    pub definition: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The line of the problem, or `None` at the end of the file.
+    pub line: Option<usize>,
+    /// The exact extent of the problem, where it is known.
+    pub range: Option<Range<usize>>,
+    pub message: String,
+    /// Another place involved in the problem, with a note on its role.
+    pub related: Option<(Range<usize>, String)>,
+}
+
+/// Everything the language server knows about a document.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub definitions: Vec<Definition>,
+    pub references: Vec<Reference>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The type of every expression, with the source it was parsed from.
+    pub types: Vec<(Range<usize>, Type)>,
+}
+
+impl Analysis {
+    pub fn new(source: &str) -> Self {
+        match CstParser::parse(source) {
+            Ok(tree) => Analysis::with_tree(&tree),
+            Err(err) => Analysis::failed(err.line(), err.to_string()),
+        }
+    }
+
+    /// Analyses an already parsed module, see `incremental::reparse`.
+    pub fn with_tree(tree: &SyntaxNode) -> Self {
+        let module = match tree.lower() {
+            Ok(module) => module,
+            Err(err) => return Analysis::failed(err.line(), err.to_string()),
+        };
+
+        let mut analysis = Analysis::default();
+
+        let resolution = Resolver::resolve(&module);
+        for err in resolution.errors() {
+            let position = err.position();
+            analysis.diagnostics.push(Diagnostic {
+                line: Some(err.line()),
+                range: Some(*position.start()..*position.end()),
+                message: err.to_string(),
+                related: err.declaration().map(|declaration| {
+                    let range = *declaration.start()..*declaration.end();
+                    (range, "declared here".to_string())
+                }),
+            });
+        }
+
+        let types = TypeChecker::check(&module);
+        for err in types.errors() {
+            analysis.diagnostics.push(Diagnostic {
+                line: Some(err.line()),
+                range: None,
+                message: err.to_string(),
+                related: None,
+            });
+        }
+
+        let mut collector = Collector {
+            module: &module,
+            types: &types,
+            analysis: &mut analysis,
+            scopes: vec![(tree.full_range(), vec![])],
+            parents: vec![],
+        };
+        for &expr in &module.exprs {
+            collector.expr(expr);
+        }
+
+        for (id, ty) in types.exprs().iter() {
+            let position = module.position(id);
+            let range = *position.start()..*position.end();
+            analysis.types.push((range, ty.clone()));
+        }
+
+        analysis
+    }
+
+    fn failed(line: Option<usize>, message: String) -> Self {
+        Analysis {
+            diagnostics: vec![Diagnostic {
+                line,
+                range: None,
+                message,
+                related: None,
+            }],
+            ..Analysis::default()
+        }
+    }
+
+    /// The definition of the name at `offset`, whether the name is the
+    /// definition itself or a reference to it.
+    pub fn definition_at(&self, offset: usize) -> Option<usize> {
+        let contains = |range: &Range<usize>| range.start <= offset && offset <= range.end;
+
+        self.definitions
+            .iter()
+            .position(|d| contains(&d.range))
+            .or_else(|| {
+                self.references
+                    .iter()
+                    .find(|r| contains(&r.range))
+                    .map(|r| r.definition)
+            })
+    }
+
+    /// The type of the innermost expression at `offset`.
+    pub fn type_at(&self, offset: usize) -> Option<&Type> {
+        self.types
+            .iter()
+            .filter(|(range, _)| range.start <= offset && offset < range.end)
+            .min_by_key(|(range, _)| range.len())
+            .map(|(_, ty)| ty)
+    }
+
+    pub fn references_to(&self, definition: usize) -> impl Iterator<Item = &Range<usize>> {
+        self.references
+            .iter()
+            .filter(move |r| r.definition == definition)
+            .map(|r| &r.range)
+    }
+
+    /// The values visible at `offset`, inner definitions shadowing outer
+    /// ones.
+    pub fn visible_at(&self, offset: usize) -> Vec<&Definition> {
+        let mut visible: Vec<&Definition> = vec![];
+        for definition in &self.definitions {
+            if !definition.kind.is_value()
+                || definition.visible.start > offset
+                || offset > definition.visible.end
+            {
+                continue;
+            }
+            match visible.iter().position(|d| d.name == definition.name) {
+                Some(i) => visible[i] = definition,
+                None => visible.push(definition),
+            }
+        }
+        visible
+    }
+}
+
+struct Collector<'a> {
+    module: &'a ModuleAst,
+    types: &'a TypeInfo,
+    analysis: &'a mut Analysis,
+    /// The extent of every open scope and the definitions made in it.
+    scopes: Vec<(Range<usize>, Vec<usize>)>,
+    parents: Vec<usize>,
+}
+
+impl<'a> Collector<'a> {
+    fn expr(&mut self, id: ExprId) {
+        let module = self.module;
+        match &module[id] {
+            Expr::Literal(_) => {}
+            Expr::Grouping(grouping) => self.expr(grouping.expr),
+            Expr::Binary(binary) => {
+                self.expr(binary.lhs);
+                self.expr(binary.rhs);
+            }
+            Expr::Unary(unary) => self.expr(unary.expr),
+            Expr::LetAssign(let_assign) => {
+                self.expr(let_assign.initializer);
+                if let Some(otherwise) = let_assign.otherwise {
+                    self.expr(otherwise);
+                }
+                for variable in let_assign.pattern.variables() {
+                    let range = range_of(variable);
+                    self.define(
+                        variable,
+                        SymbolKind::Variable,
+                        range,
+                        let_assign.doc.clone(),
+                    );
+                }
+            }
+            Expr::LetGet(let_get) => self.reference(&let_get.variable),
+            Expr::LetSet(let_set) => {
+                self.reference(&let_set.variable);
+                self.expr(let_set.initializer);
+            }
+            Expr::Function(fun) => self.function(id, fun),
+            Expr::Block(block) => {
+                for &expr in &block.exprs {
+                    self.expr(expr);
+                }
+            }
+            Expr::Return(ret) => {
+                if let Some(expr) = ret.expr {
+                    self.expr(expr);
+                }
+            }
+            Expr::Tuple(tuple) => {
+                for &element in &tuple.elements {
+                    self.expr(element);
+                }
+            }
+            Expr::TupleIndex(index) => self.expr(index.tuple),
+            Expr::Call(call) => {
+                self.expr(call.callee);
+                for &argument in &call.arguments {
+                    self.expr(argument);
+                }
+            }
+            Expr::Match(match_expr) => {
+                self.expr(match_expr.scrutinee);
+                for arm in &match_expr.arms {
+                    // Arms have no extent of their own, the scope only keeps
+                    // their variables from being referred to after the arm.
+                    let extent = self.scopes.last().unwrap().0.clone();
+                    self.scopes.push((extent, vec![]));
+                    for variable in arm.pattern.variables() {
+                        let range = range_of(variable);
+                        self.define(variable, SymbolKind::Variable, range, None);
+                    }
+                    self.expr(arm.body);
+                    self.scopes.pop();
+                }
+            }
+            Expr::Type(ty) => {
+                let kind = match ty.definition {
+                    TypeDefinition::Record(_) => SymbolKind::Struct,
+                    TypeDefinition::Variant(_) => SymbolKind::Enum,
+                };
+                let extent = self.extent(id);
+                let index = self.define(&ty.variable, kind, extent, ty.doc.clone());
+
+                self.parents.push(index);
+                match &ty.definition {
+                    TypeDefinition::Record(fields) => {
+                        for field in fields {
+                            self.define(field, SymbolKind::Field, range_of(field), None);
+                        }
+                    }
+                    TypeDefinition::Variant(constructors) => {
+                        for constructor in constructors {
+                            let position = &constructor.position;
+                            let range = *position.start()..*position.end();
+                            let variable = &constructor.variable;
+                            self.define(variable, SymbolKind::Constructor, range, ty.doc.clone());
+                        }
+                    }
+                }
+                self.parents.pop();
+            }
+            Expr::Impl(imp) => {
+                // The functions of a type are not in scope by their name.
+                for (id, fun) in module.functions(imp) {
+                    let extent = self.extent(id);
+                    self.scopes.push((extent, vec![]));
+                    self.body(fun);
+                    self.scopes.pop();
+                }
+            }
+            Expr::Interface(interface) => {
+                for signature in &interface.functions {
+                    let variable = &signature.variable;
+                    let range = range_of(variable);
+                    self.define(variable, SymbolKind::Function, range, interface.doc.clone());
+                }
+            }
+            Expr::Record(record) => {
+                for &(_, value) in &record.fields {
+                    self.expr(value);
+                }
+            }
+            Expr::Field(field) => self.expr(field.record),
+            Expr::SetField(set_field) => {
+                self.expr(set_field.record);
+                self.expr(set_field.value);
+            }
+            Expr::MethodCall(call) => {
+                self.expr(call.receiver);
+                self.reference(&call.method);
+                for &argument in &call.arguments {
+                    self.expr(argument);
+                }
+            }
+            Expr::Try(try_expr) => self.expr(try_expr.expr),
+        }
+    }
+
+    /// The range of a whole expression, for functions up to their `end`.
+    fn extent(&self, id: ExprId) -> Range<usize> {
+        let position = self.module.position(id);
+        *position.start()..*position.end()
+    }
+
+    fn function(&mut self, id: ExprId, fun: &FunctionExpr) {
+        let extent = self.extent(id);
+        let index = self.define(
+            &fun.variable,
+            SymbolKind::Function,
+            extent.clone(),
+            fun.doc.clone(),
+        );
+
+        self.scopes.push((extent, vec![]));
+        self.parents.push(index);
+        self.body(fun);
+        self.parents.pop();
+        self.scopes.pop();
+    }
+
+    fn body(&mut self, fun: &FunctionExpr) {
+        for param in &fun.declaration.parameters {
+            let range = range_of(param);
+            self.define(param, SymbolKind::Parameter, range, None);
+        }
+        for &expr in &fun.declaration.body.exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn define(
+        &mut self,
+        variable: &Variable,
+        kind: SymbolKind,
+        full_range: Range<usize>,
+        doc: Option<String>,
+    ) -> usize {
+        let range = range_of(variable);
+        let (extent, definitions) = self.scopes.last_mut().unwrap();
+        let visible = match kind {
+            SymbolKind::Parameter => extent.clone(),
+            _ => range.start..extent.end,
+        };
+
+        let index = self.analysis.definitions.len();
+        // Types and fields are not in scope, names only refer to values.
+        if kind.is_value() {
+            definitions.push(index);
+        }
+        self.analysis.definitions.push(Definition {
+            name: variable.name,
+            kind,
+            range,
+            full_range,
+            visible,
+            parent: self.parents.last().cloned(),
+            doc,
+            ty: self.types.binding(variable).cloned(),
+        });
+        index
+    }
+
+    fn reference(&mut self, variable: &Variable) {
+        let definitions = &self.analysis.definitions;
+        let definition = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|(_, scope)| scope.iter().rev())
+            .find(|&&d| definitions[d].name == variable.name);
+
+        if let Some(&definition) = definition {
+            self.analysis.references.push(Reference {
+                range: range_of(variable),
+                definition,
+            });
+        }
+    }
+}
+
+fn range_of(variable: &Variable) -> Range<usize> {
+    *variable.position.start()..*variable.position.end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"let x = 1
+/// Doubles.
+def double(x)
+    let y = x * 2
+    return y
+end
+let z = x
+"#;
+
+    fn offset(pattern: &str) -> usize {
+        SOURCE.find(pattern).unwrap()
+    }
+
+    #[test]
+    fn resolve_references() {
+        let analysis = Analysis::new(SOURCE);
+
+        let outer_x = analysis.definition_at(offset("x =")).unwrap();
+        let param_x = analysis.definition_at(offset("x)")).unwrap();
+        assert_ne!(outer_x, param_x);
+        assert_eq!(SymbolKind::Parameter, analysis.definitions[param_x].kind);
+
+        assert_eq!(Some(param_x), analysis.definition_at(offset("x * 2")));
+        assert_eq!(Some(outer_x), analysis.definition_at(offset("x\n")));
+
+        let references: Vec<&Range<usize>> = analysis.references_to(outer_x).collect();
+        assert_eq!(vec![&(offset("x\n")..offset("x\n") + 1)], references);
+    }
+
+    #[test]
+    fn definitions() {
+        let analysis = Analysis::new(SOURCE);
+
+        let double = &analysis.definitions[analysis.definition_at(offset("double")).unwrap()];
+        assert_eq!(SymbolKind::Function, double.kind);
+        assert_eq!(Some("Doubles.".to_string()), double.doc);
+        assert_eq!(offset("def")..offset("end") + 3, double.full_range);
+        assert_eq!(
+            "(number) -> number",
+            double.ty.as_ref().unwrap().to_string()
+        );
+
+        let y = &analysis.definitions[analysis.definition_at(offset("y =")).unwrap()];
+        assert_eq!(
+            Some(analysis.definition_at(offset("double")).unwrap()),
+            y.parent
+        );
+    }
+
+    #[test]
+    fn visible_names() {
+        let analysis = Analysis::new(SOURCE);
+
+        let names = |offset: usize| -> Vec<&str> {
+            analysis
+                .visible_at(offset)
+                .iter()
+                .map(|d| d.name.as_str())
+                .collect()
+        };
+
+        assert_eq!(vec!["x", "double", "y"], names(offset("return")));
+        assert_eq!(vec!["x", "double", "z"], names(SOURCE.len()));
+    }
+
+    #[test]
+    fn expression_types() {
+        let source = "let pair = (1, \"one\")\nlet n = pair.0 * 2\n";
+        let analysis = Analysis::new(source);
+        let type_at = |pattern: &str| {
+            let offset = source.find(pattern).unwrap();
+            analysis.type_at(offset).map(|ty| ty.to_string())
+        };
+
+        assert_eq!(Some("(number, string)".to_string()), type_at("(1"));
+        assert_eq!(Some("string".to_string()), type_at("\"one"));
+        assert_eq!(Some("number".to_string()), type_at(".0"));
+        assert_eq!(Some("(number, string)".to_string()), type_at("pair.0"));
+        assert_eq!(Some("nil".to_string()), type_at("let n"));
+        assert_eq!(None, type_at("\nlet n"));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let analysis = Analysis::new("let x = 5\nlet y = )\n");
+        assert_eq!(
+            vec![Diagnostic {
+                line: Some(2),
+                range: None,
+                message: "expected an expression, found RightParen".to_string(),
+                related: None,
+            }],
+            analysis.diagnostics
+        );
+
+        let analysis = Analysis::new("let mut x = 5\nx = \"five\"\n");
+        assert_eq!(
+            vec![Diagnostic {
+                line: Some(2),
+                range: None,
+                message: "expected number, found string".to_string(),
+                related: None,
+            }],
+            analysis.diagnostics
+        );
+
+        let analysis = Analysis::new("let x = y\n");
+        assert_eq!(
+            vec![Diagnostic {
+                line: Some(1),
+                range: Some(8..9),
+                message: "undefined variable `y`".to_string(),
+                related: None,
+            }],
+            analysis.diagnostics
+        );
+
+        let analysis = Analysis::new("let x = 5\nx = 6\n");
+        assert_eq!(
+            vec![Diagnostic {
+                line: Some(2),
+                range: Some(10..11),
+                message: "cannot assign to `x`, it is not declared with `let mut`".to_string(),
+                related: Some((4..5, "declared here".to_string())),
+            }],
+            analysis.diagnostics
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use serde_json::{json, Value};
+use std::ops::Range;
+
+/// Converts between byte offsets and LSP positions, which count lines from
+/// zero and characters in UTF-16 code units.
+pub struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex {
+            source,
+            line_starts,
+        }
+    }
+
+    pub fn position(&self, offset: usize) -> Value {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        let start = self.line_starts[line];
+        let character: usize = self.source[start..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+        json!({ "line": line, "character": character })
+    }
+
+    pub fn range(&self, range: &Range<usize>) -> Value {
+        json!({ "start": self.position(range.start), "end": self.position(range.end) })
+    }
+
+    /// The range of a whole line, `line` counting from one like the lexer.
+    /// Without a line the last line is used.
+    pub fn line_range(&self, line: Option<usize>) -> Value {
+        let line = line
+            .map(|line| line.saturating_sub(1))
+            .unwrap_or(self.line_starts.len() - 1)
+            .min(self.line_starts.len() - 1);
+        let start = self.line_starts[line];
+        let end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+        self.range(&(start..end))
+    }
+
+    pub fn offset(&self, position: &Value) -> Option<usize> {
+        let line = position.get("line")?.as_u64()? as usize;
+        let character = position.get("character")?.as_u64()? as usize;
+
+        let start = *self.line_starts.get(line)?;
+        let mut units = 0;
+        for (i, c) in self.source[start..].char_indices() {
+            if units >= character || c == '\n' {
+                return Some(start + i);
+            }
+            units += c.len_utf16();
+        }
+        Some(self.source.len())
+    }
+}
@@ -0,0 +1,674 @@
+use crate::lsp::analysis::{Analysis, SymbolKind};
+use crate::lsp::line_index::LineIndex;
+use crate::lsp::transport::{read_message, write_message};
+use crate::syntax::cst::SyntaxNode;
+use crate::syntax::cst_parser::CstParser;
+use crate::syntax::error::SyntaxError;
+use crate::syntax::incremental::{reparse, TextEdit};
+use crate::syntax::token::Keyword;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+mod analysis;
+mod line_index;
+mod transport;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+struct Document {
+    text: String,
+    tree: Result<SyntaxNode, SyntaxError>,
+    analysis: Analysis,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        let tree = CstParser::parse(&text);
+        Document::with_tree(text, tree)
+    }
+
+    fn with_tree(text: String, tree: Result<SyntaxNode, SyntaxError>) -> Self {
+        let analysis = match &tree {
+            Ok(tree) => Analysis::with_tree(tree),
+            Err(_) => Analysis::new(&text),
+        };
+        Document {
+            text,
+            tree,
+            analysis,
+        }
+    }
+
+    /// Applies a change from `didChange`, either a range replaced by new
+    /// text or the whole new text. A range which is not in the document is
+    /// an error.
+    fn change(&self, change: &Value) -> Result<Self, String> {
+        let text = change["text"].as_str().unwrap_or_default().to_string();
+        let range = &change["range"];
+        if range.is_null() {
+            return Ok(Document::new(text));
+        }
+
+        let index = LineIndex::new(&self.text);
+        let (start, end) = match (index.offset(&range["start"]), index.offset(&range["end"])) {
+            (Some(start), Some(end)) if start <= end => (start, end),
+            _ => return Err(format!("the range {} is not in the document", range)),
+        };
+        let edit = TextEdit::new(start..end, text);
+        Ok(match &self.tree {
+            Ok(tree) => {
+                let tree = reparse(tree, &edit);
+                Document::with_tree(edit.apply(&self.text), tree)
+            }
+            Err(_) => Document::new(edit.apply(&self.text)),
+        })
+    }
+}
+
+/// A language server speaking JSON-RPC, see `run`.
+pub struct Server<W: Write> {
+    writer: W,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+/// Serves requests from `reader` until the client sends `exit`. Returns the
+/// exit code the protocol asks for: 0 if `shutdown` was requested first.
+pub fn run<R: BufRead, W: Write>(mut reader: R, writer: W) -> io::Result<i32> {
+    let mut server = Server {
+        writer,
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+
+    while let Some(message) = read_message(&mut reader)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        if method == "exit" {
+            break;
+        }
+
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => server.request(id, method, params)?,
+            None => server.notification(method, params)?,
+        }
+    }
+
+    Ok(if server.shutdown { 0 } else { 1 })
+}
+
+impl<W: Write> Server<W> {
+    fn request(&mut self, id: &Value, method: &str, params: &Value) -> io::Result<()> {
+        let result = match method {
+            "initialize" => Some(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Some(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/completion" => self.completion(params),
+            _ => {
+                let error = json!({ "code": METHOD_NOT_FOUND, "message": "method not found" });
+                return self.send(json!({ "jsonrpc": "2.0", "id": id, "error": error }));
+            }
+        };
+
+        match result {
+            Some(result) => self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+            None => {
+                let error =
+                    json!({ "code": INVALID_PARAMS, "message": "unknown document or position" });
+                self.send(json!({ "jsonrpc": "2.0", "id": id, "error": error }))
+            }
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => uri.to_string(),
+            None => return Ok(()),
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents
+                    .insert(uri.clone(), Document::new(text.to_string()));
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                let document = changes.and_then(|_| self.documents.remove(&uri));
+                if let (Some(changes), Some(mut document)) = (changes, document) {
+                    // The changes after one which cannot be applied would
+                    // not apply to the text they were made to either.
+                    for change in changes {
+                        match document.change(change) {
+                            Ok(changed) => document = changed,
+                            Err(err) => {
+                                self.log(&format!("cannot apply a change to {}: {}", uri, err))?;
+                                break;
+                            }
+                        }
+                    }
+                    self.documents.insert(uri.clone(), document);
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+            }
+            _ => return Ok(()),
+        }
+
+        self.publish_diagnostics(&uri)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics: Vec<Value> = match self.documents.get(uri) {
+            Some(document) => {
+                let index = LineIndex::new(&document.text);
+                document
+                    .analysis
+                    .diagnostics
+                    .iter()
+                    .map(|diagnostic| {
+                        let range = match &diagnostic.range {
+                            Some(range) => index.range(range),
+                            None => index.line_range(diagnostic.line),
+                        };
+                        let related: Vec<Value> = diagnostic
+                            .related
+                            .iter()
+                            .map(|(range, message)| {
+                                json!({
+                                    "location": { "uri": uri, "range": index.range(range) },
+                                    "message": message,
+                                })
+                            })
+                            .collect();
+                        json!({
+                            "range": range,
+                            "severity": 1,
+                            "source": "amber",
+                            "message": diagnostic.message,
+                            "relatedInformation": related,
+                        })
+                    })
+                    .collect()
+            }
+            None => vec![],
+        };
+
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    /// Looks up the document and the byte offset of the position in the
+    /// parameters of a request.
+    fn locate<'p>(&self, params: &'p Value) -> Option<(&'p str, &Document, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let offset = LineIndex::new(&document.text).offset(&params["position"])?;
+        Some((uri, document, offset))
+    }
+
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let (uri, document, offset) = self.locate(params)?;
+        let index = LineIndex::new(&document.text);
+
+        Some(match document.analysis.definition_at(offset) {
+            Some(definition) => {
+                let range = &document.analysis.definitions[definition].range;
+                json!({ "uri": uri, "range": index.range(range) })
+            }
+            None => Value::Null,
+        })
+    }
+
+    fn references(&self, params: &Value) -> Option<Value> {
+        let (uri, document, offset) = self.locate(params)?;
+        let index = LineIndex::new(&document.text);
+        let analysis = &document.analysis;
+
+        let definition = match analysis.definition_at(offset) {
+            Some(definition) => definition,
+            None => return Some(json!([])),
+        };
+
+        let mut locations = vec![];
+        if params["context"]["includeDeclaration"].as_bool() == Some(true) {
+            let range = &analysis.definitions[definition].range;
+            locations.push(json!({ "uri": uri, "range": index.range(range) }));
+        }
+        for range in analysis.references_to(definition) {
+            locations.push(json!({ "uri": uri, "range": index.range(range) }));
+        }
+        Some(Value::Array(locations))
+    }
+
+    fn hover(&self, params: &Value) -> Option<Value> {
+        let (_, document, offset) = self.locate(params)?;
+        let definition = match document.analysis.definition_at(offset) {
+            Some(definition) => &document.analysis.definitions[definition],
+            None => {
+                // Elsewhere, the type of the expression under the cursor.
+                return Some(match document.analysis.type_at(offset) {
+                    Some(ty) => {
+                        let contents = format!("```amber\n{}\n```", ty);
+                        json!({ "contents": { "kind": "markdown", "value": contents } })
+                    }
+                    None => Value::Null,
+                });
+            }
+        };
+
+        let mut contents = match &definition.ty {
+            Some(ty) => format!("```amber\n{}: {}\n```", definition.name, ty),
+            None => format!("```amber\n{}\n```", definition.name),
+        };
+        if let Some(doc) = &definition.doc {
+            contents.push_str("\n\n");
+            contents.push_str(doc);
+        }
+
+        Some(json!({ "contents": { "kind": "markdown", "value": contents } }))
+    }
+
+    fn document_symbols(&self, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let index = LineIndex::new(&document.text);
+        let analysis = &document.analysis;
+
+        fn symbol(analysis: &Analysis, index: &LineIndex, i: usize) -> Value {
+            let definition = &analysis.definitions[i];
+            let children: Vec<Value> = (0..analysis.definitions.len())
+                .filter(|&child| {
+                    analysis.definitions[child].parent == Some(i)
+                        && analysis.definitions[child].kind != SymbolKind::Parameter
+                })
+                .map(|child| symbol(analysis, index, child))
+                .collect();
+            let kind = match definition.kind {
+                SymbolKind::Function => 12,
+                SymbolKind::Variable | SymbolKind::Parameter => 13,
+                SymbolKind::Constructor => 9,
+                SymbolKind::Struct => 23,
+                SymbolKind::Enum => 10,
+                SymbolKind::Field => 8,
+            };
+
+            json!({
+                "name": definition.name.as_str(),
+                "detail": definition.ty.as_ref().map(|ty| ty.to_string()),
+                "kind": kind,
+                "range": index.range(&definition.full_range),
+                "selectionRange": index.range(&definition.range),
+                "children": children,
+            })
+        }
+
+        let symbols: Vec<Value> = (0..analysis.definitions.len())
+            .filter(|&i| analysis.definitions[i].parent.is_none())
+            .map(|i| symbol(analysis, &index, i))
+            .collect();
+        Some(Value::Array(symbols))
+    }
+
+    fn completion(&self, params: &Value) -> Option<Value> {
+        let (_, document, offset) = self.locate(params)?;
+
+        let mut items: Vec<Value> = document
+            .analysis
+            .visible_at(offset)
+            .iter()
+            .map(|definition| {
+                let kind = match definition.kind {
+                    SymbolKind::Function => 3,
+                    SymbolKind::Variable | SymbolKind::Parameter => 6,
+                    SymbolKind::Constructor => 4,
+                    SymbolKind::Struct => 22,
+                    SymbolKind::Enum => 13,
+                    SymbolKind::Field => 5,
+                };
+                json!({
+                    "label": definition.name.as_str(),
+                    "kind": kind,
+                    "detail": definition.ty.as_ref().map(|ty| ty.to_string()),
+                })
+            })
+            .collect();
+        items.extend(
+            Keyword::ALL
+                .iter()
+                .map(|keyword| json!({ "label": keyword.as_str(), "kind": 14 })),
+        );
+
+        Some(Value::Array(items))
+    }
+
+    /// Shows an error in the log of the client.
+    fn log(&mut self, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "window/logMessage",
+            "params": { "type": 1, "message": message },
+        }))
+    }
+
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        write_message(&mut self.writer, &message)
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": 2,
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "hoverProvider": true,
+            "documentSymbolProvider": true,
+            "completionProvider": {},
+        },
+        "serverInfo": { "name": "amber" },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///double.amber";
+    const SOURCE: &str = "/// Doubles x.\ndef double(x)\n    return x * 2\nend\nlet y = double\n";
+
+    /// Plays a scripted client against the server and returns the server's
+    /// messages and exit code.
+    fn session(messages: Vec<Value>) -> (Vec<Value>, i32) {
+        let mut input = vec![];
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+
+        let mut output = vec![];
+        let code = run(input.as_slice(), &mut output).unwrap();
+
+        let mut reader = output.as_slice();
+        let mut responses = vec![];
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            responses.push(message);
+        }
+        (responses, code)
+    }
+
+    fn open(text: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "languageId": "amber", "version": 1, "text": text } },
+        })
+    }
+
+    fn request(id: u64, method: &str, line: u64, character: u64) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            },
+        })
+    }
+
+    fn range(start: (u64, u64), end: (u64, u64)) -> Value {
+        json!({
+            "start": { "line": start.0, "character": start.1 },
+            "end": { "line": end.0, "character": end.1 },
+        })
+    }
+
+    #[test]
+    fn lifecycle() {
+        let (responses, code) = session(vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "workspace/symbol", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+
+        assert_eq!(3, responses.len());
+        assert_eq!(
+            json!(true),
+            responses[0]["result"]["capabilities"]["hoverProvider"]
+        );
+        assert_eq!(json!(METHOD_NOT_FOUND), responses[1]["error"]["code"]);
+        assert_eq!(json!(3), responses[2]["id"]);
+        assert_eq!(0, code);
+
+        let (_, code) = session(vec![json!({ "jsonrpc": "2.0", "method": "exit" })]);
+        assert_eq!(1, code);
+    }
+
+    #[test]
+    fn diagnostics() {
+        let (responses, _) = session(vec![
+            open("let x = 5\nlet y = )\n"),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": 2 },
+                    "contentChanges": [{ "text": "let x = 5\n" }],
+                },
+            }),
+        ]);
+
+        let diagnostics = &responses[0]["params"]["diagnostics"];
+        assert_eq!(range((1, 0), (1, 9)), diagnostics[0]["range"]);
+        assert_eq!(
+            json!("expected an expression, found RightParen"),
+            diagnostics[0]["message"]
+        );
+        assert_eq!(json!([]), responses[1]["params"]["diagnostics"]);
+    }
+
+    #[test]
+    fn incremental_changes() {
+        let change = |version: u64, start: (u64, u64), end: (u64, u64), text: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": version },
+                    "contentChanges": [{ "range": range(start, end), "text": text }],
+                },
+            })
+        };
+        let (responses, _) = session(vec![
+            open(SOURCE),
+            change(2, (2, 15), (2, 16), "\"two\""),
+            change(3, (2, 11), (2, 20), "x"),
+            request(1, "textDocument/hover", 1, 4),
+        ]);
+
+        assert_eq!(
+            json!("expected number, found string"),
+            responses[1]["params"]["diagnostics"][0]["message"]
+        );
+        assert_eq!(json!([]), responses[2]["params"]["diagnostics"]);
+        assert_eq!(
+            json!("```amber\ndouble: ('a) -> 'a\n```\n\nDoubles x."),
+            responses[3]["result"]["contents"]["value"]
+        );
+    }
+
+    #[test]
+    fn changes_out_of_range() {
+        let change = |version: u64, changes: Value| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": version },
+                    "contentChanges": changes,
+                },
+            })
+        };
+        let (responses, _) = session(vec![
+            open(SOURCE),
+            change(
+                2,
+                json!([
+                    { "range": range((9, 0), (9, 1)), "text": "x" },
+                    { "range": range((0, 0), (0, 0)), "text": "let\n" },
+                ]),
+            ),
+            request(1, "textDocument/hover", 1, 4),
+        ]);
+
+        assert_eq!(json!("window/logMessage"), responses[1]["method"]);
+        assert_eq!(json!(1), responses[1]["params"]["type"]);
+        assert_eq!(
+            json!(format!(
+                "cannot apply a change to {}: the range {} is not in the document",
+                URI,
+                range((9, 0), (9, 1))
+            )),
+            responses[1]["params"]["message"]
+        );
+        // Neither change was applied.
+        assert_eq!(json!([]), responses[2]["params"]["diagnostics"]);
+        assert_eq!(
+            json!("```amber\ndouble: (number) -> number\n```\n\nDoubles x."),
+            responses[3]["result"]["contents"]["value"]
+        );
+    }
+
+    #[test]
+    fn navigation() {
+        let (responses, _) = session(vec![
+            open(SOURCE),
+            request(1, "textDocument/definition", 2, 11),
+            request(2, "textDocument/references", 1, 11),
+        ]);
+
+        assert_eq!(json!([]), responses[0]["params"]["diagnostics"]);
+        assert_eq!(
+            json!({ "uri": URI, "range": range((1, 11), (1, 12)) }),
+            responses[1]["result"]
+        );
+        assert_eq!(
+            json!([
+                { "uri": URI, "range": range((1, 11), (1, 12)) },
+                { "uri": URI, "range": range((2, 11), (2, 12)) },
+            ]),
+            responses[2]["result"]
+        );
+    }
+
+    #[test]
+    fn hover() {
+        let (responses, _) = session(vec![
+            open(SOURCE),
+            request(1, "textDocument/hover", 4, 10),
+            request(2, "textDocument/hover", 2, 13),
+        ]);
+
+        assert_eq!(
+            json!("```amber\ndouble: (number) -> number\n```\n\nDoubles x."),
+            responses[1]["result"]["contents"]["value"]
+        );
+        assert_eq!(
+            json!("```amber\nnumber\n```"),
+            responses[2]["result"]["contents"]["value"]
+        );
+    }
+
+    #[test]
+    fn document_symbols() {
+        let (responses, _) = session(vec![
+            open(SOURCE),
+            request(1, "textDocument/documentSymbol", 0, 0),
+        ]);
+
+        let symbols = &responses[1]["result"];
+        assert_eq!(json!("double"), symbols[0]["name"]);
+        assert_eq!(json!(12), symbols[0]["kind"]);
+        assert_eq!(range((1, 0), (3, 3)), symbols[0]["range"]);
+        assert_eq!(json!("y"), symbols[1]["name"]);
+        assert_eq!(json!("(number) -> number"), symbols[1]["detail"]);
+    }
+
+    #[test]
+    fn type_symbols() {
+        let source = "type point = { x, y }\ntype shape =\n    | Circle(r)\n    | Empty\n";
+        let (responses, _) = session(vec![
+            open(source),
+            request(1, "textDocument/documentSymbol", 0, 0),
+        ]);
+
+        let symbols = &responses[1]["result"];
+        assert_eq!(json!("point"), symbols[0]["name"]);
+        assert_eq!(json!(23), symbols[0]["kind"]);
+        assert_eq!(range((0, 0), (0, 21)), symbols[0]["range"]);
+        assert_eq!(json!("x"), symbols[0]["children"][0]["name"]);
+        assert_eq!(json!(8), symbols[0]["children"][0]["kind"]);
+        assert_eq!(json!("y"), symbols[0]["children"][1]["name"]);
+
+        assert_eq!(json!("shape"), symbols[1]["name"]);
+        assert_eq!(json!(10), symbols[1]["kind"]);
+        let constructors = &symbols[1]["children"];
+        assert_eq!(json!("Circle"), constructors[0]["name"]);
+        assert_eq!(json!(9), constructors[0]["kind"]);
+        assert_eq!(range((2, 6), (2, 15)), constructors[0]["range"]);
+        assert_eq!(json!("('a) -> shape"), constructors[0]["detail"]);
+        assert_eq!(json!("Empty"), constructors[1]["name"]);
+        assert_eq!(2, symbols.as_array().unwrap().len());
+    }
+
+    #[test]
+    fn completion() {
+        let (responses, _) = session(vec![
+            open(SOURCE),
+            request(1, "textDocument/completion", 2, 4),
+        ]);
+
+        let labels: Vec<&str> = responses[1]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                "double",
+                "x",
+                "let",
+                "mut",
+                "def",
+                "return",
+                "else",
+                "end",
+                "for",
+                "while",
+                "nil",
+                "match",
+                "type",
+                "impl",
+                "interface",
+                "where"
+            ],
+            labels
+        );
+    }
+}
@@ -1,16 +1,287 @@
-use crate::syntax::lexer::Lexer;
-use crate::syntax::parser::AstParser;
+use amber::formatter;
+use amber::lsp;
+use amber::resolver::{Resolution, Resolver};
+use amber::syntax::ast::ModuleAst;
+use amber::syntax::lexer::Lexer;
+use amber::syntax::parser::AstParser;
+use amber::types::checker::TypeChecker;
+use amber::vm::compiler::Compiler;
+use amber::vm::limits::Limits;
+use amber::vm::value::GcConfig;
+use amber::vm::Vm;
+use std::io::Read;
+use std::{env, fmt, fs, io, process};
 
-mod syntax;
+const USAGE: &str = "usage:
+    amber <file>                  Print the syntax tree of a file
+    amber ast [--json] <file>     Print the syntax tree as S-expressions, or as JSON
+    amber run [options] <file>    Run a file and print the value of its last expression
+        --gc-stress               Collect garbage before every instruction
+        --gc-stats                Print what the garbage collector did
+        --fuel <n>                Stop after executing n instructions
+        --max-heap <bytes>        Stop when the heap grows past this many bytes
+        --max-depth <n>           Stop when calls nest deeper than n
+    amber fmt [--check] [files]   Format files, or stdin when no files are given
+    amber lsp                     Run the language server over stdio";
 
 fn main() {
-    let source = r#"def double(x)
-        return x * 2
-    end
-    "#;
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    let mut tokens = Lexer::tokenize(source).unwrap();
-    let exprs = AstParser::parse(&mut tokens).unwrap();
+    let code = match args.first().map(String::as_str) {
+        Some("fmt") => fmt(&args[1..]),
+        Some("lsp") => lsp(),
+        Some("ast") => match args[1..].split_last() {
+            Some((path, options)) if !path.starts_with('-') => ast(path, options),
+            _ => {
+                eprintln!("{}", USAGE);
+                2
+            }
+        },
+        Some("run") => match args[1..].split_last() {
+            Some((path, options)) if !path.starts_with('-') => run(path, options),
+            _ => {
+                eprintln!("{}", USAGE);
+                2
+            }
+        },
+        Some(path) if !path.starts_with('-') => parse(path),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    };
 
-    println!("{:?}", exprs);
+    process::exit(code);
+}
+
+fn parse(path: &str) -> i32 {
+    match load(path) {
+        Some((_, module, _)) => {
+            println!("{:?}", module);
+            0
+        }
+        None => 1,
+    }
+}
+
+fn ast(path: &str, options: &[String]) -> i32 {
+    let json = match options {
+        [] => false,
+        [option] if option == "--json" => true,
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    match read(path) {
+        Some((_, module)) if json => {
+            println!("{:#}", module.to_json());
+            0
+        }
+        Some((_, module)) => {
+            print!("{}", module.to_sexp());
+            0
+        }
+        None => 1,
+    }
+}
+
+/// Reads, parses and resolves a file, printing the errors if there are
+/// any.
+fn load(path: &str) -> Option<(String, ModuleAst, Resolution)> {
+    let (source, module) = read(path)?;
+
+    let resolution = Resolver::resolve(&module);
+    for err in resolution.errors() {
+        eprintln!("{}:{}: {}", path, err.line(), err);
+        if let Some(declaration) = err.declaration() {
+            eprintln!("{}:{}: note: declared here", path, declaration.line());
+        }
+    }
+    if !resolution.errors().is_empty() {
+        return None;
+    }
+
+    Some((source, module, resolution))
+}
+
+/// Reads and parses a file, printing the error if there is one.
+fn read(path: &str) -> Option<(String, ModuleAst)> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return None;
+        }
+    };
+
+    let mut tokens = match Lexer::tokenize(&source) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            report(path, err.line(), &err);
+            return None;
+        }
+    };
+    let module = match AstParser::parse(&mut tokens) {
+        Ok(module) => module,
+        Err(err) => {
+            report(path, err.line(), &err);
+            return None;
+        }
+    };
+
+    Some((source, module))
+}
+
+/// Prints an error as `path:line: message`, or `path: message` when its line
+/// is not known.
+fn report(path: &str, line: Option<usize>, err: &dyn fmt::Display) {
+    match line {
+        Some(line) => eprintln!("{}:{}: {}", path, line, err),
+        None => eprintln!("{}: {}", path, err),
+    }
+}
+
+fn run(path: &str, options: &[String]) -> i32 {
+    let mut config = GcConfig::default();
+    let mut limits = Limits::default();
+    let mut print_stats = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        // The number after an option which takes one.
+        let mut number = || options.next().and_then(|n| n.parse().ok());
+        let valid = match option.as_str() {
+            "--gc-stress" => {
+                config.stress = true;
+                true
+            }
+            "--gc-stats" => {
+                print_stats = true;
+                true
+            }
+            "--fuel" => {
+                limits.fuel = number().map(|n: usize| n as u64);
+                limits.fuel.is_some()
+            }
+            "--max-heap" => {
+                limits.max_heap_bytes = number();
+                limits.max_heap_bytes.is_some()
+            }
+            "--max-depth" => {
+                limits.max_call_depth = number();
+                limits.max_call_depth.is_some()
+            }
+            _ => false,
+        };
+        if !valid {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    }
+
+    let (source, module, resolution) = match load(path) {
+        Some(loaded) => loaded,
+        None => return 1,
+    };
+
+    let info = TypeChecker::check(&module);
+    for err in info.errors() {
+        eprintln!("{}:{}: {}", path, err.line(), err);
+    }
+    if !info.errors().is_empty() {
+        return 1;
+    }
+
+    let function = Compiler::compile(&module, &resolution, &info);
+    let mut vm = Vm::with_gc(config);
+    vm.set_limits(limits);
+    let result = vm.run(function);
+    if print_stats {
+        let stats = vm.heap().stats();
+        eprintln!(
+            "gc: {} collections, {} objects allocated, {} freed, {} live ({} bytes)",
+            stats.collections, stats.allocated, stats.freed, stats.live, stats.bytes
+        );
+    }
+    match result {
+        Ok(value) => {
+            println!("{}", vm.heap().display(value));
+            0
+        }
+        Err(panic) => {
+            eprint!("{}", panic.report(path, &source));
+            1
+        }
+    }
+}
+
+/// Formats the given files in place. With `--check` nothing is written;
+/// instead every unformatted file is listed and the exit code is nonzero.
+fn fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+
+    if paths.is_empty() {
+        let mut source = String::new();
+        if let Err(err) = io::stdin().read_to_string(&mut source) {
+            eprintln!("<stdin>: {}", err);
+            return 1;
+        }
+        return match formatter::format(&source) {
+            Ok(formatted) if check => (formatted != source) as i32,
+            Ok(formatted) => {
+                print!("{}", formatted);
+                0
+            }
+            Err(err) => {
+                report("<stdin>", err.line(), &err);
+                1
+            }
+        };
+    }
+
+    let mut code = 0;
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                code = 1;
+                continue;
+            }
+        };
+        let formatted = match formatter::format(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                report(path, err.line(), &err);
+                code = 1;
+                continue;
+            }
+        };
+
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", path);
+            code = 1;
+        } else if let Err(err) = fs::write(path, formatted) {
+            eprintln!("{}: {}", path, err);
+            code = 1;
+        }
+    }
+
+    code
+}
+
+fn lsp() -> i32 {
+    let stdin = io::stdin();
+    match lsp::run(stdin.lock(), io::stdout().lock()) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("lsp: {}", err);
+            1
+        }
+    }
 }
@@ -0,0 +1,40 @@
+//! The types and functions every module can use without declaring them.
+//! Declarations of the same names in a module take their place.
+
+/// A built-in variant type.
+pub struct VariantType {
+    pub name: &'static str,
+    /// The type parameters, `t` and `e` of `result<t, e>`.
+    pub params: &'static [&'static str],
+    /// The constructors, and the type parameter of their field if they have
+    /// one.
+    pub constructors: &'static [(&'static str, Option<&'static str>)],
+}
+
+pub static TYPES: [VariantType; 2] = [
+    VariantType {
+        name: "option",
+        params: &["t"],
+        constructors: &[("Some", Some("t")), ("None", None)],
+    },
+    VariantType {
+        name: "result",
+        params: &["t", "e"],
+        constructors: &[("Ok", Some("t")), ("Err", Some("e"))],
+    },
+];
+
+/// The functions implemented by the runtime.
+pub static FUNCTIONS: [&str; 1] = ["panic"];
+
+pub fn variant_type(name: &str) -> Option<&'static VariantType> {
+    TYPES.iter().find(|ty| ty.name == name)
+}
+
+/// The name of every global the prelude declares.
+pub fn globals() -> impl Iterator<Item = &'static str> {
+    TYPES
+        .iter()
+        .flat_map(|ty| ty.constructors.iter().map(|(name, _)| *name))
+        .chain(FUNCTIONS.iter().copied())
+}
@@ -0,0 +1,1302 @@
+use crate::prelude;
+use crate::syntax::ast::*;
+use crate::syntax::symbol::Symbol;
+use crate::syntax::token::Position;
+use crate::types::host::Host;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    Undefined(Symbol, Position),
+    UndeclaredAssignment(Symbol, Position),
+    Duplicate(Symbol, Position),
+    /// An assignment to a variable declared without `mut`, and where the
+    /// variable was declared.
+    Immutable(Symbol, Position, Position),
+    /// A pattern in a `let` without `else` which may not match.
+    Refutable(Position),
+    UndefinedType(Symbol, Position),
+    /// A `type`, `interface` or `impl` inside of a function.
+    NotTopLevel(Position),
+    UndefinedInterface(Symbol, Position),
+    /// A function in an `impl` which its interface does not have, and the
+    /// interface.
+    NotInInterface(Symbol, Symbol, Position),
+    /// A function of the interface which an `impl` leaves out, and the
+    /// interface.
+    MissingFunction(Symbol, Symbol, Position),
+    /// A function of an interface without a parameter of type `Self`,
+    /// which could not tell which implementation to call.
+    NoReceiver(Position),
+    /// A `where` clause on a function of an `impl`.
+    ConstrainedMethod(Position),
+    /// A `?` at the top level, which has no function to return from.
+    TryOutsideFunction(Position),
+}
+
+impl ResolveError {
+    pub fn position(&self) -> &Position {
+        match self {
+            ResolveError::Undefined(_, position)
+            | ResolveError::UndeclaredAssignment(_, position)
+            | ResolveError::Duplicate(_, position)
+            | ResolveError::Immutable(_, position, _)
+            | ResolveError::Refutable(position)
+            | ResolveError::UndefinedType(_, position)
+            | ResolveError::NotTopLevel(position)
+            | ResolveError::UndefinedInterface(_, position)
+            | ResolveError::NotInInterface(_, _, position)
+            | ResolveError::MissingFunction(_, _, position)
+            | ResolveError::NoReceiver(position)
+            | ResolveError::ConstrainedMethod(position)
+            | ResolveError::TryOutsideFunction(position) => position,
+        }
+    }
+
+    /// The declaration the error refers to, if any.
+    pub fn declaration(&self) -> Option<&Position> {
+        match self {
+            ResolveError::Immutable(_, _, declaration) => Some(declaration),
+            _ => None,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        *self.position().line()
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::Undefined(name, _) => write!(f, "undefined variable `{}`", name),
+            ResolveError::UndeclaredAssignment(name, _) => {
+                write!(f, "assignment to undeclared variable `{}`", name)
+            }
+            ResolveError::Duplicate(name, _) => {
+                write!(f, "`{}` is already declared in this scope", name)
+            }
+            ResolveError::Immutable(name, _, _) => write!(
+                f,
+                "cannot assign to `{}`, it is not declared with `let mut`",
+                name
+            ),
+            ResolveError::Refutable(_) => {
+                write!(f, "pattern may not match, handle that with `else return`")
+            }
+            ResolveError::UndefinedType(name, _) => write!(f, "undefined type `{}`", name),
+            ResolveError::NotTopLevel(_) => write!(
+                f,
+                "types, interfaces and `impl` blocks can only be declared at the top level"
+            ),
+            ResolveError::UndefinedInterface(name, _) => {
+                write!(f, "undefined interface `{}`", name)
+            }
+            ResolveError::NotInInterface(name, interface, _) => {
+                write!(f, "`{}` is not a function of `{}`", name, interface)
+            }
+            ResolveError::MissingFunction(name, interface, _) => {
+                write!(f, "missing `{}` of `{}`", name, interface)
+            }
+            ResolveError::NoReceiver(_) => write!(
+                f,
+                "a function of an interface needs a parameter of type `Self` to pick the implementation by"
+            ),
+            ResolveError::ConstrainedMethod(_) => {
+                write!(f, "functions of an `impl` cannot have a `where` clause")
+            }
+            ResolveError::TryOutsideFunction(_) => {
+                write!(f, "`?` can only be used inside a `def`")
+            }
+        }
+    }
+}
+
+/// Where the value of a variable lives at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    /// A slot in the frame of the function the variable is used in.
+    Local(usize),
+    /// An entry in the upvalues of the function the variable is used in.
+    Upvalue(usize),
+    /// A variable declared at the top level of the module.
+    Global(Symbol),
+}
+
+/// A variable a function captures from the function it is declared in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upvalue {
+    /// The slot or upvalue index in the enclosing function.
+    pub index: usize,
+    /// Whether `index` is a slot of the enclosing function rather than one
+    /// of its upvalues.
+    pub is_local: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct FunctionInfo {
+    /// The number of slots the frame needs, parameters first. Slots of
+    /// variables in a closed scope are reused.
+    pub locals: usize,
+    pub upvalues: Vec<Upvalue>,
+    /// The dictionaries the function can use, by type parameter and
+    /// interface: those of its own `where` clause, which are passed before
+    /// its parameters, and those of the functions it is declared in.
+    pub dictionaries: HashMap<(Symbol, Symbol), Binding>,
+}
+
+/// The outcome of resolving a module.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    bindings: SideTable<Binding, VariableId>,
+    /// The bindings of the variables expressions read or assign.
+    uses: SideTable<Binding>,
+    functions: SideTable<FunctionInfo>,
+    module: FunctionInfo,
+    globals: Globals,
+    errors: Vec<ResolveError>,
+}
+
+impl Resolution {
+    /// The binding a declared or used variable refers to.
+    pub fn binding(&self, variable: &Variable) -> Option<&Binding> {
+        self.bindings.get(variable.id)
+    }
+
+    /// The binding a `LetGet` or `LetSet` expression reads or assigns.
+    pub fn binding_of(&self, id: ExprId) -> Option<&Binding> {
+        self.uses.get(id)
+    }
+
+    /// The frame of the function an `Expr::Function` declares.
+    pub fn function(&self, id: ExprId) -> Option<&FunctionInfo> {
+        self.functions.get(id)
+    }
+
+    /// The frame of the top level, which holds the variables of scopes
+    /// opened there.
+    pub fn module(&self) -> &FunctionInfo {
+        &self.module
+    }
+
+    /// What the module declared at the top level, with what the modules
+    /// run before it did.
+    pub fn globals(&self) -> &Globals {
+        &self.globals
+    }
+
+    pub fn errors(&self) -> &[ResolveError] {
+        &self.errors
+    }
+}
+
+/// The globals, types and interfaces declared by the modules run before,
+/// which a module run in the same VM can use.
+#[derive(Debug, Clone, Default)]
+pub struct Globals {
+    globals: HashMap<Symbol, Local>,
+    types: HashSet<Symbol>,
+    interfaces: HashMap<Symbol, Vec<Symbol>>,
+}
+
+#[derive(Debug, Clone)]
+struct Local {
+    name: Symbol,
+    mutable: bool,
+    position: Position,
+    /// The number of scopes the variable is nested in within its function.
+    depth: usize,
+}
+
+impl Local {
+    fn new(variable: &Variable, mutable: bool, depth: usize) -> Self {
+        Local {
+            name: variable.name,
+            mutable,
+            position: variable.position.clone(),
+            depth,
+        }
+    }
+}
+
+struct FunctionScope {
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    dictionaries: HashMap<(Symbol, Symbol), Binding>,
+    depth: usize,
+    slots: usize,
+}
+
+impl FunctionScope {
+    fn new() -> Self {
+        FunctionScope {
+            locals: vec![],
+            upvalues: vec![],
+            dictionaries: HashMap::new(),
+            depth: 0,
+            slots: 0,
+        }
+    }
+
+    fn info(self) -> FunctionInfo {
+        FunctionInfo {
+            locals: self.slots,
+            upvalues: self.upvalues,
+            dictionaries: self.dictionaries,
+        }
+    }
+}
+
+/// The name of the local holding the dictionary of a type parameter for an
+/// interface, which no variable can have.
+fn dictionary_name(parameter: Symbol, interface: Symbol) -> Symbol {
+    Symbol::intern(&format!("{}: {}", parameter, interface))
+}
+
+/// Binds every variable to a local slot, an upvalue or a global. Shadowing a
+/// variable of an enclosing scope is allowed, declaring a name twice in the
+/// same scope is not. Only variables declared with `let mut` may be
+/// assigned to.
+///
+/// Function bodies may refer to globals declared further down, since they
+/// run after the module has been evaluated; the top level itself may not.
+/// Types are known throughout the module, their constructors are globals.
+/// So are interfaces, and their functions. The prelude is declared before
+/// the module, which may shadow it, and so are the globals of the host.
+pub struct Resolver<'a> {
+    module: &'a ModuleAst,
+    globals: HashMap<Symbol, Local>,
+    /// The globals declared before the module runs, by the host or by the
+    /// modules run before it.
+    host: HashSet<Symbol>,
+    types: HashSet<Symbol>,
+    /// The names of the functions of every interface.
+    interfaces: HashMap<Symbol, Vec<Symbol>>,
+    declared: HashSet<Symbol>,
+    /// The functions being resolved, the first is the top level.
+    functions: Vec<FunctionScope>,
+    resolution: Resolution,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(module: &'a ModuleAst) -> Self {
+        let globals = module
+            .top_level()
+            .flat_map(|expr| match expr {
+                Expr::LetAssign(let_assign) => let_assign
+                    .pattern
+                    .variables()
+                    .into_iter()
+                    .map(|variable| Local::new(variable, let_assign.mutable, 0))
+                    .collect(),
+                Expr::Function(fun) => vec![Local::new(&fun.variable, false, 0)],
+                Expr::Type(ty) => constructors(ty)
+                    .map(|variable| Local::new(variable, false, 0))
+                    .collect(),
+                Expr::Interface(interface) => interface
+                    .functions
+                    .iter()
+                    .map(|signature| Local::new(&signature.variable, false, 0))
+                    .collect(),
+                _ => vec![],
+            })
+            .map(|global| (global.name, global))
+            .collect();
+        let types = module
+            .top_level()
+            .filter_map(|expr| match expr {
+                Expr::Type(ty) => Some(ty.variable.name),
+                _ => None,
+            })
+            .chain(prelude::TYPES.iter().map(|ty| Symbol::intern(ty.name)))
+            .collect();
+        let interfaces = module
+            .top_level()
+            .filter_map(|expr| match expr {
+                Expr::Interface(interface) => {
+                    let functions = interface
+                        .functions
+                        .iter()
+                        .map(|signature| signature.variable.name)
+                        .collect();
+                    Some((interface.variable.name, functions))
+                }
+                _ => None,
+            })
+            .collect();
+
+        Resolver {
+            module,
+            globals,
+            host: HashSet::new(),
+            types,
+            interfaces,
+            declared: HashSet::new(),
+            functions: vec![FunctionScope::new()],
+            resolution: Resolution::default(),
+        }
+    }
+
+    pub fn resolve(module: &ModuleAst) -> Resolution {
+        Resolver::resolve_with(module, &Host::default(), &Globals::default())
+    }
+
+    /// Resolves a module run after the host has declared globals and types
+    /// of its own, and after the modules which declared `globals`. The
+    /// module's own declarations shadow theirs.
+    pub fn resolve_with(module: &ModuleAst, host: &Host, globals: &Globals) -> Resolution {
+        let mut resolver = Resolver::new(module);
+        resolver.host = host
+            .globals
+            .keys()
+            .chain(globals.globals.keys())
+            .copied()
+            .collect();
+        resolver.types.extend(host.types.keys().copied());
+        resolver.types.extend(&globals.types);
+        for (name, global) in &globals.globals {
+            resolver
+                .globals
+                .entry(*name)
+                .or_insert_with(|| global.clone());
+        }
+        for (name, functions) in &globals.interfaces {
+            resolver
+                .interfaces
+                .entry(*name)
+                .or_insert_with(|| functions.clone());
+        }
+        for &expr in &module.exprs {
+            resolver.visit_expr(expr);
+        }
+
+        let mut resolution = resolver.resolution;
+        resolution.module = resolver.functions.pop().unwrap().info();
+        resolution.globals = Globals {
+            globals: resolver.globals,
+            types: resolver.types,
+            interfaces: resolver.interfaces,
+        };
+        resolution
+    }
+
+    /// Declares the variables of the pattern and binds the constructors in
+    /// it.
+    fn pattern(&mut self, pattern: &Pattern, mutable: bool) {
+        match pattern {
+            Pattern::Variable(variable) => self.declare(variable, mutable),
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+            Pattern::Tuple(elements) => {
+                for element in elements {
+                    self.pattern(element, mutable);
+                }
+            }
+            Pattern::Record(fields) => {
+                for field in fields {
+                    self.declare(field, mutable);
+                }
+            }
+            Pattern::List(list) => {
+                for element in list.elements.iter().chain(list.rest.as_deref()) {
+                    self.pattern(element, mutable);
+                }
+            }
+            Pattern::Variant(variant) => {
+                let constructor = &variant.constructor;
+                if self.use_variable(constructor).is_none() {
+                    let error =
+                        ResolveError::Undefined(constructor.name, constructor.position.clone());
+                    self.resolution.errors.push(error);
+                }
+                for field in &variant.fields {
+                    self.pattern(field, mutable);
+                }
+            }
+        }
+    }
+
+    /// Checks that a type or `impl` is declared at the top level.
+    fn top_level(&mut self, variable: &Variable) -> bool {
+        let is_top_level = self.functions.len() == 1;
+        if !is_top_level {
+            let error = ResolveError::NotTopLevel(variable.position.clone());
+            self.resolution.errors.push(error);
+        }
+        is_top_level
+    }
+
+    fn type_name(&mut self, variable: &Variable) {
+        if !self.types.contains(&variable.name) {
+            let error = ResolveError::UndefinedType(variable.name, variable.position.clone());
+            self.resolution.errors.push(error);
+        }
+    }
+
+    /// Checks that an `impl` of an interface has exactly its functions.
+    /// Interfaces can also be implemented for the built-in types.
+    fn implementation(&mut self, imp: &ImplExpr, interface: &Variable) {
+        if !BUILT_IN_TYPES.contains(&imp.variable.name.as_str()) {
+            self.type_name(&imp.variable);
+        }
+
+        let functions = match self.interfaces.get(&interface.name) {
+            Some(functions) => functions,
+            None => {
+                let error =
+                    ResolveError::UndefinedInterface(interface.name, interface.position.clone());
+                self.resolution.errors.push(error);
+                return;
+            }
+        };
+
+        let mut errors = vec![];
+        for (_, fun) in self.module.functions(imp) {
+            if !functions.contains(&fun.variable.name) {
+                errors.push(ResolveError::NotInInterface(
+                    fun.variable.name,
+                    interface.name,
+                    fun.variable.position.clone(),
+                ));
+            }
+        }
+        for name in functions {
+            if !self
+                .module
+                .functions(imp)
+                .any(|(_, fun)| &fun.variable.name == name)
+            {
+                errors.push(ResolveError::MissingFunction(
+                    *name,
+                    interface.name,
+                    imp.variable.position.clone(),
+                ));
+            }
+        }
+        self.resolution.errors.extend(errors);
+    }
+
+    /// Checks that the `where` clause of a function constrains its type
+    /// parameters by interfaces.
+    fn constraints(&mut self, declaration: &FunctionDeclaration) {
+        for constraint in &declaration.constraints {
+            let parameter = &constraint.parameter;
+            let is_annotated = declaration
+                .annotations
+                .iter()
+                .flatten()
+                .flat_map(TypeAnnotation::names)
+                .any(|name| name.name == parameter.name);
+            if !is_annotated {
+                let error = ResolveError::UndefinedType(parameter.name, parameter.position.clone());
+                self.resolution.errors.push(error);
+            }
+
+            let interface = &constraint.interface;
+            if !self.interfaces.contains_key(&interface.name) {
+                let error =
+                    ResolveError::UndefinedInterface(interface.name, interface.position.clone());
+                self.resolution.errors.push(error);
+            }
+        }
+    }
+
+    fn function(&mut self, id: ExprId, fun: &'a FunctionExpr) {
+        // Declared before the body, so the function can call itself.
+        self.declare(&fun.variable, false);
+        self.function_body(id, fun);
+    }
+
+    fn function_body(&mut self, id: ExprId, fun: &'a FunctionExpr) {
+        self.constraints(&fun.declaration);
+        self.functions.push(FunctionScope::new());
+        self.dictionaries(&fun.declaration);
+        for param in &fun.declaration.parameters {
+            self.declare(param, false);
+        }
+        walk_block(self, &fun.declaration.body);
+
+        let scope = self.functions.pop().unwrap();
+        self.resolution.functions.insert(id, scope.info());
+    }
+
+    /// Declares the dictionaries of the `where` clause of the function
+    /// being resolved, and captures those of the functions it is declared
+    /// in, which it may need to call a function of an interface.
+    fn dictionaries(&mut self, declaration: &FunctionDeclaration) {
+        let function = self.functions.len() - 1;
+        let mut dictionaries = HashMap::new();
+        for constraint in &declaration.constraints {
+            let key = (constraint.parameter.name, constraint.interface.name);
+            let scope = &mut self.functions[function];
+            let name = dictionary_name(key.0, key.1);
+            scope.locals.push(Local {
+                name,
+                mutable: false,
+                position: constraint.parameter.position.clone(),
+                depth: 0,
+            });
+            scope.slots = scope.locals.len();
+            dictionaries.insert(key, Binding::Local(scope.locals.len() - 1));
+        }
+
+        let enclosing: Vec<(Symbol, Symbol)> = self.functions[function - 1]
+            .dictionaries
+            .keys()
+            .copied()
+            .collect();
+        for key in enclosing {
+            if dictionaries.contains_key(&key) {
+                continue;
+            }
+            let name = dictionary_name(key.0, key.1);
+            if let Some(index) = self.upvalue(function, name) {
+                dictionaries.insert(key, Binding::Upvalue(index));
+            }
+        }
+        self.functions[function].dictionaries = dictionaries;
+    }
+
+    fn begin_scope(&mut self) {
+        self.functions.last_mut().unwrap().depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.functions.last_mut().unwrap();
+        scope.depth -= 1;
+        let depth = scope.depth;
+        scope.locals.retain(|local| local.depth <= depth);
+    }
+
+    fn declare(&mut self, variable: &Variable, mutable: bool) {
+        let name = variable.name;
+        let is_global = self.functions.len() == 1 && self.functions[0].depth == 0;
+        let scope = self.functions.last_mut().unwrap();
+        let local = Local::new(variable, mutable, scope.depth);
+
+        let (binding, duplicate) = if is_global {
+            self.globals.insert(name, local);
+            (Binding::Global(name), !self.declared.insert(name))
+        } else {
+            let duplicate = scope
+                .locals
+                .iter()
+                .any(|other| other.name == name && other.depth == local.depth);
+            scope.locals.push(local);
+            scope.slots = scope.slots.max(scope.locals.len());
+            (Binding::Local(scope.locals.len() - 1), duplicate)
+        };
+
+        if duplicate {
+            let error = ResolveError::Duplicate(name, variable.position.clone());
+            self.resolution.errors.push(error);
+        }
+        self.bind(variable, binding);
+    }
+
+    /// Binds a variable which is read or assigned, returning its binding if
+    /// it was found.
+    fn use_variable(&mut self, variable: &Variable) -> Option<Binding> {
+        let name = variable.name;
+        let function = self.functions.len() - 1;
+        let is_global = match function {
+            0 => self.declared.contains(&name),
+            _ => self.globals.contains_key(&name),
+        } || prelude::globals().any(|global| name == global)
+            || self.host.contains(&name);
+
+        let binding = match self.local(function, name) {
+            Some(slot) => Binding::Local(slot),
+            None => match self.upvalue(function, name) {
+                Some(index) => Binding::Upvalue(index),
+                None if is_global => Binding::Global(name),
+                None => return None,
+            },
+        };
+        self.bind(variable, binding.clone());
+        Some(binding)
+    }
+
+    fn local(&self, function: usize, name: Symbol) -> Option<usize> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+    }
+
+    /// The declaration a variable which has been bound refers to.
+    fn declaration(&self, name: Symbol) -> Option<&Local> {
+        self.functions
+            .iter()
+            .rev()
+            .find_map(|scope| scope.locals.iter().rev().find(|local| local.name == name))
+            .or_else(|| self.globals.get(&name))
+    }
+
+    fn upvalue(&mut self, function: usize, name: Symbol) -> Option<usize> {
+        if function == 0 {
+            return None;
+        }
+
+        let upvalue = match self.local(function - 1, name) {
+            Some(index) => Upvalue {
+                index,
+                is_local: true,
+            },
+            None => Upvalue {
+                index: self.upvalue(function - 1, name)?,
+                is_local: false,
+            },
+        };
+
+        let upvalues = &mut self.functions[function].upvalues;
+        Some(match upvalues.iter().position(|u| *u == upvalue) {
+            Some(index) => index,
+            None => {
+                upvalues.push(upvalue);
+                upvalues.len() - 1
+            }
+        })
+    }
+
+    fn bind(&mut self, variable: &Variable, binding: Binding) {
+        self.resolution.bindings.insert(variable.id, binding);
+    }
+}
+
+impl<'a> Visitor<'a> for Resolver<'a> {
+    fn module(&self) -> &'a ModuleAst {
+        self.module
+    }
+
+    fn visit_let_assign(&mut self, _id: ExprId, let_assign: &'a LetAssignExpr) {
+        // The initializer still sees an outer variable of the same name.
+        self.visit_expr(let_assign.initializer);
+        match &let_assign.otherwise {
+            Some(otherwise) => self.visit_expr(*otherwise),
+            None => {
+                if let Some(position) = let_assign.pattern.refutable() {
+                    let error = ResolveError::Refutable(position.clone());
+                    self.resolution.errors.push(error);
+                }
+            }
+        }
+        self.pattern(&let_assign.pattern, let_assign.mutable);
+    }
+
+    fn visit_let_get(&mut self, id: ExprId, let_get: &'a LetGetExpr) {
+        match self.use_variable(&let_get.variable) {
+            Some(binding) => self.resolution.uses.insert(id, binding),
+            None => {
+                let variable = &let_get.variable;
+                let error = ResolveError::Undefined(variable.name, variable.position.clone());
+                self.resolution.errors.push(error);
+            }
+        }
+    }
+
+    fn visit_let_set(&mut self, id: ExprId, let_set: &'a LetSetExpr) {
+        self.visit_expr(let_set.initializer);
+        let variable = &let_set.variable;
+        // The prelude is not declared by the module, so it cannot be
+        // assigned to either.
+        let binding = self.use_variable(variable);
+        if binding.is_none() || self.declaration(variable.name).is_none() {
+            let error =
+                ResolveError::UndeclaredAssignment(variable.name, variable.position.clone());
+            self.resolution.errors.push(error);
+            return;
+        }
+        self.resolution.uses.insert(id, binding.unwrap());
+
+        let declaration = self.declaration(variable.name).unwrap();
+        if !declaration.mutable {
+            let error = ResolveError::Immutable(
+                variable.name,
+                variable.position.clone(),
+                declaration.position.clone(),
+            );
+            self.resolution.errors.push(error);
+        }
+    }
+
+    fn visit_function(&mut self, id: ExprId, fun: &'a FunctionExpr) {
+        self.function(id, fun)
+    }
+
+    fn visit_match(&mut self, _id: ExprId, match_expr: &'a MatchExpr) {
+        self.visit_expr(match_expr.scrutinee);
+        for arm in &match_expr.arms {
+            self.begin_scope();
+            self.pattern(&arm.pattern, false);
+            self.visit_expr(arm.body);
+            self.end_scope();
+        }
+    }
+
+    fn visit_type(&mut self, _id: ExprId, ty: &'a TypeExpr) {
+        if self.top_level(&ty.variable) {
+            for variable in constructors(ty) {
+                self.declare(variable, false);
+            }
+        }
+    }
+
+    fn visit_impl(&mut self, _id: ExprId, imp: &'a ImplExpr) {
+        if !self.top_level(&imp.variable) {
+            return;
+        }
+        match &imp.interface {
+            Some(interface) => self.implementation(imp, interface),
+            None => self.type_name(&imp.variable),
+        }
+        // Functions of a type are not variables.
+        for (id, fun) in self.module.functions(imp) {
+            if let Some(constraint) = fun.declaration.constraints.first() {
+                let position = constraint.parameter.position.clone();
+                let error = ResolveError::ConstrainedMethod(position);
+                self.resolution.errors.push(error);
+            }
+            self.function_body(id, fun);
+        }
+    }
+
+    fn visit_interface(&mut self, _id: ExprId, interface: &'a InterfaceExpr) {
+        if self.top_level(&interface.variable) {
+            for signature in &interface.functions {
+                if !has_receiver(signature) {
+                    let position = signature.variable.position.clone();
+                    self.resolution
+                        .errors
+                        .push(ResolveError::NoReceiver(position));
+                }
+                self.declare(&signature.variable, false);
+            }
+        }
+    }
+
+    fn visit_record(&mut self, _id: ExprId, record: &'a RecordExpr) {
+        self.type_name(&record.variable);
+        for &(_, value) in &record.fields {
+            self.visit_expr(value);
+        }
+    }
+
+    fn visit_method_call(&mut self, _id: ExprId, call: &'a MethodCallExpr) {
+        self.visit_expr(call.receiver);
+        for &argument in &call.arguments {
+            self.visit_expr(argument);
+        }
+        // Only bound if a function of that name is in scope, which is
+        // called when the type has no such method.
+        self.use_variable(&call.method);
+    }
+
+    fn visit_try(&mut self, _id: ExprId, try_expr: &'a TryExpr) {
+        self.visit_expr(try_expr.expr);
+        if self.functions.len() == 1 {
+            let error = ResolveError::TryOutsideFunction(try_expr.position.clone());
+            self.resolution.errors.push(error);
+        }
+    }
+}
+
+/// The types which are not declared with `type`.
+pub const BUILT_IN_TYPES: [&str; 4] = ["number", "string", "bool", "nil"];
+
+/// Whether a function of an interface has a parameter of type `Self`: an
+/// unannotated first parameter, or one annotated with `Self`.
+fn has_receiver(signature: &Signature) -> bool {
+    let mut annotations = signature.annotations.iter();
+    match annotations.next() {
+        None => false,
+        Some(None) => true,
+        Some(Some(first)) => std::iter::once(first)
+            .chain(annotations.flatten())
+            .flat_map(TypeAnnotation::names)
+            .any(|name| name.name == "Self"),
+    }
+}
+
+fn constructors(ty: &TypeExpr) -> impl Iterator<Item = &Variable> {
+    let constructors = match &ty.definition {
+        TypeDefinition::Record(_) => &[][..],
+        TypeDefinition::Variant(constructors) => &constructors[..],
+    };
+    constructors.iter().map(|constructor| &constructor.variable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::lexer::Lexer;
+    use crate::syntax::parser::AstParser;
+
+    fn resolve(source: &str) -> (ModuleAst, Resolution) {
+        let mut tokens = Lexer::tokenize(source).unwrap();
+        let module = AstParser::parse(&mut tokens).unwrap();
+        let resolution = Resolver::resolve(&module);
+        (module, resolution)
+    }
+
+    fn binding(source: &str, pattern: &str) -> Binding {
+        let (module, resolution) = resolve(source);
+        let offset = source.find(pattern).unwrap();
+        let variable = module.variable_at(offset).unwrap();
+        resolution.binding(variable).unwrap().clone()
+    }
+
+    #[test]
+    fn resolve_bindings() {
+        let source = r#"
+        let x = 1
+        def outer(a)
+            let b = a
+            def inner(c)
+                return a + b + c + x
+            end
+            inner
+        end
+        "#;
+
+        assert_eq!(Binding::Global(Symbol::intern("x")), binding(source, "x ="));
+        assert_eq!(Binding::Local(0), binding(source, "a)"));
+        assert_eq!(Binding::Local(1), binding(source, "b ="));
+        assert_eq!(Binding::Local(2), binding(source, "inner("));
+        assert_eq!(Binding::Local(0), binding(source, "c)"));
+        assert_eq!(Binding::Upvalue(0), binding(source, "a +"));
+        assert_eq!(Binding::Upvalue(1), binding(source, "b +"));
+        assert_eq!(Binding::Local(0), binding(source, "c +"));
+        assert_eq!(Binding::Global(Symbol::intern("x")), binding(source, "x\n"));
+        assert_eq!(Binding::Local(2), binding(source, "inner\n"));
+
+        let (module, resolution) = resolve(source);
+        let outer = module.exprs[1];
+        let inner = match &module[outer] {
+            Expr::Function(fun) => fun.declaration.body.exprs[1],
+            _ => unreachable!(),
+        };
+        assert_eq!(3, resolution.function(outer).unwrap().locals);
+        assert_eq!(
+            vec![
+                Upvalue {
+                    index: 0,
+                    is_local: true
+                },
+                Upvalue {
+                    index: 1,
+                    is_local: true
+                },
+            ],
+            resolution.function(inner).unwrap().upvalues
+        );
+    }
+
+    #[test]
+    fn resolve_transitive_upvalues() {
+        let source = r#"
+        def a(x)
+            def b()
+                def c()
+                    x
+                end
+            end
+        end
+        "#;
+
+        assert_eq!(Binding::Upvalue(0), binding(source, "x\n"));
+
+        let (module, resolution) = resolve(source);
+        let b = match &module[module.exprs[0]] {
+            Expr::Function(fun) => fun.declaration.body.exprs[0],
+            _ => unreachable!(),
+        };
+        let b_upvalues = &resolution.function(b).unwrap().upvalues;
+        assert_eq!(
+            &vec![Upvalue {
+                index: 0,
+                is_local: true
+            }],
+            b_upvalues
+        );
+    }
+
+    #[test]
+    fn shadowing() {
+        let source = r#"
+        let x = 1
+        def f(x)
+            x
+        end
+        "#;
+        let (_, resolution) = resolve(source);
+
+        assert!(resolution.errors().is_empty());
+        assert_eq!(Binding::Local(0), binding(source, "x\n"));
+    }
+
+    #[test]
+    fn globals_declared_later() {
+        let source = r#"
+        def f()
+            y
+        end
+        let y = 1
+        "#;
+        let (_, resolution) = resolve(source);
+
+        assert!(resolution.errors().is_empty());
+        assert_eq!(Binding::Global(Symbol::intern("y")), binding(source, "y\n"));
+    }
+
+    #[test]
+    fn assign_mutable() {
+        let source = r#"
+        let mut x = 1
+        def f(y)
+            let mut z = y
+            z = 2
+            x = z
+        end
+        "#;
+        let (_, resolution) = resolve(source);
+
+        assert!(resolution.errors().is_empty());
+    }
+
+    #[test]
+    fn assign_immutable() {
+        let source = r#"let x = 1
+def f(y)
+    y = 2
+    x = 3
+    f = nil
+end
+"#;
+        let (_, resolution) = resolve(source);
+
+        let errors: Vec<(String, usize, usize)> = resolution
+            .errors()
+            .iter()
+            .map(|err| {
+                let declaration = err.declaration().unwrap();
+                (
+                    err.to_string(),
+                    *err.position().start(),
+                    *declaration.start(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "cannot assign to `y`, it is not declared with `let mut`".to_string(),
+                    23,
+                    16
+                ),
+                (
+                    "cannot assign to `x`, it is not declared with `let mut`".to_string(),
+                    33,
+                    4
+                ),
+                (
+                    "cannot assign to `f`, it is not declared with `let mut`".to_string(),
+                    43,
+                    14
+                ),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn destructure() {
+        let source = r#"
+        def f(pair, point, xs)
+            let (a, _) = pair
+            let { x, y } = point
+            let [first, ..rest] = xs else return a
+            let [..all] = xs
+            first
+        end
+        "#;
+        let (_, resolution) = resolve(source);
+
+        assert!(resolution.errors().is_empty());
+        assert_eq!(Binding::Local(3), binding(source, "a,"));
+        assert_eq!(Binding::Local(5), binding(source, "y }"));
+        assert_eq!(Binding::Local(7), binding(source, "rest]"));
+        assert_eq!(Binding::Local(6), binding(source, "first\n"));
+    }
+
+    #[test]
+    fn reject_refutable_patterns() {
+        let source = r#"let [a, ..] = xs
+let (b, 5) = pair
+let [..c] = xs
+"#;
+        let (_, resolution) = resolve(source);
+
+        let errors: Vec<(String, usize)> = resolution
+            .errors()
+            .iter()
+            .filter(|err| matches!(err, ResolveError::Refutable(_)))
+            .map(|err| (err.to_string(), *err.position().start()))
+            .collect();
+        let message = "pattern may not match, handle that with `else return`";
+        assert_eq!(
+            vec![(message.to_string(), 4), (message.to_string(), 25)],
+            errors
+        );
+    }
+
+    #[test]
+    fn scope_match_arms() {
+        let source = r#"
+        let pair = (1, 2)
+        let x = match pair {
+            | (a, b) => a
+            | c => c
+        }
+        def f(p)
+            match p {
+                | (d, e) => d + e + pair.0
+            }
+        end
+        "#;
+        let (module, resolution) = resolve(source);
+
+        assert!(resolution.errors().is_empty());
+        assert_eq!(Binding::Global(Symbol::intern("x")), binding(source, "x ="));
+        assert_eq!(Binding::Local(0), binding(source, "a,"));
+        assert_eq!(Binding::Local(1), binding(source, "b)"));
+        assert_eq!(Binding::Local(0), binding(source, "c =>"));
+        assert_eq!(Binding::Local(2), binding(source, "e)"));
+        assert_eq!(2, resolution.module().locals);
+
+        assert_eq!(3, resolution.function(module.exprs[2]).unwrap().locals);
+
+        let source = "let y = match 1 {\n    | z => z\n}\nlet w = z\n";
+        let (_, resolution) = resolve(source);
+        assert_eq!(
+            vec![ResolveError::Undefined(
+                Symbol::intern("z"),
+                Position::new(41, 42, 4)
+            )],
+            resolution.errors()
+        );
+    }
+
+    #[test]
+    fn resolve_types() {
+        let source = r#"
+        def area(s)
+            match s {
+                | Circle(r) => r
+                | Empty => 0
+            }
+        end
+        type shape =
+            | Circle(radius)
+            | Empty
+        impl shape
+            def add(a, b)
+                area(a) + area(b)
+            end
+        end
+        "#;
+        let (module, resolution) = resolve(source);
+
+        assert!(resolution.errors().is_empty());
+        assert_eq!(
+            Binding::Global(Symbol::intern("Circle")),
+            binding(source, "Circle(r)")
+        );
+        assert_eq!(Binding::Local(1), binding(source, "r) =>"));
+        assert_eq!(Binding::Local(0), binding(source, "a, b"));
+        assert_eq!(
+            Binding::Global(Symbol::intern("area")),
+            binding(source, "area(a)")
+        );
+
+        let imp = match &module[module.exprs[2]] {
+            Expr::Impl(imp) => imp,
+            _ => unreachable!(),
+        };
+        assert_eq!(2, resolution.function(imp.functions[0]).unwrap().locals);
+    }
+
+    #[test]
+    fn report_type_errors() {
+        let source = r#"let p = point { x: 1 }
+impl point
+end
+def f(x)
+    type inner = { x }
+    match x {
+        | Circle(y) => y
+    }
+end
+"#;
+        let (_, resolution) = resolve(source);
+
+        let errors: Vec<(String, usize)> = resolution
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("undefined type `point`".to_string(), 1),
+                ("undefined type `point`".to_string(), 2),
+                (
+                    "types, interfaces and `impl` blocks can only be declared at the top level"
+                        .to_string(),
+                    5
+                ),
+                ("undefined variable `Circle`".to_string(), 7),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn resolve_interfaces() {
+        let source = r#"
+        def print(x: a) where a: Show
+            show(x)
+        end
+        interface Show
+            def show(value)
+        end
+        impl Show for number
+            def show(n)
+                "number"
+            end
+        end
+        "#;
+        let (_, resolution) = resolve(source);
+
+        assert!(resolution.errors().is_empty());
+        assert_eq!(
+            Binding::Global(Symbol::intern("show")),
+            binding(source, "show(x)")
+        );
+        assert_eq!(
+            Binding::Global(Symbol::intern("show")),
+            binding(source, "show(value)")
+        );
+        assert_eq!(Binding::Local(0), binding(source, "n)"));
+    }
+
+    #[test]
+    fn report_interface_errors() {
+        let source = r#"interface Show
+    def show(x)
+    def make()
+end
+impl Show for point
+    def show(p)
+        nil
+    end
+    def hide(p)
+        nil
+    end
+end
+impl Eq for number
+end
+def f(x: a) where b: Show, a: Eq
+    nil
+end
+"#;
+        let (_, resolution) = resolve(source);
+
+        let errors: Vec<(String, usize)> = resolution
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "a function of an interface needs a parameter of type `Self` to pick the implementation by"
+                        .to_string(),
+                    3
+                ),
+                ("undefined type `point`".to_string(), 5),
+                ("`hide` is not a function of `Show`".to_string(), 9),
+                ("missing `make` of `Show`".to_string(), 5),
+                ("undefined interface `Eq`".to_string(), 13),
+                ("undefined type `b`".to_string(), 15),
+                ("undefined interface `Eq`".to_string(), 15),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn resolve_prelude() {
+        let source = r#"
+        let a = Some(1)?
+        def f(x: option<number>)
+            let y = x?
+            Ok(y)
+        end
+        def g()
+            def Ok(x)
+                x
+            end
+            Ok(None)
+        end
+        "#;
+        let (_, resolution) = resolve(source);
+
+        let errors: Vec<(String, usize)> = resolution
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![("`?` can only be used inside a `def`".to_string(), 2)],
+            errors
+        );
+        assert_eq!(
+            Binding::Global(Symbol::intern("Ok")),
+            binding(source, "Ok(y)")
+        );
+        assert_eq!(Binding::Local(0), binding(source, "Ok(None)"));
+        assert_eq!(
+            Binding::Global(Symbol::intern("None")),
+            binding(source, "None)")
+        );
+    }
+
+    #[test]
+    fn report_errors() {
+        let source = r#"let a = b
+c = 5
+let a = 1
+def f(x, x)
+    let y = 1
+    let y = 2
+end
+"#;
+        let (_, resolution) = resolve(source);
+
+        let errors: Vec<(String, usize, usize)> = resolution
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), *err.position().start(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("undefined variable `b`".to_string(), 8, 1),
+                ("assignment to undeclared variable `c`".to_string(), 10, 2),
+                ("`a` is already declared in this scope".to_string(), 20, 3),
+                ("`x` is already declared in this scope".to_string(), 35, 4),
+                ("`y` is already declared in this scope".to_string(), 60, 6),
+            ],
+            errors
+        );
+    }
+}
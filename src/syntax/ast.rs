@@ -1,17 +1,282 @@
-use crate::syntax::token::TokenType;
+use crate::syntax::symbol::{sym, Symbol};
+use crate::syntax::token::{Position, TokenType};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
 
-#[derive(PartialEq, Debug)] // TODO Custom Debug impl
+/// A parsed module. Its expressions live in an arena, in the order they were
+/// parsed, and refer to their children by `ExprId`.
+#[derive(Debug, Default)]
 pub struct ModuleAst {
-    exprs: Vec<Expr>,
+    /// The expressions at the top level.
+    pub exprs: Vec<ExprId>,
+    nodes: Vec<Expr>,
+    /// The source each expression was parsed from, from its first token to
+    /// its last.
+    positions: Vec<Position>,
+    /// The number of names in the module.
+    variables: u32,
 }
 
 impl ModuleAst {
-    pub fn new(exprs: Vec<Expr>) -> Self {
-        ModuleAst { exprs }
+    pub fn alloc(&mut self, expr: Expr, position: Position) -> ExprId {
+        self.nodes.push(expr);
+        self.positions.push(position);
+        ExprId(self.nodes.len() as u32 - 1)
+    }
+
+    /// The expressions at the top level, in source order.
+    pub fn top_level(&self) -> impl Iterator<Item = &Expr> {
+        self.exprs.iter().map(move |&id| &self[id])
+    }
+
+    /// The functions of an `impl` with their ids. The parser allocates them
+    /// as `Expr::Function`s; anything a `Folder` replaced one with is not a
+    /// function of the type, and is skipped.
+    pub fn functions<'a>(
+        &'a self,
+        imp: &'a ImplExpr,
+    ) -> impl Iterator<Item = (ExprId, &'a FunctionExpr)> {
+        imp.functions.iter().filter_map(move |&id| match &self[id] {
+            Expr::Function(fun) => Some((id, fun)),
+            _ => None,
+        })
+    }
+
+    /// The expressions an expression directly contains.
+    pub fn children(&self, id: ExprId) -> Vec<ExprId> {
+        struct Children<'a> {
+            module: &'a ModuleAst,
+            children: Vec<ExprId>,
+        }
+
+        impl<'a> Visitor<'a> for Children<'a> {
+            fn module(&self) -> &'a ModuleAst {
+                self.module
+            }
+
+            fn visit_expr(&mut self, id: ExprId) {
+                self.children.push(id);
+            }
+        }
+
+        let mut children = Children {
+            module: self,
+            children: vec![],
+        };
+        walk_expr(&mut children, id);
+        children.children
+    }
+
+    /// A new occurrence of a name in the module.
+    pub fn variable(&mut self, name: Symbol, position: Position) -> Variable {
+        self.variables += 1;
+        Variable::new(VariableId(self.variables - 1), name, position)
+    }
+
+    pub fn position(&self, id: ExprId) -> &Position {
+        &self.positions[id.index()]
+    }
+
+    pub fn set_position(&mut self, id: ExprId, position: Position) {
+        self.positions[id.index()] = position;
+    }
+
+    /// The number of expressions in the arena, nested ones included.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
     }
 }
 
-#[derive(PartialEq, Debug)] // TODO Custom Debug impl
+impl Index<ExprId> for ModuleAst {
+    type Output = Expr;
+
+    fn index(&self, id: ExprId) -> &Expr {
+        &self.nodes[id.index()]
+    }
+}
+
+impl IndexMut<ExprId> for ModuleAst {
+    fn index_mut(&mut self, id: ExprId) -> &mut Expr {
+        &mut self.nodes[id.index()]
+    }
+}
+
+/// Modules are compared by their trees from the top level down, not by
+/// where they were parsed from or the order their nodes were allocated in.
+impl PartialEq for ModuleAst {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (self.canonical(), other.canonical());
+        a.exprs == b.exprs && a.nodes == b.nodes
+    }
+}
+
+impl ModuleAst {
+    /// A copy of the module which holds only the nodes reachable from the
+    /// top level, each allocated after its children.
+    fn canonical(&self) -> ModuleAst {
+        let mut order = PostOrder {
+            module: self,
+            order: vec![],
+        };
+        for &expr in &self.exprs {
+            order.visit_expr(expr);
+        }
+
+        let mut canonical = ModuleAst::default();
+        let mut renumber = Renumber {
+            ids: HashMap::new(),
+        };
+        for id in order.order {
+            let copy = canonical.alloc(self[id].clone(), self.position(id).clone());
+            walk_expr_mut(&mut renumber, &mut canonical, copy);
+            renumber.ids.insert(id, copy);
+        }
+        canonical.exprs = self.exprs.iter().map(|id| renumber.ids[id]).collect();
+        canonical
+    }
+}
+
+#[cfg(test)]
+impl ModuleAst {
+    /// The first name in the tree which starts at a byte offset, for tests
+    /// which point at names in their source.
+    pub fn variable_at(&self, offset: usize) -> Option<&Variable> {
+        struct Find<'a> {
+            module: &'a ModuleAst,
+            offset: usize,
+            found: Option<&'a Variable>,
+        }
+
+        impl<'a> Visitor<'a> for Find<'a> {
+            fn module(&self) -> &'a ModuleAst {
+                self.module
+            }
+
+            fn visit_variable(&mut self, variable: &'a Variable) {
+                if self.found.is_none() && *variable.position.start() == self.offset {
+                    self.found = Some(variable);
+                }
+            }
+        }
+
+        let mut find = Find {
+            module: self,
+            offset,
+            found: None,
+        };
+        for &expr in &self.exprs {
+            find.visit_expr(expr);
+        }
+        find.found
+    }
+}
+
+/// The expressions of a module, every one after its children.
+struct PostOrder<'a> {
+    module: &'a ModuleAst,
+    order: Vec<ExprId>,
+}
+
+impl<'a> Visitor<'a> for PostOrder<'a> {
+    fn module(&self) -> &'a ModuleAst {
+        self.module
+    }
+
+    fn visit_expr(&mut self, id: ExprId) {
+        walk_expr(self, id);
+        self.order.push(id);
+    }
+}
+
+/// Points the children of a copied node at their copies.
+struct Renumber {
+    ids: HashMap<ExprId, ExprId>,
+}
+
+impl Folder for Renumber {
+    fn fold_expr(&mut self, _module: &mut ModuleAst, id: ExprId) -> ExprId {
+        self.ids[&id]
+    }
+}
+
+/// The index of an expression in the arena of its module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+/// The index of a name in its module. Every occurrence of a name in the
+/// tree, declaring or using a variable, has its own.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VariableId(u32);
+
+/// An index side tables are keyed by.
+pub trait Id: Copy {
+    fn index(self) -> usize;
+    fn from_index(index: usize) -> Self;
+}
+
+impl Id for ExprId {
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    fn from_index(index: usize) -> Self {
+        ExprId(index as u32)
+    }
+}
+
+impl Id for VariableId {
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    fn from_index(index: usize) -> Self {
+        VariableId(index as u32)
+    }
+}
+
+/// Facts about expressions or variables kept beside the tree rather than
+/// in it, like their types or the variables they refer to.
+#[derive(Debug, Clone)]
+pub struct SideTable<T, I = ExprId> {
+    values: Vec<Option<T>>,
+    ids: PhantomData<I>,
+}
+
+impl<T, I> Default for SideTable<T, I> {
+    fn default() -> Self {
+        SideTable {
+            values: vec![],
+            ids: PhantomData,
+        }
+    }
+}
+
+impl<T, I: Id> SideTable<T, I> {
+    pub fn insert(&mut self, id: I, value: T) {
+        if self.values.len() <= id.index() {
+            self.values.resize_with(id.index() + 1, || None);
+        }
+        self.values[id.index()] = Some(value);
+    }
+
+    pub fn get(&self, id: I) -> Option<&T> {
+        self.values.get(id.index()).and_then(Option::as_ref)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (I, &T)> {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, value)| Some((I::from_index(i), value.as_ref()?)))
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub enum Expr {
     Literal(LiteralExpr),
     Grouping(GroupingExpr),
@@ -23,26 +288,39 @@ pub enum Expr {
     Function(FunctionExpr),
     Block(BlockExpr),
     Return(ReturnExpr),
+    Tuple(TupleExpr),
+    TupleIndex(TupleIndexExpr),
+    Match(MatchExpr),
+    Call(CallExpr),
+    Type(TypeExpr),
+    Impl(ImplExpr),
+    Interface(InterfaceExpr),
+    Record(RecordExpr),
+    Field(FieldExpr),
+    SetField(SetFieldExpr),
+    MethodCall(MethodCallExpr),
+    Try(TryExpr),
 }
 
-#[derive(PartialEq, Debug)] // TODO Custom Debug impl
+#[derive(Clone, PartialEq, Debug)]
 pub enum LiteralExpr {
     Number(f64),
+    String(String),
     Nil,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct GroupingExpr {
-    expr: Box<Expr>,
+    pub expr: ExprId,
 }
 
 impl GroupingExpr {
-    pub fn new(expr: Box<Expr>) -> Self {
+    pub fn new(expr: ExprId) -> Self {
         GroupingExpr { expr }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum BinaryOperator {
     Equal,
     BangEqual,
@@ -75,22 +353,54 @@ impl BinaryOperator {
 
         Some(op)
     }
+
+    /// The method an `impl` block defines to overload the operator. The
+    /// other comparisons are derived from `eq` and `lt`: `a > b` is
+    /// `lt(b, a)`, `a <= b` is `!lt(b, a)`, and so on.
+    pub fn method(&self) -> Symbol {
+        match self {
+            BinaryOperator::Add => sym::ADD,
+            BinaryOperator::Subtract => sym::SUB,
+            BinaryOperator::Multiply => sym::MUL,
+            BinaryOperator::Divide => sym::DIV,
+            BinaryOperator::Equal | BinaryOperator::BangEqual => sym::EQ,
+            BinaryOperator::LessThan
+            | BinaryOperator::LessThanEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanEqual => sym::LT,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOperator::Equal => "==",
+            BinaryOperator::BangEqual => "!=",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::GreaterThanEqual => ">=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::LessThanEqual => "<=",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Multiply => "*",
+        }
+    }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct BinaryExpr {
-    lhs: Box<Expr>,
-    rhs: Box<Expr>,
-    operator: BinaryOperator,
+    pub lhs: ExprId,
+    pub rhs: ExprId,
+    pub operator: BinaryOperator,
 }
 
 impl BinaryExpr {
-    pub fn new(operator: BinaryOperator, lhs: Box<Expr>, rhs: Box<Expr>) -> BinaryExpr {
+    pub fn new(operator: BinaryOperator, lhs: ExprId, rhs: ExprId) -> BinaryExpr {
         BinaryExpr { operator, lhs, rhs }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum UnaryOperator {
     Negate,
     Not,
@@ -104,36 +414,482 @@ impl UnaryOperator {
             _ => return None,
         })
     }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnaryOperator::Negate => "-",
+            UnaryOperator::Not => "!",
+        }
+    }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct UnaryExpr {
-    operator: UnaryOperator,
-    expr: Box<Expr>,
+    pub operator: UnaryOperator,
+    pub expr: ExprId,
 }
 
 impl UnaryExpr {
-    pub fn new(operator: UnaryOperator, expr: Box<Expr>) -> UnaryExpr {
+    pub fn new(operator: UnaryOperator, expr: ExprId) -> UnaryExpr {
         UnaryExpr { operator, expr }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct LetAssignExpr {
-    pub variable: Variable,
-    pub initializer: Box<Expr>,
+    pub pattern: Pattern,
+    pub initializer: ExprId,
+    /// Declared with `let mut`, only then can it be reassigned.
+    pub mutable: bool,
+    /// The `return` after `else`, run when the pattern does not match.
+    pub otherwise: Option<ExprId>,
+    pub doc: Option<String>,
 }
 
 impl LetAssignExpr {
-    pub fn new(variable: Variable, initializer: Box<Expr>) -> Self {
+    pub fn new(pattern: Pattern, initializer: ExprId) -> Self {
         LetAssignExpr {
-            variable,
+            pattern,
             initializer,
+            mutable: false,
+            otherwise: None,
+            doc: None,
         }
     }
 }
 
-#[derive(PartialEq, Debug)]
+/// `callee(arguments)`, `x |> f(y)` is parsed as `f(x, y)`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CallExpr {
+    pub callee: ExprId,
+    pub arguments: Vec<ExprId>,
+}
+
+impl CallExpr {
+    pub fn new(callee: ExprId, arguments: Vec<ExprId>) -> Self {
+        CallExpr { callee, arguments }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TupleExpr {
+    pub elements: Vec<ExprId>,
+}
+
+impl TupleExpr {
+    pub fn new(elements: Vec<ExprId>) -> Self {
+        TupleExpr { elements }
+    }
+}
+
+/// `tuple.0`
+#[derive(Clone, PartialEq, Debug)]
+pub struct TupleIndexExpr {
+    pub tuple: ExprId,
+    pub index: usize,
+}
+
+impl TupleIndexExpr {
+    pub fn new(tuple: ExprId, index: usize) -> Self {
+        TupleIndexExpr { tuple, index }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MatchExpr {
+    pub scrutinee: ExprId,
+    pub arms: Vec<MatchArm>,
+}
+
+impl MatchExpr {
+    pub fn new(scrutinee: ExprId, arms: Vec<MatchArm>) -> Self {
+        MatchExpr { scrutinee, arms }
+    }
+}
+
+/// `| pattern => body`, the body sees the variables the pattern binds.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: ExprId,
+}
+
+impl MatchArm {
+    pub fn new(pattern: Pattern, body: ExprId) -> Self {
+        MatchArm { pattern, body }
+    }
+}
+
+/// `type point = { x, y }` or `type shape = | Circle(radius) | Square(side)`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TypeExpr {
+    pub variable: Variable,
+    pub definition: TypeDefinition,
+    pub doc: Option<String>,
+}
+
+impl TypeExpr {
+    pub fn new(variable: Variable, definition: TypeDefinition) -> Self {
+        TypeExpr {
+            variable,
+            definition,
+            doc: None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TypeDefinition {
+    Record(Vec<Variable>),
+    Variant(Vec<Constructor>),
+}
+
+/// A case of a variant type, `Circle(radius)`. Constructors with fields are
+/// called like functions, the others are values.
+#[derive(Clone, Debug)]
+pub struct Constructor {
+    pub variable: Variable,
+    pub fields: Vec<Variable>,
+    /// From the name to the closing parenthesis.
+    pub position: Position,
+}
+
+impl Constructor {
+    pub fn new(variable: Variable, fields: Vec<Variable>, position: Position) -> Self {
+        Constructor {
+            variable,
+            fields,
+            position,
+        }
+    }
+}
+
+/// Compared without the position, like `Variable`.
+impl PartialEq for Constructor {
+    fn eq(&self, other: &Self) -> bool {
+        self.variable == other.variable && self.fields == other.fields
+    }
+}
+
+/// `impl point ... end`, the functions of a type. A function named after an
+/// operator, like `add` for `+`, overloads it; see `BinaryOperator::method`.
+///
+/// `impl Show for point ... end` implements an interface for the type.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImplExpr {
+    pub variable: Variable,
+    pub interface: Option<Variable>,
+    /// The functions, see `ModuleAst::functions`.
+    pub functions: Vec<ExprId>,
+}
+
+impl ImplExpr {
+    pub fn new(variable: Variable, functions: Vec<ExprId>) -> Self {
+        ImplExpr {
+            variable,
+            interface: None,
+            functions,
+        }
+    }
+}
+
+/// `interface Show ... end`, the functions a type needs to implement it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct InterfaceExpr {
+    pub variable: Variable,
+    pub functions: Vec<Signature>,
+    pub doc: Option<String>,
+}
+
+impl InterfaceExpr {
+    pub fn new(variable: Variable, functions: Vec<Signature>) -> Self {
+        InterfaceExpr {
+            variable,
+            functions,
+            doc: None,
+        }
+    }
+}
+
+/// `def show(self)`, a function of an interface. Its first parameter is the
+/// value whose type picks the implementation.
+#[derive(Clone, Debug)]
+pub struct Signature {
+    pub variable: Variable,
+    pub parameters: Vec<Variable>,
+    pub annotations: Vec<Option<TypeAnnotation>>,
+    /// From `def` to the closing parenthesis.
+    pub position: Position,
+}
+
+impl Signature {
+    pub fn new(
+        variable: Variable,
+        parameters: Vec<Variable>,
+        annotations: Vec<Option<TypeAnnotation>>,
+        position: Position,
+    ) -> Self {
+        Signature {
+            variable,
+            parameters,
+            annotations,
+            position,
+        }
+    }
+}
+
+/// Compared without the position, like `Variable`.
+impl PartialEq for Signature {
+    fn eq(&self, other: &Self) -> bool {
+        self.variable == other.variable
+            && self.parameters == other.parameters
+            && self.annotations == other.annotations
+    }
+}
+
+/// The type written after a parameter, `x: number`. A name which is not a
+/// type is a type parameter, which stands for any type.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TypeAnnotation {
+    Name(Variable),
+    /// `option<number>`, a type with type arguments.
+    Generic(Variable, Vec<TypeAnnotation>),
+    Tuple(Vec<TypeAnnotation>),
+}
+
+impl TypeAnnotation {
+    /// The names of the type parameters and types in the annotation.
+    pub fn names(&self) -> Vec<&Variable> {
+        match self {
+            TypeAnnotation::Name(variable) => vec![variable],
+            TypeAnnotation::Generic(variable, arguments) => std::iter::once(variable)
+                .chain(arguments.iter().flat_map(TypeAnnotation::names))
+                .collect(),
+            TypeAnnotation::Tuple(elements) => {
+                elements.iter().flat_map(TypeAnnotation::names).collect()
+            }
+        }
+    }
+}
+
+/// `a: Show` in a `where` clause, the type parameter `a` must implement the
+/// interface.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Constraint {
+    pub parameter: Variable,
+    pub interface: Variable,
+}
+
+impl Constraint {
+    pub fn new(parameter: Variable, interface: Variable) -> Self {
+        Constraint {
+            parameter,
+            interface,
+        }
+    }
+}
+
+/// `point { x: 1, y }`, where `y` is short for `y: y`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RecordExpr {
+    pub variable: Variable,
+    pub fields: Vec<(Variable, ExprId)>,
+}
+
+impl RecordExpr {
+    pub fn new(variable: Variable, fields: Vec<(Variable, ExprId)>) -> Self {
+        RecordExpr { variable, fields }
+    }
+}
+
+/// `record.field`
+#[derive(Clone, PartialEq, Debug)]
+pub struct FieldExpr {
+    pub record: ExprId,
+    pub field: Variable,
+}
+
+impl FieldExpr {
+    pub fn new(record: ExprId, field: Variable) -> Self {
+        FieldExpr { record, field }
+    }
+}
+
+/// `record.field = value`. Records cannot be changed, only the properties
+/// of host types with a setter can be assigned.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SetFieldExpr {
+    pub record: ExprId,
+    pub field: Variable,
+    pub value: ExprId,
+}
+
+impl SetFieldExpr {
+    pub fn new(record: ExprId, field: Variable, value: ExprId) -> Self {
+        SetFieldExpr {
+            record,
+            field,
+            value,
+        }
+    }
+}
+
+/// `value.method(arguments)`. It calls the function of that name in the
+/// `impl` for the type of the value, or else the function in scope with the
+/// value as the first argument.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MethodCallExpr {
+    pub receiver: ExprId,
+    pub method: Variable,
+    pub arguments: Vec<ExprId>,
+}
+
+impl MethodCallExpr {
+    pub fn new(receiver: ExprId, method: Variable, arguments: Vec<ExprId>) -> Self {
+        MethodCallExpr {
+            receiver,
+            method,
+            arguments,
+        }
+    }
+}
+
+/// `value?`, which unwraps `Some(x)` and `Ok(x)` to `x` and returns `None`
+/// and `Err(e)` from the enclosing `def`.
+#[derive(Clone, Debug)]
+pub struct TryExpr {
+    pub expr: ExprId,
+    /// The position of the `?`.
+    pub position: Position,
+}
+
+impl TryExpr {
+    pub fn new(expr: ExprId, position: Position) -> Self {
+        TryExpr { expr, position }
+    }
+}
+
+/// Compared without the position, like `Variable`.
+impl PartialEq for TryExpr {
+    fn eq(&self, other: &Self) -> bool {
+        self.expr == other.expr
+    }
+}
+
+/// The left-hand side of a `let` or a `match` arm.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Pattern {
+    Variable(Variable),
+    /// `_`, which matches anything without binding it.
+    Wildcard,
+    Literal(LiteralPattern),
+    Tuple(Vec<Pattern>),
+    /// `{ x, y }`, binding each field to a variable of the same name.
+    Record(Vec<Variable>),
+    List(ListPattern),
+    /// `Circle(r)` or `Apple`, told apart from a variable by the capital.
+    Variant(VariantPattern),
+}
+
+impl Pattern {
+    /// The variables the pattern binds, in source order.
+    pub fn variables(&self) -> Vec<&Variable> {
+        match self {
+            Pattern::Variable(variable) => vec![variable],
+            Pattern::Wildcard | Pattern::Literal(_) => vec![],
+            Pattern::Tuple(elements) => elements.iter().flat_map(Pattern::variables).collect(),
+            Pattern::Record(fields) => fields.iter().collect(),
+            Pattern::List(list) => list
+                .elements
+                .iter()
+                .chain(list.rest.as_deref())
+                .flat_map(Pattern::variables)
+                .collect(),
+            Pattern::Variant(variant) => {
+                variant.fields.iter().flat_map(Pattern::variables).collect()
+            }
+        }
+    }
+
+    /// The position of the first part which does not match every value of
+    /// the right type, if there is one.
+    pub fn refutable(&self) -> Option<&Position> {
+        match self {
+            Pattern::Variable(_) | Pattern::Wildcard | Pattern::Record(_) => None,
+            Pattern::Literal(literal) => Some(&literal.position),
+            Pattern::Tuple(elements) => elements.iter().find_map(Pattern::refutable),
+            // Only `[..rest]` matches lists of every length.
+            Pattern::List(list) if !list.elements.is_empty() || list.rest.is_none() => {
+                Some(&list.position)
+            }
+            Pattern::List(list) => list.rest.as_ref().and_then(|rest| rest.refutable()),
+            Pattern::Variant(variant) => Some(&variant.constructor.position),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LiteralPattern {
+    pub literal: LiteralExpr,
+    pub position: Position,
+}
+
+impl LiteralPattern {
+    pub fn new(literal: LiteralExpr, position: Position) -> Self {
+        LiteralPattern { literal, position }
+    }
+}
+
+/// Compared without the position, like `Variable`.
+impl PartialEq for LiteralPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.literal == other.literal
+    }
+}
+
+/// `[first, second, ..rest]`, where the rest is optional and a bare `..`
+/// is a wildcard.
+#[derive(Clone, Debug)]
+pub struct ListPattern {
+    pub elements: Vec<Pattern>,
+    pub rest: Option<Box<Pattern>>,
+    /// The position of the opening bracket.
+    pub position: Position,
+}
+
+impl ListPattern {
+    pub fn new(elements: Vec<Pattern>, rest: Option<Box<Pattern>>, position: Position) -> Self {
+        ListPattern {
+            elements,
+            rest,
+            position,
+        }
+    }
+}
+
+/// Compared without the position, like `Variable`.
+impl PartialEq for ListPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.elements == other.elements && self.rest == other.rest
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct VariantPattern {
+    pub constructor: Variable,
+    pub fields: Vec<Pattern>,
+}
+
+impl VariantPattern {
+    pub fn new(constructor: Variable, fields: Vec<Pattern>) -> Self {
+        VariantPattern {
+            constructor,
+            fields,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct LetGetExpr {
     pub variable: Variable,
 }
@@ -144,14 +900,14 @@ impl LetGetExpr {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct LetSetExpr {
     pub variable: Variable,
-    pub initializer: Box<Expr>,
+    pub initializer: ExprId,
 }
 
 impl LetSetExpr {
-    pub fn new(variable: Variable, initializer: Box<Expr>) -> Self {
+    pub fn new(variable: Variable, initializer: ExprId) -> Self {
         LetSetExpr {
             variable,
             initializer,
@@ -159,22 +915,32 @@ impl LetSetExpr {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct FunctionDeclaration {
     pub parameters: Vec<Variable>,
+    /// The annotation of every parameter, if it has one.
+    pub annotations: Vec<Option<TypeAnnotation>>,
+    /// The `where` clause.
+    pub constraints: Vec<Constraint>,
     pub body: BlockExpr,
 }
 
 impl FunctionDeclaration {
     pub fn new(parameters: Vec<Variable>, body: BlockExpr) -> Self {
-        FunctionDeclaration { parameters, body }
+        FunctionDeclaration {
+            annotations: parameters.iter().map(|_| None).collect(),
+            parameters,
+            constraints: vec![],
+            body,
+        }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct FunctionExpr {
     pub variable: Variable,
     pub declaration: FunctionDeclaration,
+    pub doc: Option<String>,
 }
 
 impl FunctionExpr {
@@ -182,39 +948,789 @@ impl FunctionExpr {
         FunctionExpr {
             variable,
             declaration,
+            doc: None,
         }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct BlockExpr {
-    pub exprs: Vec<Expr>,
+    pub exprs: Vec<ExprId>,
 }
 
 impl BlockExpr {
-    pub fn new(exprs: Vec<Expr>) -> Self {
+    pub fn new(exprs: Vec<ExprId>) -> Self {
         BlockExpr { exprs }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct ReturnExpr {
-    pub expr: Option<Box<Expr>>,
+    pub expr: Option<ExprId>,
 }
 
 impl ReturnExpr {
-    pub fn new(expr: Option<Box<Expr>>) -> Self {
+    pub fn new(expr: Option<ExprId>) -> Self {
         ReturnExpr { expr }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Debug)]
 pub struct Variable {
-    pub name: String,
+    pub id: VariableId,
+    pub name: Symbol,
+    pub position: Position,
 }
 
 impl Variable {
-    pub fn new(name: String) -> Self {
-        Variable { name }
+    pub fn new(id: VariableId, name: Symbol, position: Position) -> Self {
+        Variable { id, name, position }
+    }
+}
+
+/// Variables are compared by name only, so trees parsed from differently
+/// laid out sources compare equal.
+impl PartialEq for Variable {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+/// A pass over a module which reads it. Every method walks the children of
+/// its node by default, so a pass only overrides the nodes it cares about,
+/// calling the `walk_` function of the node to keep going into it.
+pub trait Visitor<'a> {
+    /// The module the visited expressions are in.
+    fn module(&self) -> &'a ModuleAst;
+
+    fn visit_expr(&mut self, id: ExprId) {
+        walk_expr(self, id)
+    }
+
+    fn visit_literal(&mut self, _id: ExprId, _literal: &'a LiteralExpr) {}
+
+    fn visit_grouping(&mut self, _id: ExprId, grouping: &'a GroupingExpr) {
+        self.visit_expr(grouping.expr)
+    }
+
+    fn visit_binary(&mut self, _id: ExprId, binary: &'a BinaryExpr) {
+        self.visit_expr(binary.lhs);
+        self.visit_expr(binary.rhs);
+    }
+
+    fn visit_unary(&mut self, _id: ExprId, unary: &'a UnaryExpr) {
+        self.visit_expr(unary.expr)
+    }
+
+    fn visit_let_assign(&mut self, _id: ExprId, let_assign: &'a LetAssignExpr) {
+        walk_let_assign(self, let_assign)
+    }
+
+    fn visit_let_get(&mut self, _id: ExprId, let_get: &'a LetGetExpr) {
+        self.visit_variable(&let_get.variable)
+    }
+
+    fn visit_let_set(&mut self, _id: ExprId, let_set: &'a LetSetExpr) {
+        self.visit_variable(&let_set.variable);
+        self.visit_expr(let_set.initializer);
+    }
+
+    fn visit_function(&mut self, _id: ExprId, fun: &'a FunctionExpr) {
+        walk_function(self, fun)
+    }
+
+    fn visit_block(&mut self, _id: ExprId, block: &'a BlockExpr) {
+        walk_block(self, block)
+    }
+
+    fn visit_return(&mut self, _id: ExprId, ret: &'a ReturnExpr) {
+        if let Some(expr) = ret.expr {
+            self.visit_expr(expr);
+        }
+    }
+
+    fn visit_tuple(&mut self, _id: ExprId, tuple: &'a TupleExpr) {
+        for &element in &tuple.elements {
+            self.visit_expr(element);
+        }
+    }
+
+    fn visit_tuple_index(&mut self, _id: ExprId, tuple_index: &'a TupleIndexExpr) {
+        self.visit_expr(tuple_index.tuple)
+    }
+
+    fn visit_match(&mut self, _id: ExprId, match_expr: &'a MatchExpr) {
+        walk_match(self, match_expr)
+    }
+
+    fn visit_call(&mut self, _id: ExprId, call: &'a CallExpr) {
+        self.visit_expr(call.callee);
+        for &argument in &call.arguments {
+            self.visit_expr(argument);
+        }
+    }
+
+    fn visit_type(&mut self, _id: ExprId, ty: &'a TypeExpr) {
+        walk_type(self, ty)
+    }
+
+    fn visit_impl(&mut self, _id: ExprId, imp: &'a ImplExpr) {
+        walk_impl(self, imp)
+    }
+
+    fn visit_interface(&mut self, _id: ExprId, interface: &'a InterfaceExpr) {
+        walk_interface(self, interface)
+    }
+
+    fn visit_record(&mut self, _id: ExprId, record: &'a RecordExpr) {
+        self.visit_variable(&record.variable);
+        for (field, value) in &record.fields {
+            self.visit_variable(field);
+            self.visit_expr(*value);
+        }
+    }
+
+    fn visit_field(&mut self, _id: ExprId, field: &'a FieldExpr) {
+        self.visit_expr(field.record);
+        self.visit_variable(&field.field);
+    }
+
+    fn visit_set_field(&mut self, _id: ExprId, set_field: &'a SetFieldExpr) {
+        self.visit_expr(set_field.record);
+        self.visit_variable(&set_field.field);
+        self.visit_expr(set_field.value);
+    }
+
+    fn visit_method_call(&mut self, _id: ExprId, method_call: &'a MethodCallExpr) {
+        self.visit_expr(method_call.receiver);
+        self.visit_variable(&method_call.method);
+        for &argument in &method_call.arguments {
+            self.visit_expr(argument);
+        }
+    }
+
+    fn visit_try(&mut self, _id: ExprId, try_expr: &'a TryExpr) {
+        self.visit_expr(try_expr.expr)
+    }
+
+    fn visit_pattern(&mut self, pattern: &'a Pattern) {
+        walk_pattern(self, pattern)
+    }
+
+    fn visit_annotation(&mut self, annotation: &'a TypeAnnotation) {
+        walk_annotation(self, annotation)
+    }
+
+    /// Called for every name in the tree, whether it declares a variable,
+    /// refers to one, or names a type, field or method.
+    fn visit_variable(&mut self, _variable: &'a Variable) {}
+}
+
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, id: ExprId) {
+    match &visitor.module()[id] {
+        Expr::Literal(literal) => visitor.visit_literal(id, literal),
+        Expr::Grouping(grouping) => visitor.visit_grouping(id, grouping),
+        Expr::Binary(binary) => visitor.visit_binary(id, binary),
+        Expr::Unary(unary) => visitor.visit_unary(id, unary),
+        Expr::LetAssign(let_assign) => visitor.visit_let_assign(id, let_assign),
+        Expr::LetGet(let_get) => visitor.visit_let_get(id, let_get),
+        Expr::LetSet(let_set) => visitor.visit_let_set(id, let_set),
+        Expr::Function(fun) => visitor.visit_function(id, fun),
+        Expr::Block(block) => visitor.visit_block(id, block),
+        Expr::Return(ret) => visitor.visit_return(id, ret),
+        Expr::Tuple(tuple) => visitor.visit_tuple(id, tuple),
+        Expr::TupleIndex(tuple_index) => visitor.visit_tuple_index(id, tuple_index),
+        Expr::Match(match_expr) => visitor.visit_match(id, match_expr),
+        Expr::Call(call) => visitor.visit_call(id, call),
+        Expr::Type(ty) => visitor.visit_type(id, ty),
+        Expr::Impl(imp) => visitor.visit_impl(id, imp),
+        Expr::Interface(interface) => visitor.visit_interface(id, interface),
+        Expr::Record(record) => visitor.visit_record(id, record),
+        Expr::Field(field) => visitor.visit_field(id, field),
+        Expr::SetField(set_field) => visitor.visit_set_field(id, set_field),
+        Expr::MethodCall(method_call) => visitor.visit_method_call(id, method_call),
+        Expr::Try(try_expr) => visitor.visit_try(id, try_expr),
+    }
+}
+
+/// Visits the initializer before the pattern, which is the order the
+/// variables come into scope.
+pub fn walk_let_assign<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    let_assign: &'a LetAssignExpr,
+) {
+    visitor.visit_expr(let_assign.initializer);
+    if let Some(otherwise) = let_assign.otherwise {
+        visitor.visit_expr(otherwise);
+    }
+    visitor.visit_pattern(&let_assign.pattern);
+}
+
+pub fn walk_function<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, fun: &'a FunctionExpr) {
+    visitor.visit_variable(&fun.variable);
+    let declaration = &fun.declaration;
+    for parameter in &declaration.parameters {
+        visitor.visit_variable(parameter);
+    }
+    for annotation in declaration.annotations.iter().flatten() {
+        visitor.visit_annotation(annotation);
+    }
+    for constraint in &declaration.constraints {
+        visitor.visit_variable(&constraint.parameter);
+        visitor.visit_variable(&constraint.interface);
+    }
+    walk_block(visitor, &declaration.body);
+}
+
+pub fn walk_block<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, block: &'a BlockExpr) {
+    for &expr in &block.exprs {
+        visitor.visit_expr(expr);
+    }
+}
+
+pub fn walk_match<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, match_expr: &'a MatchExpr) {
+    visitor.visit_expr(match_expr.scrutinee);
+    for arm in &match_expr.arms {
+        visitor.visit_pattern(&arm.pattern);
+        visitor.visit_expr(arm.body);
+    }
+}
+
+pub fn walk_type<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, ty: &'a TypeExpr) {
+    visitor.visit_variable(&ty.variable);
+    match &ty.definition {
+        TypeDefinition::Record(fields) => {
+            for field in fields {
+                visitor.visit_variable(field);
+            }
+        }
+        TypeDefinition::Variant(constructors) => {
+            for constructor in constructors {
+                visitor.visit_variable(&constructor.variable);
+                for field in &constructor.fields {
+                    visitor.visit_variable(field);
+                }
+            }
+        }
+    }
+}
+
+pub fn walk_impl<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, imp: &'a ImplExpr) {
+    if let Some(interface) = &imp.interface {
+        visitor.visit_variable(interface);
+    }
+    visitor.visit_variable(&imp.variable);
+    for &fun in &imp.functions {
+        visitor.visit_expr(fun);
+    }
+}
+
+pub fn walk_interface<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, interface: &'a InterfaceExpr) {
+    visitor.visit_variable(&interface.variable);
+    for signature in &interface.functions {
+        visitor.visit_variable(&signature.variable);
+        for parameter in &signature.parameters {
+            visitor.visit_variable(parameter);
+        }
+        for annotation in signature.annotations.iter().flatten() {
+            visitor.visit_annotation(annotation);
+        }
+    }
+}
+
+pub fn walk_pattern<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, pattern: &'a Pattern) {
+    match pattern {
+        Pattern::Variable(variable) => visitor.visit_variable(variable),
+        Pattern::Wildcard | Pattern::Literal(_) => {}
+        Pattern::Tuple(elements) => {
+            for element in elements {
+                visitor.visit_pattern(element);
+            }
+        }
+        Pattern::Record(fields) => {
+            for field in fields {
+                visitor.visit_variable(field);
+            }
+        }
+        Pattern::List(list) => {
+            for element in &list.elements {
+                visitor.visit_pattern(element);
+            }
+            if let Some(rest) = &list.rest {
+                visitor.visit_pattern(rest);
+            }
+        }
+        Pattern::Variant(variant) => {
+            visitor.visit_variable(&variant.constructor);
+            for field in &variant.fields {
+                visitor.visit_pattern(field);
+            }
+        }
+    }
+}
+
+pub fn walk_annotation<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    annotation: &'a TypeAnnotation,
+) {
+    match annotation {
+        TypeAnnotation::Name(variable) => visitor.visit_variable(variable),
+        TypeAnnotation::Generic(variable, arguments) => {
+            visitor.visit_variable(variable);
+            for argument in arguments {
+                visitor.visit_annotation(argument);
+            }
+        }
+        TypeAnnotation::Tuple(elements) => {
+            for element in elements {
+                visitor.visit_annotation(element);
+            }
+        }
+    }
+}
+
+/// A pass over a module which rewrites it, the mutable counterpart of
+/// `Visitor`. `fold_expr` returns the id of the expression which takes the
+/// place of the one it was given, which is a node allocated with
+/// `ModuleAst::alloc` or the same id after changing the node in place.
+///
+/// While the children of a node are folded, the node itself is taken out
+/// of the module, and a `nil` literal stands in its place.
+pub trait Folder {
+    fn fold_expr(&mut self, module: &mut ModuleAst, id: ExprId) -> ExprId {
+        walk_expr_mut(self, module, id);
+        id
+    }
+
+    fn fold_literal(&mut self, _module: &mut ModuleAst, _literal: &mut LiteralExpr) {}
+
+    fn fold_grouping(&mut self, module: &mut ModuleAst, grouping: &mut GroupingExpr) {
+        grouping.expr = self.fold_expr(module, grouping.expr);
+    }
+
+    fn fold_binary(&mut self, module: &mut ModuleAst, binary: &mut BinaryExpr) {
+        binary.lhs = self.fold_expr(module, binary.lhs);
+        binary.rhs = self.fold_expr(module, binary.rhs);
+    }
+
+    fn fold_unary(&mut self, module: &mut ModuleAst, unary: &mut UnaryExpr) {
+        unary.expr = self.fold_expr(module, unary.expr);
+    }
+
+    fn fold_let_assign(&mut self, module: &mut ModuleAst, let_assign: &mut LetAssignExpr) {
+        let_assign.initializer = self.fold_expr(module, let_assign.initializer);
+        if let Some(otherwise) = let_assign.otherwise {
+            let_assign.otherwise = Some(self.fold_expr(module, otherwise));
+        }
+        self.fold_pattern(module, &mut let_assign.pattern);
+    }
+
+    fn fold_let_get(&mut self, _module: &mut ModuleAst, let_get: &mut LetGetExpr) {
+        self.fold_variable(&mut let_get.variable)
+    }
+
+    fn fold_let_set(&mut self, module: &mut ModuleAst, let_set: &mut LetSetExpr) {
+        self.fold_variable(&mut let_set.variable);
+        let_set.initializer = self.fold_expr(module, let_set.initializer);
+    }
+
+    fn fold_function(&mut self, module: &mut ModuleAst, fun: &mut FunctionExpr) {
+        walk_function_mut(self, module, fun)
+    }
+
+    fn fold_block(&mut self, module: &mut ModuleAst, block: &mut BlockExpr) {
+        fold_exprs(self, module, &mut block.exprs)
+    }
+
+    fn fold_return(&mut self, module: &mut ModuleAst, ret: &mut ReturnExpr) {
+        if let Some(expr) = ret.expr {
+            ret.expr = Some(self.fold_expr(module, expr));
+        }
+    }
+
+    fn fold_tuple(&mut self, module: &mut ModuleAst, tuple: &mut TupleExpr) {
+        fold_exprs(self, module, &mut tuple.elements)
+    }
+
+    fn fold_tuple_index(&mut self, module: &mut ModuleAst, tuple_index: &mut TupleIndexExpr) {
+        tuple_index.tuple = self.fold_expr(module, tuple_index.tuple);
+    }
+
+    fn fold_match(&mut self, module: &mut ModuleAst, match_expr: &mut MatchExpr) {
+        match_expr.scrutinee = self.fold_expr(module, match_expr.scrutinee);
+        for arm in &mut match_expr.arms {
+            self.fold_pattern(module, &mut arm.pattern);
+            arm.body = self.fold_expr(module, arm.body);
+        }
+    }
+
+    fn fold_call(&mut self, module: &mut ModuleAst, call: &mut CallExpr) {
+        call.callee = self.fold_expr(module, call.callee);
+        fold_exprs(self, module, &mut call.arguments)
+    }
+
+    fn fold_type(&mut self, _module: &mut ModuleAst, ty: &mut TypeExpr) {
+        self.fold_variable(&mut ty.variable);
+        match &mut ty.definition {
+            TypeDefinition::Record(fields) => {
+                for field in fields {
+                    self.fold_variable(field);
+                }
+            }
+            TypeDefinition::Variant(constructors) => {
+                for constructor in constructors {
+                    self.fold_variable(&mut constructor.variable);
+                    for field in &mut constructor.fields {
+                        self.fold_variable(field);
+                    }
+                }
+            }
+        }
+    }
+
+    /// A function replaced by anything but an `Expr::Function` is no longer
+    /// a function of the type, see `ModuleAst::functions`.
+    fn fold_impl(&mut self, module: &mut ModuleAst, imp: &mut ImplExpr) {
+        if let Some(interface) = &mut imp.interface {
+            self.fold_variable(interface);
+        }
+        self.fold_variable(&mut imp.variable);
+        fold_exprs(self, module, &mut imp.functions)
+    }
+
+    fn fold_interface(&mut self, _module: &mut ModuleAst, interface: &mut InterfaceExpr) {
+        self.fold_variable(&mut interface.variable);
+        for signature in &mut interface.functions {
+            self.fold_variable(&mut signature.variable);
+            for parameter in &mut signature.parameters {
+                self.fold_variable(parameter);
+            }
+            for annotation in signature.annotations.iter_mut().flatten() {
+                self.fold_annotation(annotation);
+            }
+        }
+    }
+
+    fn fold_record(&mut self, module: &mut ModuleAst, record: &mut RecordExpr) {
+        self.fold_variable(&mut record.variable);
+        for (field, value) in &mut record.fields {
+            self.fold_variable(field);
+            *value = self.fold_expr(module, *value);
+        }
+    }
+
+    fn fold_field(&mut self, module: &mut ModuleAst, field: &mut FieldExpr) {
+        field.record = self.fold_expr(module, field.record);
+        self.fold_variable(&mut field.field);
+    }
+
+    fn fold_set_field(&mut self, module: &mut ModuleAst, set_field: &mut SetFieldExpr) {
+        set_field.record = self.fold_expr(module, set_field.record);
+        self.fold_variable(&mut set_field.field);
+        set_field.value = self.fold_expr(module, set_field.value);
+    }
+
+    fn fold_method_call(&mut self, module: &mut ModuleAst, method_call: &mut MethodCallExpr) {
+        method_call.receiver = self.fold_expr(module, method_call.receiver);
+        self.fold_variable(&mut method_call.method);
+        fold_exprs(self, module, &mut method_call.arguments)
+    }
+
+    fn fold_try(&mut self, module: &mut ModuleAst, try_expr: &mut TryExpr) {
+        try_expr.expr = self.fold_expr(module, try_expr.expr);
+    }
+
+    fn fold_pattern(&mut self, module: &mut ModuleAst, pattern: &mut Pattern) {
+        walk_pattern_mut(self, module, pattern)
+    }
+
+    fn fold_annotation(&mut self, annotation: &mut TypeAnnotation) {
+        walk_annotation_mut(self, annotation)
+    }
+
+    /// Called for every name in the tree, like `Visitor::visit_variable`.
+    fn fold_variable(&mut self, _variable: &mut Variable) {}
+}
+
+pub fn walk_expr_mut<F: Folder + ?Sized>(folder: &mut F, module: &mut ModuleAst, id: ExprId) {
+    let mut expr = std::mem::replace(&mut module[id], Expr::Literal(LiteralExpr::Nil));
+    match &mut expr {
+        Expr::Literal(literal) => folder.fold_literal(module, literal),
+        Expr::Grouping(grouping) => folder.fold_grouping(module, grouping),
+        Expr::Binary(binary) => folder.fold_binary(module, binary),
+        Expr::Unary(unary) => folder.fold_unary(module, unary),
+        Expr::LetAssign(let_assign) => folder.fold_let_assign(module, let_assign),
+        Expr::LetGet(let_get) => folder.fold_let_get(module, let_get),
+        Expr::LetSet(let_set) => folder.fold_let_set(module, let_set),
+        Expr::Function(fun) => folder.fold_function(module, fun),
+        Expr::Block(block) => folder.fold_block(module, block),
+        Expr::Return(ret) => folder.fold_return(module, ret),
+        Expr::Tuple(tuple) => folder.fold_tuple(module, tuple),
+        Expr::TupleIndex(tuple_index) => folder.fold_tuple_index(module, tuple_index),
+        Expr::Match(match_expr) => folder.fold_match(module, match_expr),
+        Expr::Call(call) => folder.fold_call(module, call),
+        Expr::Type(ty) => folder.fold_type(module, ty),
+        Expr::Impl(imp) => folder.fold_impl(module, imp),
+        Expr::Interface(interface) => folder.fold_interface(module, interface),
+        Expr::Record(record) => folder.fold_record(module, record),
+        Expr::Field(field) => folder.fold_field(module, field),
+        Expr::SetField(set_field) => folder.fold_set_field(module, set_field),
+        Expr::MethodCall(method_call) => folder.fold_method_call(module, method_call),
+        Expr::Try(try_expr) => folder.fold_try(module, try_expr),
+    }
+    module[id] = expr;
+}
+
+/// Folds every top-level expression of the module.
+pub fn fold_module<F: Folder + ?Sized>(folder: &mut F, module: &mut ModuleAst) {
+    let mut exprs = std::mem::take(&mut module.exprs);
+    fold_exprs(folder, module, &mut exprs);
+    module.exprs = exprs;
+}
+
+fn fold_exprs<F: Folder + ?Sized>(folder: &mut F, module: &mut ModuleAst, exprs: &mut [ExprId]) {
+    for expr in exprs {
+        *expr = folder.fold_expr(module, *expr);
+    }
+}
+
+pub fn walk_function_mut<F: Folder + ?Sized>(
+    folder: &mut F,
+    module: &mut ModuleAst,
+    fun: &mut FunctionExpr,
+) {
+    folder.fold_variable(&mut fun.variable);
+    let declaration = &mut fun.declaration;
+    for parameter in &mut declaration.parameters {
+        folder.fold_variable(parameter);
+    }
+    for annotation in declaration.annotations.iter_mut().flatten() {
+        folder.fold_annotation(annotation);
+    }
+    for constraint in &mut declaration.constraints {
+        folder.fold_variable(&mut constraint.parameter);
+        folder.fold_variable(&mut constraint.interface);
+    }
+    fold_exprs(folder, module, &mut declaration.body.exprs);
+}
+
+pub fn walk_pattern_mut<F: Folder + ?Sized>(
+    folder: &mut F,
+    module: &mut ModuleAst,
+    pattern: &mut Pattern,
+) {
+    match pattern {
+        Pattern::Variable(variable) => folder.fold_variable(variable),
+        Pattern::Wildcard => {}
+        Pattern::Literal(literal) => folder.fold_literal(module, &mut literal.literal),
+        Pattern::Tuple(elements) => {
+            for element in elements {
+                folder.fold_pattern(module, element);
+            }
+        }
+        Pattern::Record(fields) => {
+            for field in fields {
+                folder.fold_variable(field);
+            }
+        }
+        Pattern::List(list) => {
+            for element in &mut list.elements {
+                folder.fold_pattern(module, element);
+            }
+            if let Some(rest) = &mut list.rest {
+                folder.fold_pattern(module, rest);
+            }
+        }
+        Pattern::Variant(variant) => {
+            folder.fold_variable(&mut variant.constructor);
+            for field in &mut variant.fields {
+                folder.fold_pattern(module, field);
+            }
+        }
+    }
+}
+
+pub fn walk_annotation_mut<F: Folder + ?Sized>(folder: &mut F, annotation: &mut TypeAnnotation) {
+    match annotation {
+        TypeAnnotation::Name(variable) => folder.fold_variable(variable),
+        TypeAnnotation::Generic(variable, arguments) => {
+            folder.fold_variable(variable);
+            for argument in arguments {
+                folder.fold_annotation(argument);
+            }
+        }
+        TypeAnnotation::Tuple(elements) => {
+            for element in elements {
+                folder.fold_annotation(element);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::lexer::Lexer;
+    use crate::syntax::parser::AstParser;
+
+    fn parse(source: &str) -> ModuleAst {
+        let mut tokens = Lexer::tokenize(source).unwrap();
+        AstParser::parse(&mut tokens).unwrap()
+    }
+
+    /// Collects the names which are read, and counts the literals.
+    struct Names<'a> {
+        module: &'a ModuleAst,
+        names: Vec<&'a str>,
+        literals: usize,
+    }
+
+    impl<'a> Visitor<'a> for Names<'a> {
+        fn module(&self) -> &'a ModuleAst {
+            self.module
+        }
+
+        fn visit_literal(&mut self, _id: ExprId, _literal: &'a LiteralExpr) {
+            self.literals += 1;
+        }
+
+        fn visit_let_get(&mut self, _id: ExprId, let_get: &'a LetGetExpr) {
+            self.names.push(let_get.variable.name.as_str());
+        }
+
+        // Skips the bodies of functions.
+        fn visit_function(&mut self, _id: ExprId, _fun: &'a FunctionExpr) {}
+    }
+
+    #[test]
+    fn visit() {
+        let module = parse(
+            r#"
+            let (a, b) = (1, 2)
+            def f(x)
+                x + a
+            end
+            match f(b) {
+                | 3 => p.x
+                | _ => a.len()
+            }
+            "#,
+        );
+        let mut names = Names {
+            module: &module,
+            names: vec![],
+            literals: 0,
+        };
+        for &expr in &module.exprs {
+            names.visit_expr(expr);
+        }
+
+        assert_eq!(vec!["f", "b", "p", "a"], names.names);
+        // The literal of the pattern is not an expression.
+        assert_eq!(2, names.literals);
+    }
+
+    /// Replaces arithmetic on numbers with its result.
+    struct ConstantFolder;
+
+    impl Folder for ConstantFolder {
+        fn fold_expr(&mut self, module: &mut ModuleAst, id: ExprId) -> ExprId {
+            walk_expr_mut(self, module, id);
+            let number = |id| match &module[id] {
+                Expr::Literal(LiteralExpr::Number(n)) => Some(*n),
+                _ => None,
+            };
+            let result = match &module[id] {
+                Expr::Grouping(grouping) => number(grouping.expr),
+                Expr::Binary(binary) => match (number(binary.lhs), number(binary.rhs)) {
+                    (Some(lhs), Some(rhs)) => match binary.operator {
+                        BinaryOperator::Add => Some(lhs + rhs),
+                        BinaryOperator::Multiply => Some(lhs * rhs),
+                        _ => None,
+                    },
+                    _ => None,
+                },
+                _ => None,
+            };
+            match result {
+                Some(n) => {
+                    let position = module.position(id).clone();
+                    module.alloc(Expr::Literal(LiteralExpr::Number(n)), position)
+                }
+                None => id,
+            }
+        }
+    }
+
+    #[test]
+    fn fold() {
+        let mut module = parse("let x = (1 + 2) * 3\ndef f(y)\n    y * (2 + 2)\nend\n");
+        fold_module(&mut ConstantFolder, &mut module);
+
+        let top_level: Vec<_> = module.top_level().collect();
+        let initializer = match top_level[0] {
+            Expr::LetAssign(let_assign) => let_assign.initializer,
+            _ => unreachable!(),
+        };
+        assert_eq!(Expr::Literal(LiteralExpr::Number(9.0)), module[initializer]);
+        // The folded literal keeps the position of what it replaced.
+        assert_eq!(8, *module.position(initializer).start());
+
+        let body = match top_level[1] {
+            Expr::Function(fun) => fun.declaration.body.exprs[0],
+            _ => unreachable!(),
+        };
+        let rhs = match &module[body] {
+            Expr::Binary(binary) => binary.rhs,
+            _ => unreachable!(),
+        };
+        assert_eq!(Expr::Literal(LiteralExpr::Number(4.0)), module[rhs]);
+    }
+
+    /// Replaces every function with `nil`.
+    struct RemoveFunctions;
+
+    impl Folder for RemoveFunctions {
+        fn fold_expr(&mut self, module: &mut ModuleAst, id: ExprId) -> ExprId {
+            walk_expr_mut(self, module, id);
+            match &module[id] {
+                Expr::Function(_) => {
+                    let position = module.position(id).clone();
+                    module.alloc(Expr::Literal(LiteralExpr::Nil), position)
+                }
+                _ => id,
+            }
+        }
+    }
+
+    #[test]
+    fn fold_impl_functions() {
+        let mut module = parse("type p = { x }\nimpl p\n    def f(a)\n        a\n    end\nend\n");
+        fold_module(&mut RemoveFunctions, &mut module);
+
+        let imp = match module.top_level().nth(1) {
+            Some(Expr::Impl(imp)) => imp,
+            _ => unreachable!(),
+        };
+        assert_eq!(0, module.functions(imp).count());
+        // Passes over the folded module skip what is not a function.
+        let resolution = crate::resolver::Resolver::resolve(&module);
+        assert!(resolution.errors().is_empty());
+    }
+
+    #[test]
+    fn compare_trees() {
+        // The pipe allocates its left side before the call it is passed to.
+        assert_eq!(parse("let z = 5 |> f(2)\n"), parse("let z = f(5, 2)\n"));
+        assert_eq!(
+            parse("def f(x)\n    x\nend\n"),
+            parse("def  f( x )\nx\nend\n")
+        );
+        assert_ne!(parse("let z = 5 |> f(2)\n"), parse("let z = f(2, 5)\n"));
+
+        // Folded away nodes are left in the arena, but not in the tree.
+        let mut module = parse("let x = 1 + 2\n");
+        fold_module(&mut ConstantFolder, &mut module);
+        assert_eq!(parse("let x = 3\n"), module);
     }
 }
@@ -0,0 +1,343 @@
+use crate::syntax::ast::ModuleAst;
+use crate::syntax::error::ParserError;
+use crate::syntax::parser::AstParser;
+use crate::syntax::token::{Position, Token, TokenType};
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    Semicolon,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trivia {
+    kind: TriviaKind,
+    text: String,
+}
+
+impl Trivia {
+    pub fn new(kind: TriviaKind, text: String) -> Self {
+        Trivia { kind, text }
+    }
+
+    pub fn kind(&self) -> TriviaKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Module,
+    Let,
+    Def,
+    ParamList,
+    Block,
+    Return,
+    Type,
+    Impl,
+    Interface,
+    ExprStmt,
+}
+
+/// An immutable token, without any knowledge of where it lives in the tree.
+/// Identical tokens can be shared between trees.
+#[derive(Debug, PartialEq)]
+pub struct GreenToken {
+    token_type: TokenType,
+    text: String,
+    leading: Vec<Trivia>,
+    trailing: Vec<Trivia>,
+}
+
+impl GreenToken {
+    pub fn new(
+        token_type: TokenType,
+        text: String,
+        leading: Vec<Trivia>,
+        trailing: Vec<Trivia>,
+    ) -> Self {
+        GreenToken {
+            token_type,
+            text,
+            leading,
+            trailing,
+        }
+    }
+
+    pub fn token_type(&self) -> &TokenType {
+        &self.token_type
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn leading_trivia(&self) -> &[Trivia] {
+        &self.leading
+    }
+
+    pub fn trailing_trivia(&self) -> &[Trivia] {
+        &self.trailing
+    }
+
+    fn leading_len(&self) -> usize {
+        self.leading.iter().map(|t| t.text.len()).sum()
+    }
+
+    /// The length of the token including its trivia.
+    pub fn full_len(&self) -> usize {
+        self.leading_len()
+            + self.text.len()
+            + self.trailing.iter().map(|t| t.text.len()).sum::<usize>()
+    }
+}
+
+impl fmt::Display for GreenToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for trivia in &self.leading {
+            f.write_str(&trivia.text)?;
+        }
+        f.write_str(&self.text)?;
+        for trivia in &self.trailing {
+            f.write_str(&trivia.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// An immutable node, without any knowledge of where it lives in the tree.
+/// Unchanged subtrees can be shared between trees.
+#[derive(Debug, PartialEq)]
+pub struct GreenNode {
+    kind: NodeKind,
+    full_len: usize,
+    children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new(kind: NodeKind, children: Vec<GreenElement>) -> Self {
+        let full_len = children.iter().map(GreenElement::full_len).sum();
+        GreenNode {
+            kind,
+            full_len,
+            children,
+        }
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+
+    pub fn full_len(&self) -> usize {
+        self.full_len
+    }
+}
+
+impl fmt::Display for GreenNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => write!(f, "{}", node)?,
+                GreenElement::Token(token) => write!(f, "{}", token)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn full_len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.full_len(),
+            GreenElement::Token(token) => token.full_len(),
+        }
+    }
+}
+
+/// A cursor into a green tree which knows its absolute offset and its parent.
+#[derive(Debug, Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Debug)]
+struct NodeData {
+    green: Rc<GreenNode>,
+    offset: usize,
+    parent: Option<SyntaxNode>,
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> Self {
+        SyntaxNode(Rc::new(NodeData {
+            green,
+            offset: 0,
+            parent: None,
+        }))
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.0.green.kind()
+    }
+
+    pub fn parent(&self) -> Option<&SyntaxNode> {
+        self.0.parent.as_ref()
+    }
+
+    /// The range of the node in the source, including all trivia.
+    pub fn full_range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.full_len()
+    }
+
+    pub fn children(&self) -> Vec<SyntaxElement> {
+        let mut offset = self.0.offset;
+        self.0
+            .green
+            .children()
+            .iter()
+            .map(|child| {
+                let element = match child {
+                    GreenElement::Node(green) => {
+                        SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                            green: green.clone(),
+                            offset,
+                            parent: Some(self.clone()),
+                        })))
+                    }
+                    GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                        green: green.clone(),
+                        offset,
+                        parent: self.clone(),
+                    }),
+                };
+                offset += child.full_len();
+                element
+            })
+            .collect()
+    }
+
+    pub fn child_nodes(&self) -> Vec<SyntaxNode> {
+        self.children()
+            .into_iter()
+            .filter_map(|child| match child {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None,
+            })
+            .collect()
+    }
+
+    /// All tokens below this node, in source order.
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut tokens = vec![];
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    /// Lowers the tree into the abstract syntax tree. Trivia is dropped and
+    /// the remaining tokens are handed to the `AstParser`.
+    pub fn lower(&self) -> Result<ModuleAst, ParserError> {
+        let source = self.to_string();
+        let syntax_tokens = self.tokens();
+
+        let mut line = 1;
+        let mut counted = self.0.offset;
+        let mut tokens = vec![];
+        for token in &syntax_tokens {
+            let range = token.text_range();
+            line += source[counted - self.0.offset..range.end - self.0.offset]
+                .matches('\n')
+                .count();
+            counted = range.end;
+
+            // The lexer trims tokens, which leaves line breaks without text.
+            let text = token.text().trim_end();
+            tokens.push(Token::new(
+                token.token_type().clone(),
+                text,
+                Position::new(range.start, range.start + text.len(), line),
+            ));
+        }
+
+        AstParser::parse(&mut tokens)
+    }
+}
+
+impl PartialEq for SyntaxNode {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0.green, &other.0.green) && self.0.offset == other.0.offset
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.green)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    offset: usize,
+    parent: SyntaxNode,
+}
+
+impl SyntaxToken {
+    pub fn green(&self) -> &Rc<GreenToken> {
+        &self.green
+    }
+
+    pub fn token_type(&self) -> &TokenType {
+        self.green.token_type()
+    }
+
+    pub fn text(&self) -> &str {
+        self.green.text()
+    }
+
+    pub fn parent(&self) -> &SyntaxNode {
+        &self.parent
+    }
+
+    /// The range of the token text in the source, excluding trivia.
+    pub fn text_range(&self) -> Range<usize> {
+        let start = self.offset + self.green.leading_len();
+        start..start + self.green.text().len()
+    }
+
+    /// The range of the token in the source, including its trivia.
+    pub fn full_range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.full_len()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
@@ -0,0 +1,451 @@
+use crate::syntax::cst::*;
+use crate::syntax::error::SyntaxError;
+use crate::syntax::lexer::Lexer;
+use crate::syntax::token::{Keyword, TokenType};
+use std::rc::Rc;
+
+type Result<T> = std::result::Result<T, SyntaxError>;
+
+/// Builds a lossless concrete syntax tree. The tree only captures the
+/// structure of items (`let`, `def`, `return`, `type`, `impl`, `interface`
+/// and expression statements);
+/// expressions are kept as flat token sequences and are left to the
+/// `AstParser` when the tree is lowered.
+pub struct CstParser {
+    tokens: Vec<Rc<GreenToken>>,
+}
+
+impl CstParser {
+    fn new(mut tokens: Vec<Rc<GreenToken>>) -> Self {
+        tokens.reverse();
+        CstParser { tokens }
+    }
+
+    pub fn parse(source: &str) -> Result<SyntaxNode> {
+        let mut parser = CstParser::new(lossless_tokens(source)?);
+        Ok(SyntaxNode::new_root(Rc::new(parser.module())))
+    }
+
+    /// Parses a stretch of a module which follows a line break, ending in
+    /// its own EOF token.
+    pub fn parse_items(source: &str) -> Result<Vec<GreenElement>> {
+        // Lexed after a line break of its own, since at the start of a file
+        // blank lines are a token rather than trivia.
+        let mut tokens = lossless_tokens(&format!("\n{}", source))?;
+        tokens.remove(0);
+
+        let mut parser = CstParser::new(tokens);
+        Ok(parser.module().children().to_vec())
+    }
+
+    fn module(&mut self) -> GreenNode {
+        let mut children = vec![];
+        loop {
+            match self.peek_type() {
+                None => break,
+                Some(TokenType::EOF) => {
+                    children.push(self.bump());
+                    break;
+                }
+                Some(TokenType::Line) => children.push(self.bump()),
+                Some(_) => children.push(self.item()),
+            }
+        }
+
+        GreenNode::new(NodeKind::Module, children)
+    }
+
+    fn item(&mut self) -> GreenElement {
+        let mut children = vec![];
+        while self.check(&TokenType::DocComment) {
+            children.push(self.bump());
+            if self.check(&TokenType::Line) {
+                children.push(self.bump());
+            }
+        }
+
+        let kind = match self.peek_type() {
+            Some(TokenType::Keyword(Keyword::Let)) => {
+                self.rest_of_line(&mut children);
+                NodeKind::Let
+            }
+            Some(TokenType::Keyword(Keyword::Def)) => {
+                self.def(&mut children);
+                NodeKind::Def
+            }
+            Some(TokenType::Keyword(Keyword::Return)) => {
+                self.rest_of_line(&mut children);
+                NodeKind::Return
+            }
+            // Like a `match`, the lines of a type after the first are items
+            // of their own.
+            Some(TokenType::Keyword(Keyword::Type)) => {
+                self.rest_of_line(&mut children);
+                NodeKind::Type
+            }
+            Some(TokenType::Keyword(Keyword::Impl)) => {
+                self.impl_block(&mut children);
+                NodeKind::Impl
+            }
+            Some(TokenType::Keyword(Keyword::Interface)) => {
+                self.interface(&mut children);
+                NodeKind::Interface
+            }
+            _ => {
+                self.rest_of_line(&mut children);
+                NodeKind::ExprStmt
+            }
+        };
+
+        GreenElement::Node(Rc::new(GreenNode::new(kind, children)))
+    }
+
+    fn def(&mut self, children: &mut Vec<GreenElement>) {
+        // Consume "def".
+        children.push(self.bump());
+
+        if self.check(&TokenType::Identifier) {
+            children.push(self.bump());
+        }
+        if self.check(&TokenType::LeftParen) {
+            children.push(self.param_list());
+        }
+        // A `where` clause.
+        while !matches!(
+            self.peek_type(),
+            None | Some(TokenType::Line | TokenType::EOF)
+        ) {
+            children.push(self.bump());
+        }
+        children.push(self.block());
+        if self.check(&TokenType::Line) {
+            children.push(self.bump());
+        }
+    }
+
+    fn impl_block(&mut self, children: &mut Vec<GreenElement>) {
+        // Consume "impl".
+        children.push(self.bump());
+
+        if self.check(&TokenType::Identifier) {
+            children.push(self.bump());
+        }
+        children.push(self.block());
+        if self.check(&TokenType::Line) {
+            children.push(self.bump());
+        }
+    }
+
+    /// The functions of an interface have no body, each line up to the `end`
+    /// is a token of the interface.
+    fn interface(&mut self, children: &mut Vec<GreenElement>) {
+        loop {
+            match self.peek_type() {
+                None | Some(TokenType::EOF) => break,
+                Some(TokenType::Keyword(Keyword::End)) => {
+                    children.push(self.bump());
+                    if self.check(&TokenType::Line) {
+                        children.push(self.bump());
+                    }
+                    break;
+                }
+                Some(_) => children.push(self.bump()),
+            }
+        }
+    }
+
+    fn param_list(&mut self) -> GreenElement {
+        let mut children = vec![];
+        while let Some(token_type) = self.peek_type() {
+            if let TokenType::Line | TokenType::EOF = token_type {
+                break;
+            }
+
+            let is_end = token_type == &TokenType::RightParen;
+            children.push(self.bump());
+            if is_end {
+                break;
+            }
+        }
+
+        GreenElement::Node(Rc::new(GreenNode::new(NodeKind::ParamList, children)))
+    }
+
+    fn block(&mut self) -> GreenElement {
+        let mut children = vec![];
+        loop {
+            match self.peek_type() {
+                None | Some(TokenType::EOF) => break,
+                Some(TokenType::Keyword(Keyword::End)) => {
+                    children.push(self.bump());
+                    break;
+                }
+                Some(TokenType::Line) => children.push(self.bump()),
+                Some(_) => children.push(self.item()),
+            }
+        }
+
+        GreenElement::Node(Rc::new(GreenNode::new(NodeKind::Block, children)))
+    }
+
+    fn rest_of_line(&mut self, children: &mut Vec<GreenElement>) {
+        while let Some(token_type) = self.peek_type() {
+            match token_type {
+                TokenType::EOF => break,
+                TokenType::Line => {
+                    children.push(self.bump());
+                    break;
+                }
+                _ => children.push(self.bump()),
+            }
+        }
+    }
+
+    fn bump(&mut self) -> GreenElement {
+        GreenElement::Token(self.tokens.pop().unwrap())
+    }
+
+    fn check(&self, token_type: &TokenType) -> bool {
+        self.peek_type() == Some(token_type)
+    }
+
+    fn peek_type(&self) -> Option<&TokenType> {
+        self.tokens.last().map(|t| t.token_type())
+    }
+}
+
+/// Tokenizes the source and fills the gaps between the tokens with trivia.
+/// Trivia up to the end of the line belongs to the preceding token, the
+/// rest leads the following token.
+fn lossless_tokens(source: &str) -> Result<Vec<Rc<GreenToken>>> {
+    let mut tokens: Vec<(TokenType, String, Vec<Trivia>, Vec<Trivia>)> = vec![];
+
+    let mut cursor = 0;
+    for token in Lexer::tokenize(source)? {
+        let start = *token.position().start();
+        let mut leading = scan_trivia(&source[cursor..start]);
+
+        if let Some((token_type, _, _, trailing)) = tokens.last_mut() {
+            if token_type != &TokenType::Line {
+                let line_end = leading
+                    .iter()
+                    .position(|t| matches!(t.kind(), TriviaKind::Newline | TriviaKind::Semicolon))
+                    .unwrap_or(leading.len());
+                *trailing = leading.drain(..line_end).collect();
+            }
+        }
+
+        // A line break is the only token the lexer trims down to nothing.
+        let end = match token.token_type() {
+            TokenType::Line => start + 1,
+            _ => *token.position().end(),
+        };
+
+        tokens.push((
+            token.token_type().clone(),
+            source[start..end].to_string(),
+            leading,
+            vec![],
+        ));
+        cursor = end;
+    }
+
+    Ok(tokens
+        .into_iter()
+        .map(|(token_type, text, leading, trailing)| {
+            Rc::new(GreenToken::new(token_type, text, leading, trailing))
+        })
+        .collect())
+}
+
+fn scan_trivia(source: &str) -> Vec<Trivia> {
+    let mut trivia = vec![];
+
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        let (kind, len) = match c {
+            '\r' if rest.starts_with("\r\n") => (TriviaKind::Newline, 2),
+            '\n' | '\r' => (TriviaKind::Newline, 1),
+            ';' => (TriviaKind::Semicolon, 1),
+            '/' if rest.starts_with("//") => (
+                TriviaKind::Comment,
+                rest.find(['\n', '\r']).unwrap_or(rest.len()),
+            ),
+            '/' if rest.starts_with("/*") => (TriviaKind::Comment, block_comment_len(rest)),
+            _ => (
+                TriviaKind::Whitespace,
+                rest.find(|c: char| !c.is_whitespace() || c == '\n' || c == '\r')
+                    .unwrap_or(rest.len()),
+            ),
+        };
+
+        trivia.push(Trivia::new(kind, rest[..len].to_string()));
+        rest = &rest[len..];
+    }
+
+    trivia
+}
+
+/// The lexer has already rejected unterminated block comments, so every
+/// block comment found in trivia is balanced.
+fn block_comment_len(source: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < source.len() {
+        if source[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if source[i..].starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                break;
+            }
+        } else {
+            i += source[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parser::AstParser;
+
+    const SOURCE: &str = r#"// Leading comment.
+/// Doubles x.
+def double(x) // The only function.
+    /* nested /* block */ comment */
+    return x * 2
+end;;
+
+let y = 5   ;  let z = y
+"#;
+
+    #[test]
+    fn round_trip() {
+        let cst = CstParser::parse(SOURCE).unwrap();
+
+        assert_eq!(SOURCE, cst.to_string());
+        assert_eq!(0..SOURCE.len(), cst.full_range());
+    }
+
+    #[test]
+    fn round_trip_crlf() {
+        let source = "let x = 1\r\n\r\nlet y = x\r\n";
+        let cst = CstParser::parse(source).unwrap();
+
+        assert_eq!(source, cst.to_string());
+    }
+
+    #[test]
+    fn structure() {
+        let cst = CstParser::parse(SOURCE).unwrap();
+
+        let kinds: Vec<NodeKind> = cst.child_nodes().iter().map(|n| n.kind()).collect();
+        assert_eq!(vec![NodeKind::Def, NodeKind::Let, NodeKind::Let,], kinds);
+
+        let def = &cst.child_nodes()[0];
+        let def_kinds: Vec<NodeKind> = def.child_nodes().iter().map(|n| n.kind()).collect();
+        assert_eq!(vec![NodeKind::ParamList, NodeKind::Block], def_kinds);
+        assert_eq!(
+            "(x) // The only function.",
+            def.child_nodes()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn impl_structure() {
+        let source = "type point = { x }\nimpl point\n    def get(p)\n        p.x\n    end\nend\n";
+        let cst = CstParser::parse(source).unwrap();
+
+        let kinds: Vec<NodeKind> = cst.child_nodes().iter().map(|n| n.kind()).collect();
+        assert_eq!(vec![NodeKind::Type, NodeKind::Impl], kinds);
+
+        let block = &cst.child_nodes()[1].child_nodes()[0];
+        assert_eq!(NodeKind::Block, block.kind());
+        let def_kinds: Vec<NodeKind> = block.child_nodes().iter().map(|n| n.kind()).collect();
+        assert_eq!(vec![NodeKind::Def], def_kinds);
+    }
+
+    #[test]
+    fn interface_structure() {
+        let source =
+            "interface Show\n    def show(x)\nend\ndef f(x: a) where a: Show\n    show(x)\nend\n";
+        let cst = CstParser::parse(source).unwrap();
+
+        let kinds: Vec<NodeKind> = cst.child_nodes().iter().map(|n| n.kind()).collect();
+        assert_eq!(vec![NodeKind::Interface, NodeKind::Def], kinds);
+        assert!(cst.child_nodes()[0].child_nodes().is_empty());
+
+        let def_kinds: Vec<NodeKind> = cst.child_nodes()[1]
+            .child_nodes()
+            .iter()
+            .map(|n| n.kind())
+            .collect();
+        assert_eq!(vec![NodeKind::ParamList, NodeKind::Block], def_kinds);
+        assert_eq!(source, cst.to_string());
+    }
+
+    #[test]
+    fn trivia_attachment() {
+        let cst = CstParser::parse(SOURCE).unwrap();
+        let tokens = cst.tokens();
+
+        let line = &tokens[0];
+        assert_eq!(&TokenType::Line, line.token_type());
+        assert_eq!(
+            &[Trivia::new(
+                TriviaKind::Comment,
+                "// Leading comment.".to_string()
+            )],
+            line.green().leading_trivia()
+        );
+        assert_eq!(&TokenType::DocComment, tokens[1].token_type());
+
+        let right_paren = tokens
+            .iter()
+            .find(|t| t.token_type() == &TokenType::RightParen)
+            .unwrap();
+        assert_eq!(
+            &[
+                Trivia::new(TriviaKind::Whitespace, " ".to_string()),
+                Trivia::new(TriviaKind::Comment, "// The only function.".to_string()),
+            ],
+            right_paren.green().trailing_trivia()
+        );
+        assert_eq!(
+            SOURCE.find(')').unwrap()..SOURCE.find(')').unwrap() + 1,
+            right_paren.text_range()
+        );
+    }
+
+    #[test]
+    fn tokens_match_lexer() {
+        let cst = CstParser::parse(SOURCE).unwrap();
+        let expect: Vec<(TokenType, usize)> = Lexer::tokenize(SOURCE)
+            .unwrap()
+            .iter()
+            .map(|t| (t.token_type().clone(), *t.position().start()))
+            .collect();
+
+        let actual: Vec<(TokenType, usize)> = cst
+            .tokens()
+            .iter()
+            .map(|t| (t.token_type().clone(), t.text_range().start))
+            .collect();
+
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn lower() {
+        let mut tokens = Lexer::tokenize(SOURCE).unwrap();
+        let expect = AstParser::parse(&mut tokens).unwrap();
+
+        let actual = CstParser::parse(SOURCE).unwrap().lower().unwrap();
+
+        assert_eq!(expect, actual);
+    }
+}
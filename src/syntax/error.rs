@@ -1,13 +1,102 @@
+use crate::syntax::parser::MAX_DEPTH;
 use crate::syntax::token::TokenType;
+use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum SyntaxError {
-    UnterminatedString,
+    UnterminatedString(usize),
+    UnterminatedComment(usize),
+    UnexpectedChar(char, usize),
     UnexpectedEOF,
 }
 
+impl SyntaxError {
+    /// The line the error occurred on, if it is known.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            SyntaxError::UnterminatedString(line)
+            | SyntaxError::UnterminatedComment(line)
+            | SyntaxError::UnexpectedChar(_, line) => Some(*line),
+            SyntaxError::UnexpectedEOF => None,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxError::UnterminatedString(_) => write!(f, "unterminated string"),
+            SyntaxError::UnterminatedComment(_) => write!(f, "unterminated block comment"),
+            SyntaxError::UnexpectedChar(c, _) => write!(f, "unexpected character '{}'", c),
+            SyntaxError::UnexpectedEOF => write!(f, "unexpected end of file"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ParserError {
     UnexpectedEOF,
     Expect(TokenType, TokenType, usize), // TODO It's posssible to name enum kind variables?
+    ExpectedExpression(TokenType, usize),
+    ExpectedPattern(TokenType, usize),
+    MisplacedRest(usize),
+    InvalidTupleIndex(String, usize),
+    NotCallable(usize),
+    /// An `=` after something which is neither a variable nor a field.
+    InvalidAssignment(usize),
+    ExpectedFunction(usize),
+    /// Expressions, patterns or types nested deeper than the parser allows.
+    TooDeep(usize),
+}
+
+impl ParserError {
+    /// The line the error occurred on, if it is known.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            ParserError::Expect(_, _, line)
+            | ParserError::ExpectedExpression(_, line)
+            | ParserError::ExpectedPattern(_, line)
+            | ParserError::MisplacedRest(line)
+            | ParserError::InvalidTupleIndex(_, line)
+            | ParserError::NotCallable(line)
+            | ParserError::InvalidAssignment(line)
+            | ParserError::ExpectedFunction(line)
+            | ParserError::TooDeep(line) => Some(*line),
+            ParserError::UnexpectedEOF => None,
+        }
+    }
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParserError::UnexpectedEOF => write!(f, "unexpected end of file"),
+            ParserError::Expect(expect, actual, _) => {
+                write!(f, "expected {:?}, found {:?}", expect, actual)
+            }
+            ParserError::ExpectedExpression(actual, _) => {
+                write!(f, "expected an expression, found {:?}", actual)
+            }
+            ParserError::ExpectedPattern(actual, _) => {
+                write!(f, "expected a pattern, found {:?}", actual)
+            }
+            ParserError::MisplacedRest(_) => {
+                write!(f, "`..` can only come last in a list pattern")
+            }
+            ParserError::InvalidTupleIndex(index, _) => {
+                write!(f, "`{}` is not a tuple index", index)
+            }
+            ParserError::NotCallable(_) => write!(
+                f,
+                "the right side of `|>` must be a function or a call, like `xs |> map(double)`"
+            ),
+            ParserError::InvalidAssignment(_) => {
+                write!(f, "only variables and fields can be assigned")
+            }
+            ParserError::ExpectedFunction(_) => {
+                write!(f, "`impl` and `interface` blocks can only contain `def`s")
+            }
+            ParserError::TooDeep(_) => write!(f, "nested deeper than {}", MAX_DEPTH),
+        }
+    }
 }
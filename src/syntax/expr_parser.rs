@@ -3,6 +3,8 @@ use crate::syntax::error::ParserError;
 use crate::syntax::parser::AstParser;
 use crate::syntax::token::*;
 
+// `or` and `and` are not parsed yet.
+#[allow(dead_code)]
 #[derive(PartialEq, PartialOrd)]
 enum Precedence {
     None,
@@ -10,21 +12,23 @@ enum Precedence {
     Or,
     And,
     Equality,   // == !=
+    Pipeline,   // |>
     Comparison, // < <= > >=
     Term,       // + -
     Factor,     // * /
     Unary,      // ! -
-    Call,       // ()
+    Call,       // () . ?
     Primary,
 }
 
-impl<'a> From<&TokenType> for Precedence {
+impl From<&TokenType> for Precedence {
     fn from(token: &TokenType) -> Precedence {
         match token {
             TokenType::Equal => Precedence::Assign,
             // TokenType::Or => Precedence::Or, TODO
             // TokenType::And => Precedence::And, TODO
             TokenType::BangEqual | TokenType::EqualEqual => Precedence::Equality,
+            TokenType::Pipeline => Precedence::Pipeline,
             TokenType::LessThan
             | TokenType::LessThanEqual
             | TokenType::GreaterThan
@@ -33,32 +37,34 @@ impl<'a> From<&TokenType> for Precedence {
             TokenType::Star | TokenType::Slash => Precedence::Factor,
             TokenType::Bang => Precedence::Unary,
             TokenType::LeftParen => Precedence::Call,
-            TokenType::Dot => Precedence::Call,
+            TokenType::Dot | TokenType::Question => Precedence::Call,
             _ => Precedence::None,
         }
     }
 }
 
-pub fn parse(parser: &mut AstParser) -> Result<Expr, ParserError> {
+pub fn parse(parser: &mut AstParser) -> Result<ExprId, ParserError> {
     parse_expr(parser, Precedence::None)
 }
 
-fn parse_expr(parser: &mut AstParser, precedence: Precedence) -> Result<Expr, ParserError> {
-    let mut expr = parse_prefix(parser)?;
-    while !parser.is_eof()? {
-        let next_precedence = Precedence::from(parser.peek_type()?);
-        if precedence >= next_precedence {
-            break;
+fn parse_expr(parser: &mut AstParser, precedence: Precedence) -> Result<ExprId, ParserError> {
+    parser.nested(|parser| {
+        let mut expr = parse_prefix(parser)?;
+        while !parser.is_eof()? {
+            let next_precedence = Precedence::from(parser.peek_type()?);
+            if precedence >= next_precedence {
+                break;
+            }
+            expr = parse_infix(parser, expr)?;
         }
-        expr = parse_infix(parser, expr)?;
-    }
-    Ok(expr)
+        Ok(expr)
+    })
 }
 
-fn parse_prefix(parser: &mut AstParser) -> Result<Expr, ParserError> {
+fn parse_prefix(parser: &mut AstParser) -> Result<ExprId, ParserError> {
     match parser.peek_type()? {
         TokenType::Number
-        // TODO | TokenType::Nil
+        | TokenType::Keyword(Keyword::Nil)
         // TODO | TokenType::This
         // TODO | TokenType::True
         // TODO | TokenType::False
@@ -66,12 +72,12 @@ fn parse_prefix(parser: &mut AstParser) -> Result<Expr, ParserError> {
         | TokenType::String => parse_primary(parser),
         TokenType::Bang | TokenType::Minus => parse_unary(parser),
         TokenType::LeftParen => parse_grouping(parser),
-        _ => todo!(),
-        // _ => Err(SyntaxError::Unexpected(parser.peek_token().clone())), TODO
+        TokenType::Keyword(Keyword::Match) => parse_match(parser),
+        _ => Err(parser.expected_expression()?),
     }
 }
 
-fn parse_infix(parser: &mut AstParser, left: Expr) -> Result<Expr, ParserError> {
+fn parse_infix(parser: &mut AstParser, left: ExprId) -> Result<ExprId, ParserError> {
     match parser.peek_type()? {
         TokenType::BangEqual
         | TokenType::EqualEqual
@@ -83,59 +89,248 @@ fn parse_infix(parser: &mut AstParser, left: Expr) -> Result<Expr, ParserError>
         | TokenType::Minus
         | TokenType::Star
         | TokenType::Slash => parse_binary(parser, left),
+        TokenType::Dot => parse_dot(parser, left),
+        TokenType::Pipeline => parse_pipeline(parser, left),
         // TokenType::Or | TokenType::And => parse_logical(parser, left), TODO
-        // TokenType::Equal => parse_assign(parser, left),
-        // TokenType::LeftParen => parse_call(parser, left), TODO
-        _ => todo!(),
-        // _ => Err(SyntaxError::Unexpected(parser.peek_token().clone())),
+        TokenType::Equal => parse_assign(parser, left),
+        TokenType::LeftParen => parse_call(parser, left),
+        TokenType::Question => parse_try(parser, left),
+        _ => Err(parser.expected_expression()?),
+    }
+}
+
+/// Parses `record.field = value`, after the field. Variables are assigned
+/// by `parse_primary`, anything else cannot be.
+fn parse_assign(parser: &mut AstParser, left: ExprId) -> Result<ExprId, ParserError> {
+    let start = parser.position(left);
+    let line = *parser.expect(TokenType::Equal)?.position().line();
+    let value = parse_expr(parser, Precedence::None)?;
+
+    let target = std::mem::replace(parser.node_mut(left), Expr::Literal(LiteralExpr::Nil));
+    match target {
+        Expr::Field(field) => {
+            let set_field = SetFieldExpr::new(field.record, field.field, value);
+            *parser.node_mut(left) = Expr::SetField(set_field);
+            parser.extend(left, &start);
+            parser.measure(left);
+            Ok(left)
+        }
+        _ => Err(ParserError::InvalidAssignment(line)),
     }
 }
 
-fn parse_grouping(parser: &mut AstParser) -> Result<Expr, ParserError> {
+/// Parses a grouping, or a tuple if there is a comma: `()`, `(a,)` and
+/// `(a, b)` are tuples, `(a)` is not.
+fn parse_grouping(parser: &mut AstParser) -> Result<ExprId, ParserError> {
+    let start = parser.start()?;
     parser.expect(TokenType::LeftParen)?;
+    if parser.match_(&TokenType::RightParen)? {
+        return Ok(parser.alloc(Expr::Tuple(TupleExpr::new(vec![])), &start));
+    }
+
     let expr = parse_expr(parser, Precedence::None)?;
+    if parser.match_(&TokenType::RightParen)? {
+        return Ok(parser.alloc(Expr::Grouping(GroupingExpr::new(expr)), &start));
+    }
+
+    let mut elements = vec![expr];
+    while parser.match_(&TokenType::Comma)? && !parser.check(&TokenType::RightParen)? {
+        elements.push(parse_expr(parser, Precedence::None)?);
+    }
+    parser.expect(TokenType::RightParen)?;
+
+    Ok(parser.alloc(Expr::Tuple(TupleExpr::new(elements)), &start))
+}
+
+/// Parses `record.field`, `value.method(arguments)` or `tuple.0`.
+fn parse_dot(parser: &mut AstParser, left: ExprId) -> Result<ExprId, ParserError> {
+    let start = parser.position(left);
+    parser.expect(TokenType::Dot)?;
+    if parser.check(&TokenType::Identifier)? {
+        let name = parser.variable()?;
+        if parser.check(&TokenType::LeftParen)? {
+            let arguments = parse_arguments(parser)?;
+            let call = MethodCallExpr::new(left, name, arguments);
+            return Ok(parser.alloc(Expr::MethodCall(call), &start));
+        }
+        return Ok(parser.alloc(Expr::Field(FieldExpr::new(left, name)), &start));
+    }
+    parse_tuple_index(parser, left)
+}
+
+fn parse_tuple_index(parser: &mut AstParser, left: ExprId) -> Result<ExprId, ParserError> {
+    let start = parser.position(left);
+    let token = parser.expect(TokenType::Number)?;
+
+    // `pair.0.1` is lexed as `pair`, `.` and the number `0.1`.
+    let mut expr = left;
+    for index in token.source().split('.') {
+        let index = index.parse().map_err(|_| {
+            ParserError::InvalidTupleIndex(token.source().to_string(), *token.position().line())
+        })?;
+        expr = parser.alloc(Expr::TupleIndex(TupleIndexExpr::new(expr, index)), &start);
+    }
+    Ok(expr)
+}
+
+fn parse_call(parser: &mut AstParser, callee: ExprId) -> Result<ExprId, ParserError> {
+    let start = parser.position(callee);
+    let arguments = parse_arguments(parser)?;
+    Ok(parser.alloc(Expr::Call(CallExpr::new(callee, arguments)), &start))
+}
+
+fn parse_arguments(parser: &mut AstParser) -> Result<Vec<ExprId>, ParserError> {
+    parser.expect(TokenType::LeftParen)?;
+
+    let mut arguments = vec![];
+    while !parser.check(&TokenType::RightParen)? {
+        arguments.push(parse_expr(parser, Precedence::None)?);
+        if !parser.match_(&TokenType::Comma)? {
+            break;
+        }
+    }
     parser.expect(TokenType::RightParen)?;
 
-    Ok(Expr::Grouping(GroupingExpr::new(Box::new(expr))))
+    Ok(arguments)
+}
+
+fn parse_try(parser: &mut AstParser, expr: ExprId) -> Result<ExprId, ParserError> {
+    let start = parser.position(expr);
+    let position = parser.expect(TokenType::Question)?.position().clone();
+    Ok(parser.alloc(Expr::Try(TryExpr::new(expr, position)), &start))
+}
+
+/// Parses `x |> f`, which becomes `f(x)`, and `x |> f(y)`, which becomes
+/// `f(x, y)`.
+fn parse_pipeline(parser: &mut AstParser, left: ExprId) -> Result<ExprId, ParserError> {
+    let start = parser.position(left);
+    let line = *parser.expect(TokenType::Pipeline)?.position().line();
+    let right = parse_expr(parser, Precedence::Pipeline)?;
+
+    match parser.node_mut(right) {
+        Expr::Call(call) => {
+            call.arguments.insert(0, left);
+            parser.extend(right, &start);
+            Ok(right)
+        }
+        Expr::LetGet(_) | Expr::Grouping(_) | Expr::TupleIndex(_) => {
+            Ok(parser.alloc(Expr::Call(CallExpr::new(right, vec![left])), &start))
+        }
+        _ => Err(ParserError::NotCallable(line)),
+    }
+}
+
+/// Parses `point { x: 1, y }`, after the name of the type. The fields may
+/// be on lines of their own.
+fn parse_record(parser: &mut AstParser, variable: Variable) -> Result<ExprId, ParserError> {
+    let start = variable.position.clone();
+    parser.expect(TokenType::LeftBrace)?;
+
+    let mut fields = vec![];
+    parser.skip_lines()?;
+    while !parser.check(&TokenType::RightBrace)? {
+        let field = parser.variable()?;
+        let value = if parser.match_(&TokenType::Colon)? {
+            parse_expr(parser, Precedence::None)?
+        } else {
+            let variable = parser.name(field.name.as_str(), field.position.clone());
+            parser.alloc(Expr::LetGet(LetGetExpr::new(variable)), &field.position)
+        };
+        fields.push((field, value));
+
+        parser.skip_lines()?;
+        if !parser.match_(&TokenType::Comma)? {
+            break;
+        }
+        parser.skip_lines()?;
+    }
+    parser.expect(TokenType::RightBrace)?;
+
+    Ok(parser.alloc(Expr::Record(RecordExpr::new(variable, fields)), &start))
 }
 
-fn parse_primary(parser: &mut AstParser) -> Result<Expr, ParserError> {
+/// Parses `match x { | pattern => body ... }`, where every arm may be on a
+/// line of its own.
+fn parse_match(parser: &mut AstParser) -> Result<ExprId, ParserError> {
+    let start = parser.start()?;
+    parser.expect(TokenType::Keyword(Keyword::Match))?;
+    let scrutinee = parse_expr(parser, Precedence::None)?;
+    parser.expect(TokenType::LeftBrace)?;
+
+    let mut arms = vec![];
+    while parser.match_(&TokenType::Line)? {}
+    while parser.match_(&TokenType::Pipe)? {
+        let pattern = parser.parse_pattern()?;
+        parser.expect(TokenType::FatArrow)?;
+        let body = parse_expr(parser, Precedence::None)?;
+        arms.push(MatchArm::new(pattern, body));
+
+        while parser.match_(&TokenType::Line)? {}
+    }
+    parser.expect(TokenType::RightBrace)?;
+
+    Ok(parser.alloc(Expr::Match(MatchExpr::new(scrutinee, arms)), &start))
+}
+
+fn parse_primary(parser: &mut AstParser) -> Result<ExprId, ParserError> {
     let token = parser.consume()?;
-    match token.token_type() {
-        TokenType::Keyword(Keyword::Nil) => Ok(Expr::Literal(LiteralExpr::Nil)),
-        TokenType::Number => Ok(Expr::Literal(LiteralExpr::Number(
-            token.source().parse::<f64>().unwrap(),
-        ))),
+    let start = token.position().clone();
+    let expr = match token.token_type() {
+        TokenType::Keyword(Keyword::Nil) => Expr::Literal(LiteralExpr::Nil),
+        TokenType::Number => {
+            Expr::Literal(LiteralExpr::Number(token.source().parse::<f64>().unwrap()))
+        }
+        TokenType::String => {
+            let source = token.source();
+            Expr::Literal(LiteralExpr::String(source[1..source.len() - 1].to_string()))
+        }
         TokenType::Identifier => {
-            let var = Variable::new(token.source().to_string());
+            let var = parser.name(token.source(), token.position().clone());
 
-            Ok(if parser.match_(&TokenType::Equal)? {
+            // `match x {` is not a record, its arms start with `|`.
+            let mut after_brace = 0;
+            while parser.peek_type_after(after_brace) == Some(&TokenType::Line) {
+                after_brace += 1;
+            }
+            let is_record = parser.check(&TokenType::LeftBrace)?
+                && matches!(
+                    parser.peek_type_after(after_brace),
+                    Some(TokenType::Identifier) | Some(TokenType::RightBrace)
+                );
+            if is_record {
+                return parse_record(parser, var);
+            }
+
+            if parser.match_(&TokenType::Equal)? {
                 // let initializer = parser.parse_expression()?; TODO
                 let initializer = parser.expression()?;
 
-                Expr::LetSet(LetSetExpr::new(var, Box::new(initializer)))
+                Expr::LetSet(LetSetExpr::new(var, initializer))
             } else {
                 Expr::LetGet(LetGetExpr::new(var))
-            })
+            }
         }
-        //_ => Err(ParserError::ExpectedPrimary(tc.clone())), TODO
-        _ => todo!(),
-    }
+        token_type => {
+            return Err(ParserError::ExpectedExpression(
+                token_type.clone(),
+                *token.position().line(),
+            ))
+        }
+    };
+    Ok(parser.alloc(expr, &start))
 }
 
-fn parse_binary(parser: &mut AstParser, left: Expr) -> Result<Expr, ParserError> {
+fn parse_binary(parser: &mut AstParser, left: ExprId) -> Result<ExprId, ParserError> {
+    let start = parser.position(left);
     let precedence = Precedence::from(parser.peek_type()?);
     let operator = BinaryOperator::from_token(parser.consume()?.token_type()).unwrap(); // TODO Unwrap
     let right = parse_expr(parser, precedence)?;
-    Ok(Expr::Binary(BinaryExpr::new(
-        operator,
-        Box::new(left),
-        Box::new(right),
-    )))
+    Ok(parser.alloc(Expr::Binary(BinaryExpr::new(operator, left, right)), &start))
 }
 
-fn parse_unary(parser: &mut AstParser) -> Result<Expr, ParserError> {
+fn parse_unary(parser: &mut AstParser) -> Result<ExprId, ParserError> {
+    let start = parser.start()?;
     let operator = UnaryOperator::from_token(parser.consume()?.token_type()).unwrap(); // TODO Unwrap
     let right = parse_expr(parser, Precedence::Unary)?;
-    Ok(Expr::Unary(UnaryExpr::new(operator, Box::new(right))))
+    Ok(parser.alloc(Expr::Unary(UnaryExpr::new(operator, right)), &start))
 }
@@ -0,0 +1,273 @@
+use crate::syntax::cst::*;
+use crate::syntax::cst_parser::CstParser;
+use crate::syntax::error::SyntaxError;
+use crate::syntax::token::{Keyword, TokenType};
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Replaces the text in `range` with `text`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn new(range: Range<usize>, text: String) -> Self {
+        TextEdit { range, text }
+    }
+
+    pub fn apply(&self, source: &str) -> String {
+        let mut result = String::with_capacity(source.len() + self.text.len());
+        result.push_str(&source[..self.range.start]);
+        result.push_str(&self.text);
+        result.push_str(&source[self.range.end..]);
+        result
+    }
+}
+
+/// Applies the edit to the module tree. Only the top-level items touched by
+/// the edit are lexed and parsed again, all other items are shared with the
+/// old tree. Whenever the edit could affect how the rest of the module is
+/// read (an opened block comment, a `def` without `end`, ...) this falls
+/// back to parsing the whole module, so the result always equals a full
+/// reparse.
+pub fn reparse(tree: &SyntaxNode, edit: &TextEdit) -> Result<SyntaxNode, SyntaxError> {
+    let text = edit.apply(&tree.to_string());
+
+    match reparse_items(tree.green(), edit, &text) {
+        Some(module) => Ok(SyntaxNode::new_root(Rc::new(module))),
+        None => CstParser::parse(&text),
+    }
+}
+
+fn reparse_items(module: &GreenNode, edit: &TextEdit, text: &str) -> Option<GreenNode> {
+    let children = module.children();
+
+    let mut starts = Vec::with_capacity(children.len());
+    let mut offset = 0;
+    for child in children {
+        starts.push(offset);
+        offset += child.full_len();
+    }
+    let end_of = |i: usize| starts[i] + children[i].full_len();
+
+    let first = (0..children.len()).find(|&i| end_of(i) >= edit.range.start)?;
+    let last = (first..children.len())
+        .rev()
+        .find(|&i| starts[i] <= edit.range.end)?;
+
+    // Widen by an item on each side, edits can join or split lines.
+    let first = first.saturating_sub(1);
+    let last = (last + 1).min(children.len() - 1);
+
+    let start = starts[first];
+    let end = end_of(last) + edit.text.len() - edit.range.len();
+    let region = &text[start..end];
+    let mut items = match first {
+        0 => CstParser::parse(region).ok()?.green().children().to_vec(),
+        _ => CstParser::parse_items(region).ok()?,
+    };
+
+    // The region has its own end of file, which only stays if the region
+    // reaches the end of the module.
+    let eof = items.pop()?;
+    if last == children.len() - 1 {
+        items.push(eof);
+    } else if eof.full_len() != 0 || !ends_cleanly(items.last()) {
+        return None;
+    }
+
+    let mut module = children[..first].to_vec();
+    module.extend(items);
+    module.extend_from_slice(&children[last + 1..]);
+    Some(GreenNode::new(NodeKind::Module, module))
+}
+
+/// Checks whether the element ends in a way that cannot affect how the
+/// following items are parsed.
+fn ends_cleanly(element: Option<&GreenElement>) -> bool {
+    let node = match element {
+        None => return true,
+        Some(GreenElement::Token(token)) => return token.token_type() == &TokenType::Line,
+        Some(GreenElement::Node(node)) => node,
+    };
+
+    let all = tokens(node);
+    let ends_with_line = all
+        .last()
+        .is_some_and(|t| t.token_type() == &TokenType::Line);
+    // Doc comments attach to the item that follows them.
+    let documents_nothing = all
+        .iter()
+        .all(|t| matches!(t.token_type(), TokenType::DocComment | TokenType::Line));
+    let closes_blocks = node.children().iter().all(|child| match child {
+        GreenElement::Node(block) if block.kind() == NodeKind::Block => tokens(block)
+            .last()
+            .is_some_and(|t| t.token_type() == &TokenType::Keyword(Keyword::End)),
+        _ => true,
+    });
+    // An interface without `end` takes in the rest of the module.
+    let closes_interface = node.kind() != NodeKind::Interface
+        || all
+            .iter()
+            .any(|t| t.token_type() == &TokenType::Keyword(Keyword::End));
+
+    ends_with_line && !documents_nothing && closes_blocks && closes_interface
+}
+
+fn tokens(node: &GreenNode) -> Vec<Rc<GreenToken>> {
+    let mut tokens = vec![];
+    for child in node.children() {
+        match child {
+            GreenElement::Node(node) => tokens.extend(self::tokens(node)),
+            GreenElement::Token(token) => tokens.push(token.clone()),
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"let x = 1
+
+/// Doubles x.
+def double(x)
+    return x * 2
+end
+
+def triple(x)
+    return x * 3
+end
+let y = double(x)
+"#;
+
+    fn assert_reparse(source: &str, edit: TextEdit) -> SyntaxNode {
+        let tree = CstParser::parse(source).unwrap();
+        let text = edit.apply(source);
+
+        let actual = reparse(&tree, &edit);
+        match CstParser::parse(&text) {
+            Ok(expect) => {
+                let actual = actual.unwrap();
+                assert_eq!(text, actual.to_string());
+                assert_eq!(expect.green(), actual.green(), "{:?} on {:?}", edit, source);
+                actual
+            }
+            Err(_) => {
+                assert!(actual.is_err(), "{:?} on {:?}", edit, source);
+                tree
+            }
+        }
+    }
+
+    fn item(tree: &SyntaxNode, text: &str) -> Rc<GreenNode> {
+        tree.child_nodes()
+            .into_iter()
+            .find(|node| node.to_string().contains(text))
+            .map(|node| node.green().clone())
+            .unwrap()
+    }
+
+    #[test]
+    fn reuse_untouched_items() {
+        let tree = CstParser::parse(SOURCE).unwrap();
+        let start = SOURCE.rfind("double").unwrap();
+        let edit = TextEdit::new(start..start + 6, "triple".to_string());
+
+        let new_tree = reparse(&tree, &edit).unwrap();
+        assert_eq!(assert_reparse(SOURCE, edit).green(), new_tree.green());
+
+        assert!(Rc::ptr_eq(&item(&tree, "let x"), &item(&new_tree, "let x")));
+        assert!(Rc::ptr_eq(
+            &item(&tree, "def double"),
+            &item(&new_tree, "def double")
+        ));
+        assert!(!Rc::ptr_eq(
+            &item(&tree, "def triple"),
+            &item(&new_tree, "def triple")
+        ));
+    }
+
+    #[test]
+    fn insert_item() {
+        let start = SOURCE.find("def triple").unwrap();
+        assert_reparse(
+            SOURCE,
+            TextEdit::new(start..start, "let z = 3\n".to_string()),
+        );
+    }
+
+    #[test]
+    fn join_lines() {
+        let start = SOURCE.find("\nlet y").unwrap();
+        assert_reparse(SOURCE, TextEdit::new(start..start + 1, " ".to_string()));
+    }
+
+    #[test]
+    fn open_block_comment() {
+        let start = SOURCE.find("def double").unwrap();
+        assert_reparse(SOURCE, TextEdit::new(start..start, "/*".to_string()));
+
+        let source = "/* */ let x = 1\nlet y = 2\nlet z = 3\nlet w = 4\n";
+        let start = source.find("*/").unwrap();
+        assert_reparse(source, TextEdit::new(start..start + 2, String::new()));
+    }
+
+    #[test]
+    fn unclosed_def() {
+        let start = SOURCE.find("let x").unwrap();
+        assert_reparse(SOURCE, TextEdit::new(start..start, "def f()\n".to_string()));
+    }
+
+    #[test]
+    fn unclosed_interface() {
+        let start = SOURCE.find("def triple").unwrap();
+        assert_reparse(
+            SOURCE,
+            TextEdit::new(start..start, "interface Show\n".to_string()),
+        );
+    }
+
+    #[test]
+    fn dangling_doc_comment() {
+        let start = SOURCE.find("let x").unwrap();
+        assert_reparse(
+            SOURCE,
+            TextEdit::new(start..start, "/// Docs.\n".to_string()),
+        );
+    }
+
+    /// Deletes and inserts characters at every position and compares the
+    /// result with a full reparse.
+    #[test]
+    fn equals_full_reparse() {
+        let insertions = [
+            "\n",
+            "x",
+            "(",
+            ")",
+            "\"",
+            "/*",
+            "*/",
+            "///",
+            "end\n",
+            "def f()\n",
+        ];
+
+        let offsets = SOURCE
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(SOURCE.len()));
+        for start in offsets {
+            let end = (start + 1).min(SOURCE.len());
+            assert_reparse(SOURCE, TextEdit::new(start..end, String::new()));
+
+            for insertion in &insertions {
+                assert_reparse(SOURCE, TextEdit::new(start..start, insertion.to_string()));
+            }
+        }
+    }
+}
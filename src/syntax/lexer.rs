@@ -1,5 +1,5 @@
 use crate::syntax::error::SyntaxError;
-use crate::syntax::token::{Keyword, Position, Token, TokenType};
+use crate::syntax::token::{FileId, Keyword, Position, Token, TokenType};
 use std::iter::Peekable;
 use std::str::{CharIndices, FromStr};
 
@@ -9,19 +9,31 @@ pub struct Lexer<'a> {
     source: &'a str,
     chars: Peekable<CharIndices<'a>>,
     line: usize,
+    /// How many parentheses and brackets are open. Line breaks inside of
+    /// them do not end the line, so long argument lists can be wrapped.
+    nesting: usize,
+    file: FileId,
 }
 
 impl<'a> Lexer<'a> {
-    fn new(source: &'a str) -> Self {
+    fn new(source: &'a str, file: FileId) -> Self {
         Lexer {
             source,
             chars: source.char_indices().peekable(),
             line: 1,
+            nesting: 0,
+            file,
         }
     }
 
-    pub fn tokenize(source: &str) -> Result<Vec<Token>> {
-        let mut lexer = Lexer::new(source);
+    pub fn tokenize(source: &str) -> Result<Vec<Token<'_>>> {
+        Lexer::tokenize_file(source, FileId::default())
+    }
+
+    /// Tokenizes the source of a file, whose id every position is tagged
+    /// with.
+    pub fn tokenize_file(source: &str, file: FileId) -> Result<Vec<Token<'_>>> {
+        let mut lexer = Lexer::new(source, file);
 
         let mut tokens = vec![];
         loop {
@@ -44,30 +56,68 @@ impl<'a> Lexer<'a> {
 
         let (start, c) = self.advance().ok_or(SyntaxError::UnexpectedEOF)?;
 
-        if c.is_alphabetic() {
+        if c.is_alphabetic() || c == '_' {
             return self.identifier(start);
         }
-        if c.is_digit(10) {
+        if c.is_ascii_digit() {
             return self.number(start);
         }
 
         let token_type = match c {
-            '(' => TokenType::LeftParen,
-            ')' => TokenType::RightParen,
-            '[' => TokenType::LeftBracket,
-            ']' => TokenType::RightBracket,
+            '(' => {
+                self.nesting += 1;
+                TokenType::LeftParen
+            }
+            ')' => {
+                self.nesting = self.nesting.saturating_sub(1);
+                TokenType::RightParen
+            }
+            '[' => {
+                self.nesting += 1;
+                TokenType::LeftBracket
+            }
+            ']' => {
+                self.nesting = self.nesting.saturating_sub(1);
+                TokenType::RightBracket
+            }
+            '\n' | '\r' if self.nesting > 0 => return self.read_token(),
             '{' => TokenType::LeftBrace,
             '}' => TokenType::RightBrace,
             ';' | '\n' | '\r' => {
-                self.skip_lines();
-                TokenType::Line
+                let token = self.make_token(TokenType::Line, start);
+                self.skip_lines()?;
+                return Ok(token);
             }
             ',' => TokenType::Comma,
-            '.' => TokenType::Dot,
+            ':' => TokenType::Colon,
+            '.' => {
+                if self.peek() == Some('.') {
+                    self.advance();
+                    TokenType::DotDot
+                } else {
+                    TokenType::Dot
+                }
+            }
             '+' => TokenType::Plus,
             '-' => TokenType::Minus,
             '*' => TokenType::Star,
-            '/' => TokenType::Slash,
+            '?' => TokenType::Question,
+            '|' => {
+                if self.peek() == Some('>') {
+                    self.advance();
+                    TokenType::Pipeline
+                } else {
+                    TokenType::Pipe
+                }
+            }
+            '/' => match self.peek() {
+                Some('/') => return self.line_comment(start),
+                Some('*') => {
+                    self.block_comment()?;
+                    return self.read_token();
+                }
+                _ => TokenType::Slash,
+            },
             '!' => {
                 if self.check('=')? {
                     self.advance();
@@ -96,19 +146,22 @@ impl<'a> Lexer<'a> {
                 if self.check('=')? {
                     self.advance();
                     TokenType::EqualEqual
+                } else if self.check('>')? {
+                    self.advance();
+                    TokenType::FatArrow
                 } else {
                     TokenType::Equal
                 }
             }
             '"' => self.string()?,
-            _ => todo!(),
+            _ => return Err(SyntaxError::UnexpectedChar(c, self.line)),
         };
 
         Ok(self.make_token(token_type, start))
     }
 
     fn identifier(&mut self, start: usize) -> Result<Token<'a>> {
-        self.advance_while(|&c| c.is_alphanumeric());
+        self.advance_while(|&c| c.is_alphanumeric() || c == '_');
 
         let source = self.token_contents(start);
 
@@ -120,17 +173,17 @@ impl<'a> Lexer<'a> {
     }
 
     fn number(&mut self, start: usize) -> Result<Token<'a>> {
-        self.advance_while(|c| c.is_digit(10));
+        self.advance_while(|c| c.is_ascii_digit());
 
         // Look for a fractional part
         if let Some(peek) = self.peek() {
             if peek == '.' {
                 if let Some(next) = self.peek_next() {
-                    if next.is_digit(10) {
+                    if next.is_ascii_digit() {
                         // Consume the '.'.
                         self.advance();
 
-                        self.advance_while(|c| c.is_digit(10));
+                        self.advance_while(|c| c.is_ascii_digit());
                     }
                 }
             }
@@ -140,9 +193,10 @@ impl<'a> Lexer<'a> {
     }
 
     fn string(&mut self) -> Result<TokenType> {
+        let line = self.line;
         self.advance_while(|&c| c != '"');
         if self.is_at_end() {
-            return Err(SyntaxError::UnterminatedString);
+            return Err(SyntaxError::UnterminatedString(line));
         }
 
         // Consume the '"'.
@@ -151,6 +205,42 @@ impl<'a> Lexer<'a> {
         Ok(TokenType::String)
     }
 
+    fn line_comment(&mut self, start: usize) -> Result<Token<'a>> {
+        // Consume the second '/'.
+        self.advance();
+
+        let is_doc = self.is_doc_comment(0);
+        self.advance_while(|&c| c != '\n' && c != '\r');
+
+        if is_doc {
+            Ok(self.make_token(TokenType::DocComment, start))
+        } else {
+            self.read_token()
+        }
+    }
+
+    fn block_comment(&mut self) -> Result<()> {
+        // Consume the '*'.
+        self.advance();
+
+        let mut depth = 1;
+        while depth > 0 {
+            let (_, c) = self
+                .advance()
+                .ok_or(SyntaxError::UnterminatedComment(self.line))?;
+
+            if c == '/' && self.peek() == Some('*') {
+                self.advance();
+                depth += 1;
+            } else if c == '*' && self.peek() == Some('/') {
+                self.advance();
+                depth -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
     fn eof(&mut self) -> Result<Token<'a>> {
         Ok(self.make_token(TokenType::EOF, self.source.len()))
     }
@@ -160,7 +250,7 @@ impl<'a> Lexer<'a> {
         Token::new(
             token_type,
             source,
-            Position::new(start, start + source.len(), self.line),
+            Position::new(start, start + source.len(), self.line).in_file(self.file),
         )
     }
 
@@ -170,15 +260,37 @@ impl<'a> Lexer<'a> {
             .peek()
             .map(|&(i, _)| i)
             .unwrap_or(self.source.len());
-        &self.source[start..end].trim_end()
+        self.source[start..end].trim_end()
     }
 
     fn skip_whitespace(&mut self) {
         self.advance_while(|&c| c == ' ' || c == '\t');
     }
 
-    fn skip_lines(&mut self) {
-        self.advance_while(|&c| c == ';' || c == '\n' || c == '\r');
+    /// Skips everything that cannot end up in a token after a line break:
+    /// further line breaks, indentation and ordinary comments. Doc comments
+    /// are left alone as they are significant to the parser.
+    fn skip_lines(&mut self) -> Result<()> {
+        loop {
+            self.advance_while(|&c| c.is_whitespace() || c == ';');
+
+            if self.peek() != Some('/') {
+                return Ok(());
+            }
+            match self.peek_next() {
+                Some('/') => {
+                    if self.is_doc_comment(2) {
+                        return Ok(());
+                    }
+                    self.advance_while(|&c| c != '\n' && c != '\r');
+                }
+                Some('*') => {
+                    self.advance();
+                    self.block_comment()?;
+                }
+                _ => return Ok(()),
+            }
+        }
     }
 
     fn advance_while<F>(&mut self, f: F) -> usize
@@ -212,8 +324,18 @@ impl<'a> Lexer<'a> {
             .ok_or(SyntaxError::UnexpectedEOF)
     }
 
+    /// Checks whether the third '/' of a comment sits `offset` characters
+    /// ahead. "////" is an ordinary comment, just like in Rust.
+    fn is_doc_comment(&self, offset: usize) -> bool {
+        self.peek_nth(offset) == Some('/') && self.peek_nth(offset + 1) != Some('/')
+    }
+
     fn peek_next(&mut self) -> Option<char> {
-        self.chars.nth(1).map(|(_, c)| c)
+        self.peek_nth(1)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.chars.clone().nth(n).map(|(_, c)| c)
     }
 
     fn peek(&mut self) -> Option<char> {
@@ -352,4 +474,75 @@ mod tests {
         let actual = Lexer::tokenize(source).unwrap();
         assert_eq!(expect, actual);
     }
+
+    #[test]
+    fn tokenize_lines_in_parens() {
+        let expect = vec![
+            Token::new(TokenType::LeftParen, "(", Position::new(0, 1, 1)),
+            Token::new(TokenType::Identifier, "x", Position::new(18, 19, 2)),
+            Token::new(TokenType::Comma, ",", Position::new(19, 20, 2)),
+            Token::new(TokenType::Identifier, "y", Position::new(37, 38, 3)),
+            Token::new(TokenType::RightParen, ")", Position::new(51, 52, 4)),
+            Token::new(TokenType::Line, "", Position::new(52, 52, 5)),
+            Token::new(TokenType::EOF, "", Position::new(54, 54, 5)),
+        ];
+
+        let source = "(\n                x,\n                y\n            )\n ";
+
+        let actual = Lexer::tokenize(source).unwrap();
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn tokenize_comments() {
+        let expect = vec![
+            Token::new(TokenType::Number, "1", Position::new(0, 1, 1)),
+            Token::new(TokenType::Line, "", Position::new(8, 8, 2)),
+            Token::new(TokenType::Number, "2", Position::new(103, 104, 4)),
+            Token::new(TokenType::Slash, "/", Position::new(105, 106, 4)),
+            Token::new(TokenType::Number, "3", Position::new(107, 108, 4)),
+            Token::new(TokenType::EOF, "", Position::new(108, 108, 4)),
+        ];
+
+        let source = r#"1 // one
+            // Skipped entirely.
+            /* outer /* nested */ still outer */
+            2 / 3"#;
+
+        let actual = Lexer::tokenize(source).unwrap();
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn tokenize_doc_comments() {
+        let expect = vec![
+            Token::new(
+                TokenType::DocComment,
+                "/// Doubles x.",
+                Position::new(0, 14, 1),
+            ),
+            Token::new(TokenType::Line, "", Position::new(14, 14, 2)),
+            Token::new(
+                TokenType::Keyword(Keyword::Def),
+                "def",
+                Position::new(54, 57, 3),
+            ),
+            Token::new(TokenType::EOF, "", Position::new(57, 57, 3)),
+        ];
+
+        let source = r#"/// Doubles x.
+            //// Not docs.
+            def"#;
+
+        let actual = Lexer::tokenize(source).unwrap();
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn tokenize_unterminated_block_comment() {
+        let source = r#"/* /* */"#;
+
+        let actual = Lexer::tokenize(source);
+        assert!(matches!(actual, Err(SyntaxError::UnterminatedComment(1))));
+    }
 }
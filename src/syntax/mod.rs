@@ -1,6 +1,11 @@
-mod ast;
-mod error;
+pub mod ast;
+pub mod cst;
+pub mod cst_parser;
+pub mod error;
 mod expr_parser;
+pub mod incremental;
 pub mod lexer;
 pub mod parser;
+pub mod print;
+pub mod symbol;
 pub mod token;
@@ -1,79 +1,364 @@
 use crate::syntax::ast::*;
 use crate::syntax::error::ParserError;
-use crate::syntax::token::{Keyword, Token, TokenType};
+use crate::syntax::symbol::Symbol;
+use crate::syntax::token::{Keyword, Position, Token, TokenType};
 
 type Result<T> = std::result::Result<T, ParserError>;
 
+/// How deeply expressions, patterns and types may nest. The passes after
+/// the parser recurse over the tree, so this keeps them from running out
+/// of stack.
+pub const MAX_DEPTH: usize = 200;
+
 pub struct AstParser<'a> {
     tokens: &'a mut Vec<Token<'a>>,
+    module: ModuleAst,
+    /// The end of the last token consumed, not counting line ends.
+    end: usize,
+    /// How many expressions, patterns and types are being parsed inside
+    /// each other.
+    depth: usize,
+    /// The height of the tree under each expression, which can exceed the
+    /// depth of the parser: `1 + 2 + 3` is parsed in a loop.
+    heights: SideTable<usize>,
+    /// The line of the first expression nested too deeply.
+    too_deep: Option<usize>,
 }
 
 impl<'a> AstParser<'a> {
     fn new(tokens: &'a mut Vec<Token<'a>>) -> Self {
         tokens.reverse();
-        AstParser { tokens }
+        AstParser {
+            tokens,
+            module: ModuleAst::default(),
+            end: 0,
+            depth: 0,
+            heights: SideTable::default(),
+            too_deep: None,
+        }
     }
 
     pub fn parse(tokens: &'a mut Vec<Token<'a>>) -> Result<ModuleAst> {
         let mut parser = AstParser::new(tokens);
 
-        let mut exprs = vec![];
-
         while !(parser.is_eof()?) {
             // TODO Hacky fix.
             while parser.peek_type()? == &TokenType::Line {
                 parser.consume()?;
             }
+            if parser.skip_dangling_doc_comment()? {
+                continue;
+            }
 
-            exprs.push(parser.parse_top_level_expr()?);
+            let expr = parser.parse_top_level_expr()?;
+            if let Some(line) = parser.too_deep {
+                return Err(ParserError::TooDeep(line));
+            }
+            parser.module.exprs.push(expr);
         }
 
-        Ok(ModuleAst::new(exprs))
+        Ok(parser.module)
     }
 
-    fn parse_top_level_expr(&mut self) -> Result<Expr> {
-        match self.peek_type()? {
-            TokenType::Keyword(Keyword::Let) => self.declare_let(),
-            TokenType::Keyword(Keyword::Def) => self.declare_def(),
-            TokenType::Keyword(Keyword::Return) => self.parse_return(),
-            _ => self.parse_expression_statement(),
+    /// Adds an expression to the module, which spans from `start` to the
+    /// last token consumed.
+    pub fn alloc(&mut self, expr: Expr, start: &Position) -> ExprId {
+        let position = self.span(start);
+        let id = self.module.alloc(expr, position);
+        self.measure(id);
+        id
+    }
+
+    /// Records the height of an expression, once its children are parsed.
+    pub fn measure(&mut self, id: ExprId) {
+        let height = 1 + self
+            .module
+            .children(id)
+            .into_iter()
+            .map(|child| self.heights.get(child).copied().unwrap_or(0))
+            .max()
+            .unwrap_or(0);
+        if height > MAX_DEPTH && self.too_deep.is_none() {
+            self.too_deep = Some(*self.module.position(id).line());
+        }
+        self.heights.insert(id, height);
+    }
+
+    /// Parses something which can contain more of itself, like a grouping
+    /// or a tuple pattern, failing once that nests too deeply.
+    pub fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth == MAX_DEPTH {
+            return Err(ParserError::TooDeep(*self.peek()?.position().line()));
         }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Makes an expression start at `start`, after more was parsed in front
+    /// of it.
+    pub fn extend(&mut self, id: ExprId, start: &Position) {
+        let position = self.span(start);
+        self.module.set_position(id, position);
+    }
+
+    fn span(&self, start: &Position) -> Position {
+        let end = self.end.max(*start.start());
+        Position::new(*start.start(), end, *start.line()).in_file(start.file())
+    }
+
+    /// The position of the next token, where an expression parsed from
+    /// here starts.
+    pub fn start(&self) -> Result<Position> {
+        Ok(self.peek()?.position().clone())
+    }
+
+    pub fn position(&self, id: ExprId) -> Position {
+        self.module.position(id).clone()
+    }
+
+    pub fn node_mut(&mut self, id: ExprId) -> &mut Expr {
+        &mut self.module[id]
+    }
+
+    fn parse_top_level_expr(&mut self) -> Result<ExprId> {
+        self.nested(|parser| match parser.peek_type()? {
+            TokenType::Keyword(Keyword::Let) => parser.declare_let(),
+            TokenType::Keyword(Keyword::Def) => parser.declare_def(),
+            TokenType::Keyword(Keyword::Return) => parser.parse_return(),
+            TokenType::Keyword(Keyword::Type) => parser.declare_type(),
+            TokenType::Keyword(Keyword::Impl) => parser.declare_impl(),
+            TokenType::Keyword(Keyword::Interface) => parser.declare_interface(),
+            TokenType::DocComment => parser.parse_documented(),
+            _ => parser.parse_expression_statement(),
+        })
     }
 
-    fn declare_let(&mut self) -> Result<Expr> {
+    /// Parses a declaration and the `///` lines in front of it. In front of
+    /// anything else they are ordinary comments.
+    fn parse_documented(&mut self) -> Result<ExprId> {
+        let doc = self.doc_comment()?;
+
+        let id = match self.peek_type()? {
+            TokenType::Keyword(Keyword::Let) => self.declare_let()?,
+            TokenType::Keyword(Keyword::Def) => self.declare_def()?,
+            TokenType::Keyword(Keyword::Type) => self.declare_type()?,
+            TokenType::Keyword(Keyword::Interface) => self.declare_interface()?,
+            _ => return self.parse_top_level_expr(),
+        };
+        match &mut self.module[id] {
+            Expr::LetAssign(let_assign) => let_assign.doc = doc,
+            Expr::Function(fun) => fun.doc = doc,
+            Expr::Type(ty) => ty.doc = doc,
+            Expr::Interface(interface) => interface.doc = doc,
+            _ => unreachable!(),
+        }
+        Ok(id)
+    }
+
+    /// Skips `///` lines at the end of the source or of a block, which are
+    /// ordinary comments as nothing follows them. Returns whether there
+    /// were any.
+    fn skip_dangling_doc_comment(&mut self) -> Result<bool> {
+        let next = self
+            .tokens
+            .iter()
+            .rev()
+            .map(Token::token_type)
+            .find(|ty| !matches!(ty, TokenType::DocComment | TokenType::Line));
+        let dangling = self.check(&TokenType::DocComment)?
+            && matches!(
+                next,
+                Some(TokenType::EOF | TokenType::Keyword(Keyword::End))
+            );
+        if dangling {
+            while self.match_(&TokenType::DocComment)? || self.match_(&TokenType::Line)? {}
+        }
+        Ok(dangling)
+    }
+
+    /// Parses the `///` lines in front of a declaration, if there are any.
+    fn doc_comment(&mut self) -> Result<Option<String>> {
+        let mut lines = vec![];
+        while self.check(&TokenType::DocComment)? {
+            let comment = self.consume()?;
+            let text = comment.source().trim_start_matches("///");
+            lines.push(text.strip_prefix(' ').unwrap_or(text).to_string());
+
+            self.match_(&TokenType::Line)?;
+        }
+        Ok(match lines.is_empty() {
+            true => None,
+            false => Some(lines.join("\n")),
+        })
+    }
+
+    fn declare_let(&mut self) -> Result<ExprId> {
+        let start = self.start()?;
         // Consume "let".
         self.expect(TokenType::Keyword(Keyword::Let))?;
+        let mutable = self.match_(&TokenType::Keyword(Keyword::Mut))?;
 
-        let ident = self.expect(TokenType::Identifier)?;
-        let var = Variable::new(ident.source().to_string());
+        let pattern = self.parse_pattern()?;
 
+        let mut otherwise = None;
         let initializer = if self.match_(&TokenType::Equal)? {
-            self.parse_expression_statement()?
+            let initializer = self.expression()?;
+            if self.match_(&TokenType::Keyword(Keyword::Else))? {
+                // Only `return` can leave when the pattern does not match.
+                otherwise = Some(self.parse_return()?);
+            } else {
+                self.expect(TokenType::Line)?;
+            }
+            initializer
         } else {
+            // Without an initializer the variable is `nil`, which takes up
+            // no source.
+            let at = self.start()?;
+            let nil = self.alloc(Expr::Literal(LiteralExpr::Nil), &at);
             self.expect(TokenType::Line)?;
-            Expr::Literal(LiteralExpr::Nil)
+            nil
         };
 
-        Ok(Expr::LetAssign(LetAssignExpr::new(
-            var,
-            Box::new(initializer),
-        )))
+        let let_assign = LetAssignExpr {
+            mutable,
+            otherwise,
+            ..LetAssignExpr::new(pattern, initializer)
+        };
+        Ok(self.alloc(Expr::LetAssign(let_assign), &start))
     }
 
-    fn declare_def(&mut self) -> Result<Expr> {
-        // Consume "def".
-        self.expect(TokenType::Keyword(Keyword::Def))?;
+    fn declare_type(&mut self) -> Result<ExprId> {
+        let start = self.start()?;
+        // Consume "type".
+        self.expect(TokenType::Keyword(Keyword::Type))?;
+        let variable = self.variable()?;
+        self.expect(TokenType::Equal)?;
+        self.skip_lines()?;
 
-        let ident = self.expect(TokenType::Identifier)?;
-        let var = Variable::new(ident.source().to_string());
+        let definition = if self.match_(&TokenType::LeftBrace)? {
+            let fields = self.variable_list(TokenType::RightBrace)?;
+            self.expect(TokenType::Line)?;
+            TypeDefinition::Record(fields)
+        } else {
+            // Every case may be on a line of its own.
+            let mut constructors = vec![];
+            loop {
+                self.expect(TokenType::Pipe)?;
+                let start = self.start()?;
+                let variable = self.variable()?;
+                let fields = if self.match_(&TokenType::LeftParen)? {
+                    self.variable_list(TokenType::RightParen)?
+                } else {
+                    vec![]
+                };
+                let position = self.span(&start);
+                constructors.push(Constructor::new(variable, fields, position));
 
+                if !self.check(&TokenType::Pipe)? {
+                    self.expect(TokenType::Line)?;
+                    self.skip_lines()?;
+                    if !self.check(&TokenType::Pipe)? {
+                        break;
+                    }
+                }
+            }
+            TypeDefinition::Variant(constructors)
+        };
+
+        let ty = TypeExpr::new(variable, definition);
+        Ok(self.alloc(Expr::Type(ty), &start))
+    }
+
+    fn declare_impl(&mut self) -> Result<ExprId> {
+        let start = self.start()?;
+        // Consume "impl".
+        self.expect(TokenType::Keyword(Keyword::Impl))?;
+        let mut variable = self.variable()?;
+        let mut interface = None;
+        if self.match_(&TokenType::Keyword(Keyword::For))? {
+            interface = Some(variable);
+            variable = self.variable()?;
+        }
+        self.expect(TokenType::Line)?;
+
+        let mut functions = vec![];
+        loop {
+            self.skip_lines()?;
+            let line = *self.peek()?.position().line();
+            let doc = self.doc_comment()?;
+            match self.peek_type()? {
+                TokenType::Keyword(Keyword::End) => break,
+                TokenType::Keyword(Keyword::Def) => {
+                    let start = self.start()?;
+                    let fun = FunctionExpr {
+                        doc,
+                        ..self.function()?
+                    };
+                    functions.push(self.alloc(Expr::Function(fun), &start));
+                }
+                _ => return Err(ParserError::ExpectedFunction(line)),
+            }
+        }
+
+        self.expect(TokenType::Keyword(Keyword::End))?;
+        self.expect(TokenType::Line)?;
+
+        let imp = ImplExpr {
+            interface,
+            ..ImplExpr::new(variable, functions)
+        };
+        Ok(self.alloc(Expr::Impl(imp), &start))
+    }
+
+    fn declare_interface(&mut self) -> Result<ExprId> {
+        let start = self.start()?;
+        // Consume "interface".
+        self.expect(TokenType::Keyword(Keyword::Interface))?;
+        let variable = self.variable()?;
+        self.expect(TokenType::Line)?;
+
+        let mut functions = vec![];
+        loop {
+            self.skip_lines()?;
+            let line = *self.peek()?.position().line();
+            match self.peek_type()? {
+                TokenType::Keyword(Keyword::End) => break,
+                TokenType::Keyword(Keyword::Def) => {}
+                _ => return Err(ParserError::ExpectedFunction(line)),
+            }
+
+            let start = self.start()?;
+            // Consume "def".
+            self.consume()?;
+            let name = self.variable()?;
+            let (parameters, annotations) = self.parameters()?;
+            let position = self.span(&start);
+            self.expect(TokenType::Line)?;
+            functions.push(Signature::new(name, parameters, annotations, position));
+        }
+
+        self.expect(TokenType::Keyword(Keyword::End))?;
+        self.expect(TokenType::Line)?;
+
+        let interface = InterfaceExpr::new(variable, functions);
+        Ok(self.alloc(Expr::Interface(interface), &start))
+    }
+
+    /// Parses a parenthesized parameter list, each parameter may be
+    /// followed by a type annotation.
+    fn parameters(&mut self) -> Result<(Vec<Variable>, Vec<Option<TypeAnnotation>>)> {
         self.expect(TokenType::LeftParen)?;
 
-        let mut params = vec![];
+        let mut parameters = vec![];
+        let mut annotations = vec![];
         while !self.check(&TokenType::RightParen)? && !self.check(&TokenType::EOF)? {
-            let param = self.expect(TokenType::Identifier)?;
-
-            params.push(Variable::new(param.source().to_string())); // TODO clone?
+            parameters.push(self.variable()?);
+            annotations.push(match self.match_(&TokenType::Colon)? {
+                true => Some(self.annotation()?),
+                false => None,
+            });
 
             if !self.match_(&TokenType::Comma)? {
                 break;
@@ -81,18 +366,227 @@ impl<'a> AstParser<'a> {
         }
 
         self.expect(TokenType::RightParen)?;
+        Ok((parameters, annotations))
+    }
 
-        let body = match self.parse_block()? {
-            // TODO
-            Expr::Block(b) => b,
-            _ => unreachable!(),
+    fn annotation(&mut self) -> Result<TypeAnnotation> {
+        self.nested(|parser| {
+            if !parser.match_(&TokenType::LeftParen)? {
+                let variable = parser.variable()?;
+                if !parser.match_(&TokenType::LessThan)? {
+                    return Ok(TypeAnnotation::Name(variable));
+                }
+
+                let mut arguments = vec![parser.annotation()?];
+                while parser.match_(&TokenType::Comma)? {
+                    arguments.push(parser.annotation()?);
+                }
+                parser.expect(TokenType::GreaterThan)?;
+                return Ok(TypeAnnotation::Generic(variable, arguments));
+            }
+
+            let mut elements = vec![];
+            let mut trailing_comma = false;
+            while !parser.check(&TokenType::RightParen)? {
+                elements.push(parser.annotation()?);
+                trailing_comma = parser.match_(&TokenType::Comma)?;
+                if !trailing_comma {
+                    break;
+                }
+            }
+            parser.expect(TokenType::RightParen)?;
+
+            // Like patterns, a parenthesized type is not a tuple without a comma.
+            Ok(if elements.len() == 1 && !trailing_comma {
+                elements.pop().unwrap()
+            } else {
+                TypeAnnotation::Tuple(elements)
+            })
+        })
+    }
+
+    /// Parses `where a: Show, b: Eq`, if the function has such a clause.
+    fn constraints(&mut self) -> Result<Vec<Constraint>> {
+        let mut constraints = vec![];
+        if self.match_(&TokenType::Keyword(Keyword::Where))? {
+            loop {
+                let parameter = self.variable()?;
+                self.expect(TokenType::Colon)?;
+                let interface = self.variable()?;
+                constraints.push(Constraint::new(parameter, interface));
+
+                if !self.match_(&TokenType::Comma)? {
+                    break;
+                }
+            }
+        }
+        Ok(constraints)
+    }
+
+    /// Parses comma separated names up to `close`, which may span lines.
+    fn variable_list(&mut self, close: TokenType) -> Result<Vec<Variable>> {
+        let mut variables = vec![];
+        self.skip_lines()?;
+        while !self.check(&close)? {
+            variables.push(self.variable()?);
+            self.skip_lines()?;
+            if !self.match_(&TokenType::Comma)? {
+                break;
+            }
+            self.skip_lines()?;
+        }
+        self.expect(close)?;
+        Ok(variables)
+    }
+
+    pub fn variable(&mut self) -> Result<Variable> {
+        let ident = self.expect(TokenType::Identifier)?;
+        Ok(self.name(ident.source(), ident.position().clone()))
+    }
+
+    /// A name in the module, found at `position`.
+    pub fn name(&mut self, name: &str, position: Position) -> Variable {
+        self.module.variable(Symbol::intern(name), position)
+    }
+
+    pub fn skip_lines(&mut self) -> Result<()> {
+        while self.match_(&TokenType::Line)? {}
+        Ok(())
+    }
+
+    pub fn parse_pattern(&mut self) -> Result<Pattern> {
+        self.nested(|parser| {
+            let token = parser.consume()?;
+            let position = token.position().clone();
+
+            Ok(match token.token_type() {
+                TokenType::Identifier if token.source() == "_" => Pattern::Wildcard,
+                TokenType::Identifier if token.source().starts_with(char::is_uppercase) => {
+                    let constructor = parser.name(token.source(), position);
+                    let fields = if parser.match_(&TokenType::LeftParen)? {
+                        parser.parse_pattern_list(TokenType::RightParen)?.0
+                    } else {
+                        vec![]
+                    };
+                    Pattern::Variant(VariantPattern::new(constructor, fields))
+                }
+                TokenType::Identifier => Pattern::Variable(parser.name(token.source(), position)),
+                TokenType::Number => {
+                    let number = token.source().parse().unwrap();
+                    Pattern::Literal(LiteralPattern::new(LiteralExpr::Number(number), position))
+                }
+                TokenType::String => {
+                    let source = token.source();
+                    let string = source[1..source.len() - 1].to_string();
+                    Pattern::Literal(LiteralPattern::new(LiteralExpr::String(string), position))
+                }
+                TokenType::Keyword(Keyword::Nil) => {
+                    Pattern::Literal(LiteralPattern::new(LiteralExpr::Nil, position))
+                }
+                TokenType::LeftParen => {
+                    let (mut elements, trailing_comma) =
+                        parser.parse_pattern_list(TokenType::RightParen)?;
+                    // A parenthesized pattern is not a tuple without a comma.
+                    if elements.len() == 1 && !trailing_comma {
+                        elements.pop().unwrap()
+                    } else {
+                        Pattern::Tuple(elements)
+                    }
+                }
+                TokenType::LeftBrace => {
+                    let mut fields = vec![];
+                    while !parser.check(&TokenType::RightBrace)? {
+                        let field = parser.expect(TokenType::Identifier)?;
+                        let field = parser.name(field.source(), field.position().clone());
+                        fields.push(field);
+                        if !parser.match_(&TokenType::Comma)? {
+                            break;
+                        }
+                    }
+                    parser.expect(TokenType::RightBrace)?;
+                    Pattern::Record(fields)
+                }
+                TokenType::LeftBracket => {
+                    let mut elements = vec![];
+                    let mut rest = None;
+                    while !parser.check(&TokenType::RightBracket)? {
+                        if rest.is_some() {
+                            return Err(ParserError::MisplacedRest(
+                                *parser.peek()?.position().line(),
+                            ));
+                        }
+                        if parser.match_(&TokenType::DotDot)? {
+                            let pattern = match parser.peek_type()? {
+                                TokenType::Comma | TokenType::RightBracket => Pattern::Wildcard,
+                                _ => parser.parse_pattern()?,
+                            };
+                            rest = Some(Box::new(pattern));
+                        } else {
+                            elements.push(parser.parse_pattern()?);
+                        }
+                        if !parser.match_(&TokenType::Comma)? {
+                            break;
+                        }
+                    }
+                    parser.expect(TokenType::RightBracket)?;
+                    Pattern::List(ListPattern::new(elements, rest, position))
+                }
+                token_type => {
+                    return Err(ParserError::ExpectedPattern(
+                        token_type.clone(),
+                        *position.line(),
+                    ))
+                }
+            })
+        })
+    }
+
+    /// Parses comma separated patterns up to `close`, returning whether
+    /// there was a comma after the last one.
+    fn parse_pattern_list(&mut self, close: TokenType) -> Result<(Vec<Pattern>, bool)> {
+        let mut patterns = vec![];
+        let mut trailing_comma = false;
+        while !self.check(&close)? {
+            patterns.push(self.parse_pattern()?);
+            trailing_comma = self.match_(&TokenType::Comma)?;
+            if !trailing_comma {
+                break;
+            }
+        }
+        self.expect(close)?;
+        Ok((patterns, trailing_comma))
+    }
+
+    fn declare_def(&mut self) -> Result<ExprId> {
+        let start = self.start()?;
+        let fun = self.function()?;
+        Ok(self.alloc(Expr::Function(fun), &start))
+    }
+
+    /// Parses a `def`, which is an expression of its own or a function of
+    /// an `impl`.
+    fn function(&mut self) -> Result<FunctionExpr> {
+        // Consume "def".
+        self.expect(TokenType::Keyword(Keyword::Def))?;
+
+        let ident = self.expect(TokenType::Identifier)?;
+        let var = self.name(ident.source(), ident.position().clone());
+
+        let (params, annotations) = self.parameters()?;
+        let constraints = self.constraints()?;
+
+        let body = self.parse_block()?;
+        let fun_decl = FunctionDeclaration {
+            annotations,
+            constraints,
+            ..FunctionDeclaration::new(params, body)
         };
-        let fun_decl = FunctionDeclaration::new(params, body);
 
-        Ok(Expr::Function(FunctionExpr::new(var, fun_decl)))
+        Ok(FunctionExpr::new(var, fun_decl))
     }
 
-    fn parse_return(&mut self) -> Result<Expr> {
+    fn parse_return(&mut self) -> Result<ExprId> {
+        let start = self.start()?;
         // Consume "return".
         self.expect(TokenType::Keyword(Keyword::Return))?;
 
@@ -100,13 +594,13 @@ impl<'a> AstParser<'a> {
             // TODO
             None
         } else {
-            Some(Box::new(self.parse_top_level_expr()?))
+            Some(self.parse_top_level_expr()?)
         };
 
-        Ok(Expr::Return(ReturnExpr::new(return_expr)))
+        Ok(self.alloc(Expr::Return(ReturnExpr::new(return_expr)), &start))
     }
 
-    fn parse_block(&mut self) -> Result<Expr> {
+    fn parse_block(&mut self) -> Result<BlockExpr> {
         // self.consume()?; // Consume 'do' TODO
 
         self.match_(&TokenType::Line)?;
@@ -114,6 +608,7 @@ impl<'a> AstParser<'a> {
         let mut exprs = vec![];
 
         loop {
+            self.skip_dangling_doc_comment()?;
             if let TokenType::Keyword(Keyword::End) = self.peek_type()? {
                 break;
             }
@@ -129,16 +624,16 @@ impl<'a> AstParser<'a> {
         self.expect(TokenType::Keyword(Keyword::End))?;
         self.expect(TokenType::Line)?;
 
-        Ok(Expr::Block(BlockExpr::new(exprs)))
+        Ok(BlockExpr::new(exprs))
     }
 
-    pub fn parse_expression_statement(&mut self) -> Result<Expr> {
+    pub fn parse_expression_statement(&mut self) -> Result<ExprId> {
         let expr = self.expression()?;
         self.expect(TokenType::Line)?;
         Ok(expr)
     }
 
-    pub fn expression(&mut self) -> Result<Expr> {
+    pub fn expression(&mut self) -> Result<ExprId> {
         super::expr_parser::parse(self)
     }
 
@@ -149,11 +644,19 @@ impl<'a> AstParser<'a> {
             Err(ParserError::Expect(
                 expect,
                 self.peek_type()?.clone(), // TODO Clone
-                self.peek().unwrap().position().line().clone(), // TODO Clone
+                *self.peek().unwrap().position().line(),
             ))
         }
     }
 
+    pub fn expected_expression(&self) -> Result<ParserError> {
+        let token = self.peek()?;
+        Ok(ParserError::ExpectedExpression(
+            token.token_type().clone(),
+            *token.position().line(),
+        ))
+    }
+
     pub fn match_(&mut self, token_type: &TokenType) -> Result<bool> {
         if !self.check(token_type)? {
             return Ok(false);
@@ -163,24 +666,34 @@ impl<'a> AstParser<'a> {
         Ok(true)
     }
 
-    fn check(&self, token_type: &TokenType) -> Result<bool> {
+    pub fn check(&self, token_type: &TokenType) -> Result<bool> {
         Ok(self.peek_type()? == token_type)
     }
 
     pub fn consume(&mut self) -> Result<Token<'a>> {
-        self.tokens.pop().ok_or(ParserError::UnexpectedEOF)
+        let token = self.tokens.pop().ok_or(ParserError::UnexpectedEOF)?;
+        if !matches!(token.token_type(), TokenType::Line | TokenType::EOF) {
+            self.end = *token.position().end();
+        }
+        Ok(token)
     }
 
     pub fn peek_type(&self) -> Result<&TokenType> {
         Ok(self.peek()?.token_type())
     }
 
+    /// The type of the token `n` tokens after the next one.
+    pub fn peek_type_after(&self, n: usize) -> Option<&TokenType> {
+        let len = self.tokens.len();
+        len.checked_sub(n + 2).map(|i| self.tokens[i].token_type())
+    }
+
     fn peek(&self) -> Result<&Token<'a>> {
         self.tokens.last().ok_or(ParserError::UnexpectedEOF)
     }
 
     pub fn is_eof(&self) -> Result<bool> {
-        Ok(self.check(&TokenType::EOF)?)
+        self.check(&TokenType::EOF)
     }
 }
 
@@ -188,9 +701,18 @@ impl<'a> AstParser<'a> {
 mod tests {
     use super::*;
     use crate::syntax::lexer::Lexer;
+    use crate::syntax::token::{FileId, Position};
 
-    fn tokenize(source: &str) -> Vec<Token> {
-        return Lexer::tokenize(source).unwrap();
+    fn tokenize(source: &str) -> Vec<Token<'_>> {
+        Lexer::tokenize(source).unwrap()
+    }
+
+    fn variable(name: &str) -> Variable {
+        Variable::new(
+            VariableId::default(),
+            Symbol::intern(name),
+            Position::default(),
+        )
     }
 
     // #[test]
@@ -217,13 +739,27 @@ mod tests {
     //     assert_eq!(expect, actual);
     // }
 
+    /// Adds an expression to the expected module. Like the parser does,
+    /// children have to be added before their parents, in source order.
+    fn node(module: &mut ModuleAst, expr: Expr) -> ExprId {
+        module.alloc(expr, Position::default())
+    }
+
+    fn number(module: &mut ModuleAst, n: f64) -> ExprId {
+        node(module, Expr::Literal(LiteralExpr::Number(n)))
+    }
+
+    fn get(module: &mut ModuleAst, name: &str) -> ExprId {
+        node(module, Expr::LetGet(LetGetExpr::new(variable(name))))
+    }
+
     #[test]
     fn parse_declare_let() {
-        let expected_exprs = vec![Expr::LetAssign(LetAssignExpr::new(
-            Variable::new("x".to_string()),
-            Box::new(Expr::Literal(LiteralExpr::Number(5.0))),
-        ))];
-        let expect = ModuleAst::new(expected_exprs);
+        let mut expect = ModuleAst::default();
+        let five = number(&mut expect, 5.0);
+        let x = LetAssignExpr::new(Pattern::Variable(variable("x")), five);
+        let x = node(&mut expect, Expr::LetAssign(x));
+        expect.exprs.push(x);
 
         let source = r#"
         let x = 5
@@ -234,13 +770,35 @@ mod tests {
         assert_eq!(expect, actual)
     }
 
+    #[test]
+    fn parse_declare_let_mut() {
+        let mut expect = ModuleAst::default();
+        let five = number(&mut expect, 5.0);
+        let x = LetAssignExpr {
+            mutable: true,
+            ..LetAssignExpr::new(Pattern::Variable(variable("x")), five)
+        };
+        let x = node(&mut expect, Expr::LetAssign(x));
+        expect.exprs.push(x);
+
+        let source = r#"
+        let mut x = 5
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual)
+    }
+
     #[test]
     fn parse_set_let() {
-        let expected_exprs = vec![Expr::LetSet(LetSetExpr::new(
-            Variable::new("x".to_string()),
-            Box::new(Expr::Literal(LiteralExpr::Number(5.0))),
-        ))];
-        let expect = ModuleAst::new(expected_exprs);
+        let mut expect = ModuleAst::default();
+        let five = number(&mut expect, 5.0);
+        let x = node(
+            &mut expect,
+            Expr::LetSet(LetSetExpr::new(variable("x"), five)),
+        );
+        expect.exprs.push(x);
 
         let source = r#"
         x = 5
@@ -253,19 +811,14 @@ mod tests {
 
     #[test]
     fn parse_get_let() {
-        let expected_exprs = vec![
-            Expr::LetAssign(LetAssignExpr::new(
-                Variable::new("x".to_string()),
-                Box::new(Expr::Literal(LiteralExpr::Number(5.0))),
-            )),
-            Expr::LetAssign(LetAssignExpr::new(
-                Variable::new("y".to_string()),
-                Box::new(Expr::LetGet(LetGetExpr::new(Variable::new(
-                    "x".to_string(),
-                )))),
-            )),
-        ];
-        let expect = ModuleAst::new(expected_exprs);
+        let mut expect = ModuleAst::default();
+        let five = number(&mut expect, 5.0);
+        let x = LetAssignExpr::new(Pattern::Variable(variable("x")), five);
+        let x = node(&mut expect, Expr::LetAssign(x));
+        let get_x = get(&mut expect, "x");
+        let y = LetAssignExpr::new(Pattern::Variable(variable("y")), get_x);
+        let y = node(&mut expect, Expr::LetAssign(y));
+        expect.exprs = vec![x, y];
 
         let source = r#"
         let x = 5
@@ -279,22 +832,18 @@ mod tests {
 
     #[test]
     fn parse_def() {
-        let expected_exprs = vec![Expr::Function(FunctionExpr::new(
-            Variable::new("double".to_string()),
-            FunctionDeclaration::new(
-                vec![Variable::new("x".to_string())],
-                BlockExpr::new(vec![Expr::Return(ReturnExpr::new(Some(Box::new(
-                    Expr::Binary(BinaryExpr::new(
-                        BinaryOperator::Multiply,
-                        Box::new(Expr::LetGet(LetGetExpr::new(Variable::new(
-                            "x".to_string(),
-                        )))),
-                        Box::new(Expr::Literal(LiteralExpr::Number(2.0))),
-                    )),
-                ))))]),
-            ),
-        ))];
-        let expect = ModuleAst::new(expected_exprs);
+        let mut expect = ModuleAst::default();
+        let x = get(&mut expect, "x");
+        let two = number(&mut expect, 2.0);
+        let product = BinaryExpr::new(BinaryOperator::Multiply, x, two);
+        let product = node(&mut expect, Expr::Binary(product));
+        let ret = node(&mut expect, Expr::Return(ReturnExpr::new(Some(product))));
+        let double = FunctionExpr::new(
+            variable("double"),
+            FunctionDeclaration::new(vec![variable("x")], BlockExpr::new(vec![ret])),
+        );
+        let double = node(&mut expect, Expr::Function(double));
+        expect.exprs.push(double);
 
         let source = r#"
         def double(x)
@@ -306,4 +855,672 @@ mod tests {
 
         assert_eq!(expect, actual);
     }
+
+    #[test]
+    fn parse_patterns() {
+        let source = r#"
+        let (a, (b,), _) = x
+        let { left, right } = y
+        let [first, ..rest] = z else return nil
+        let [..] = z
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        let patterns: Vec<&Pattern> = actual
+            .top_level()
+            .map(|expr| match expr {
+                Expr::LetAssign(let_assign) => &let_assign.pattern,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            &Pattern::Tuple(vec![
+                Pattern::Variable(variable("a")),
+                Pattern::Tuple(vec![Pattern::Variable(variable("b"))]),
+                Pattern::Wildcard,
+            ]),
+            patterns[0]
+        );
+        assert_eq!(
+            &Pattern::Record(vec![variable("left"), variable("right")]),
+            patterns[1]
+        );
+        assert_eq!(
+            &Pattern::List(ListPattern::new(
+                vec![Pattern::Variable(variable("first"))],
+                Some(Box::new(Pattern::Variable(variable("rest")))),
+                Position::default(),
+            )),
+            patterns[2]
+        );
+        assert_eq!(
+            &Pattern::List(ListPattern::new(
+                vec![],
+                Some(Box::new(Pattern::Wildcard)),
+                Position::default(),
+            )),
+            patterns[3]
+        );
+
+        let otherwise = match &actual[actual.exprs[2]] {
+            Expr::LetAssign(let_assign) => let_assign.otherwise.unwrap(),
+            _ => unreachable!(),
+        };
+        match &actual[otherwise] {
+            Expr::Return(ret) => {
+                assert_eq!(Expr::Literal(LiteralExpr::Nil), actual[ret.expr.unwrap()])
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_invalid_patterns() {
+        let mut tokens = tokenize("let [..rest, last] = x\n");
+        let actual = AstParser::parse(&mut tokens);
+        assert!(matches!(actual, Err(ParserError::MisplacedRest(1))));
+
+        let mut tokens = tokenize("let 5 + 3 = x\n");
+        let actual = AstParser::parse(&mut tokens);
+        assert!(matches!(
+            actual,
+            Err(ParserError::Expect(TokenType::Line, TokenType::Plus, 1))
+        ));
+
+        let mut tokens = tokenize("let - = x\n");
+        let actual = AstParser::parse(&mut tokens);
+        assert!(matches!(
+            actual,
+            Err(ParserError::ExpectedPattern(TokenType::Minus, 1))
+        ));
+
+        let mut tokens = tokenize("let [a] = x else nil\n");
+        let actual = AstParser::parse(&mut tokens);
+        assert!(matches!(
+            actual,
+            Err(ParserError::Expect(
+                TokenType::Keyword(Keyword::Return),
+                TokenType::Keyword(Keyword::Nil),
+                1
+            ))
+        ));
+    }
+
+    #[test]
+    fn parse_too_deep() {
+        let deep = [
+            format!("let x = {}1{}\n", "(".repeat(50_000), ")".repeat(50_000)),
+            format!("let x = 1{}\n", " + 1".repeat(50_000)),
+            format!("let x = a{}\n", ".b".repeat(50_000)),
+            format!("let {}a{} = 1\n", "[".repeat(50_000), "]".repeat(50_000)),
+            format!(
+                "def f(x: {}a{})\n    x\nend\n",
+                "(".repeat(50_000),
+                ",)".repeat(50_000)
+            ),
+            format!(
+                "1\n\nf({}1{})\n",
+                "(".repeat(MAX_DEPTH),
+                ")".repeat(MAX_DEPTH)
+            ),
+        ];
+        for (source, line) in deep.iter().zip([1, 1, 1, 1, 1, 3]) {
+            let mut tokens = tokenize(source);
+            let actual = AstParser::parse(&mut tokens);
+            assert!(matches!(actual, Err(ParserError::TooDeep(l)) if l == line));
+        }
+
+        let source = format!("let x = {}1{}\n", "(".repeat(100), ")".repeat(100));
+        let mut tokens = tokenize(&source);
+        assert!(AstParser::parse(&mut tokens).is_ok());
+    }
+
+    #[test]
+    fn parse_tuples() {
+        let mut expect = ModuleAst::default();
+        let empty = node(&mut expect, Expr::Tuple(TupleExpr::new(vec![])));
+        let one = number(&mut expect, 1.0);
+        let grouping = node(&mut expect, Expr::Grouping(GroupingExpr::new(one)));
+        let one = number(&mut expect, 1.0);
+        let single = node(&mut expect, Expr::Tuple(TupleExpr::new(vec![one])));
+        let one = number(&mut expect, 1.0);
+        let two = number(&mut expect, 2.0);
+        let pair = node(&mut expect, Expr::Tuple(TupleExpr::new(vec![one, two])));
+        let t = get(&mut expect, "t");
+        let first = node(&mut expect, Expr::TupleIndex(TupleIndexExpr::new(t, 0)));
+        let nested = node(&mut expect, Expr::TupleIndex(TupleIndexExpr::new(first, 1)));
+        let t = get(&mut expect, "t");
+        let third = node(&mut expect, Expr::TupleIndex(TupleIndexExpr::new(t, 2)));
+        let two = number(&mut expect, 2.0);
+        let product = BinaryExpr::new(BinaryOperator::Multiply, third, two);
+        let product = node(&mut expect, Expr::Binary(product));
+        expect.exprs = vec![empty, grouping, single, pair, nested, product];
+
+        let source = r#"
+        ()
+        (1)
+        (1,)
+        (
+            1,
+            2,
+        )
+        t.0.1
+        t.2 * 2
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn parse_pipeline() {
+        let mut expect = ModuleAst::default();
+        let call = |module: &mut ModuleAst, callee, arguments| {
+            node(module, Expr::Call(CallExpr::new(callee, arguments)))
+        };
+        let xs = get(&mut expect, "xs");
+        let map = get(&mut expect, "map");
+        let double = get(&mut expect, "double");
+        let mapped = call(&mut expect, map, vec![xs, double]);
+        let sum = get(&mut expect, "sum");
+        let summed = call(&mut expect, sum, vec![mapped]);
+        let a = get(&mut expect, "a");
+        let b = get(&mut expect, "b");
+        let added = node(
+            &mut expect,
+            Expr::Binary(BinaryExpr::new(BinaryOperator::Add, a, b)),
+        );
+        let f = get(&mut expect, "f");
+        let applied = call(&mut expect, f, vec![added]);
+        let c = get(&mut expect, "c");
+        let equal = node(
+            &mut expect,
+            Expr::Binary(BinaryExpr::new(BinaryOperator::Equal, applied, c)),
+        );
+        expect.exprs = vec![summed, equal];
+
+        let source = r#"
+        xs |> map(double) |> sum
+        a + b |> f == c
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn parse_method_calls() {
+        let mut expect = ModuleAst::default();
+        let method = |module: &mut ModuleAst, receiver, name, arguments| {
+            let call = MethodCallExpr::new(receiver, variable(name), arguments);
+            node(module, Expr::MethodCall(call))
+        };
+        let p = get(&mut expect, "p");
+        let two = number(&mut expect, 2.0);
+        let three = number(&mut expect, 3.0);
+        let scaled = method(&mut expect, p, "scale", vec![two, three]);
+        let x = node(
+            &mut expect,
+            Expr::Field(FieldExpr::new(scaled, variable("x"))),
+        );
+        let n = get(&mut expect, "n");
+        let once = method(&mut expect, n, "double", vec![]);
+        let twice = method(&mut expect, once, "double", vec![]);
+        expect.exprs = vec![x, twice];
+
+        let source = r#"
+        p.scale(2, 3).x
+        n.double().double()
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn parse_set_field() {
+        let mut tokens = tokenize("r.a.b = n + 1\n");
+        let module = AstParser::parse(&mut tokens).unwrap();
+        assert_eq!(
+            "(set (. (. (get r) a) b) (+ (get n) 1))\n",
+            module.to_sexp()
+        );
+        let position = module.position(module.exprs[0]);
+        assert_eq!((0, 13), (*position.start(), *position.end()));
+
+        for source in ["1 = 2\n", "x + r.a = 1\n", "f() = 1\n"] {
+            let mut tokens = tokenize(source);
+            let actual = AstParser::parse(&mut tokens);
+            assert!(matches!(actual, Err(ParserError::InvalidAssignment(1))));
+        }
+    }
+
+    #[test]
+    fn parse_try() {
+        let mut expect = ModuleAst::default();
+        let try_expr = |module: &mut ModuleAst, expr| {
+            node(module, Expr::Try(TryExpr::new(expr, Position::default())))
+        };
+        let r = get(&mut expect, "r");
+        let unwrapped = try_expr(&mut expect, r);
+        let called = node(&mut expect, Expr::Call(CallExpr::new(unwrapped, vec![])));
+        let unwrapped = try_expr(&mut expect, called);
+        let x = node(
+            &mut expect,
+            Expr::Field(FieldExpr::new(unwrapped, variable("x"))),
+        );
+        let parse = FunctionExpr::new(
+            variable("parse"),
+            FunctionDeclaration {
+                annotations: vec![Some(TypeAnnotation::Generic(
+                    variable("result"),
+                    vec![
+                        TypeAnnotation::Name(variable("number")),
+                        TypeAnnotation::Generic(
+                            variable("option"),
+                            vec![TypeAnnotation::Name(variable("string"))],
+                        ),
+                    ],
+                ))],
+                ..FunctionDeclaration::new(vec![variable("r")], BlockExpr::new(vec![x]))
+            },
+        );
+        let parse = node(&mut expect, Expr::Function(parse));
+        expect.exprs.push(parse);
+
+        let source = r#"
+        def parse(r: result<number, option<string>>)
+            r?()?.x
+        end
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn parse_pipeline_into_value() {
+        let mut tokens = tokenize("xs |> 5\n");
+        let actual = AstParser::parse(&mut tokens);
+        assert!(matches!(actual, Err(ParserError::NotCallable(1))));
+
+        let mut tokens = tokenize("xs |> f + 1\n");
+        let actual = AstParser::parse(&mut tokens);
+        assert!(matches!(actual, Err(ParserError::NotCallable(1))));
+    }
+
+    #[test]
+    fn parse_match() {
+        let mut expect = ModuleAst::default();
+        let pair = get(&mut expect, "pair");
+        let y = get(&mut expect, "y");
+        let nil = node(&mut expect, Expr::Literal(LiteralExpr::Nil));
+        let arms = vec![
+            MatchArm::new(
+                Pattern::Tuple(vec![
+                    Pattern::Literal(LiteralPattern::new(
+                        LiteralExpr::Number(0.0),
+                        Position::default(),
+                    )),
+                    Pattern::Variable(variable("y")),
+                ]),
+                y,
+            ),
+            MatchArm::new(Pattern::Wildcard, nil),
+        ];
+        let match_expr = node(&mut expect, Expr::Match(MatchExpr::new(pair, arms)));
+        expect.exprs.push(match_expr);
+
+        let source = r#"
+        match pair {
+            | (0, y) => y
+            | _ => nil
+        }
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn parse_types() {
+        let mut documented = TypeExpr::new(
+            variable("shape"),
+            TypeDefinition::Variant(vec![
+                Constructor::new(
+                    variable("Circle"),
+                    vec![variable("radius")],
+                    Position::default(),
+                ),
+                Constructor::new(variable("Empty"), vec![], Position::default()),
+            ]),
+        );
+        documented.doc = Some("A shape.".to_string());
+        let mut expect = ModuleAst::default();
+        let point = Expr::Type(TypeExpr::new(
+            variable("point"),
+            TypeDefinition::Record(vec![variable("x"), variable("y")]),
+        ));
+        let fruit = Expr::Type(TypeExpr::new(
+            variable("fruit"),
+            TypeDefinition::Variant(vec![
+                Constructor::new(variable("Apple"), vec![], Position::default()),
+                Constructor::new(variable("Pear"), vec![], Position::default()),
+            ]),
+        ));
+        expect.exprs = vec![
+            node(&mut expect, point),
+            node(&mut expect, Expr::Type(documented)),
+            node(&mut expect, fruit),
+        ];
+
+        let source = r#"
+        type point = {
+            x,
+            y
+        }
+        /// A shape.
+        type shape =
+            | Circle(radius)
+            | Empty
+        type fruit = | Apple | Pear
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn parse_impl() {
+        let mut expect = ModuleAst::default();
+        let field = |module: &mut ModuleAst, record, name| {
+            let record = get(module, record);
+            node(module, Expr::Field(FieldExpr::new(record, variable(name))))
+        };
+        let a_x = field(&mut expect, "a", "x");
+        let b_x = field(&mut expect, "b", "x");
+        let x = node(
+            &mut expect,
+            Expr::Binary(BinaryExpr::new(BinaryOperator::Add, a_x, b_x)),
+        );
+        let y = get(&mut expect, "y");
+        let record = RecordExpr::new(
+            variable("point"),
+            vec![(variable("x"), x), (variable("y"), y)],
+        );
+        let record = node(&mut expect, Expr::Record(record));
+        let add = FunctionExpr::new(
+            variable("add"),
+            FunctionDeclaration::new(
+                vec![variable("a"), variable("b")],
+                BlockExpr::new(vec![record]),
+            ),
+        );
+        let add = node(&mut expect, Expr::Function(add));
+        let imp = node(
+            &mut expect,
+            Expr::Impl(ImplExpr::new(variable("point"), vec![add])),
+        );
+        expect.exprs.push(imp);
+
+        let source = r#"
+        impl point
+            def add(a, b)
+                point {
+                    x: a.x + b.x,
+                    y
+                }
+            end
+        end
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+        assert_eq!(expect, actual);
+
+        let mut tokens = tokenize("impl point\n    let x = 1\nend\n");
+        let actual = AstParser::parse(&mut tokens);
+        assert!(matches!(actual, Err(ParserError::ExpectedFunction(2))));
+    }
+
+    #[test]
+    fn parse_interfaces() {
+        let name = |name| Some(TypeAnnotation::Name(variable(name)));
+        let show = Signature::new(
+            variable("show"),
+            vec![variable("x")],
+            vec![None],
+            Position::default(),
+        );
+        let describe = Signature::new(
+            variable("describe"),
+            vec![variable("x"), variable("prefix")],
+            vec![None, name("string")],
+            Position::default(),
+        );
+        let print = FunctionExpr::new(
+            variable("print"),
+            FunctionDeclaration {
+                annotations: vec![
+                    name("a"),
+                    Some(TypeAnnotation::Tuple(vec![
+                        TypeAnnotation::Name(variable("a")),
+                        TypeAnnotation::Name(variable("number")),
+                    ])),
+                ],
+                constraints: vec![Constraint::new(variable("a"), variable("Show"))],
+                ..FunctionDeclaration::new(
+                    vec![variable("x"), variable("pair")],
+                    BlockExpr::new(vec![]),
+                )
+            },
+        );
+        let mut expect = ModuleAst::default();
+        let interface = Expr::Interface(InterfaceExpr {
+            doc: Some("Values with a text form.".to_string()),
+            ..InterfaceExpr::new(variable("Show"), vec![show, describe])
+        });
+        let imp = Expr::Impl(ImplExpr {
+            interface: Some(variable("Show")),
+            ..ImplExpr::new(variable("number"), vec![])
+        });
+        expect.exprs = vec![
+            node(&mut expect, interface),
+            node(&mut expect, imp),
+            node(&mut expect, Expr::Function(print)),
+        ];
+
+        let source = r#"
+        /// Values with a text form.
+        interface Show
+            def show(x)
+
+            def describe(x, prefix: string)
+        end
+        impl Show for number
+        end
+        def print(x: a, pair: (a, number)) where a: Show
+        end
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+        assert_eq!(expect, actual);
+
+        let mut tokens = tokenize("interface Show\n    let x = 1\nend\n");
+        let actual = AstParser::parse(&mut tokens);
+        assert!(matches!(actual, Err(ParserError::ExpectedFunction(2))));
+    }
+
+    #[test]
+    fn parse_variant_patterns() {
+        let mut expect = ModuleAst::default();
+        let shape = get(&mut expect, "shape");
+        let r = get(&mut expect, "r");
+        let zero = number(&mut expect, 0.0);
+        let arms = vec![
+            MatchArm::new(
+                Pattern::Variant(VariantPattern::new(
+                    variable("Circle"),
+                    vec![Pattern::Variable(variable("r"))],
+                )),
+                r,
+            ),
+            MatchArm::new(
+                Pattern::Variant(VariantPattern::new(variable("Empty"), vec![])),
+                zero,
+            ),
+        ];
+        let match_expr = node(&mut expect, Expr::Match(MatchExpr::new(shape, arms)));
+        expect.exprs.push(match_expr);
+
+        let source = r#"
+        match shape {
+            | Circle(r) => r
+            | Empty => 0
+        }
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn parse_doc_comments() {
+        let mut expect = ModuleAst::default();
+        let five = number(&mut expect, 5.0);
+        let mut expected_let = LetAssignExpr::new(Pattern::Variable(variable("x")), five);
+        expected_let.doc = Some("The answer, roughly.\nSee `double`.".to_string());
+        let mut expected_def = FunctionExpr::new(
+            variable("double"),
+            FunctionDeclaration::new(vec![], BlockExpr::new(vec![])),
+        );
+        expected_def.doc = Some("Doubles nothing.".to_string());
+        expect.exprs = vec![
+            node(&mut expect, Expr::LetAssign(expected_let)),
+            node(&mut expect, Expr::Function(expected_def)),
+        ];
+
+        let source = r#"
+        /// The answer, roughly.
+        /// See `double`.
+        let x = 5 // Not part of the docs.
+
+        /// Doubles nothing.
+        /* Ignored. */
+        def double()
+        end
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn parse_positions() {
+        let source = "let y = -a + b.c(1)\nf(x)?\n";
+        let mut tokens = tokenize(source);
+        let module = AstParser::parse(&mut tokens).unwrap();
+
+        let text = |id: ExprId| {
+            let position = module.position(id);
+            &source[*position.start()..*position.end()]
+        };
+        let let_y = module.exprs[0];
+        assert_eq!("let y = -a + b.c(1)", text(let_y));
+        let sum = match &module[let_y] {
+            Expr::LetAssign(let_assign) => let_assign.initializer,
+            _ => unreachable!(),
+        };
+        assert_eq!("-a + b.c(1)", text(sum));
+        let (negated, call) = match &module[sum] {
+            Expr::Binary(binary) => (binary.lhs, binary.rhs),
+            _ => unreachable!(),
+        };
+        assert_eq!("-a", text(negated));
+        assert_eq!("b.c(1)", text(call));
+        assert_eq!("f(x)?", text(module.exprs[1]));
+    }
+
+    #[test]
+    fn parse_declaration_positions() {
+        let source = "type shape =\n    | Circle(r)\ninterface Area\n    def area(s)\nend\nimpl Area for shape\n    def area(s)\n        s\n    end\nend\n";
+        let file = FileId::new(3);
+        let mut tokens = Lexer::tokenize_file(source, file).unwrap();
+        let module = AstParser::parse(&mut tokens).unwrap();
+
+        let text = |position: &Position| {
+            assert_eq!(file, position.file());
+            &source[*position.start()..*position.end()]
+        };
+        match &module[module.exprs[0]] {
+            Expr::Type(ty) => match &ty.definition {
+                TypeDefinition::Variant(constructors) => {
+                    assert_eq!("Circle(r)", text(&constructors[0].position));
+                    assert_eq!("r", text(&constructors[0].fields[0].position));
+                }
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+        match &module[module.exprs[1]] {
+            Expr::Interface(interface) => {
+                assert_eq!("def area(s)", text(&interface.functions[0].position))
+            }
+            _ => unreachable!(),
+        }
+        let imp = match &module[module.exprs[2]] {
+            Expr::Impl(imp) => imp,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            "def area(s)\n        s\n    end",
+            text(module.position(imp.functions[0]))
+        );
+        assert!(text(module.position(module.exprs[2])).ends_with("end\nend"));
+    }
+
+    #[test]
+    fn parse_dangling_doc_comment() {
+        // Doc comments which document nothing are ordinary comments.
+        let source = r#"
+        /// Documents nothing.
+        5
+        def f()
+            1
+            /// Nor this.
+        end
+        impl point
+            def g(p)
+                p
+            end
+            /// Nor this.
+        end
+        /// Nor this.
+        "#;
+        let without = r#"
+        5
+        def f()
+            1
+        end
+        impl point
+            def g(p)
+                p
+            end
+        end
+        "#;
+        let mut tokens = tokenize(source);
+        let actual = AstParser::parse(&mut tokens).unwrap();
+        let mut tokens = tokenize(without);
+        let expect = AstParser::parse(&mut tokens).unwrap();
+
+        assert_eq!(expect, actual);
+    }
 }
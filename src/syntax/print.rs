@@ -0,0 +1,915 @@
+//! Renderings of the syntax tree: back to amber source with `Display`, as
+//! S-expressions for `amber ast` and tests, and as JSON for other tools.
+
+use crate::syntax::ast::*;
+use crate::syntax::token::Position;
+use serde_json::{json, Value};
+use std::fmt::{self, Write};
+
+const INDENT: &str = "    ";
+
+/// Renders the module as amber source. Parentheses are only written where
+/// the source had them, so the result parses to the same tree.
+impl fmt::Display for ModuleAst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = SourcePrinter {
+            module: self,
+            out: String::new(),
+            indent: 0,
+        };
+        for &expr in &self.exprs {
+            printer.statement(expr);
+        }
+        f.write_str(&printer.out)
+    }
+}
+
+impl ModuleAst {
+    /// The S-expressions of the top level, one per line, like
+    /// `(let x (* (get y) 2))`.
+    pub fn to_sexp(&self) -> String {
+        self.exprs
+            .iter()
+            .map(|&expr| format!("{}\n", self.sexp(expr)))
+            .collect()
+    }
+
+    /// The S-expression of a single expression.
+    pub fn sexp(&self, id: ExprId) -> String {
+        let list =
+            |head: &str, exprs: &[ExprId]| sexp_list(head, exprs.iter().map(|&e| self.sexp(e)));
+        match &self[id] {
+            Expr::Literal(literal) => literal.to_string(),
+            Expr::Grouping(grouping) => format!("(group {})", self.sexp(grouping.expr)),
+            Expr::Binary(binary) => format!(
+                "({} {} {})",
+                binary.operator.as_str(),
+                self.sexp(binary.lhs),
+                self.sexp(binary.rhs)
+            ),
+            Expr::Unary(unary) => {
+                format!("({} {})", unary.operator.as_str(), self.sexp(unary.expr))
+            }
+            Expr::LetAssign(let_assign) => {
+                let mut items = vec![];
+                if let_assign.mutable {
+                    items.push("mut".to_string());
+                }
+                items.push(pattern_sexp(&let_assign.pattern));
+                items.push(self.sexp(let_assign.initializer));
+                if let Some(otherwise) = let_assign.otherwise {
+                    items.push(format!("(else {})", self.sexp(otherwise)));
+                }
+                sexp_list("let", items)
+            }
+            Expr::LetGet(let_get) => format!("(get {})", let_get.variable),
+            Expr::LetSet(let_set) => {
+                format!(
+                    "(set {} {})",
+                    let_set.variable,
+                    self.sexp(let_set.initializer)
+                )
+            }
+            Expr::Function(fun) => self.function_sexp(fun),
+            Expr::Block(block) => list("block", &block.exprs),
+            Expr::Return(ret) => match ret.expr {
+                Some(expr) => format!("(return {})", self.sexp(expr)),
+                None => "(return)".to_string(),
+            },
+            Expr::Tuple(tuple) => list("tuple", &tuple.elements),
+            Expr::TupleIndex(tuple_index) => {
+                format!("(. {} {})", self.sexp(tuple_index.tuple), tuple_index.index)
+            }
+            Expr::Match(match_expr) => {
+                let arms = match_expr.arms.iter().map(|arm| {
+                    format!(
+                        "(=> {} {})",
+                        pattern_sexp(&arm.pattern),
+                        self.sexp(arm.body)
+                    )
+                });
+                sexp_list(
+                    "match",
+                    std::iter::once(self.sexp(match_expr.scrutinee)).chain(arms),
+                )
+            }
+            Expr::Call(call) => sexp_list(
+                "call",
+                std::iter::once(self.sexp(call.callee))
+                    .chain(call.arguments.iter().map(|&argument| self.sexp(argument))),
+            ),
+            Expr::Type(ty) => {
+                let definition = match &ty.definition {
+                    TypeDefinition::Record(fields) => {
+                        sexp_list("record", fields.iter().map(Variable::to_string))
+                    }
+                    TypeDefinition::Variant(constructors) => sexp_list(
+                        "variant",
+                        constructors.iter().map(|constructor| {
+                            if constructor.fields.is_empty() {
+                                constructor.variable.to_string()
+                            } else {
+                                let fields = constructor.fields.iter().map(Variable::to_string);
+                                sexp_list(constructor.variable.name.as_str(), fields)
+                            }
+                        }),
+                    ),
+                };
+                format!("(type {} {})", ty.variable, definition)
+            }
+            Expr::Impl(imp) => {
+                let name = match &imp.interface {
+                    Some(interface) => format!("({} for {})", interface, imp.variable),
+                    None => imp.variable.to_string(),
+                };
+                sexp_list(
+                    "impl",
+                    std::iter::once(name)
+                        .chain(self.functions(imp).map(|(_, fun)| self.function_sexp(fun))),
+                )
+            }
+            Expr::Interface(interface) => {
+                let signatures = interface.functions.iter().map(|signature| {
+                    let parameters = parameters_sexp(&signature.parameters, &signature.annotations);
+                    format!("(def {} {})", signature.variable, parameters)
+                });
+                sexp_list(
+                    "interface",
+                    std::iter::once(interface.variable.to_string()).chain(signatures),
+                )
+            }
+            Expr::Record(record) => sexp_list(
+                "new",
+                std::iter::once(record.variable.to_string()).chain(
+                    record
+                        .fields
+                        .iter()
+                        .map(|(field, value)| format!("({} {})", field, self.sexp(*value))),
+                ),
+            ),
+            Expr::Field(field) => format!("(. {} {})", self.sexp(field.record), field.field),
+            Expr::SetField(set_field) => format!(
+                "(set (. {} {}) {})",
+                self.sexp(set_field.record),
+                set_field.field,
+                self.sexp(set_field.value)
+            ),
+            Expr::MethodCall(method_call) => sexp_list(
+                "method",
+                vec![
+                    self.sexp(method_call.receiver),
+                    method_call.method.to_string(),
+                ]
+                .into_iter()
+                .chain(
+                    method_call
+                        .arguments
+                        .iter()
+                        .map(|&argument| self.sexp(argument)),
+                ),
+            ),
+            Expr::Try(try_expr) => format!("(? {})", self.sexp(try_expr.expr)),
+        }
+    }
+
+    fn function_sexp(&self, fun: &FunctionExpr) -> String {
+        let declaration = &fun.declaration;
+        let mut items = vec![
+            fun.variable.to_string(),
+            parameters_sexp(&declaration.parameters, &declaration.annotations),
+        ];
+        if !declaration.constraints.is_empty() {
+            let constraints = declaration
+                .constraints
+                .iter()
+                .map(|constraint| format!("({} {})", constraint.parameter, constraint.interface));
+            items.push(sexp_list("where", constraints));
+        }
+        items.extend(declaration.body.exprs.iter().map(|&expr| self.sexp(expr)));
+        sexp_list("def", items)
+    }
+
+    /// The tree as JSON. Every expression is an object with its `kind`, its
+    /// `span` and its parts, where the children are nested objects.
+    pub fn to_json(&self) -> Value {
+        let exprs: Vec<Value> = self.exprs.iter().map(|&expr| self.json(expr)).collect();
+        json!({ "exprs": exprs })
+    }
+
+    fn json(&self, id: ExprId) -> Value {
+        let exprs =
+            |exprs: &[ExprId]| -> Vec<Value> { exprs.iter().map(|&e| self.json(e)).collect() };
+        let (kind, mut value) = match &self[id] {
+            Expr::Literal(literal) => (
+                "Literal",
+                match literal {
+                    LiteralExpr::Number(n) => json!({ "value": n }),
+                    LiteralExpr::String(s) => json!({ "value": s }),
+                    LiteralExpr::Nil => json!({ "value": null }),
+                },
+            ),
+            Expr::Grouping(grouping) => ("Grouping", json!({ "expr": self.json(grouping.expr) })),
+            Expr::Binary(binary) => (
+                "Binary",
+                json!({
+                    "operator": binary.operator.as_str(),
+                    "lhs": self.json(binary.lhs),
+                    "rhs": self.json(binary.rhs),
+                }),
+            ),
+            Expr::Unary(unary) => (
+                "Unary",
+                json!({ "operator": unary.operator.as_str(), "expr": self.json(unary.expr) }),
+            ),
+            Expr::LetAssign(let_assign) => (
+                "LetAssign",
+                json!({
+                    "pattern": pattern_json(&let_assign.pattern),
+                    "initializer": self.json(let_assign.initializer),
+                    "mutable": let_assign.mutable,
+                    "otherwise": let_assign.otherwise.map(|otherwise| self.json(otherwise)),
+                    "doc": let_assign.doc,
+                }),
+            ),
+            Expr::LetGet(let_get) => (
+                "LetGet",
+                json!({ "variable": variable_json(&let_get.variable) }),
+            ),
+            Expr::LetSet(let_set) => (
+                "LetSet",
+                json!({
+                    "variable": variable_json(&let_set.variable),
+                    "initializer": self.json(let_set.initializer),
+                }),
+            ),
+            Expr::Function(fun) => ("Function", self.function_json(fun)),
+            Expr::Block(block) => ("Block", json!({ "exprs": exprs(&block.exprs) })),
+            Expr::Return(ret) => (
+                "Return",
+                json!({ "expr": ret.expr.map(|expr| self.json(expr)) }),
+            ),
+            Expr::Tuple(tuple) => ("Tuple", json!({ "elements": exprs(&tuple.elements) })),
+            Expr::TupleIndex(tuple_index) => (
+                "TupleIndex",
+                json!({ "tuple": self.json(tuple_index.tuple), "index": tuple_index.index }),
+            ),
+            Expr::Match(match_expr) => {
+                let arms: Vec<Value> = match_expr
+                    .arms
+                    .iter()
+                    .map(|arm| json!({ "pattern": pattern_json(&arm.pattern), "body": self.json(arm.body) }))
+                    .collect();
+                (
+                    "Match",
+                    json!({ "scrutinee": self.json(match_expr.scrutinee), "arms": arms }),
+                )
+            }
+            Expr::Call(call) => (
+                "Call",
+                json!({ "callee": self.json(call.callee), "arguments": exprs(&call.arguments) }),
+            ),
+            Expr::Type(ty) => {
+                let definition = match &ty.definition {
+                    TypeDefinition::Record(fields) => json!({ "record": variables_json(fields) }),
+                    TypeDefinition::Variant(constructors) => {
+                        let constructors: Vec<Value> = constructors
+                            .iter()
+                            .map(|constructor| {
+                                json!({
+                                    "variable": variable_json(&constructor.variable),
+                                    "fields": variables_json(&constructor.fields),
+                                    "span": span_json(&constructor.position),
+                                })
+                            })
+                            .collect();
+                        json!({ "variant": constructors })
+                    }
+                };
+                (
+                    "Type",
+                    json!({
+                        "variable": variable_json(&ty.variable),
+                        "definition": definition,
+                        "doc": ty.doc,
+                    }),
+                )
+            }
+            Expr::Impl(imp) => (
+                "Impl",
+                json!({
+                    "variable": variable_json(&imp.variable),
+                    "interface": imp.interface.as_ref().map(variable_json),
+                    "functions": exprs(&imp.functions),
+                }),
+            ),
+            Expr::Interface(interface) => {
+                let functions: Vec<Value> = interface
+                    .functions
+                    .iter()
+                    .map(|signature| {
+                        json!({
+                            "variable": variable_json(&signature.variable),
+                            "parameters": parameters_json(&signature.parameters, &signature.annotations),
+                            "span": span_json(&signature.position),
+                        })
+                    })
+                    .collect();
+                (
+                    "Interface",
+                    json!({
+                        "variable": variable_json(&interface.variable),
+                        "functions": functions,
+                        "doc": interface.doc,
+                    }),
+                )
+            }
+            Expr::Record(record) => {
+                let fields: Vec<Value> = record
+                    .fields
+                    .iter()
+                    .map(|(field, value)| json!({ "field": variable_json(field), "value": self.json(*value) }))
+                    .collect();
+                (
+                    "Record",
+                    json!({ "variable": variable_json(&record.variable), "fields": fields }),
+                )
+            }
+            Expr::Field(field) => (
+                "Field",
+                json!({ "record": self.json(field.record), "field": variable_json(&field.field) }),
+            ),
+            Expr::SetField(set_field) => (
+                "SetField",
+                json!({
+                    "record": self.json(set_field.record),
+                    "field": variable_json(&set_field.field),
+                    "value": self.json(set_field.value),
+                }),
+            ),
+            Expr::MethodCall(method_call) => (
+                "MethodCall",
+                json!({
+                    "receiver": self.json(method_call.receiver),
+                    "method": variable_json(&method_call.method),
+                    "arguments": exprs(&method_call.arguments),
+                }),
+            ),
+            Expr::Try(try_expr) => ("Try", json!({ "expr": self.json(try_expr.expr) })),
+        };
+        value["kind"] = json!(kind);
+        value["span"] = span_json(self.position(id));
+        value
+    }
+
+    fn function_json(&self, fun: &FunctionExpr) -> Value {
+        let declaration = &fun.declaration;
+        let constraints: Vec<Value> = declaration
+            .constraints
+            .iter()
+            .map(|constraint| {
+                json!({
+                    "parameter": variable_json(&constraint.parameter),
+                    "interface": variable_json(&constraint.interface),
+                })
+            })
+            .collect();
+        let body: Vec<Value> = declaration
+            .body
+            .exprs
+            .iter()
+            .map(|&expr| self.json(expr))
+            .collect();
+        json!({
+            "variable": variable_json(&fun.variable),
+            "parameters": parameters_json(&declaration.parameters, &declaration.annotations),
+            "constraints": constraints,
+            "body": body,
+            "doc": fun.doc,
+        })
+    }
+}
+
+fn sexp_list(head: &str, items: impl IntoIterator<Item = String>) -> String {
+    let mut out = format!("({}", head);
+    for item in items {
+        out.push(' ');
+        out.push_str(&item);
+    }
+    out.push(')');
+    out
+}
+
+/// `(x (y number))`, where annotated parameters are lists.
+fn parameters_sexp(parameters: &[Variable], annotations: &[Option<TypeAnnotation>]) -> String {
+    let parameters = parameters
+        .iter()
+        .zip(annotations)
+        .map(|(parameter, annotation)| match annotation {
+            Some(annotation) => format!("({} {})", parameter, annotation_sexp(annotation)),
+            None => parameter.to_string(),
+        });
+    let parameters: Vec<String> = parameters.collect();
+    format!("({})", parameters.join(" "))
+}
+
+fn annotation_sexp(annotation: &TypeAnnotation) -> String {
+    match annotation {
+        TypeAnnotation::Name(variable) => variable.to_string(),
+        TypeAnnotation::Generic(variable, arguments) => sexp_list(
+            variable.name.as_str(),
+            arguments.iter().map(annotation_sexp),
+        ),
+        TypeAnnotation::Tuple(elements) => sexp_list("tuple", elements.iter().map(annotation_sexp)),
+    }
+}
+
+fn pattern_sexp(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Variable(variable) => variable.to_string(),
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Literal(literal) => literal.literal.to_string(),
+        Pattern::Tuple(elements) => sexp_list("tuple", elements.iter().map(pattern_sexp)),
+        Pattern::Record(fields) => sexp_list("record", fields.iter().map(Variable::to_string)),
+        Pattern::List(list) => sexp_list(
+            "list",
+            list.elements.iter().map(pattern_sexp).chain(
+                list.rest
+                    .iter()
+                    .map(|rest| format!("(.. {})", pattern_sexp(rest))),
+            ),
+        ),
+        Pattern::Variant(variant) if variant.fields.is_empty() => variant.constructor.to_string(),
+        Pattern::Variant(variant) => sexp_list(
+            variant.constructor.name.as_str(),
+            variant.fields.iter().map(pattern_sexp),
+        ),
+    }
+}
+
+fn span_json(position: &Position) -> Value {
+    json!({
+        "file": position.file().index(),
+        "start": position.start(),
+        "end": position.end(),
+        "line": position.line(),
+    })
+}
+
+fn variable_json(variable: &Variable) -> Value {
+    json!({ "name": variable.name.as_str(), "span": span_json(&variable.position) })
+}
+
+fn variables_json(variables: &[Variable]) -> Vec<Value> {
+    variables.iter().map(variable_json).collect()
+}
+
+fn parameters_json(parameters: &[Variable], annotations: &[Option<TypeAnnotation>]) -> Vec<Value> {
+    parameters
+        .iter()
+        .zip(annotations)
+        .map(|(parameter, annotation)| {
+            json!({
+                "variable": variable_json(parameter),
+                "annotation": annotation.as_ref().map(|annotation| annotation.to_string()),
+            })
+        })
+        .collect()
+}
+
+fn pattern_json(pattern: &Pattern) -> Value {
+    let patterns =
+        |patterns: &[Pattern]| -> Vec<Value> { patterns.iter().map(pattern_json).collect() };
+    match pattern {
+        Pattern::Variable(variable) => {
+            json!({ "kind": "Variable", "variable": variable_json(variable) })
+        }
+        Pattern::Wildcard => json!({ "kind": "Wildcard" }),
+        Pattern::Literal(literal) => json!({
+            "kind": "Literal",
+            "literal": literal.literal.to_string(),
+            "span": span_json(&literal.position),
+        }),
+        Pattern::Tuple(elements) => json!({ "kind": "Tuple", "elements": patterns(elements) }),
+        Pattern::Record(fields) => json!({ "kind": "Record", "fields": variables_json(fields) }),
+        Pattern::List(list) => json!({
+            "kind": "List",
+            "elements": patterns(&list.elements),
+            "rest": list.rest.as_deref().map(pattern_json),
+            "span": span_json(&list.position),
+        }),
+        Pattern::Variant(variant) => json!({
+            "kind": "Variant",
+            "constructor": variable_json(&variant.constructor),
+            "fields": patterns(&variant.fields),
+        }),
+    }
+}
+
+struct SourcePrinter<'a> {
+    module: &'a ModuleAst,
+    out: String,
+    indent: usize,
+}
+
+impl SourcePrinter<'_> {
+    /// Starts a line at the current indentation.
+    fn line(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Writes an expression on a line of its own, after its doc comment.
+    fn statement(&mut self, id: ExprId) {
+        let doc = match &self.module[id] {
+            Expr::LetAssign(let_assign) => &let_assign.doc,
+            Expr::Function(fun) => &fun.doc,
+            Expr::Type(ty) => &ty.doc,
+            Expr::Interface(interface) => &interface.doc,
+            _ => &None,
+        };
+        if let Some(doc) = doc {
+            for line in doc.lines() {
+                self.line();
+                let _ = writeln!(self.out, "/// {}", line);
+            }
+        }
+        self.line();
+        self.expr(id);
+        self.out.push('\n');
+    }
+
+    fn block(&mut self, exprs: &[ExprId]) {
+        self.indent += 1;
+        for &expr in exprs {
+            self.statement(expr);
+        }
+        self.indent -= 1;
+        self.line();
+    }
+
+    fn list(&mut self, exprs: &[ExprId]) {
+        for (i, &expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, id: ExprId) {
+        let module = self.module;
+        match &module[id] {
+            Expr::Literal(literal) => {
+                let _ = write!(self.out, "{}", literal);
+            }
+            Expr::Grouping(grouping) => {
+                self.out.push('(');
+                self.expr(grouping.expr);
+                self.out.push(')');
+            }
+            Expr::Binary(binary) => {
+                self.expr(binary.lhs);
+                let _ = write!(self.out, " {} ", binary.operator.as_str());
+                self.expr(binary.rhs);
+            }
+            Expr::Unary(unary) => {
+                self.out.push_str(unary.operator.as_str());
+                self.expr(unary.expr);
+            }
+            Expr::LetAssign(let_assign) => {
+                self.out.push_str(if let_assign.mutable {
+                    "let mut "
+                } else {
+                    "let "
+                });
+                let _ = write!(self.out, "{} = ", let_assign.pattern);
+                self.expr(let_assign.initializer);
+                if let Some(otherwise) = let_assign.otherwise {
+                    self.out.push_str(" else ");
+                    self.expr(otherwise);
+                }
+            }
+            Expr::LetGet(let_get) => {
+                let _ = write!(self.out, "{}", let_get.variable);
+            }
+            Expr::LetSet(let_set) => {
+                let _ = write!(self.out, "{} = ", let_set.variable);
+                self.expr(let_set.initializer);
+            }
+            Expr::Function(fun) => self.function(fun),
+            // Blocks only occur as the bodies of functions, which have no
+            // syntax of their own.
+            Expr::Block(block) => {
+                for (i, &expr) in block.exprs.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push('\n');
+                        self.line();
+                    }
+                    self.expr(expr);
+                }
+            }
+            Expr::Return(ret) => {
+                self.out.push_str("return");
+                if let Some(expr) = ret.expr {
+                    self.out.push(' ');
+                    self.expr(expr);
+                }
+            }
+            Expr::Tuple(tuple) => {
+                self.out.push('(');
+                self.list(&tuple.elements);
+                if tuple.elements.len() == 1 {
+                    self.out.push(',');
+                }
+                self.out.push(')');
+            }
+            Expr::TupleIndex(tuple_index) => {
+                self.expr(tuple_index.tuple);
+                let _ = write!(self.out, ".{}", tuple_index.index);
+            }
+            Expr::Match(match_expr) => {
+                self.out.push_str("match ");
+                self.expr(match_expr.scrutinee);
+                self.out.push_str(" {\n");
+                self.indent += 1;
+                for arm in &match_expr.arms {
+                    self.line();
+                    let _ = write!(self.out, "| {} => ", arm.pattern);
+                    self.expr(arm.body);
+                    self.out.push('\n');
+                }
+                self.indent -= 1;
+                self.line();
+                self.out.push('}');
+            }
+            Expr::Call(call) => {
+                self.expr(call.callee);
+                self.out.push('(');
+                self.list(&call.arguments);
+                self.out.push(')');
+            }
+            Expr::Type(ty) => {
+                let _ = write!(self.out, "type {} =", ty.variable);
+                match &ty.definition {
+                    TypeDefinition::Record(fields) => {
+                        let _ = write!(self.out, " {{ {} }}", join(fields));
+                    }
+                    TypeDefinition::Variant(constructors) => {
+                        for constructor in constructors {
+                            let _ = write!(self.out, " | {}", constructor.variable);
+                            if !constructor.fields.is_empty() {
+                                let _ = write!(self.out, "({})", join(&constructor.fields));
+                            }
+                        }
+                    }
+                }
+            }
+            Expr::Impl(imp) => {
+                self.out.push_str("impl ");
+                if let Some(interface) = &imp.interface {
+                    let _ = write!(self.out, "{} for ", interface);
+                }
+                let _ = writeln!(self.out, "{}", imp.variable);
+                self.block(&imp.functions);
+                self.out.push_str("end");
+            }
+            Expr::Interface(interface) => {
+                let _ = writeln!(self.out, "interface {}", interface.variable);
+                self.indent += 1;
+                for signature in &interface.functions {
+                    self.line();
+                    let _ = writeln!(
+                        self.out,
+                        "def {}({})",
+                        signature.variable,
+                        parameters(&signature.parameters, &signature.annotations)
+                    );
+                }
+                self.indent -= 1;
+                self.line();
+                self.out.push_str("end");
+            }
+            Expr::Record(record) => {
+                let _ = write!(self.out, "{} {{", record.variable);
+                for (i, (field, value)) in record.fields.iter().enumerate() {
+                    self.out.push_str(if i > 0 { ", " } else { " " });
+                    let _ = write!(self.out, "{}", field);
+                    // `y: y` is written `y`.
+                    match &module[*value] {
+                        Expr::LetGet(let_get) if let_get.variable == *field => {}
+                        _ => {
+                            self.out.push_str(": ");
+                            self.expr(*value);
+                        }
+                    }
+                }
+                self.out.push_str(" }");
+            }
+            Expr::Field(field) => {
+                self.expr(field.record);
+                let _ = write!(self.out, ".{}", field.field);
+            }
+            Expr::SetField(set_field) => {
+                self.expr(set_field.record);
+                let _ = write!(self.out, ".{} = ", set_field.field);
+                self.expr(set_field.value);
+            }
+            Expr::MethodCall(method_call) => {
+                self.expr(method_call.receiver);
+                let _ = write!(self.out, ".{}(", method_call.method);
+                self.list(&method_call.arguments);
+                self.out.push(')');
+            }
+            Expr::Try(try_expr) => {
+                self.expr(try_expr.expr);
+                self.out.push('?');
+            }
+        }
+    }
+
+    fn function(&mut self, fun: &FunctionExpr) {
+        let declaration = &fun.declaration;
+        let _ = write!(
+            self.out,
+            "def {}({})",
+            fun.variable,
+            parameters(&declaration.parameters, &declaration.annotations)
+        );
+        for (i, constraint) in declaration.constraints.iter().enumerate() {
+            self.out.push_str(if i > 0 { ", " } else { " where " });
+            let _ = write!(
+                self.out,
+                "{}: {}",
+                constraint.parameter, constraint.interface
+            );
+        }
+        self.out.push('\n');
+        self.block(&declaration.body.exprs);
+        self.out.push_str("end");
+    }
+}
+
+fn join<T: fmt::Display>(items: &[T]) -> String {
+    let items: Vec<String> = items.iter().map(T::to_string).collect();
+    items.join(", ")
+}
+
+/// `x, y: number`
+fn parameters(parameters: &[Variable], annotations: &[Option<TypeAnnotation>]) -> String {
+    let parameters: Vec<String> = parameters
+        .iter()
+        .zip(annotations)
+        .map(|(parameter, annotation)| match annotation {
+            Some(annotation) => format!("{}: {}", parameter, annotation),
+            None => parameter.to_string(),
+        })
+        .collect();
+    parameters.join(", ")
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl fmt::Display for LiteralExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LiteralExpr::Number(n) => write!(f, "{}", n),
+            LiteralExpr::String(s) => write!(f, "\"{}\"", s),
+            LiteralExpr::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Variable(variable) => write!(f, "{}", variable),
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Literal(literal) => write!(f, "{}", literal.literal),
+            Pattern::Tuple(elements) if elements.len() == 1 => write!(f, "({},)", elements[0]),
+            Pattern::Tuple(elements) => write!(f, "({})", join(elements)),
+            Pattern::Record(fields) => write!(f, "{{ {} }}", join(fields)),
+            Pattern::List(list) => {
+                let mut items: Vec<String> = list.elements.iter().map(Pattern::to_string).collect();
+                match list.rest.as_deref() {
+                    Some(Pattern::Wildcard) => items.push("..".to_string()),
+                    Some(rest) => items.push(format!("..{}", rest)),
+                    None => {}
+                }
+                write!(f, "[{}]", items.join(", "))
+            }
+            Pattern::Variant(variant) if variant.fields.is_empty() => {
+                write!(f, "{}", variant.constructor)
+            }
+            Pattern::Variant(variant) => {
+                write!(f, "{}({})", variant.constructor, join(&variant.fields))
+            }
+        }
+    }
+}
+
+impl fmt::Display for TypeAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeAnnotation::Name(variable) => write!(f, "{}", variable),
+            TypeAnnotation::Generic(variable, arguments) => {
+                write!(f, "{}<{}>", variable, join(arguments))
+            }
+            TypeAnnotation::Tuple(elements) => write!(f, "({})", join(elements)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::lexer::Lexer;
+    use crate::syntax::parser::AstParser;
+
+    const SOURCE: &str = r#"/// A point.
+type point = { x, y }
+type shape = | Circle(r) | Empty
+interface Show
+    def show(x, prefix: string)
+end
+impl point
+    def add(a, b)
+        point { x: a.x + b.x, y }
+    end
+end
+impl Show for number
+    def show(x, prefix: string)
+        prefix
+    end
+end
+def area(s: option<number>, pair: (a, number)) where a: Show
+    let Some(r) = s else return nil
+    match s {
+        | Some(0) => -(r * 2)
+        | [first, ..rest] => first
+        | _ => (1,).0
+    }
+end
+let mut y = (1, "two")
+y = (nil, !x)
+let z = p.scale(2)?.x
+"#;
+
+    fn parse(source: &str) -> ModuleAst {
+        let mut tokens = Lexer::tokenize(source).unwrap();
+        AstParser::parse(&mut tokens).unwrap()
+    }
+
+    #[test]
+    fn display() {
+        let module = parse(SOURCE);
+        assert_eq!(SOURCE, module.to_string());
+
+        // Pipelines are printed as the calls they stand for.
+        let module = parse("let x = y.0 |> f(2) |> g\n");
+        assert_eq!("let x = g(f(y.0, 2))\n", module.to_string());
+        assert_eq!(module.to_sexp(), parse(&module.to_string()).to_sexp());
+    }
+
+    #[test]
+    fn sexp() {
+        let module = parse("let x = y * 2\n");
+        assert_eq!("(let x (* (get y) 2))\n", module.to_sexp());
+
+        let module = parse(SOURCE);
+        let sexp = module.to_sexp();
+        let lines: Vec<&str> = sexp.lines().collect();
+        assert_eq!("(type shape (variant (Circle r) Empty))", lines[1]);
+        assert_eq!(
+            "(impl point (def add (a b) (new point (x (+ (. (get a) x) (. (get b) x))) (y (get y)))))",
+            lines[3]
+        );
+        assert_eq!(
+            "(let z (. (? (method (get p) scale 2)) x))",
+            lines[lines.len() - 1]
+        );
+    }
+
+    #[test]
+    fn json() {
+        let module = parse("let x = y * 2\n");
+        let json = module.to_json();
+        let let_x = &json["exprs"][0];
+
+        assert_eq!("LetAssign", let_x["kind"]);
+        assert_eq!(
+            json!({ "file": 0, "start": 0, "end": 13, "line": 1 }),
+            let_x["span"]
+        );
+        assert_eq!("x", let_x["pattern"]["variable"]["name"]);
+        let product = &let_x["initializer"];
+        assert_eq!("*", product["operator"]);
+        assert_eq!("y", product["lhs"]["variable"]["name"]);
+        assert_eq!(2.0, product["rhs"]["value"]);
+        assert_eq!(12, product["rhs"]["span"]["start"]);
+    }
+}
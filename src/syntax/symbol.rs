@@ -0,0 +1,146 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ptr;
+use std::sync::{Mutex, OnceLock};
+
+/// An interned string. Every occurrence of a name is the same symbol, so
+/// names are compared and hashed by address and stored once.
+///
+/// Only names are interned: the strings of a running program are not, so
+/// the interner grows with the source, not with what it computes.
+#[derive(Clone, Copy)]
+pub struct Symbol(&'static str);
+
+/// The symbols the VM and the checker look up by themselves, interned
+/// before any other so they need no lookup.
+pub mod sym {
+    use super::Symbol;
+
+    pub static ADD: Symbol = Symbol("add");
+    pub static SUB: Symbol = Symbol("sub");
+    pub static MUL: Symbol = Symbol("mul");
+    pub static DIV: Symbol = Symbol("div");
+    pub static EQ: Symbol = Symbol("eq");
+    pub static LT: Symbol = Symbol("lt");
+    pub static NIL: Symbol = Symbol("nil");
+    pub static BOOL: Symbol = Symbol("bool");
+    pub static NUMBER: Symbol = Symbol("number");
+    pub static STRING: Symbol = Symbol("string");
+    pub static TUPLE: Symbol = Symbol("tuple");
+    pub static FUNCTION: Symbol = Symbol("function");
+
+    pub(super) static ALL: [&Symbol; 12] = [
+        &ADD, &SUB, &MUL, &DIV, &EQ, &LT, &NIL, &BOOL, &NUMBER, &STRING, &TUPLE, &FUNCTION,
+    ];
+}
+
+/// The strings of all symbols. They are never freed, there are only as many
+/// as there are distinct names.
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| {
+        let symbols = sym::ALL.iter().map(|symbol| (symbol.0, **symbol)).collect();
+        Mutex::new(Interner { symbols })
+    })
+}
+
+impl Symbol {
+    pub fn intern(string: &str) -> Symbol {
+        let mut interner = interner().lock().unwrap();
+        if let Some(symbol) = interner.symbols.get(string) {
+            return *symbol;
+        }
+
+        let string: &'static str = Box::leak(string.to_string().into_boxed_str());
+        let symbol = Symbol(string);
+        interner.symbols.insert(string, symbol);
+        symbol
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_ptr().hash(state)
+    }
+}
+
+/// Symbols are ordered by their strings, which are distinct.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(other.0)
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Self {
+        Symbol::intern(string)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intern() {
+        let a = Symbol::intern("point");
+        let b = Symbol::from("point");
+        let c = Symbol::intern("points");
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!("point", a.as_str());
+        assert_eq!(a, "point");
+        assert_eq!("\"points\"", format!("{:?}", c));
+        assert_eq!(sym::ADD, Symbol::intern("add"));
+        assert!(a < c);
+    }
+}
@@ -30,6 +30,7 @@ impl<'a> Token<'a> {
 }
 
 #[derive(Debug, PartialEq, Clone)] // TODO Clone
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenType {
     // Single-character tokens
     LeftParen,
@@ -39,16 +40,22 @@ pub enum TokenType {
     LeftBracket,
     RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Star,
+    Pipe,
+    Pipeline,
+    Question,
 
     // One or two character tokens
+    DotDot,
     Bang,
     BangEqual,
     Equal,
     EqualEqual,
+    FatArrow,
     LessThan,
     LessThanEqual,
     GreaterThan,
@@ -60,6 +67,8 @@ pub enum TokenType {
     String,
     Number,
 
+    DocComment,
+
     // Keywords
     Keyword(Keyword),
     Identifier,
@@ -71,41 +80,108 @@ pub enum TokenType {
 #[derive(Debug, PartialEq, Clone)] // TODO Clone
 pub enum Keyword {
     Let,
+    Mut,
     Def,
     Return,
+    Else,
     End,
     For,
     While,
     Nil,
+    Match,
+    Type,
+    Impl,
+    Interface,
+    Where,
+}
+
+impl Keyword {
+    pub const ALL: [Keyword; 14] = [
+        Keyword::Let,
+        Keyword::Mut,
+        Keyword::Def,
+        Keyword::Return,
+        Keyword::Else,
+        Keyword::End,
+        Keyword::For,
+        Keyword::While,
+        Keyword::Nil,
+        Keyword::Match,
+        Keyword::Type,
+        Keyword::Impl,
+        Keyword::Interface,
+        Keyword::Where,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Keyword::Let => "let",
+            Keyword::Mut => "mut",
+            Keyword::Def => "def",
+            Keyword::Return => "return",
+            Keyword::Else => "else",
+            Keyword::End => "end",
+            Keyword::For => "for",
+            Keyword::While => "while",
+            Keyword::Nil => "nil",
+            Keyword::Match => "match",
+            Keyword::Type => "type",
+            Keyword::Impl => "impl",
+            Keyword::Interface => "interface",
+            Keyword::Where => "where",
+        }
+    }
 }
 
 impl FromStr for Keyword {
     type Err = ();
 
     fn from_str(source: &str) -> Result<Self, Self::Err> {
-        Ok(match source {
-            "let" => Keyword::Let,
-            "def" => Keyword::Def,
-            "return" => Keyword::Return,
-            "end" => Keyword::End,
-            "for" => Keyword::For,
-            "while" => Keyword::While,
-            "nil" => Keyword::Nil,
-            _ => return Err(()),
-        })
+        Keyword::ALL
+            .iter()
+            .find(|keyword| keyword.as_str() == source)
+            .cloned()
+            .ok_or(())
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// The source file a position is in. Sources which are not read from a
+/// file, like those in tests, are all in the default one.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct FileId(u32);
+
+impl FileId {
+    pub fn new(index: u32) -> Self {
+        FileId(index)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A span of source, the bytes from `start` to `end` of a file, and the
+/// line it starts on.
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct Position {
     start: usize,
     end: usize,
     line: usize,
+    file: FileId,
 }
 
 impl Position {
     pub fn new(start: usize, end: usize, line: usize) -> Self {
-        Position { start, end, line }
+        Position {
+            start,
+            end,
+            line,
+            file: FileId::default(),
+        }
+    }
+
+    pub fn in_file(self, file: FileId) -> Self {
+        Position { file, ..self }
     }
 
     pub fn start(&self) -> &usize {
@@ -119,4 +195,8 @@ impl Position {
     pub fn line(&self) -> &usize {
         &self.line
     }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
 }
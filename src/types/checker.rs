@@ -0,0 +1,2359 @@
+use crate::prelude;
+use crate::syntax::ast::*;
+use crate::syntax::symbol::Symbol;
+use crate::types::host::{Host, Property};
+use crate::types::ty::Type;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+
+/// The type parameter standing for the implementing type in the functions
+/// of an interface.
+const SELF: &str = "Self";
+
+/// How many parts the type of an expression may have. Tuples of tuples can
+/// double it with every line, which would run out of memory.
+pub const MAX_TYPE_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    Mismatch(Type, Type, usize),
+    /// Indexing a value which is not a tuple, or past its end.
+    NoElement(Type, usize, usize),
+    NotCallable(Type, usize),
+    NoField(Type, Symbol, usize),
+    MissingField(Type, Symbol, usize),
+    /// An operator used on a type whose `impl` does not overload it.
+    NoOperator(Type, &'static str, usize),
+    /// A type which does not implement an interface it needs to.
+    NotImplemented(Type, Symbol, usize),
+    /// A method called on a type which has none of that name, while there
+    /// is no function of that name either.
+    NoMethod(Type, Symbol, usize),
+    /// A `?` on a value which is neither a `result` nor an `option`.
+    NotTryable(Type, usize),
+    /// A field of a record, or a property without a setter, assigned to.
+    NotAssignable(Type, Symbol, usize),
+    /// A record literal or pattern of a type which is not a record.
+    NotARecord(Type, usize),
+    /// A list pattern, which nothing can match while there are no lists.
+    ListPattern(usize),
+    /// A function of an interface used where the type implementing it is
+    /// not known.
+    Ambiguous(Symbol, usize),
+    /// A method called on a value whose type is not known.
+    UnknownReceiver(Symbol, usize),
+    /// An expression whose type has more than `MAX_TYPE_SIZE` parts.
+    TooLarge(usize),
+    /// A name given type arguments which is not a type.
+    UnknownType(Symbol, usize),
+    /// A type given a different number of type arguments than it takes.
+    TypeArguments(Symbol, usize, usize, usize),
+}
+
+impl TypeError {
+    pub fn line(&self) -> usize {
+        match self {
+            TypeError::Mismatch(_, _, line)
+            | TypeError::NoElement(_, _, line)
+            | TypeError::NotCallable(_, line)
+            | TypeError::NoField(_, _, line)
+            | TypeError::MissingField(_, _, line)
+            | TypeError::NoOperator(_, _, line)
+            | TypeError::NotImplemented(_, _, line)
+            | TypeError::NoMethod(_, _, line)
+            | TypeError::NotTryable(_, line)
+            | TypeError::NotAssignable(_, _, line)
+            | TypeError::NotARecord(_, line)
+            | TypeError::ListPattern(line)
+            | TypeError::Ambiguous(_, line)
+            | TypeError::UnknownReceiver(_, line)
+            | TypeError::TooLarge(line)
+            | TypeError::UnknownType(_, line)
+            | TypeError::TypeArguments(_, _, _, line) => *line,
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::Mismatch(expect, actual, _) => {
+                write!(f, "expected {}, found {}", expect, actual)
+            }
+            TypeError::NoElement(ty, index, _) => write!(f, "{} has no element {}", ty, index),
+            TypeError::NotCallable(ty, _) => write!(f, "{} is not a function", ty),
+            TypeError::NoField(ty, field, _) => write!(f, "{} has no field `{}`", ty, field),
+            TypeError::MissingField(ty, field, _) => {
+                write!(f, "missing field `{}` of {}", field, ty)
+            }
+            TypeError::NoOperator(ty, operator, _) => {
+                write!(f, "`{}` is not defined for {}", operator, ty)
+            }
+            TypeError::NotImplemented(ty, interface, _) => {
+                write!(f, "{} does not implement `{}`", ty, interface)
+            }
+            TypeError::NoMethod(ty, name, _) => write!(f, "{} has no method `{}`", ty, name),
+            TypeError::NotTryable(ty, _) => {
+                write!(f, "`?` needs a result or an option, found {}", ty)
+            }
+            TypeError::NotAssignable(ty, field, _) => {
+                write!(f, "`{}` of {} cannot be assigned", field, ty)
+            }
+            TypeError::NotARecord(ty, _) => write!(f, "{} is not a record", ty),
+            TypeError::ListPattern(_) => {
+                write!(
+                    f,
+                    "list patterns cannot match anything, there are no lists yet"
+                )
+            }
+            TypeError::Ambiguous(interface, _) => write!(
+                f,
+                "cannot tell which implementation of `{}` to use, the type is not known",
+                interface
+            ),
+            TypeError::UnknownReceiver(name, _) => write!(
+                f,
+                "cannot tell which `{}` to call, the type of the value is not known",
+                name
+            ),
+            TypeError::TooLarge(_) => write!(
+                f,
+                "type too large, it has more than {} parts",
+                MAX_TYPE_SIZE
+            ),
+            TypeError::UnknownType(name, _) => write!(f, "unknown type `{}`", name),
+            TypeError::TypeArguments(name, expect, actual, _) => write!(
+                f,
+                "expected {} type arguments for `{}`, found {}",
+                expect, name, actual
+            ),
+        }
+    }
+}
+
+/// Where the functions of an interface come from, for the type a function
+/// of it is used with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dictionary {
+    /// The `impl` of the interface for a type, by the names of the type and
+    /// the interface, and the names of the functions of the interface.
+    Impl(Symbol, Symbol, Vec<Symbol>),
+    /// The dictionary the enclosing function is passed for a type parameter
+    /// of its `where` clause, by parameter and interface.
+    Param(Symbol, Symbol),
+}
+
+/// What a variable which refers to a function with constraints stands for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instance {
+    /// The function of an interface of the given name, from a dictionary.
+    Function(Dictionary, Symbol),
+    /// A generic function applied to the dictionaries of its `where`
+    /// clause.
+    Generic(Vec<Dictionary>),
+}
+
+/// The function a method call calls.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    /// The function of the `impl` of a type, by type and function name.
+    Method(Symbol, Symbol),
+    /// The function of that name in scope.
+    Function,
+}
+
+/// The outcome of type checking a module.
+#[derive(Debug, Default)]
+pub struct TypeInfo {
+    bindings: SideTable<Type, VariableId>,
+    exprs: SideTable<Type>,
+    instances: SideTable<Instance>,
+    callees: SideTable<Callee>,
+    globals: Globals,
+    errors: Vec<TypeError>,
+}
+
+impl TypeInfo {
+    /// The type of the binding a variable declares.
+    pub fn binding(&self, variable: &Variable) -> Option<&Type> {
+        self.bindings.get(variable.id)
+    }
+
+    /// The type of every expression.
+    pub fn exprs(&self) -> &SideTable<Type> {
+        &self.exprs
+    }
+
+    /// What the `LetGet`s and method calls which refer to a function of an
+    /// interface, or to a function with a `where` clause, stand for.
+    pub fn instances(&self) -> &SideTable<Instance> {
+        &self.instances
+    }
+
+    /// The function every method call calls.
+    pub fn callees(&self) -> &SideTable<Callee> {
+        &self.callees
+    }
+
+    /// What the module declared at the top level, with what the modules
+    /// run before it did.
+    pub fn globals(&self) -> &Globals {
+        &self.globals
+    }
+
+    pub fn errors(&self) -> &[TypeError] {
+        &self.errors
+    }
+}
+
+/// The globals, types, interfaces and `impl`s declared by the modules run
+/// before, which a module run in the same VM can use.
+#[derive(Debug, Clone, Default)]
+pub struct Globals {
+    scope: HashMap<Symbol, Scheme>,
+    types: HashSet<Symbol>,
+    records: HashMap<Symbol, Vec<(Symbol, Type)>>,
+    methods: HashMap<(Symbol, Symbol), Type>,
+    interfaces: HashMap<Symbol, HashMap<Symbol, Type>>,
+    impls: HashSet<(Symbol, Symbol)>,
+}
+
+/// The type of a variable. Wherever a generic function is used its type
+/// parameters are replaced by fresh variables, which must then satisfy its
+/// `where` clause.
+#[derive(Debug, Clone)]
+struct Scheme {
+    ty: Type,
+    params: Vec<Symbol>,
+    /// The interfaces type parameters must implement.
+    constraints: Vec<(Symbol, Symbol)>,
+    /// The interface the function belongs to, if it is one of its
+    /// functions.
+    interface: Option<Symbol>,
+}
+
+/// A use of a function with constraints, whose dictionaries are known once
+/// all types are.
+struct Use {
+    id: ExprId,
+    name: Symbol,
+    interface: Option<Symbol>,
+    /// The types the constraints of the function are instantiated with.
+    constraints: Vec<(Type, Symbol)>,
+    line: usize,
+}
+
+/// A method call on a value whose type was not known where it was checked.
+struct Deferred {
+    id: ExprId,
+    name: Symbol,
+    receiver: Type,
+    /// The type of the call, from the receiver and arguments to the result.
+    call: Type,
+    line: usize,
+}
+
+/// An interface a type has to implement, checked once all types are known.
+struct Obligation {
+    ty: Type,
+    interface: Symbol,
+    line: usize,
+    /// The `where` clauses in scope where the obligation arose.
+    assumptions: Vec<(Symbol, Symbol)>,
+}
+
+/// Infers types by unification. Names which cannot be found are given a
+/// fresh type variable; reporting them is left to name resolution.
+///
+/// The fields of a record have one type for all its values, and so do the
+/// functions in an `impl`. Only functions with type parameters in their
+/// annotations are generic, and the functions of interfaces, which are
+/// generic over the implementing type.
+pub struct TypeChecker<'a> {
+    module: &'a ModuleAst,
+    substitution: Vec<Option<Type>>,
+    /// The names of the types declared with `type`, and of the prelude.
+    types: HashSet<Symbol>,
+    records: HashMap<Symbol, Vec<(Symbol, Type)>>,
+    /// The properties of every type implemented by the host.
+    properties: HashMap<Symbol, HashMap<Symbol, Property>>,
+    /// The type of every function in an `impl`, by type and function name.
+    methods: HashMap<(Symbol, Symbol), Type>,
+    /// The type of every function of an interface, in terms of `Self`.
+    interfaces: HashMap<Symbol, HashMap<Symbol, Type>>,
+    /// The interfaces implemented by every type, by type and interface name.
+    impls: HashSet<(Symbol, Symbol)>,
+    scopes: Vec<HashMap<Symbol, Scheme>>,
+    /// The type parameters of the functions being checked.
+    params: Vec<Symbol>,
+    /// The `where` clauses of the functions being checked.
+    assumptions: Vec<(Symbol, Symbol)>,
+    obligations: Vec<Obligation>,
+    uses: Vec<Use>,
+    deferred: Vec<Deferred>,
+    returns: Vec<Type>,
+    bindings: SideTable<Type, VariableId>,
+    exprs: SideTable<Type>,
+    callees: SideTable<Callee>,
+    errors: Vec<TypeError>,
+    /// The line of the declaration being checked, expressions do not carry
+    /// their own position.
+    line: usize,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn new(module: &'a ModuleAst) -> Self {
+        TypeChecker {
+            module,
+            substitution: vec![],
+            types: HashSet::new(),
+            records: HashMap::new(),
+            properties: HashMap::new(),
+            methods: HashMap::new(),
+            interfaces: HashMap::new(),
+            impls: HashSet::new(),
+            scopes: vec![HashMap::new()],
+            params: vec![],
+            assumptions: vec![],
+            obligations: vec![],
+            uses: vec![],
+            deferred: vec![],
+            returns: vec![],
+            bindings: SideTable::default(),
+            exprs: SideTable::default(),
+            callees: SideTable::default(),
+            errors: vec![],
+            line: 1,
+        }
+    }
+
+    pub fn check(module: &'a ModuleAst) -> TypeInfo {
+        TypeChecker::check_with(module, &Host::default(), &Globals::default())
+    }
+
+    /// Checks a module run after the host has declared globals and types of
+    /// its own, and after the modules which declared `globals`.
+    pub fn check_with(module: &'a ModuleAst, host: &Host, globals: &Globals) -> TypeInfo {
+        let mut checker = TypeChecker::new(module);
+        checker.declare_prelude();
+        checker.declare_globals(globals);
+        checker.declare_host(host);
+        // Types, interfaces and their functions can be used before they
+        // are declared.
+        for expr in module.top_level() {
+            if let Expr::Type(ty) = expr {
+                checker.declare_type(ty);
+            }
+        }
+        for expr in module.top_level() {
+            if let Expr::Interface(interface) = expr {
+                checker.declare_interface(interface);
+            }
+        }
+        for expr in module.top_level() {
+            if let Expr::Impl(imp) = expr {
+                for (_, fun) in module.functions(imp) {
+                    let ty = checker.function_type(fun);
+                    checker.bindings.insert(fun.variable.id, ty.clone());
+                    let key = (imp.variable.name, fun.variable.name);
+                    checker.methods.insert(key, ty);
+                }
+                if let Some(interface) = &imp.interface {
+                    let ty = checker.named_type(imp.variable.name);
+                    checker.impls.insert((ty.impl_name(), interface.name));
+                }
+            }
+        }
+        // So are the functions at the top level.
+        for expr in module.top_level() {
+            if let Expr::Function(fun) = expr {
+                let scheme = checker.function_scheme(fun);
+                checker.declare_scheme(&fun.variable, scheme);
+            }
+        }
+        for &expr in &module.exprs {
+            checker.infer(expr);
+        }
+        for deferred in mem::take(&mut checker.deferred) {
+            checker.resolve_method(deferred);
+        }
+        for obligation in mem::take(&mut checker.obligations) {
+            checker.discharge(obligation);
+        }
+        let mut instances = SideTable::default();
+        for use_ in mem::take(&mut checker.uses) {
+            if let Some(instance) = checker.instance(&use_) {
+                instances.insert(use_.id, instance);
+            }
+        }
+
+        let mut bindings = SideTable::default();
+        for (id, ty) in checker.bindings.iter() {
+            bindings.insert(id, checker.apply(ty));
+        }
+        let mut exprs = SideTable::default();
+        for (id, ty) in checker.exprs.iter() {
+            exprs.insert(id, checker.apply(ty));
+        }
+        let globals = checker.globals();
+        TypeInfo {
+            bindings,
+            exprs,
+            instances,
+            callees: checker.callees,
+            globals,
+            errors: checker.errors,
+        }
+    }
+
+    /// Infers the type of an expression and records it.
+    fn infer(&mut self, id: ExprId) -> Type {
+        let mut ty = self.infer_expr(id);
+        if self.too_large(&ty) {
+            self.errors.push(TypeError::TooLarge(self.line));
+            ty = self.fresh();
+        }
+        self.exprs.insert(id, ty.clone());
+        ty
+    }
+
+    fn infer_expr(&mut self, id: ExprId) -> Type {
+        let module = self.module;
+        match &module[id] {
+            Expr::Literal(literal) => literal_type(literal),
+            Expr::Grouping(grouping) => self.infer(grouping.expr),
+            Expr::Binary(binary) => self.infer_binary(binary),
+            Expr::Unary(unary) => {
+                let (operand, result) = match unary.operator {
+                    UnaryOperator::Negate => (Type::Number, Type::Number),
+                    UnaryOperator::Not => (Type::Bool, Type::Bool),
+                };
+                let actual = self.infer(unary.expr);
+                self.expect(&operand, &actual);
+                result
+            }
+            Expr::LetAssign(let_assign) => {
+                if let Some(variable) = let_assign.pattern.variables().first() {
+                    self.line = *variable.position.line();
+                }
+                let ty = self.infer(let_assign.initializer);
+                if let Some(otherwise) = &let_assign.otherwise {
+                    self.infer(*otherwise);
+                }
+                self.bind(&let_assign.pattern, ty);
+                Type::Nil
+            }
+            Expr::LetGet(let_get) => {
+                self.line = *let_get.variable.position.line();
+                match self.find(let_get.variable.name) {
+                    Some(scheme) => self.instantiate_use(id, let_get.variable.name, &scheme),
+                    None => self.fresh(),
+                }
+            }
+            Expr::LetSet(let_set) => {
+                self.line = *let_set.variable.position.line();
+                let expect = self.lookup(let_set.variable.name);
+                let actual = self.infer(let_set.initializer);
+                self.expect(&expect, &actual);
+                Type::Nil
+            }
+            Expr::Function(fun) => {
+                self.infer_function(fun);
+                Type::Nil
+            }
+            Expr::Block(block) => self.infer_block(block),
+            Expr::Return(ret) => {
+                let actual = match ret.expr {
+                    Some(expr) => self.infer(expr),
+                    None => Type::Nil,
+                };
+                if let Some(expect) = self.returns.last().cloned() {
+                    self.expect(&expect, &actual);
+                }
+                self.fresh()
+            }
+            Expr::Tuple(tuple) => {
+                Type::Tuple(tuple.elements.iter().map(|&e| self.infer(e)).collect())
+            }
+            Expr::TupleIndex(index) => {
+                let ty = self.infer(index.tuple);
+                match self.shallow(&ty) {
+                    Type::Tuple(elements) if index.index < elements.len() => {
+                        elements[index.index].clone()
+                    }
+                    // The length of the tuple is not known yet.
+                    Type::Var(_) => self.fresh(),
+                    ty => {
+                        let error = TypeError::NoElement(self.apply(&ty), index.index, self.line);
+                        self.errors.push(error);
+                        self.fresh()
+                    }
+                }
+            }
+            Expr::Call(call) => {
+                let callee = self.infer(call.callee);
+                let arguments = call.arguments.iter().map(|&a| self.infer(a)).collect();
+                match self.shallow(&callee) {
+                    Type::Function(_, _) | Type::Var(_) => {
+                        let ret = self.fresh();
+                        let actual = Type::Function(arguments, Box::new(ret.clone()));
+                        self.expect(&callee, &actual);
+                        ret
+                    }
+                    ty => {
+                        let error = TypeError::NotCallable(self.apply(&ty), self.line);
+                        self.errors.push(error);
+                        self.fresh()
+                    }
+                }
+            }
+            Expr::Type(_) | Expr::Interface(_) => Type::Nil,
+            Expr::Impl(imp) => {
+                let receiver = self.named_type(imp.variable.name);
+                let interface = imp
+                    .interface
+                    .as_ref()
+                    .and_then(|interface| self.interfaces.get(&interface.name))
+                    .cloned()
+                    .unwrap_or_default();
+
+                for (_, fun) in module.functions(imp) {
+                    self.line = *fun.variable.position.line();
+                    let key = (imp.variable.name, fun.variable.name);
+                    let ty = self.methods[&key].clone();
+                    match interface.get(&fun.variable.name) {
+                        Some(signature) => {
+                            let mut params =
+                                HashMap::from([(Symbol::intern(SELF), receiver.clone())]);
+                            let expect = self.replace_params(signature, &mut params);
+                            self.expect(&expect, &ty);
+                        }
+                        None => {
+                            if let Type::Function(params, _) = &ty {
+                                if let Some(first) = params.first() {
+                                    self.expect(&receiver, first);
+                                }
+                            }
+                        }
+                    }
+                    self.infer_body(fun, ty);
+                }
+                Type::Nil
+            }
+            Expr::Record(record) => {
+                self.line = *record.variable.position.line();
+                let ty = Type::Named(record.variable.name, vec![]);
+                if self.properties.contains_key(&record.variable.name) {
+                    self.errors
+                        .push(TypeError::NotARecord(ty.clone(), self.line));
+                }
+                let fields = match self.records.get(&record.variable.name) {
+                    Some(fields) => fields.clone(),
+                    None => {
+                        for &(_, value) in &record.fields {
+                            self.infer(value);
+                        }
+                        return self.fresh();
+                    }
+                };
+
+                for (field, value) in &record.fields {
+                    let actual = self.infer(*value);
+                    match fields.iter().find(|(name, _)| name == &field.name) {
+                        Some((_, expect)) => self.expect(expect, &actual),
+                        None => {
+                            let error = TypeError::NoField(ty.clone(), field.name, self.line);
+                            self.errors.push(error);
+                        }
+                    }
+                }
+                for (name, _) in &fields {
+                    if !record.fields.iter().any(|(field, _)| &field.name == name) {
+                        let error = TypeError::MissingField(ty.clone(), *name, self.line);
+                        self.errors.push(error);
+                    }
+                }
+                ty
+            }
+            Expr::Field(field) => {
+                let ty = self.infer(field.record);
+                self.infer_record(&ty, &[field.field.name]);
+                let found = match self.shallow(&ty) {
+                    // The record is not known yet.
+                    Type::Var(_) => return self.fresh(),
+                    Type::Named(name, _) => match self.properties.get(&name) {
+                        Some(properties) => properties
+                            .get(&field.field.name)
+                            .map(|property| property.ty.clone())
+                            .map(|ty| self.replace_params(&ty, &mut HashMap::new())),
+                        None => self.records.get(&name).and_then(|fields| {
+                            fields
+                                .iter()
+                                .find(|(name, _)| name == &field.field.name)
+                                .map(|(_, ty)| ty.clone())
+                        }),
+                    },
+                    _ => None,
+                };
+                found.unwrap_or_else(|| {
+                    let error = TypeError::NoField(self.apply(&ty), field.field.name, self.line);
+                    self.errors.push(error);
+                    self.fresh()
+                })
+            }
+            Expr::SetField(set_field) => {
+                let ty = self.infer(set_field.record);
+                let value = self.infer(set_field.value);
+                let name = set_field.field.name;
+                self.line = *set_field.field.position.line();
+                let property = match self.shallow(&ty) {
+                    // The type is not known yet.
+                    Type::Var(_) => return Type::Nil,
+                    Type::Named(ty, _) => self
+                        .properties
+                        .get(&ty)
+                        .and_then(|properties| properties.get(&name))
+                        .cloned(),
+                    _ => None,
+                };
+                match property {
+                    Some(property) if property.settable => {
+                        let expect = self.replace_params(&property.ty, &mut HashMap::new());
+                        self.expect(&expect, &value);
+                    }
+                    _ => {
+                        let error = TypeError::NotAssignable(self.apply(&ty), name, self.line);
+                        self.errors.push(error);
+                    }
+                }
+                Type::Nil
+            }
+            Expr::MethodCall(call) => self.infer_method_call(id, call),
+            Expr::Try(try_expr) => self.infer_try(try_expr),
+            Expr::Match(match_expr) => {
+                let scrutinee = self.infer(match_expr.scrutinee);
+                let result = self.fresh();
+                for arm in &match_expr.arms {
+                    self.scopes.push(HashMap::new());
+                    self.bind(&arm.pattern, scrutinee.clone());
+                    let actual = self.infer(arm.body);
+                    self.expect(&result, &actual);
+                    self.scopes.pop();
+                }
+                result
+            }
+        }
+    }
+
+    /// Checks a call of the method of the receiver's type, or of the
+    /// function of that name in scope if there is no such method. A
+    /// receiver whose type is not known yet is left for the end.
+    fn infer_method_call(&mut self, id: ExprId, call: &MethodCallExpr) -> Type {
+        let receiver = self.infer(call.receiver);
+        let mut arguments = vec![receiver.clone()];
+        arguments.extend(call.arguments.iter().map(|&a| self.infer(a)));
+        self.line = *call.method.position.line();
+
+        let name = call.method.name;
+        let ret = self.fresh();
+        let call = Type::Function(arguments, Box::new(ret.clone()));
+        let receiver = self.shallow(&receiver);
+        let method = match &receiver {
+            Type::Var(_) => None,
+            ty => self.methods.get(&(ty.impl_name(), name)).cloned(),
+        };
+        let function = match (method, self.find(name)) {
+            (Some(method), _) => {
+                self.callees
+                    .insert(id, Callee::Method(receiver.impl_name(), name));
+                self.replace_params(&method, &mut HashMap::new())
+            }
+            (None, Some(scheme)) => {
+                self.callees.insert(id, Callee::Function);
+                self.instantiate_use(id, name, &scheme)
+            }
+            (None, None) if matches!(receiver, Type::Var(_)) => {
+                self.deferred.push(Deferred {
+                    id,
+                    name,
+                    receiver,
+                    call,
+                    line: self.line,
+                });
+                return ret;
+            }
+            (None, None) => {
+                self.callees
+                    .insert(id, Callee::Method(receiver.impl_name(), name));
+                let error = TypeError::NoMethod(self.apply(&receiver), name, self.line);
+                self.errors.push(error);
+                return ret;
+            }
+        };
+
+        self.expect(&function, &call);
+        ret
+    }
+
+    /// Checks a method call on a receiver whose type was not known where
+    /// it was checked. If it is still not known, it is the one type with
+    /// a method of that name.
+    fn resolve_method(&mut self, deferred: Deferred) {
+        self.line = deferred.line;
+        let name = deferred.name;
+        let receiver = match self.shallow(&deferred.receiver) {
+            Type::Var(_) => {
+                let mut types = self.methods.keys().filter(|(_, method)| *method == name);
+                let ty = match (types.next(), types.next()) {
+                    (Some(&(ty, _)), None) => self.named_type(ty),
+                    _ => {
+                        let error = TypeError::UnknownReceiver(name, self.line);
+                        self.errors.push(error);
+                        return;
+                    }
+                };
+                self.expect(&ty, &deferred.receiver);
+                ty
+            }
+            ty => ty,
+        };
+
+        self.callees
+            .insert(deferred.id, Callee::Method(receiver.impl_name(), name));
+        match self.methods.get(&(receiver.impl_name(), name)).cloned() {
+            Some(method) => {
+                let method = self.replace_params(&method, &mut HashMap::new());
+                self.expect(&method, &deferred.call);
+            }
+            None => {
+                let error = TypeError::NoMethod(self.apply(&receiver), name, self.line);
+                self.errors.push(error);
+            }
+        }
+    }
+
+    /// Checks `value?`, which returns the `None` or `Err` of the value from
+    /// the enclosing function. Unwrapping a value of a type not known yet
+    /// takes the return type as a hint.
+    fn infer_try(&mut self, try_expr: &TryExpr) -> Type {
+        let ty = self.infer(try_expr.expr);
+        self.line = *try_expr.position.line();
+        let ret = match self.returns.last() {
+            Some(ret) => ret.clone(),
+            // Reported by name resolution.
+            None => return self.fresh(),
+        };
+
+        let ty = match (self.shallow(&ty), self.shallow(&ret)) {
+            (Type::Var(_), Type::Named(name, arguments))
+                if prelude::variant_type(name.as_str()).is_some() =>
+            {
+                let mut fresh: Vec<Type> = arguments.iter().map(|_| self.fresh()).collect();
+                if name == "result" {
+                    fresh[1] = arguments[1].clone();
+                }
+                let expect = Type::Named(name, fresh);
+                self.expect(&expect, &ty);
+                expect
+            }
+            (ty, _) => ty,
+        };
+
+        match ty {
+            Type::Named(name, arguments) if name == "option" => {
+                let expect = Type::Named(name, vec![self.fresh()]);
+                self.expect(&ret, &expect);
+                arguments[0].clone()
+            }
+            Type::Named(name, arguments) if name == "result" => {
+                let expect = Type::Named(name, vec![self.fresh(), arguments[1].clone()]);
+                self.expect(&ret, &expect);
+                arguments[0].clone()
+            }
+            // Neither the value nor the return type is known yet.
+            Type::Var(_) => self.fresh(),
+            ty => {
+                let error = TypeError::NotTryable(self.apply(&ty), self.line);
+                self.errors.push(error);
+                self.fresh()
+            }
+        }
+    }
+
+    fn infer_binary(&mut self, binary: &BinaryExpr) -> Type {
+        let lhs = self.infer(binary.lhs);
+        let rhs = self.infer(binary.rhs);
+        if let Type::Named(name, _) = self.shallow(&lhs) {
+            return self.infer_operator(name, binary, lhs, rhs);
+        }
+
+        match binary.operator {
+            BinaryOperator::Add
+            | BinaryOperator::Subtract
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide => {
+                self.expect_numbers(&lhs, &rhs);
+                Type::Number
+            }
+            BinaryOperator::LessThan
+            | BinaryOperator::LessThanEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanEqual => {
+                self.expect_numbers(&lhs, &rhs);
+                Type::Bool
+            }
+            BinaryOperator::Equal | BinaryOperator::BangEqual => {
+                self.expect(&lhs, &rhs);
+                Type::Bool
+            }
+        }
+    }
+
+    /// Expects both operands of an arithmetic or comparison operator to be
+    /// numbers. Only the first which is not is reported, `"a" + "b"` is one
+    /// mistake.
+    fn expect_numbers(&mut self, lhs: &Type, rhs: &Type) {
+        let operand = if self.unify(&Type::Number, lhs) {
+            rhs
+        } else {
+            lhs
+        };
+        self.expect(&Type::Number, operand);
+    }
+
+    /// Checks an operator on a declared type against the function of its
+    /// `impl` which overloads it.
+    fn infer_operator(&mut self, name: Symbol, binary: &BinaryExpr, lhs: Type, rhs: Type) -> Type {
+        let operator = &binary.operator;
+        let key = (name, operator.method());
+        let method = match self.methods.get(&key) {
+            Some(method) => method.clone(),
+            // Values of the same type can always be compared.
+            None if matches!(operator, BinaryOperator::Equal | BinaryOperator::BangEqual) => {
+                self.expect(&lhs, &rhs);
+                return Type::Bool;
+            }
+            None => {
+                let ty = self.apply(&lhs);
+                let error = TypeError::NoOperator(ty, operator.as_str(), self.line);
+                self.errors.push(error);
+                return self.fresh();
+            }
+        };
+
+        let arguments = match operator {
+            BinaryOperator::GreaterThan | BinaryOperator::LessThanEqual => vec![rhs, lhs],
+            _ => vec![lhs, rhs],
+        };
+        // Each operand is checked against its parameter, so a mismatch is
+        // reported as one between their types.
+        let method = self.replace_params(&method, &mut HashMap::new());
+        let ret = match self.shallow(&method) {
+            Type::Function(params, ret) if params.len() == arguments.len() => {
+                for (param, argument) in params.iter().zip(&arguments) {
+                    self.expect(param, argument);
+                }
+                *ret
+            }
+            _ => {
+                let ret = self.fresh();
+                self.expect(&method, &Type::Function(arguments, Box::new(ret.clone())));
+                ret
+            }
+        };
+
+        match operator {
+            BinaryOperator::Add
+            | BinaryOperator::Subtract
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide => ret,
+            _ => {
+                self.expect(&Type::Bool, &ret);
+                Type::Bool
+            }
+        }
+    }
+
+    fn declare_type(&mut self, ty: &TypeExpr) {
+        self.types.insert(ty.variable.name);
+        let named = Type::Named(ty.variable.name, vec![]);
+        match &ty.definition {
+            TypeDefinition::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|field| (field.name, self.fresh()))
+                    .collect();
+                self.records.insert(ty.variable.name, fields);
+            }
+            TypeDefinition::Variant(constructors) => {
+                for constructor in constructors {
+                    let ty = match constructor.fields.len() {
+                        0 => named.clone(),
+                        n => {
+                            let fields = (0..n).map(|_| self.fresh()).collect();
+                            Type::Function(fields, Box::new(named.clone()))
+                        }
+                    };
+                    self.declare(&constructor.variable, ty);
+                }
+            }
+        }
+    }
+
+    /// Declares the functions of an interface, which are generic over the
+    /// type implementing it. An unannotated first parameter is that type.
+    fn declare_interface(&mut self, interface: &InterfaceExpr) {
+        let mut functions = HashMap::new();
+        for signature in &interface.functions {
+            let params = signature
+                .annotations
+                .iter()
+                .enumerate()
+                .map(|(i, annotation)| match annotation {
+                    Some(annotation) => self.annotation_type(annotation),
+                    None if i == 0 => Type::Param(Symbol::intern(SELF)),
+                    None => self.fresh(),
+                })
+                .collect();
+            let ty = Type::Function(params, Box::new(self.fresh()));
+
+            let scheme = Scheme {
+                ty: ty.clone(),
+                params: vec![Symbol::intern(SELF)],
+                constraints: vec![(Symbol::intern(SELF), interface.variable.name)],
+                interface: Some(interface.variable.name),
+            };
+            self.declare_scheme(&signature.variable, scheme);
+            functions.insert(signature.variable.name, ty);
+        }
+        self.interfaces.insert(interface.variable.name, functions);
+    }
+
+    /// Declares the constructors of the variant types of the prelude, which
+    /// are generic over their type parameters, and its functions.
+    fn declare_prelude(&mut self) {
+        let globals = &mut self.scopes[0];
+        for ty in &prelude::TYPES {
+            self.types.insert(Symbol::intern(ty.name));
+            let params: Vec<Symbol> = ty
+                .params
+                .iter()
+                .map(|param| Symbol::intern(param))
+                .collect();
+            let named = Type::Named(
+                Symbol::intern(ty.name),
+                params.iter().cloned().map(Type::Param).collect(),
+            );
+            for (name, field) in ty.constructors {
+                let ty = match field {
+                    Some(field) => {
+                        let field = Type::Param(Symbol::intern(field));
+                        Type::Function(vec![field], Box::new(named.clone()))
+                    }
+                    None => named.clone(),
+                };
+                let scheme = Scheme {
+                    ty,
+                    params: params.clone(),
+                    constraints: vec![],
+                    interface: None,
+                };
+                globals.insert(Symbol::intern(name), scheme);
+            }
+        }
+
+        // `panic` never returns, so its result fits anywhere.
+        let panic = Scheme {
+            ty: Type::Function(
+                vec![Type::String],
+                Box::new(Type::Param(Symbol::intern("t"))),
+            ),
+            params: vec![Symbol::intern("t")],
+            constraints: vec![],
+            interface: None,
+        };
+        globals.insert(Symbol::intern("panic"), panic);
+    }
+
+    /// Declares the globals of the host, which are generic over the type
+    /// parameters in their types, and its types.
+    fn declare_host(&mut self, host: &Host) {
+        for (name, ty) in &host.globals {
+            let mut params = vec![];
+            self.collect_params(ty, &mut params);
+            let scheme = Scheme {
+                ty: ty.clone(),
+                params,
+                constraints: vec![],
+                interface: None,
+            };
+            self.scopes[0].insert(*name, scheme);
+        }
+        for (name, ty) in &host.types {
+            self.types.insert(*name);
+            for (method, function) in &ty.methods {
+                self.methods.insert((*name, *method), function.clone());
+            }
+            self.properties.insert(*name, ty.properties.clone());
+        }
+    }
+
+    /// Declares what the modules run before declared. The types they left
+    /// unknown are still to be found, by the variables they are given here.
+    fn declare_globals(&mut self, globals: &Globals) {
+        let mut vars = HashMap::new();
+        for (name, scheme) in &globals.scope {
+            let scheme = Scheme {
+                ty: self.import(&scheme.ty, &mut vars),
+                ..scheme.clone()
+            };
+            self.scopes[0].insert(*name, scheme);
+        }
+        self.types.extend(&globals.types);
+        for (name, fields) in &globals.records {
+            let fields = fields
+                .iter()
+                .map(|(field, ty)| (*field, self.import(ty, &mut vars)))
+                .collect();
+            self.records.insert(*name, fields);
+        }
+        for (key, ty) in &globals.methods {
+            let ty = self.import(ty, &mut vars);
+            self.methods.insert(*key, ty);
+        }
+        for (name, functions) in &globals.interfaces {
+            let functions = functions
+                .iter()
+                .map(|(function, ty)| (*function, self.import(ty, &mut vars)))
+                .collect();
+            self.interfaces.insert(*name, functions);
+        }
+        self.impls.extend(&globals.impls);
+    }
+
+    /// Replaces the variables of a type from another module by variables
+    /// of this one, the same for every occurrence.
+    fn import(&mut self, ty: &Type, vars: &mut HashMap<usize, Type>) -> Type {
+        match ty {
+            Type::Var(var) => vars.entry(*var).or_insert_with(|| self.fresh()).clone(),
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|p| self.import(p, vars)).collect(),
+                Box::new(self.import(ret, vars)),
+            ),
+            Type::Tuple(elements) => {
+                Type::Tuple(elements.iter().map(|e| self.import(e, vars)).collect())
+            }
+            Type::Named(name, arguments) => Type::Named(
+                *name,
+                arguments.iter().map(|a| self.import(a, vars)).collect(),
+            ),
+            ty => ty.clone(),
+        }
+    }
+
+    /// What is declared at the top level once the module is checked, for
+    /// the modules run after it.
+    fn globals(&self) -> Globals {
+        let scope = self.scopes[0]
+            .iter()
+            .map(|(name, scheme)| {
+                let scheme = Scheme {
+                    ty: self.apply(&scheme.ty),
+                    ..scheme.clone()
+                };
+                (*name, scheme)
+            })
+            .collect();
+        let records = self
+            .records
+            .iter()
+            .map(|(name, fields)| {
+                let fields = fields
+                    .iter()
+                    .map(|(field, ty)| (*field, self.apply(ty)))
+                    .collect();
+                (*name, fields)
+            })
+            .collect();
+        let methods = self
+            .methods
+            .iter()
+            .map(|(key, ty)| (*key, self.apply(ty)))
+            .collect();
+        let interfaces = self
+            .interfaces
+            .iter()
+            .map(|(name, functions)| {
+                let functions = functions
+                    .iter()
+                    .map(|(function, ty)| (*function, self.apply(ty)))
+                    .collect();
+                (*name, functions)
+            })
+            .collect();
+        Globals {
+            scope,
+            types: self.types.clone(),
+            records,
+            methods,
+            interfaces,
+            impls: self.impls.clone(),
+        }
+    }
+
+    /// The type an `impl` is for. The type arguments of a variant type of
+    /// the prelude are left open.
+    fn named_type(&mut self, name: Symbol) -> Type {
+        if let Some(ty) = Type::built_in(name.as_str()) {
+            return ty;
+        }
+        let arguments = match prelude::variant_type(name.as_str()) {
+            Some(variant) => variant.params.iter().map(|_| self.fresh()).collect(),
+            None => vec![],
+        };
+        Type::Named(name, arguments)
+    }
+
+    fn annotation_type(&mut self, annotation: &TypeAnnotation) -> Type {
+        match annotation {
+            TypeAnnotation::Name(variable) if self.types.contains(&variable.name) => {
+                self.named_type(variable.name)
+            }
+            TypeAnnotation::Generic(variable, arguments) => {
+                let name = variable.name;
+                let line = *variable.position.line();
+                if !self.types.contains(&name) && Type::built_in(name.as_str()).is_none() {
+                    self.errors.push(TypeError::UnknownType(name, line));
+                    return self.fresh();
+                }
+                let params = prelude::variant_type(name.as_str()).map_or(0, |ty| ty.params.len());
+                if arguments.len() != params {
+                    self.errors.push(TypeError::TypeArguments(
+                        name,
+                        params,
+                        arguments.len(),
+                        line,
+                    ));
+                    return self.named_type(name);
+                }
+                Type::Named(
+                    name,
+                    arguments
+                        .iter()
+                        .map(|argument| self.annotation_type(argument))
+                        .collect(),
+                )
+            }
+            TypeAnnotation::Name(variable) => {
+                Type::built_in(variable.name.as_str()).unwrap_or(Type::Param(variable.name))
+            }
+            TypeAnnotation::Tuple(elements) => Type::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.annotation_type(element))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn function_type(&mut self, fun: &FunctionExpr) -> Type {
+        let params = fun
+            .declaration
+            .annotations
+            .iter()
+            .map(|annotation| match annotation {
+                Some(annotation) => self.annotation_type(annotation),
+                None => self.fresh(),
+            })
+            .collect();
+        Type::Function(params, Box::new(self.fresh()))
+    }
+
+    fn function_scheme(&mut self, fun: &FunctionExpr) -> Scheme {
+        let ty = self.function_type(fun);
+        let mut params = vec![];
+        self.collect_params(&ty, &mut params);
+        // Type parameters of an enclosing function are fixed in this one.
+        params.retain(|param| !self.params.contains(param));
+
+        Scheme {
+            ty,
+            params,
+            constraints: constraints(&fun.declaration),
+            interface: None,
+        }
+    }
+
+    fn infer_function(&mut self, fun: &FunctionExpr) {
+        let ty = match self.bindings.get(fun.variable.id) {
+            // Declared with the top level.
+            Some(ty) => ty.clone(),
+            None => {
+                // Declared up front, so the function can call itself.
+                let scheme = self.function_scheme(fun);
+                let ty = scheme.ty.clone();
+                self.declare_scheme(&fun.variable, scheme);
+                ty
+            }
+        };
+        self.infer_body(fun, ty);
+    }
+
+    fn infer_body(&mut self, fun: &FunctionExpr, ty: Type) {
+        self.line = *fun.variable.position.line();
+        let (params, ret) = match ty {
+            Type::Function(params, ret) => (params, *ret),
+            _ => unreachable!(),
+        };
+
+        let (outer_params, outer_assumptions) = (self.params.len(), self.assumptions.len());
+        let mut in_scope = mem::take(&mut self.params);
+        for param in &params {
+            self.collect_params(param, &mut in_scope);
+        }
+        self.params = in_scope;
+        self.assumptions.extend(constraints(&fun.declaration));
+
+        self.scopes.push(HashMap::new());
+        self.returns.push(ret.clone());
+        for (param, ty) in fun.declaration.parameters.iter().zip(params) {
+            self.declare(param, ty);
+        }
+
+        let body = &fun.declaration.body;
+        let actual = self.infer_block(body);
+        match body.exprs.last().map(|&expr| &self.module[expr]) {
+            Some(Expr::Return(_)) => {}
+            _ => self.expect(&ret, &actual),
+        }
+
+        self.returns.pop();
+        self.scopes.pop();
+        self.params.truncate(outer_params);
+        self.assumptions.truncate(outer_assumptions);
+    }
+
+    fn infer_block(&mut self, block: &BlockExpr) -> Type {
+        let mut ty = Type::Nil;
+        for &expr in &block.exprs {
+            ty = self.infer(expr);
+        }
+        ty
+    }
+
+    fn bind(&mut self, pattern: &Pattern, ty: Type) {
+        match pattern {
+            Pattern::Variable(variable) => self.declare(variable, ty),
+            Pattern::Wildcard => {}
+            Pattern::Literal(literal) => {
+                self.line = *literal.position.line();
+                self.expect(&literal_type(&literal.literal), &ty);
+            }
+            Pattern::Tuple(elements) => {
+                let types: Vec<Type> = elements.iter().map(|_| self.fresh()).collect();
+                self.expect(&Type::Tuple(types.clone()), &ty);
+                for (element, ty) in elements.iter().zip(types) {
+                    self.bind(element, ty);
+                }
+            }
+            Pattern::Variant(variant) => {
+                self.line = *variant.constructor.position.line();
+                let constructor = self.lookup(variant.constructor.name);
+                let fields: Vec<Type> = variant.fields.iter().map(|_| self.fresh()).collect();
+                let expect = match fields.len() {
+                    0 => ty,
+                    _ => Type::Function(fields.clone(), Box::new(ty)),
+                };
+                self.expect(&constructor, &expect);
+                for (field, ty) in variant.fields.iter().zip(fields) {
+                    self.bind(field, ty);
+                }
+            }
+            Pattern::Record(fields) => self.bind_record(fields, ty),
+            Pattern::List(list) => {
+                self.line = *list.position.line();
+                self.errors.push(TypeError::ListPattern(self.line));
+                for variable in pattern.variables() {
+                    let ty = self.fresh();
+                    self.declare(variable, ty);
+                }
+            }
+        }
+    }
+
+    /// Binds the fields of a record pattern, whose type is inferred like
+    /// that of a field access.
+    fn bind_record(&mut self, fields: &[Variable], ty: Type) {
+        if let Some(field) = fields.first() {
+            self.line = *field.position.line();
+        }
+        let names: Vec<Symbol> = fields.iter().map(|field| field.name).collect();
+        self.infer_record(&ty, &names);
+
+        let declared = match self.shallow(&ty) {
+            Type::Var(_) => None,
+            Type::Named(name, _) if self.records.contains_key(&name) => {
+                Some(self.records[&name].clone())
+            }
+            _ => {
+                let error = TypeError::NotARecord(self.apply(&ty), self.line);
+                self.errors.push(error);
+                None
+            }
+        };
+        for field in fields {
+            let field_ty = match &declared {
+                Some(declared) => match declared.iter().find(|(name, _)| *name == field.name) {
+                    Some((_, field_ty)) => field_ty.clone(),
+                    None => {
+                        let error = TypeError::NoField(self.apply(&ty), field.name, self.line);
+                        self.errors.push(error);
+                        self.fresh()
+                    }
+                },
+                None => self.fresh(),
+            };
+            self.declare(field, field_ty);
+        }
+    }
+
+    /// Makes a value whose type is not known yet, but whose fields are used,
+    /// of the only type which has them all, if there is just one. Record
+    /// patterns and field accesses do not name their type.
+    fn infer_record(&mut self, ty: &Type, fields: &[Symbol]) {
+        if !matches!(self.shallow(ty), Type::Var(_)) {
+            return;
+        }
+        let records = self.records.iter().filter(|(_, declared)| {
+            fields
+                .iter()
+                .all(|field| declared.iter().any(|(name, _)| name == field))
+        });
+        let host = self
+            .properties
+            .iter()
+            .filter(|(_, properties)| fields.iter().all(|field| properties.contains_key(field)));
+        let mut candidates = records
+            .map(|(name, _)| *name)
+            .chain(host.map(|(name, _)| *name));
+        if let (Some(name), None) = (candidates.next(), candidates.next()) {
+            self.expect(&Type::Named(name, vec![]), ty);
+        }
+    }
+
+    fn declare(&mut self, variable: &Variable, ty: Type) {
+        let scheme = Scheme {
+            ty,
+            params: vec![],
+            constraints: vec![],
+            interface: None,
+        };
+        self.declare_scheme(variable, scheme);
+    }
+
+    fn declare_scheme(&mut self, variable: &Variable, scheme: Scheme) {
+        self.bindings.insert(variable.id, scheme.ty.clone());
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(variable.name, scheme);
+    }
+
+    fn lookup(&mut self, name: Symbol) -> Type {
+        match self.find(name) {
+            Some(scheme) => self.instantiate(&scheme),
+            None => self.fresh(),
+        }
+    }
+
+    fn find(&self, name: Symbol) -> Option<Scheme> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name).cloned())
+    }
+
+    /// Replaces the type parameters of a scheme by fresh variables, which
+    /// have to implement the interfaces the parameters are constrained by.
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        self.instantiate_constraints(scheme).0
+    }
+
+    /// Instantiates the scheme of a variable which refers to a function,
+    /// and records the types its constraints are instantiated with, which
+    /// pick the dictionaries it is passed.
+    fn instantiate_use(&mut self, id: ExprId, name: Symbol, scheme: &Scheme) -> Type {
+        let (ty, constraints) = self.instantiate_constraints(scheme);
+        if !constraints.is_empty() {
+            self.uses.push(Use {
+                id,
+                name,
+                interface: scheme.interface,
+                constraints,
+                line: self.line,
+            });
+        }
+        ty
+    }
+
+    /// Instantiates a scheme, along with the types of its constraints. A
+    /// constraint on a type parameter of an enclosing function is on that
+    /// parameter itself.
+    fn instantiate_constraints(&mut self, scheme: &Scheme) -> (Type, Vec<(Type, Symbol)>) {
+        let mut params = HashMap::new();
+        for param in &scheme.params {
+            params.insert(*param, self.fresh());
+        }
+        let ty = match params.is_empty() {
+            true => scheme.ty.clone(),
+            false => {
+                let ty = self.apply(&scheme.ty);
+                self.replace_params(&ty, &mut params)
+            }
+        };
+
+        let mut constraints = vec![];
+        for (param, interface) in &scheme.constraints {
+            match params.get(param) {
+                Some(ty) => {
+                    self.obligations.push(Obligation {
+                        ty: ty.clone(),
+                        interface: *interface,
+                        line: self.line,
+                        assumptions: self.assumptions.clone(),
+                    });
+                    constraints.push((ty.clone(), *interface));
+                }
+                None => constraints.push((Type::Param(*param), *interface)),
+            }
+        }
+        (ty, constraints)
+    }
+
+    /// What a use of a function with constraints stands for, once the types
+    /// of its constraints are known.
+    fn instance(&mut self, use_: &Use) -> Option<Instance> {
+        let mut dictionaries = vec![];
+        for (ty, interface) in &use_.constraints {
+            let dictionary = match self.apply(ty) {
+                Type::Var(_) => {
+                    let error = TypeError::Ambiguous(*interface, use_.line);
+                    self.errors.push(error);
+                    return None;
+                }
+                Type::Param(param) => Dictionary::Param(param, *interface),
+                ty => {
+                    let mut functions: Vec<Symbol> = self
+                        .interfaces
+                        .get(interface)
+                        .map(|functions| functions.keys().copied().collect())
+                        .unwrap_or_default();
+                    functions.sort();
+                    Dictionary::Impl(ty.impl_name(), *interface, functions)
+                }
+            };
+            dictionaries.push(dictionary);
+        }
+        match use_.interface {
+            Some(_) => Some(Instance::Function(dictionaries.pop()?, use_.name)),
+            None => Some(Instance::Generic(dictionaries)),
+        }
+    }
+
+    /// Replaces the type parameters in `params`, and any other by a fresh
+    /// variable which is added to `params`.
+    fn replace_params(&mut self, ty: &Type, params: &mut HashMap<Symbol, Type>) -> Type {
+        match ty {
+            Type::Param(name) if self.params.contains(name) && !params.contains_key(name) => {
+                ty.clone()
+            }
+            Type::Param(name) => params.entry(*name).or_insert_with(|| self.fresh()).clone(),
+            Type::Function(args, ret) => Type::Function(
+                args.iter()
+                    .map(|a| self.replace_params(a, params))
+                    .collect(),
+                Box::new(self.replace_params(ret, params)),
+            ),
+            Type::Tuple(elements) => Type::Tuple(
+                elements
+                    .iter()
+                    .map(|e| self.replace_params(e, params))
+                    .collect(),
+            ),
+            Type::Named(name, arguments) => Type::Named(
+                *name,
+                arguments
+                    .iter()
+                    .map(|a| self.replace_params(a, params))
+                    .collect(),
+            ),
+            ty => ty.clone(),
+        }
+    }
+
+    fn collect_params(&self, ty: &Type, params: &mut Vec<Symbol>) {
+        match self.shallow(ty) {
+            Type::Param(name) if !params.contains(&name) => params.push(name),
+            Type::Function(args, ret) => {
+                for arg in &args {
+                    self.collect_params(arg, params);
+                }
+                self.collect_params(&ret, params);
+            }
+            Type::Tuple(elements) | Type::Named(_, elements) => {
+                for element in &elements {
+                    self.collect_params(element, params);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reports an obligation which is not met. Types which are still not
+    /// known are reported where a dictionary is needed for them.
+    fn discharge(&mut self, obligation: Obligation) {
+        let interface = obligation.interface;
+        let ty = self.apply(&obligation.ty);
+        let is_met = match &ty {
+            Type::Var(_) => true,
+            Type::Param(name) => obligation.assumptions.contains(&(*name, interface)),
+            ty => self.impls.contains(&(ty.impl_name(), interface)),
+        };
+        if !is_met {
+            let error = TypeError::NotImplemented(ty, interface, obligation.line);
+            self.errors.push(error);
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.substitution.push(None);
+        Type::Var(self.substitution.len() - 1)
+    }
+
+    fn expect(&mut self, expect: &Type, actual: &Type) {
+        if !self.unify(expect, actual) {
+            let error = TypeError::Mismatch(self.apply(expect), self.apply(actual), self.line);
+            self.errors.push(error);
+        }
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> bool {
+        let a = self.shallow(a);
+        let b = self.shallow(b);
+
+        match (&a, &b) {
+            (Type::Var(a), Type::Var(b)) if a == b => true,
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+                if self.occurs(*var, ty) {
+                    return false;
+                }
+                self.substitution[*var] = Some(ty.clone());
+                true
+            }
+            (Type::Function(a_params, a_ret), Type::Function(b_params, b_ret)) => {
+                a_params.len() == b_params.len()
+                    && a_params
+                        .iter()
+                        .zip(b_params.iter())
+                        .all(|(a, b)| self.unify(a, b))
+                    && self.unify(a_ret, b_ret)
+            }
+            (Type::Tuple(a), Type::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| self.unify(a, b))
+            }
+            (Type::Named(a_name, a), Type::Named(b_name, b)) => {
+                a_name == b_name
+                    && a.len() == b.len()
+                    && a.iter().zip(b.iter()).all(|(a, b)| self.unify(a, b))
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    fn occurs(&self, var: usize, ty: &Type) -> bool {
+        match self.shallow(ty) {
+            Type::Var(other) => other == var,
+            Type::Function(params, ret) => {
+                params.iter().any(|p| self.occurs(var, p)) || self.occurs(var, &ret)
+            }
+            Type::Tuple(elements) | Type::Named(_, elements) => {
+                elements.iter().any(|e| self.occurs(var, e))
+            }
+            _ => false,
+        }
+    }
+
+    /// Follows the substitution until the outermost type is known.
+    fn shallow(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(var) = ty {
+            match &self.substitution[var] {
+                Some(next) => ty = next.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    /// Whether a type has more than `MAX_TYPE_SIZE` parts once the
+    /// substitution is applied. It stops counting there.
+    fn too_large(&self, ty: &Type) -> bool {
+        let mut size = 0;
+        let mut types = vec![ty];
+        while let Some(ty) = types.pop() {
+            match ty {
+                Type::Var(var) => {
+                    if let Some(next) = &self.substitution[*var] {
+                        types.push(next);
+                        continue;
+                    }
+                }
+                Type::Function(params, ret) => {
+                    types.extend(params);
+                    types.push(ret);
+                }
+                Type::Tuple(elements) | Type::Named(_, elements) => types.extend(elements),
+                _ => {}
+            }
+            size += 1;
+            if size > MAX_TYPE_SIZE {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Applies the substitution to the whole type.
+    fn apply(&self, ty: &Type) -> Type {
+        match self.shallow(ty) {
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|p| self.apply(p)).collect(),
+                Box::new(self.apply(&ret)),
+            ),
+            Type::Tuple(elements) => Type::Tuple(elements.iter().map(|e| self.apply(e)).collect()),
+            Type::Named(name, arguments) => {
+                Type::Named(name, arguments.iter().map(|a| self.apply(a)).collect())
+            }
+            ty => ty,
+        }
+    }
+}
+
+fn constraints(declaration: &FunctionDeclaration) -> Vec<(Symbol, Symbol)> {
+    declaration
+        .constraints
+        .iter()
+        .map(|c| (c.parameter.name, c.interface.name))
+        .collect()
+}
+
+fn literal_type(literal: &LiteralExpr) -> Type {
+    match literal {
+        LiteralExpr::Number(_) => Type::Number,
+        LiteralExpr::String(_) => Type::String,
+        LiteralExpr::Nil => Type::Nil,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::lexer::Lexer;
+    use crate::syntax::parser::AstParser;
+
+    fn check(source: &str) -> (ModuleAst, TypeInfo) {
+        let mut tokens = Lexer::tokenize(source).unwrap();
+        let module = AstParser::parse(&mut tokens).unwrap();
+        let info = TypeChecker::check(&module);
+        (module, info)
+    }
+
+    fn binding_type(source: &str, name: &str) -> String {
+        let (module, info) = check(source);
+        let offset = source.find(name).unwrap();
+        let variable = module.variable_at(offset).unwrap();
+        info.binding(variable).unwrap().to_string()
+    }
+
+    #[test]
+    fn infer_literals() {
+        let source = r#"
+        let n = 5
+        let s = "five"
+        let b = n > 3
+        let z = nil
+        "#;
+
+        assert_eq!("number", binding_type(source, "n ="));
+        assert_eq!("string", binding_type(source, "s ="));
+        assert_eq!("bool", binding_type(source, "b ="));
+        assert_eq!("nil", binding_type(source, "z ="));
+    }
+
+    #[test]
+    fn infer_function() {
+        let source = r#"
+        def double(x)
+            return x * 2
+        end
+        def same(a, b)
+            return a == b
+        end
+        def id(y)
+            y
+        end
+        "#;
+
+        assert_eq!("(number) -> number", binding_type(source, "double"));
+        assert_eq!("('a, 'a) -> bool", binding_type(source, "same"));
+        assert_eq!("('a) -> 'a", binding_type(source, "id"));
+        assert_eq!("number", binding_type(source, "x)"));
+    }
+
+    #[test]
+    fn infer_patterns() {
+        let source = r#"
+        let (a, b) = (nil, 5)
+        let 5 = "five"
+        "#;
+        let (_, info) = check(source);
+
+        assert_eq!(
+            vec![TypeError::Mismatch(Type::Number, Type::String, 3)],
+            info.errors()
+        );
+        assert_eq!("nil", binding_type(source, "a,"));
+        assert_eq!("number", binding_type(source, "b)"));
+    }
+
+    #[test]
+    fn too_large_types() {
+        // The type of `a13` has 2^14 - 1 parts.
+        let mut source = "let a0 = 1\n".to_string();
+        for i in 1..40 {
+            source += &format!("let a{} = (a{}, a{})\n", i, i - 1, i - 1);
+        }
+        let (_, info) = check(&source);
+
+        // Each type given up on is unknown, and grows again from there.
+        assert_eq!(
+            vec![
+                TypeError::TooLarge(14),
+                TypeError::TooLarge(27),
+                TypeError::TooLarge(40)
+            ],
+            info.errors()
+        );
+        assert_eq!(
+            "((number, number), (number, number))",
+            binding_type(&source, "a2 =")
+        );
+    }
+
+    #[test]
+    fn infer_record_patterns() {
+        let source = r#"
+        type point = { x, y }
+        def area(p)
+            let { x, y } = p
+            x * y
+        end
+        let { x } = point { x: 1, y: 2 }
+        let { z } = point { x: 1, y: 2 }
+        let { w } = 5
+        let n = match (1,) {
+            | [first] => first
+            | _ => 0
+        }
+        "#;
+        let (_, info) = check(source);
+
+        let errors: Vec<(String, usize)> = info
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("point has no field `z`".to_string(), 8),
+                ("number is not a record".to_string(), 9),
+                (
+                    "list patterns cannot match anything, there are no lists yet".to_string(),
+                    11
+                ),
+            ],
+            errors
+        );
+        assert_eq!("(point) -> number", binding_type(source, "area"));
+        assert_eq!("number", binding_type(source, "x }"));
+    }
+
+    #[test]
+    fn infer_tuples() {
+        let source = r#"
+        def divmod(a, b)
+            return (a / b, a - b)
+        end
+        def swap(pair)
+            let (x, y) = pair
+            return (y, x)
+        end
+        let q = (1, "one").1
+        let m = match (1, "one") {
+            | (0, s) => s
+            | (n, _) => "many"
+        }
+        "#;
+        let (_, info) = check(source);
+
+        assert!(info.errors().is_empty());
+        assert_eq!(
+            "(number, number) -> (number, number)",
+            binding_type(source, "divmod")
+        );
+        assert_eq!("(('a, 'b)) -> ('b, 'a)", binding_type(source, "swap"));
+        assert_eq!("string", binding_type(source, "q ="));
+        assert_eq!("string", binding_type(source, "m ="));
+        assert_eq!("number", binding_type(source, "n,"));
+    }
+
+    #[test]
+    fn report_tuple_errors() {
+        let source = r#"
+        let t = (1, 2)
+        let a = t.2
+        let c = match t {
+            | (x,) => x
+        }
+        "#;
+        let (_, info) = check(source);
+
+        let errors: Vec<(String, usize)> = info
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("(number, number) has no element 2".to_string(), 3),
+                ("expected ('a,), found (number, number)".to_string(), 4),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn infer_calls() {
+        let source = r#"
+        def double(x)
+            x * 2
+        end
+        def apply(x, f)
+            f(x)
+        end
+        let n = 5 |> double |> double
+        let m = 5 |> apply(double)
+        "#;
+        let (_, info) = check(source);
+
+        assert!(info.errors().is_empty());
+        assert_eq!(
+            "(number, (number) -> number) -> number",
+            binding_type(source, "apply")
+        );
+        assert_eq!("number", binding_type(source, "n ="));
+        assert_eq!("number", binding_type(source, "m ="));
+    }
+
+    #[test]
+    fn report_call_errors() {
+        let source = r#"
+        def double(x)
+            x * 2
+        end
+        let a = double("two")
+        let b = 5
+        let c = 2 |> b
+        "#;
+        let (_, info) = check(source);
+
+        let errors: Vec<(String, usize)> = info
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "expected (number) -> number, found (string) -> 'a".to_string(),
+                    5
+                ),
+                ("number is not a function".to_string(), 7),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn infer_types() {
+        let source = r#"
+        type point = { x, y }
+        type shape =
+            | Circle(radius)
+            | Empty
+        def area(s)
+            match s {
+                | Circle(r) => r * r * 3
+                | Empty => 0
+            }
+        end
+        let p = point { x: 1, y: "one" }
+        let y = p.y
+        let a = area(Circle(2))
+        "#;
+        let (_, info) = check(source);
+
+        assert!(info.errors().is_empty());
+        assert_eq!("(number) -> shape", binding_type(source, "Circle("));
+        assert_eq!("shape", binding_type(source, "Empty\n"));
+        assert_eq!("(shape) -> number", binding_type(source, "area(s)"));
+        assert_eq!("point", binding_type(source, "p ="));
+        assert_eq!("string", binding_type(source, "y = p"));
+        assert_eq!("number", binding_type(source, "a ="));
+    }
+
+    #[test]
+    fn infer_operators() {
+        let source = r#"
+        let sum = money { cents: 1 } + money { cents: 2 }
+        let less = money { cents: 1 } > money { cents: 2 }
+        type money = { cents }
+        impl money
+            def add(a, b)
+                money { cents: a.cents + b.cents }
+            end
+            def lt(a, b)
+                a.cents < b.cents
+            end
+        end
+        "#;
+        let (_, info) = check(source);
+
+        assert!(info.errors().is_empty());
+        assert_eq!("money", binding_type(source, "sum ="));
+        assert_eq!("bool", binding_type(source, "less ="));
+        assert_eq!("(money, money) -> money", binding_type(source, "add("));
+    }
+
+    #[test]
+    fn report_operator_errors() {
+        // `b.cents` makes `b` money before the operator is used.
+        let source = r#"
+        type money = { cents }
+        impl money
+            def add(a, b)
+                money { cents: a.cents + b.cents }
+            end
+        end
+        let sum = money { cents: 1 } + 1
+        "#;
+        let (_, info) = check(source);
+
+        assert_eq!(
+            vec![TypeError::Mismatch(
+                Type::Named(Symbol::intern("money"), vec![]),
+                Type::Number,
+                8
+            )],
+            info.errors()
+        );
+        assert_eq!("(money, money) -> money", binding_type(source, "add("));
+
+        let source = r#"
+        let s = "a" + "b"
+        let less = 1 < "b"
+        "#;
+        let (_, info) = check(source);
+
+        assert_eq!(
+            vec![
+                TypeError::Mismatch(Type::Number, Type::String, 2),
+                TypeError::Mismatch(Type::Number, Type::String, 3)
+            ],
+            info.errors()
+        );
+    }
+
+    #[test]
+    fn infer_results() {
+        let source = r#"
+        def parse(s: string)
+            match s {
+                | "one" => Ok(1)
+                | _ => Err("not a number")
+            }
+        end
+        def sum(a, b)
+            let x = parse(a)?
+            let y = parse(b)?
+            Ok(x + y)
+        end
+        def first(pair: (option<a>, a))
+            let x = pair.0?
+            Some(x)
+        end
+        def fail()
+            panic("failed")
+        end
+        let n = 1 + fail()
+        let o = None
+        "#;
+        let (_, info) = check(source);
+
+        assert!(info.errors().is_empty(), "{:?}", info.errors());
+        assert_eq!(
+            "(string) -> result<number, string>",
+            binding_type(source, "parse(")
+        );
+        assert_eq!(
+            "(string, string) -> result<number, string>",
+            binding_type(source, "sum(")
+        );
+        assert_eq!(
+            "((option<a>, a)) -> option<a>",
+            binding_type(source, "first(")
+        );
+        assert_eq!("number", binding_type(source, "x ="));
+        assert_eq!("option<'a>", binding_type(source, "o ="));
+
+        let source = r#"
+        def f(x: option<number>)
+            let y = x?
+            Ok(y)
+        end
+        def g(n)
+            n + 1?
+        end
+        let s = Some(1) + 1
+        "#;
+        let (_, info) = check(source);
+
+        let errors: Vec<(String, usize)> = info
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "expected option<'a>, found result<number, 'a>".to_string(),
+                    4
+                ),
+                (
+                    "`?` needs a result or an option, found number".to_string(),
+                    7
+                ),
+                ("`+` is not defined for option<number>".to_string(), 9),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn infer_type_arguments() {
+        let source = r#"
+        def f(x: Foo<a>)
+            x
+        end
+        def g(x: option<a, b>)
+            x
+        end
+        def h(x: number<a>)
+            x
+        end
+        "#;
+        let (_, info) = check(source);
+
+        let errors: Vec<(String, usize)> = info
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("unknown type `Foo`".to_string(), 2),
+                (
+                    "expected 1 type arguments for `option`, found 2".to_string(),
+                    5
+                ),
+                (
+                    "expected 0 type arguments for `number`, found 1".to_string(),
+                    8
+                ),
+            ],
+            errors
+        );
+        assert_eq!("(option<'a>) -> option<'a>", binding_type(source, "g("));
+    }
+
+    #[test]
+    fn infer_method_calls() {
+        let source = r#"
+        type point = { x, y }
+        impl point
+            def scale(p, k)
+                point { x: p.x * k, y: p.y * k }
+            end
+        end
+        let p = point { x: 1, y: 2 }
+        let q = p.scale(2)
+        let d = p.x.double()
+        def double(n)
+            n * 2
+        end
+        def unit(v)
+            v.scale(1)
+        end
+        "#;
+        let (_, info) = check(source);
+
+        assert!(info.errors().is_empty());
+        // Only points have `scale`.
+        assert_eq!("(point) -> point", binding_type(source, "unit("));
+        assert_eq!("point", binding_type(source, "q ="));
+        assert_eq!("number", binding_type(source, "d ="));
+        assert_eq!("(point, number) -> point", binding_type(source, "scale("));
+
+        let source = r#"
+        type point = { x }
+        let p = point { x: 1 }
+        let a = p.norm()
+        let b = p.x.scale(2)
+        "#;
+        let (_, info) = check(source);
+
+        let errors: Vec<(String, usize)> = info
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("point has no method `norm`".to_string(), 4),
+                ("number has no method `scale`".to_string(), 5),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn report_type_errors() {
+        let source = r#"
+        type money = { cents }
+        impl money
+            def add(a, b)
+                a.cents + b
+            end
+        end
+        let a = money { cents: 1 }
+        let b = a * a
+        let c = a + a
+        let d = money { cents: 1, euros: 2 }
+        let e = d.euros
+        a.cents = 2
+        "#;
+        let (_, info) = check(source);
+
+        let errors: Vec<(String, usize)> = info
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("`*` is not defined for money".to_string(), 9),
+                ("expected number, found money".to_string(), 10),
+                ("money has no field `euros`".to_string(), 11),
+                ("money has no field `euros`".to_string(), 12),
+                ("`cents` of money cannot be assigned".to_string(), 13),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn infer_interfaces() {
+        let source = r#"
+        interface Show
+            def show(value)
+            def same(value, other: Self)
+        end
+        type point = { x, y }
+        impl Show for point
+            def show(p)
+                "point"
+            end
+            def same(p, q)
+                p.x == q.x
+            end
+        end
+        impl Show for number
+            def show(n)
+                "number"
+            end
+            def same(m, n)
+                m == n
+            end
+        end
+        def if_same(b, s)
+            s
+        end
+        def print(x: a, y: a) where a: Show
+            if_same(same(x, y), show(x))
+        end
+        let s = print(1, 2)
+        let t = print(point { x: 1, y: 2 }, point { x: 1, y: 3 })
+        "#;
+        let (_, info) = check(source);
+
+        assert!(info.errors().is_empty());
+        assert_eq!("(Self) -> string", binding_type(source, "show(value)"));
+        assert_eq!("(Self, Self) -> bool", binding_type(source, "same(value"));
+        assert_eq!("(point) -> string", binding_type(source, "show(p)"));
+        assert_eq!("(number, number) -> bool", binding_type(source, "same(m"));
+        assert_eq!("(a, a) -> string", binding_type(source, "print"));
+        assert_eq!("string", binding_type(source, "t = print"));
+
+        // Inside `print` the functions come from the dictionary it is
+        // passed, which its callers build from the `impl` of their type.
+        let (show, same, a) = (
+            Symbol::from("show"),
+            Symbol::from("same"),
+            Symbol::from("a"),
+        );
+        let param = Dictionary::Param(a, Symbol::from("Show"));
+        let number = Dictionary::Impl(
+            Symbol::from("number"),
+            Symbol::from("Show"),
+            vec![same, show],
+        );
+        let point = Dictionary::Impl(
+            Symbol::from("point"),
+            Symbol::from("Show"),
+            vec![same, show],
+        );
+        let instances: Vec<&Instance> = info.instances().iter().map(|(_, i)| i).collect();
+        assert_eq!(
+            vec![
+                &Instance::Function(param.clone(), same),
+                &Instance::Function(param, show),
+                &Instance::Generic(vec![number]),
+                &Instance::Generic(vec![point]),
+            ],
+            instances
+        );
+    }
+
+    #[test]
+    fn report_interface_errors() {
+        let source = r#"
+        interface Show
+            def show(value)
+        end
+        impl Show for number
+            def show(n)
+                n
+            end
+        end
+        impl Show for string
+            def show(s)
+                "string"
+            end
+        end
+        def print(x: a)
+            show(x)
+        end
+        def first(x: a, y: b) where a: Show, b: Show
+            x = y
+            show(x)
+        end
+        let b = show(1 < 2)
+        "#;
+        let (_, info) = check(source);
+
+        let errors: Vec<(String, usize)> = info
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("expected number, found string".to_string(), 11),
+                ("expected a, found b".to_string(), 19),
+                ("a does not implement `Show`".to_string(), 16),
+                ("bool does not implement `Show`".to_string(), 22),
+            ],
+            errors
+        );
+
+        let source = r#"
+        interface Show
+            def show(value)
+        end
+        type point = { x }
+        type line = { a, b }
+        impl point
+            def norm(p)
+                p.x
+            end
+        end
+        impl line
+            def norm(l)
+                l.a
+            end
+        end
+        let s = show
+        def f(x)
+            x.norm()
+        end
+        "#;
+        let (_, info) = check(source);
+
+        let errors: Vec<(String, usize)> = info
+            .errors()
+            .iter()
+            .map(|err| (err.to_string(), err.line()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "cannot tell which `norm` to call, the type of the value is not known"
+                        .to_string(),
+                    19
+                ),
+                (
+                    "cannot tell which implementation of `Show` to use, the type is not known"
+                        .to_string(),
+                    17
+                ),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn report_mismatch() {
+        let source = r#"
+        let x = 5
+        let y = "five"
+        x = y
+        "#;
+        let (_, info) = check(source);
+
+        assert_eq!(
+            vec![TypeError::Mismatch(Type::Number, Type::String, 4)],
+            info.errors()
+        );
+    }
+}
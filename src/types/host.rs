@@ -0,0 +1,30 @@
+//! What the host running a module declares for it in Rust: globals, and
+//! types whose values programs can only use through their methods and
+//! properties.
+
+use crate::syntax::symbol::Symbol;
+use crate::types::ty::Type;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default)]
+pub struct Host {
+    /// The type of every global. It is generic over the type parameters in
+    /// it.
+    pub globals: HashMap<Symbol, Type>,
+    pub types: HashMap<Symbol, TypeDeclaration>,
+}
+
+/// A type implemented by the host.
+#[derive(Debug, Clone, Default)]
+pub struct TypeDeclaration {
+    /// The type of every method, whose first parameter is the receiver.
+    pub methods: HashMap<Symbol, Type>,
+    pub properties: HashMap<Symbol, Property>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    pub ty: Type,
+    /// Whether the property has a setter, so it can be assigned.
+    pub settable: bool,
+}
@@ -0,0 +1,112 @@
+use crate::syntax::symbol::Symbol;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Number,
+    String,
+    Bool,
+    Nil,
+    Function(Vec<Type>, Box<Type>),
+    Tuple(Vec<Type>),
+    /// A type declared with `type`, or a variant type of the prelude with
+    /// its type arguments.
+    Named(Symbol, Vec<Type>),
+    /// A type parameter of a generic function. Within the function it
+    /// stands for one unknown type, every use of the function picks its own.
+    Param(Symbol),
+    Var(usize),
+}
+
+impl Type {
+    /// The type a name in an annotation refers to, if it is a built-in
+    /// type.
+    pub fn built_in(name: &str) -> Option<Type> {
+        match name {
+            "number" => Some(Type::Number),
+            "string" => Some(Type::String),
+            "bool" => Some(Type::Bool),
+            "nil" => Some(Type::Nil),
+            _ => None,
+        }
+    }
+
+    /// The name `impl` blocks are looked up by, which leaves out the type
+    /// arguments: `option<number>` and `option<string>` share an `impl`.
+    pub fn impl_name(&self) -> Symbol {
+        match self {
+            Type::Named(name, _) => *name,
+            ty => Symbol::intern(&ty.to_string()),
+        }
+    }
+}
+
+impl Type {
+    fn write(&self, f: &mut fmt::Formatter<'_>, names: &mut HashMap<usize, String>) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Nil => write!(f, "nil"),
+            Type::Function(params, ret) => {
+                write!(f, "(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    param.write(f, names)?;
+                }
+                write!(f, ") -> ")?;
+                ret.write(f, names)
+            }
+            Type::Tuple(elements) => {
+                write!(f, "(")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write(f, names)?;
+                }
+                // `(number,)` is a tuple, `(number)` would not be.
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Type::Named(name, arguments) => {
+                write!(f, "{}", name)?;
+                if arguments.is_empty() {
+                    return Ok(());
+                }
+                write!(f, "<")?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    argument.write(f, names)?;
+                }
+                write!(f, ">")
+            }
+            Type::Param(name) => write!(f, "{}", name),
+            Type::Var(id) => {
+                // Name variables in order of appearance: 'a, 'b, ...
+                let next = names.len();
+                let name = names.entry(*id).or_insert_with(|| {
+                    let letter = (b'a' + (next % 26) as u8) as char;
+                    match next / 26 {
+                        0 => format!("'{}", letter),
+                        n => format!("'{}{}", letter, n),
+                    }
+                });
+                write!(f, "{}", name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut HashMap::new())
+    }
+}
@@ -0,0 +1,141 @@
+use crate::resolver::Upvalue;
+use crate::syntax::symbol::Symbol;
+use crate::syntax::token::Position;
+use crate::vm::value::Constructor;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(usize),
+    Nil,
+    Pop,
+    Dup,
+    GetLocal(usize),
+    SetLocal(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    /// The operands of the global instructions are indices into the names.
+    DefineGlobal(usize),
+    GetGlobal(usize),
+    SetGlobal(usize),
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Negate,
+    Not,
+    Equal,
+    Less,
+    Greater,
+    /// Collects the given number of values into a tuple.
+    Tuple(usize),
+    TupleIndex(usize),
+    Record(usize),
+    Field(usize),
+    /// Assigns the value on top of the stack to the property of the object
+    /// below it, popping both.
+    SetField(usize),
+    /// Pushes a constructor, or the value of one without fields.
+    Constructor(usize),
+    /// Pushes the function of an `impl`, the operands are the names of the
+    /// type and the function.
+    GetMethod(usize, usize),
+    Closure(usize),
+    /// Applies the function below the given number of values to them, as
+    /// its first arguments.
+    Partial(usize),
+    Call(usize),
+    Return,
+    /// Replaces a `Some` or `Ok` on the stack by its value, and returns a
+    /// `None` or `Err` from the function.
+    Try,
+    /// Adds the closure on the stack to the functions of a type, the
+    /// operands are the names of the type and the function.
+    Method(usize, usize),
+    Jump(usize),
+    JumpIfFalse(usize),
+    /// Binds the pattern to the value on the stack, which must match.
+    Destructure(usize),
+    /// Binds the pattern to the value on the stack if it matches, and
+    /// pushes whether it did.
+    Match(usize),
+    /// Reached when no arm of a `match` matches.
+    NoMatch,
+}
+
+/// The literals of a chunk. Strings are allocated when they are loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Nil,
+    Number(f64),
+    String(String),
+}
+
+/// The fields of a record literal, in the order their values are pushed.
+#[derive(Debug, PartialEq)]
+pub struct RecordLayout {
+    pub type_name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Pattern {
+    Bind(Target),
+    Wildcard,
+    Literal(Constant),
+    Tuple(Vec<Pattern>),
+    Record(Vec<(Symbol, Target)>),
+    /// Lists do not exist at runtime yet, so these never match.
+    List,
+    /// The global the constructor is bound to, and the patterns of its
+    /// fields.
+    Variant(Symbol, Vec<Pattern>),
+}
+
+/// Where a pattern stores a value.
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Local(usize),
+    Global(Symbol),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    /// The position in the source of every instruction.
+    pub positions: Vec<Position>,
+    pub constants: Vec<Constant>,
+    pub names: Vec<Symbol>,
+    pub functions: Vec<Rc<Function>>,
+    pub records: Vec<RecordLayout>,
+    pub constructors: Vec<Constructor>,
+    pub patterns: Vec<Pattern>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, op: Op, position: Position) -> usize {
+        self.code.push(op);
+        self.positions.push(position);
+        self.code.len() - 1
+    }
+
+    pub fn name(&mut self, name: Symbol) -> usize {
+        match self.names.iter().position(|n| *n == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name);
+                self.names.len() - 1
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub name: Symbol,
+    pub arity: usize,
+    /// The number of slots the frame needs, parameters included.
+    pub locals: usize,
+    pub upvalues: Vec<Upvalue>,
+    pub chunk: Chunk,
+}
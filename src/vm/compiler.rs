@@ -0,0 +1,457 @@
+use crate::resolver::{Binding, Resolution};
+use crate::syntax::ast::*;
+use crate::syntax::symbol::Symbol;
+use crate::syntax::token::Position;
+use crate::types::checker::{Callee, Dictionary, Instance, TypeInfo};
+use crate::vm::chunk::{self, Chunk, Constant, Function, Op, RecordLayout, Target};
+use crate::vm::value::Constructor;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+/// Compiles a module to bytecode. Every expression leaves exactly one value
+/// on the stack, `let` and `def` leave `nil`.
+///
+/// The module must have been resolved without errors. The functions of
+/// interfaces are called through the dictionaries the type checker picked.
+pub struct Compiler<'a> {
+    module: &'a ModuleAst,
+    resolution: &'a Resolution,
+    types: &'a TypeInfo,
+    chunk: Chunk,
+    /// The dictionaries the function being compiled can use.
+    dictionaries: HashMap<(Symbol, Symbol), Binding>,
+    /// The span of the expression being compiled, or of the name in it an
+    /// instruction is about, like the field it reads.
+    position: Position,
+}
+
+impl<'a> Compiler<'a> {
+    /// Compiles the top level of the module into a function without
+    /// parameters.
+    pub fn compile(
+        module: &'a ModuleAst,
+        resolution: &'a Resolution,
+        types: &'a TypeInfo,
+    ) -> Function {
+        let mut compiler = Compiler {
+            module,
+            resolution,
+            types,
+            chunk: Chunk::default(),
+            dictionaries: HashMap::new(),
+            position: Position::new(0, 0, 1),
+        };
+        compiler.exprs(&module.exprs);
+        compiler.emit(Op::Return);
+
+        Function {
+            name: Symbol::intern("<module>"),
+            arity: 0,
+            locals: resolution.module().locals,
+            upvalues: vec![],
+            chunk: compiler.chunk,
+        }
+    }
+
+    /// Compiles a sequence of expressions, keeping the value of the last.
+    fn exprs(&mut self, exprs: &[ExprId]) {
+        if exprs.is_empty() {
+            self.emit(Op::Nil);
+        }
+        for (i, &expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.emit(Op::Pop);
+            }
+            self.expr(expr);
+        }
+    }
+
+    /// Compiles an expression, whose instructions are at its span unless
+    /// they set a narrower one.
+    fn expr(&mut self, id: ExprId) {
+        let enclosing = mem::replace(&mut self.position, self.module.position(id).clone());
+        self.node(id);
+        self.position = enclosing;
+    }
+
+    fn node(&mut self, id: ExprId) {
+        let module = self.module;
+        match &module[id] {
+            Expr::Literal(literal) => {
+                let constant = constant(literal);
+                self.constant(constant);
+            }
+            Expr::Grouping(grouping) => self.expr(grouping.expr),
+            Expr::Binary(binary) => self.binary(binary),
+            Expr::Unary(unary) => {
+                self.expr(unary.expr);
+                self.emit(match unary.operator {
+                    UnaryOperator::Negate => Op::Negate,
+                    UnaryOperator::Not => Op::Not,
+                });
+            }
+            Expr::LetAssign(let_assign) => {
+                self.expr(let_assign.initializer);
+                let pattern = self.pattern(&let_assign.pattern);
+                match let_assign.otherwise {
+                    None => {
+                        self.emit(Op::Destructure(pattern));
+                    }
+                    Some(otherwise) => {
+                        self.emit(Op::Match(pattern));
+                        self.emit(Op::Not);
+                        let jump = self.emit(Op::JumpIfFalse(0));
+                        // Always returns, this never falls through.
+                        self.expr(otherwise);
+                        self.emit(Op::Pop);
+                        self.patch(jump);
+                    }
+                }
+                self.emit(Op::Nil);
+            }
+            Expr::LetGet(_) => {
+                self.function(id, self.resolution.binding_of(id).unwrap().clone());
+            }
+            Expr::LetSet(let_set) => {
+                self.expr(let_set.initializer);
+                self.position = let_set.variable.position.clone();
+                let op = match self.resolution.binding_of(id).unwrap().clone() {
+                    Binding::Local(slot) => Op::SetLocal(slot),
+                    Binding::Upvalue(index) => Op::SetUpvalue(index),
+                    Binding::Global(name) => Op::SetGlobal(self.chunk.name(name)),
+                };
+                self.emit(op);
+                self.emit(Op::Nil);
+            }
+            Expr::Function(fun) => {
+                self.closure(id, fun);
+                self.define(&fun.variable);
+                self.emit(Op::Nil);
+            }
+            Expr::Block(block) => self.exprs(&block.exprs),
+            Expr::Return(ret) => {
+                match ret.expr {
+                    Some(expr) => self.expr(expr),
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.emit(Op::Return);
+            }
+            Expr::Tuple(tuple) => {
+                for &element in &tuple.elements {
+                    self.expr(element);
+                }
+                self.emit(Op::Tuple(tuple.elements.len()));
+            }
+            Expr::TupleIndex(index) => {
+                self.expr(index.tuple);
+                self.emit(Op::TupleIndex(index.index));
+            }
+            Expr::Call(call) => {
+                self.expr(call.callee);
+                for &argument in &call.arguments {
+                    self.expr(argument);
+                }
+                // Errors in the call are reported at the callee, where a
+                // call piped into starts too.
+                self.position = module.position(call.callee).clone();
+                self.emit(Op::Call(call.arguments.len()));
+            }
+            Expr::Match(match_expr) => {
+                self.expr(match_expr.scrutinee);
+                let mut ends = vec![];
+                for arm in &match_expr.arms {
+                    self.emit(Op::Dup);
+                    let pattern = self.pattern(&arm.pattern);
+                    self.emit(Op::Match(pattern));
+                    let next = self.emit(Op::JumpIfFalse(0));
+
+                    self.emit(Op::Pop);
+                    self.expr(arm.body);
+                    ends.push(self.emit(Op::Jump(0)));
+                    self.patch(next);
+                }
+                self.emit(Op::NoMatch);
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+            Expr::Type(ty) => {
+                if let TypeDefinition::Variant(constructors) = &ty.definition {
+                    for constructor in constructors {
+                        self.chunk.constructors.push(Constructor {
+                            type_name: ty.variable.name,
+                            name: constructor.variable.name,
+                            arity: constructor.fields.len(),
+                        });
+                        self.emit(Op::Constructor(self.chunk.constructors.len() - 1));
+                        self.define(&constructor.variable);
+                    }
+                }
+                self.emit(Op::Nil);
+            }
+            Expr::Impl(imp) => {
+                let type_name = self.chunk.name(imp.variable.name);
+                for (id, fun) in module.functions(imp) {
+                    self.closure(id, fun);
+                    let name = self.chunk.name(fun.variable.name);
+                    self.emit(Op::Method(type_name, name));
+                }
+                self.emit(Op::Nil);
+            }
+            // Its functions are picked from dictionaries where they are used.
+            Expr::Interface(_) => {
+                self.emit(Op::Nil);
+            }
+            Expr::Record(record) => {
+                for &(_, value) in &record.fields {
+                    self.expr(value);
+                }
+                self.chunk.records.push(RecordLayout {
+                    type_name: record.variable.name,
+                    fields: record.fields.iter().map(|(f, _)| f.name).collect(),
+                });
+                self.emit(Op::Record(self.chunk.records.len() - 1));
+            }
+            Expr::Field(field) => {
+                self.expr(field.record);
+                self.position = field.field.position.clone();
+                let name = self.chunk.name(field.field.name);
+                self.emit(Op::Field(name));
+            }
+            Expr::SetField(set_field) => {
+                self.expr(set_field.record);
+                self.expr(set_field.value);
+                self.position = set_field.field.position.clone();
+                let name = self.chunk.name(set_field.field.name);
+                self.emit(Op::SetField(name));
+                self.emit(Op::Nil);
+            }
+            Expr::MethodCall(call) => {
+                self.position = call.method.position.clone();
+                match self.types.callees().get(id) {
+                    Some(Callee::Method(ty, name)) => {
+                        let (ty, name) = (self.chunk.name(*ty), self.chunk.name(*name));
+                        self.emit(Op::GetMethod(ty, name));
+                    }
+                    Some(Callee::Function) => match self.resolution.binding(&call.method) {
+                        Some(binding) => self.function(id, binding.clone()),
+                        None => {
+                            self.emit(Op::Nil);
+                        }
+                    },
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.expr(call.receiver);
+                for &argument in &call.arguments {
+                    self.expr(argument);
+                }
+                self.position = call.method.position.clone();
+                self.emit(Op::Call(call.arguments.len() + 1));
+            }
+            Expr::Try(try_expr) => {
+                self.expr(try_expr.expr);
+                self.position = try_expr.position.clone();
+                self.emit(Op::Try);
+            }
+        }
+    }
+
+    fn binary(&mut self, binary: &BinaryExpr) {
+        self.expr(binary.lhs);
+        self.expr(binary.rhs);
+
+        // The other comparisons are the negations of these.
+        let (op, negate) = match binary.operator {
+            BinaryOperator::Add => (Op::Add, false),
+            BinaryOperator::Subtract => (Op::Subtract, false),
+            BinaryOperator::Multiply => (Op::Multiply, false),
+            BinaryOperator::Divide => (Op::Divide, false),
+            BinaryOperator::Equal => (Op::Equal, false),
+            BinaryOperator::BangEqual => (Op::Equal, true),
+            BinaryOperator::LessThan => (Op::Less, false),
+            BinaryOperator::LessThanEqual => (Op::Greater, true),
+            BinaryOperator::GreaterThan => (Op::Greater, false),
+            BinaryOperator::GreaterThanEqual => (Op::Less, true),
+        };
+        self.emit(op);
+        if negate {
+            self.emit(Op::Not);
+        }
+    }
+
+    /// Compiles the function into its own chunk and pushes a closure of it.
+    fn closure(&mut self, id: ExprId, fun: &FunctionExpr) {
+        let info = self.resolution.function(id).unwrap();
+
+        let enclosing = mem::take(&mut self.chunk);
+        let dictionaries = mem::replace(&mut self.dictionaries, info.dictionaries.clone());
+        self.exprs(&fun.declaration.body.exprs);
+        self.emit(Op::Return);
+        let chunk = mem::replace(&mut self.chunk, enclosing);
+        self.dictionaries = dictionaries;
+
+        // The dictionaries of the `where` clause come before the
+        // parameters.
+        let declaration = &fun.declaration;
+        self.chunk.functions.push(Rc::new(Function {
+            name: fun.variable.name,
+            arity: declaration.constraints.len() + declaration.parameters.len(),
+            locals: info.locals,
+            upvalues: info.upvalues.clone(),
+            chunk,
+        }));
+        self.emit(Op::Closure(self.chunk.functions.len() - 1));
+    }
+
+    /// Pushes the function a variable refers to. The functions of
+    /// interfaces come from a dictionary, and generic functions are applied
+    /// to the dictionaries of their `where` clause.
+    fn function(&mut self, id: ExprId, binding: Binding) {
+        match self.types.instances().get(id) {
+            None => self.get(binding),
+            Some(Instance::Function(Dictionary::Impl(ty, _, _), name)) => {
+                let (ty, name) = (self.chunk.name(*ty), self.chunk.name(*name));
+                self.emit(Op::GetMethod(ty, name));
+            }
+            Some(Instance::Function(dictionary, name)) => {
+                self.dictionary(dictionary);
+                let name = self.chunk.name(*name);
+                self.emit(Op::Field(name));
+            }
+            Some(Instance::Generic(dictionaries)) => {
+                self.get(binding);
+                for dictionary in dictionaries {
+                    self.dictionary(dictionary);
+                }
+                self.emit(Op::Partial(dictionaries.len()));
+            }
+        }
+    }
+
+    /// Pushes a dictionary, a record of the functions of an interface.
+    fn dictionary(&mut self, dictionary: &Dictionary) {
+        match dictionary {
+            Dictionary::Impl(ty, interface, functions) => {
+                let ty = self.chunk.name(*ty);
+                for &function in functions {
+                    let name = self.chunk.name(function);
+                    self.emit(Op::GetMethod(ty, name));
+                }
+                self.chunk.records.push(RecordLayout {
+                    type_name: *interface,
+                    fields: functions.clone(),
+                });
+                self.emit(Op::Record(self.chunk.records.len() - 1));
+            }
+            Dictionary::Param(parameter, interface) => {
+                match self.dictionaries.get(&(*parameter, *interface)).cloned() {
+                    Some(binding) => self.get(binding),
+                    // Not in the `where` clause, which is a type error.
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+            }
+        }
+    }
+
+    fn get(&mut self, binding: Binding) {
+        let op = match binding {
+            Binding::Local(slot) => Op::GetLocal(slot),
+            Binding::Upvalue(index) => Op::GetUpvalue(index),
+            Binding::Global(name) => Op::GetGlobal(self.chunk.name(name)),
+        };
+        self.emit(op);
+    }
+
+    /// Stores the value on the stack in the variable being declared.
+    fn define(&mut self, variable: &Variable) {
+        let op = match self.binding(variable) {
+            Binding::Local(slot) => Op::SetLocal(slot),
+            Binding::Global(name) => Op::DefineGlobal(self.chunk.name(name)),
+            Binding::Upvalue(_) => unreachable!(),
+        };
+        self.emit(op);
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> usize {
+        let pattern = self.compile_pattern(pattern);
+        self.chunk.patterns.push(pattern);
+        self.chunk.patterns.len() - 1
+    }
+
+    fn compile_pattern(&mut self, pattern: &Pattern) -> chunk::Pattern {
+        use chunk::Pattern as Compiled;
+
+        match pattern {
+            Pattern::Variable(variable) => Compiled::Bind(self.target(variable)),
+            Pattern::Wildcard => Compiled::Wildcard,
+            Pattern::Literal(literal) => Compiled::Literal(constant(&literal.literal)),
+            Pattern::Tuple(elements) => {
+                Compiled::Tuple(elements.iter().map(|e| self.compile_pattern(e)).collect())
+            }
+            Pattern::Record(fields) => Compiled::Record(
+                fields
+                    .iter()
+                    .map(|field| (field.name, self.target(field)))
+                    .collect(),
+            ),
+            Pattern::List(_) => Compiled::List,
+            Pattern::Variant(variant) => {
+                let name = match self.binding(&variant.constructor) {
+                    Binding::Global(name) => name,
+                    _ => unreachable!(),
+                };
+                let fields = variant
+                    .fields
+                    .iter()
+                    .map(|field| self.compile_pattern(field))
+                    .collect();
+                Compiled::Variant(name, fields)
+            }
+        }
+    }
+
+    fn target(&self, variable: &Variable) -> Target {
+        match self.binding(variable) {
+            Binding::Local(slot) => Target::Local(slot),
+            Binding::Global(name) => Target::Global(name),
+            Binding::Upvalue(_) => unreachable!(),
+        }
+    }
+
+    fn binding(&self, variable: &Variable) -> Binding {
+        self.resolution.binding(variable).unwrap().clone()
+    }
+
+    fn constant(&mut self, constant: Constant) {
+        self.chunk.constants.push(constant);
+        self.emit(Op::Constant(self.chunk.constants.len() - 1));
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.write(op, self.position.clone())
+    }
+
+    /// Points the jump at `index` to the next instruction.
+    fn patch(&mut self, index: usize) {
+        let target = self.chunk.code.len();
+        match &mut self.chunk.code[index] {
+            Op::Jump(to) | Op::JumpIfFalse(to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn constant(literal: &LiteralExpr) -> Constant {
+    match literal {
+        LiteralExpr::Number(n) => Constant::Number(*n),
+        LiteralExpr::String(s) => Constant::String(s.clone()),
+        LiteralExpr::Nil => Constant::Nil,
+    }
+}
@@ -0,0 +1,154 @@
+use crate::syntax::symbol::Symbol;
+use crate::syntax::token::Position;
+use crate::vm::limits::Limit;
+use crate::vm::FRAMES_MAX;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    /// An operator used on a value it is not defined for, and the type of
+    /// that value.
+    Operands(&'static str, Symbol, usize),
+    NotCallable(Symbol, usize),
+    /// The number of arguments expected and given.
+    Arity(usize, usize, usize),
+    NoField(Symbol, Symbol, usize),
+    NoElement(Symbol, usize, usize),
+    /// A value which matches no arm of a `match`, or no `let` pattern.
+    NoMatch(String, usize),
+    /// A global which is used before the top level has declared it.
+    Undefined(Symbol, usize),
+    /// A function of an `impl` which has not been declared yet, or a type
+    /// without the method called on it.
+    NoMethod(Symbol, Symbol, usize),
+    /// A call of `panic`, with its message.
+    Panicked(String, usize),
+    /// An error returned by a native function, and its name.
+    Native(Symbol, String, usize),
+    /// A field assigned on a value of a type which has no setter for it.
+    NotAssignable(Symbol, Symbol, usize),
+    /// A run which went past one of the limits of its VM, or was cancelled.
+    LimitExceeded(Limit, usize),
+    /// Calls nested deeper than `FRAMES_MAX`.
+    StackOverflow(usize),
+}
+
+impl RuntimeError {
+    pub fn line(&self) -> usize {
+        match self {
+            RuntimeError::Operands(_, _, line)
+            | RuntimeError::NotCallable(_, line)
+            | RuntimeError::Arity(_, _, line)
+            | RuntimeError::NoField(_, _, line)
+            | RuntimeError::NoElement(_, _, line)
+            | RuntimeError::NoMatch(_, line)
+            | RuntimeError::Undefined(_, line)
+            | RuntimeError::NoMethod(_, _, line)
+            | RuntimeError::Panicked(_, line)
+            | RuntimeError::Native(_, _, line)
+            | RuntimeError::NotAssignable(_, _, line)
+            | RuntimeError::LimitExceeded(_, line)
+            | RuntimeError::StackOverflow(line) => *line,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::Operands(operator, ty, _) => {
+                write!(f, "`{}` is not defined for {}", operator, ty)
+            }
+            RuntimeError::NotCallable(ty, _) => write!(f, "{} is not a function", ty),
+            RuntimeError::Arity(expect, actual, _) => {
+                write!(f, "expected {} arguments, found {}", expect, actual)
+            }
+            RuntimeError::NoField(ty, field, _) => write!(f, "{} has no field `{}`", ty, field),
+            RuntimeError::NoElement(ty, index, _) => write!(f, "{} has no element {}", ty, index),
+            RuntimeError::NoMatch(value, _) => write!(f, "no pattern matches {}", value),
+            RuntimeError::Undefined(name, _) => {
+                write!(f, "`{}` is used before it is declared", name)
+            }
+            RuntimeError::NoMethod(ty, name, _) => write!(f, "{} has no method `{}`", ty, name),
+            RuntimeError::Panicked(message, _) => write!(f, "{}", message),
+            RuntimeError::Native(name, message, _) => write!(f, "{}: {}", name, message),
+            RuntimeError::NotAssignable(ty, name, _) => {
+                write!(f, "`{}` of {} cannot be assigned", name, ty)
+            }
+            RuntimeError::LimitExceeded(limit, _) => write!(f, "{}", limit),
+            RuntimeError::StackOverflow(_) => {
+                write!(f, "stack overflow, calls nested deeper than {}", FRAMES_MAX)
+            }
+        }
+    }
+}
+
+/// A runtime error, which stops the program, and the calls which were
+/// being run when it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Panic {
+    pub error: RuntimeError,
+    /// The innermost call first, the top level last.
+    pub trace: Vec<Frame>,
+}
+
+impl Panic {
+    pub fn line(&self) -> usize {
+        self.error.line()
+    }
+
+    /// Renders the error and the trace for the file the module was compiled
+    /// from: every call with its `file:line:column` and source line. A run
+    /// of the same call, as in deep recursion, is shown once.
+    pub fn report(&self, path: &str, source: &str) -> String {
+        let mut report = match self.trace.first() {
+            Some(frame) => {
+                let column = column(source, *frame.position.start());
+                format!("{}:{}:{}: {}\n", path, self.line(), column, self.error)
+            }
+            None => format!("{}:{}: {}\n", path, self.line(), self.error),
+        };
+        for frames in self.trace.chunk_by(|a, b| a == b) {
+            let position = &frames[0].position;
+            let column = column(source, *position.start());
+            report += &format!(
+                "    at {} ({}:{}:{})\n",
+                frames[0].function,
+                path,
+                position.line(),
+                column
+            );
+            if let Some(line) = source.lines().nth(position.line().saturating_sub(1)) {
+                report += &format!("        {}\n", line.trim());
+            }
+            if frames.len() > 1 {
+                report += &format!(
+                    "    ... {} more calls of {}\n",
+                    frames.len() - 1,
+                    frames[0].function
+                );
+            }
+        }
+        report
+    }
+}
+
+/// The column of a byte offset, counting characters from one.
+fn column(source: &str, offset: usize) -> usize {
+    let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    source[start..offset].chars().count() + 1
+}
+
+impl fmt::Display for Panic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+/// A call in a stack trace, and the position of the instruction it was
+/// running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: Symbol,
+    pub position: Position,
+}
@@ -0,0 +1,67 @@
+//! Bounds on what a run may use, for programs which cannot be trusted to
+//! finish on their own. Going past one stops the run with
+//! `RuntimeError::LimitExceeded`.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The limits of every run. `None` is no limit, which is the default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// The instructions a run may execute.
+    pub fuel: Option<u64>,
+    /// The bytes the heap may take up, after collecting what it can.
+    pub max_heap_bytes: Option<usize>,
+    /// How deeply calls may nest, not counting the module itself. They can
+    /// never nest deeper than `FRAMES_MAX`.
+    pub max_call_depth: Option<usize>,
+}
+
+/// The limit a run went past.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Limit {
+    Fuel(u64),
+    HeapBytes(usize),
+    CallDepth(usize),
+    /// The run was cancelled through its `CancelToken`.
+    Cancelled,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Fuel(fuel) => write!(f, "ran out of fuel after {} instructions", fuel),
+            Limit::HeapBytes(bytes) => write!(f, "the heap grew past {} bytes", bytes),
+            Limit::CallDepth(depth) => write!(f, "calls nested deeper than {}", depth),
+            Limit::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// Stops the runs of a VM from another thread. Clones share the token, and
+/// once cancelled it stays so, stopping every run after, until it is reset.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Stops the run going on, if any, before its next instruction.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Lets runs go on again.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
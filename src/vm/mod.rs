@@ -0,0 +1,1343 @@
+pub mod chunk;
+pub mod compiler;
+pub mod error;
+pub mod limits;
+pub mod value;
+
+use crate::prelude;
+use crate::syntax::symbol::{sym, Symbol};
+use crate::syntax::token::Position;
+use crate::vm::chunk::{Constant, Function, Op, Pattern, Target};
+use crate::vm::error::{Frame, Panic, RuntimeError};
+use crate::vm::limits::{CancelToken, Limit, Limits};
+use crate::vm::value::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// How deeply calls may nest, whatever the limits of the VM, so that
+/// unbounded recursion stops with an error before it uses up the memory.
+pub const FRAMES_MAX: usize = 10_000;
+
+struct CallFrame {
+    closure: ObjectRef,
+    function: Rc<Function>,
+    ip: usize,
+    /// The stack index of the first slot of the frame.
+    base: usize,
+}
+
+impl CallFrame {
+    /// The position of the instruction being run.
+    fn position(&self) -> &Position {
+        &self.function.chunk.positions[self.ip.saturating_sub(1)]
+    }
+}
+
+/// A stack machine running compiled modules. Globals and the functions of
+/// types are kept between runs, so a module can build on the ones before.
+/// The prelude is defined before the first.
+///
+/// The heap is collected between instructions, when every value in use is
+/// on the stack, in a global or a method, or captured by a closure. The
+/// value a run returns is only safe to use until the next run.
+///
+/// Runs can be bounded by `Limits`, and cancelled from another thread.
+/// Either stops the run with an error like any other, after which the VM
+/// can run again.
+pub struct Vm {
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Symbol, Value>,
+    /// The functions of every `impl`, by type and function name.
+    methods: HashMap<(Symbol, Symbol), Value>,
+    /// The properties of the types of the host, by type and property name.
+    getters: HashMap<(Symbol, Symbol), NativeFn>,
+    setters: HashMap<(Symbol, Symbol), NativeFn>,
+    /// The upvalues which still point into the stack.
+    open_upvalues: Vec<ObjectRef>,
+    limits: Limits,
+    /// The instructions the run may still execute.
+    fuel: Option<u64>,
+    cancel: CancelToken,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::with_gc(GcConfig::default())
+    }
+}
+
+impl Vm {
+    pub fn with_gc(config: GcConfig) -> Self {
+        let mut vm = Vm {
+            heap: Heap::new(config),
+            stack: vec![],
+            frames: vec![],
+            globals: HashMap::new(),
+            methods: HashMap::new(),
+            getters: HashMap::new(),
+            setters: HashMap::new(),
+            open_upvalues: vec![],
+            limits: Limits::default(),
+            fuel: None,
+            cancel: CancelToken::new(),
+        };
+        vm.define_prelude();
+        vm
+    }
+
+    fn define_prelude(&mut self) {
+        for ty in &prelude::TYPES {
+            for (name, field) in ty.constructors {
+                let constructor = self.heap.alloc(Object::Constructor(Constructor {
+                    type_name: Symbol::intern(ty.name),
+                    name: Symbol::intern(name),
+                    arity: field.iter().count(),
+                }));
+                let value = match field {
+                    Some(_) => constructor,
+                    None => self.heap.alloc(Object::Variant(Variant {
+                        constructor,
+                        fields: vec![],
+                    })),
+                };
+                self.globals
+                    .insert(Symbol::intern(name), Value::Object(value));
+            }
+        }
+        for name in prelude::FUNCTIONS {
+            let builtin = Builtin::from_name(name).unwrap();
+            let function = self.heap.alloc(Object::Builtin(builtin));
+            self.globals
+                .insert(Symbol::intern(name), Value::Object(function));
+        }
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// The heap, to allocate the values handed to a program. Nothing is
+    /// collected until the program runs its next instruction.
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// The value of a global, once it has been declared.
+    pub fn global(&self, name: Symbol) -> Option<Value> {
+        self.globals.get(&name).copied()
+    }
+
+    /// Declares a global function implemented by the host, in place of any
+    /// global of the same name.
+    pub fn define_native(&mut self, name: Symbol, arity: usize, function: NativeFn) {
+        let native = Object::Native(Native {
+            name,
+            arity,
+            function,
+        });
+        let native = self.heap.alloc(native);
+        self.globals.insert(name, Value::Object(native));
+    }
+
+    /// Declares a method of a type of the host, which is called with the
+    /// receiver and then its arguments.
+    pub fn define_method(
+        &mut self,
+        type_name: Symbol,
+        name: Symbol,
+        arity: usize,
+        function: NativeFn,
+    ) {
+        let native = Object::Native(Native {
+            name,
+            arity,
+            function,
+        });
+        let native = self.heap.alloc(native);
+        self.methods
+            .insert((type_name, name), Value::Object(native));
+    }
+
+    /// Declares a property of a type of the host, read by calling `getter`
+    /// with the object.
+    pub fn define_getter(&mut self, type_name: Symbol, name: Symbol, getter: NativeFn) {
+        self.getters.insert((type_name, name), getter);
+    }
+
+    /// Lets a property be assigned, by calling `setter` with the object and
+    /// the value.
+    pub fn define_setter(&mut self, type_name: Symbol, name: Symbol, setter: NativeFn) {
+        self.setters.insert((type_name, name), setter);
+    }
+
+    /// Sets the limits of the runs after.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// A token which cancels the runs of this VM.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Runs a compiled module, returning the value of its last expression.
+    pub fn run(&mut self, function: impl Into<Rc<Function>>) -> std::result::Result<Value, Panic> {
+        self.fuel = self.limits.fuel;
+        let closure = self.heap.alloc(Object::Closure(Closure {
+            function: function.into(),
+            upvalues: vec![],
+        }));
+        self.stack.push(Value::Object(closure));
+
+        let result = self.call_value(0).and_then(|_| self.execute());
+        result.map_err(|error| {
+            let trace = self.trace();
+            self.close_upvalues(0);
+            self.stack.clear();
+            self.frames.clear();
+            Panic { error, trace }
+        })
+    }
+
+    /// Frees the objects which are no longer in use.
+    pub fn collect_garbage(&mut self) {
+        let values = self
+            .stack
+            .iter()
+            .chain(self.globals.values())
+            .chain(self.methods.values());
+        let objects = values.filter_map(|value| match value {
+            Value::Object(object) => Some(*object),
+            _ => None,
+        });
+        let closures = self.frames.iter().map(|frame| frame.closure);
+        let roots: Vec<ObjectRef> = objects
+            .chain(closures)
+            .chain(self.open_upvalues.iter().copied())
+            .collect();
+        self.heap.collect(roots);
+    }
+
+    /// The calls being run, innermost first.
+    fn trace(&self) -> Vec<Frame> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| Frame {
+                function: frame.function.name,
+                position: frame.position().clone(),
+            })
+            .collect()
+    }
+
+    fn execute(&mut self) -> Result<Value> {
+        loop {
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+            self.check_limits()?;
+
+            let frame = self.frames.last_mut().unwrap();
+            let op = frame.function.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Constant(index) => {
+                    let value = match &self.frame().function.chunk.constants[index] {
+                        Constant::Nil => Value::Nil,
+                        Constant::Number(n) => Value::Number(*n),
+                        Constant::String(s) => {
+                            let string = Object::String(s.clone());
+                            Value::Object(self.heap.alloc(string))
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Op::Nil => self.stack.push(Value::Nil),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup => self.stack.push(self.peek(0)),
+                Op::GetLocal(slot) => {
+                    let value = self.stack[self.frame().base + slot];
+                    self.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let value = self.pop();
+                    let base = self.frame().base;
+                    self.stack[base + slot] = value;
+                }
+                Op::GetUpvalue(index) => {
+                    let upvalue = self.upvalue(index);
+                    let value = match self.heap.get(upvalue) {
+                        Object::Upvalue(Upvalue::Open(slot)) => self.stack[*slot],
+                        Object::Upvalue(Upvalue::Closed(value)) => *value,
+                        _ => unreachable!(),
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetUpvalue(index) => {
+                    let value = self.pop();
+                    let upvalue = self.upvalue(index);
+                    match self.heap.get_mut(upvalue) {
+                        Object::Upvalue(Upvalue::Open(slot)) => {
+                            let slot = *slot;
+                            self.stack[slot] = value;
+                        }
+                        Object::Upvalue(upvalue) => *upvalue = Upvalue::Closed(value),
+                        _ => unreachable!(),
+                    }
+                }
+                Op::DefineGlobal(name) => {
+                    let name = self.name(name);
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                Op::GetGlobal(name) => {
+                    let name = self.name(name);
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(*value),
+                        None => return Err(RuntimeError::Undefined(name, self.line())),
+                    }
+                }
+                Op::SetGlobal(name) => {
+                    let name = self.name(name);
+                    let value = self.pop();
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => return Err(RuntimeError::Undefined(name, self.line())),
+                    }
+                }
+                Op::Add
+                | Op::Subtract
+                | Op::Multiply
+                | Op::Divide
+                | Op::Equal
+                | Op::Less
+                | Op::Greater => self.binary(op)?,
+                Op::Negate => match self.pop() {
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    value => {
+                        let ty = self.heap.type_name(value);
+                        return Err(RuntimeError::Operands("-", ty, self.line()));
+                    }
+                },
+                Op::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!is_truthy(value)));
+                }
+                Op::Tuple(len) => {
+                    let elements = self.stack.split_off(self.stack.len() - len);
+                    let tuple = self.heap.alloc(Object::Tuple(elements));
+                    self.stack.push(Value::Object(tuple));
+                }
+                Op::TupleIndex(index) => {
+                    let value = self.pop();
+                    let element = match value {
+                        Value::Object(object) => match self.heap.get(object) {
+                            Object::Tuple(elements) => elements.get(index).copied(),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match element {
+                        Some(element) => self.stack.push(element),
+                        None => {
+                            let ty = self.heap.type_name(value);
+                            return Err(RuntimeError::NoElement(ty, index, self.line()));
+                        }
+                    }
+                }
+                Op::Record(index) => {
+                    let function = self.frame().function.clone();
+                    let layout = &function.chunk.records[index];
+                    let values = self.stack.split_off(self.stack.len() - layout.fields.len());
+                    let record = Object::Record(Record {
+                        type_name: layout.type_name,
+                        fields: layout.fields.iter().cloned().zip(values).collect(),
+                    });
+                    let record = self.heap.alloc(record);
+                    self.stack.push(Value::Object(record));
+                }
+                Op::Field(name) => {
+                    let name = self.name(name);
+                    let value = self.pop();
+                    let field = match value {
+                        Value::Object(object) => match self.heap.get(object) {
+                            Object::Record(record) => record
+                                .fields
+                                .iter()
+                                .find(|(field, _)| *field == name)
+                                .map(|(_, value)| *value),
+                            Object::Host(host) => {
+                                match self.getters.get(&(host.type_name, name)).cloned() {
+                                    Some(getter) => {
+                                        Some(self.call_native(name, &getter, &[value])?)
+                                    }
+                                    None => None,
+                                }
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match field {
+                        Some(field) => self.stack.push(field),
+                        None => {
+                            let ty = self.heap.type_name(value);
+                            return Err(RuntimeError::NoField(ty, name, self.line()));
+                        }
+                    }
+                }
+                Op::SetField(name) => {
+                    let name = self.name(name);
+                    let value = self.pop();
+                    let target = self.pop();
+                    let ty = self.heap.type_name(target);
+                    match self.setters.get(&(ty, name)).cloned() {
+                        Some(setter) => {
+                            self.call_native(name, &setter, &[target, value])?;
+                        }
+                        None => return Err(RuntimeError::NotAssignable(ty, name, self.line())),
+                    }
+                }
+                Op::Constructor(index) => {
+                    let constructor = self.frame().function.chunk.constructors[index].clone();
+                    let arity = constructor.arity;
+                    let constructor = self.heap.alloc(Object::Constructor(constructor));
+                    let value = match arity {
+                        0 => self.heap.alloc(Object::Variant(Variant {
+                            constructor,
+                            fields: vec![],
+                        })),
+                        _ => constructor,
+                    };
+                    self.stack.push(Value::Object(value));
+                }
+                Op::GetMethod(type_name, name) => {
+                    let key = (self.name(type_name), self.name(name));
+                    match self.methods.get(&key) {
+                        Some(method) => self.stack.push(*method),
+                        None => {
+                            let (ty, name) = key;
+                            return Err(RuntimeError::NoMethod(ty, name, self.line()));
+                        }
+                    }
+                }
+                Op::Closure(index) => {
+                    let function = self.frame().function.chunk.functions[index].clone();
+                    let base = self.frame().base;
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| match upvalue.is_local {
+                            true => self.capture_upvalue(base + upvalue.index),
+                            false => self.upvalue(upvalue.index),
+                        })
+                        .collect();
+                    let closure = Object::Closure(Closure { function, upvalues });
+                    let closure = self.heap.alloc(closure);
+                    self.stack.push(Value::Object(closure));
+                }
+                Op::Call(argc) => self.call_value(argc)?,
+                Op::Partial(argc) => {
+                    let args = self.stack.split_off(self.stack.len() - argc);
+                    let function = match self.pop() {
+                        Value::Object(function) => function,
+                        value => {
+                            let ty = self.heap.type_name(value);
+                            return Err(RuntimeError::NotCallable(ty, self.line()));
+                        }
+                    };
+                    let partial = self.heap.alloc(Object::Partial(Partial { function, args }));
+                    self.stack.push(Value::Object(partial));
+                }
+                Op::Return => {
+                    let result = self.pop();
+                    if self.return_value(result) {
+                        return Ok(result);
+                    }
+                }
+                Op::Try => {
+                    let value = self.pop();
+                    match self.unwrap(value) {
+                        Some(Some(inner)) => self.stack.push(inner),
+                        Some(None) => {
+                            if self.return_value(value) {
+                                return Ok(value);
+                            }
+                        }
+                        None => {
+                            let ty = self.heap.type_name(value);
+                            return Err(RuntimeError::Operands("?", ty, self.line()));
+                        }
+                    }
+                }
+                Op::Method(type_name, name) => {
+                    let key = (self.name(type_name), self.name(name));
+                    let closure = self.pop();
+                    self.methods.insert(key, closure);
+                }
+                Op::Jump(target) => self.frames.last_mut().unwrap().ip = target,
+                Op::JumpIfFalse(target) => {
+                    if !is_truthy(self.pop()) {
+                        self.frames.last_mut().unwrap().ip = target;
+                    }
+                }
+                Op::Destructure(pattern) => {
+                    let value = self.pop();
+                    if !self.match_pattern(pattern, value) {
+                        let value = self.heap.display(value);
+                        return Err(RuntimeError::NoMatch(value, self.line()));
+                    }
+                }
+                Op::Match(pattern) => {
+                    let value = self.pop();
+                    let matched = self.match_pattern(pattern, value);
+                    self.stack.push(Value::Bool(matched));
+                }
+                Op::NoMatch => {
+                    let value = self.pop();
+                    let value = self.heap.display(value);
+                    return Err(RuntimeError::NoMatch(value, self.line()));
+                }
+            }
+        }
+    }
+
+    /// Pops the frame being run and pushes its result for the caller,
+    /// returning whether it was the last frame.
+    fn return_value(&mut self, result: Value) -> bool {
+        let frame = self.frames.pop().unwrap();
+        self.close_upvalues(frame.base);
+        self.stack.truncate(frame.base - 1);
+        if self.frames.is_empty() {
+            return true;
+        }
+        self.stack.push(result);
+        false
+    }
+
+    /// The value of a `Some` or `Ok`, or `None` for a `None` or `Err`.
+    /// Returns `None` if the value is neither an `option` nor a `result`.
+    fn unwrap(&self, value: Value) -> Option<Option<Value>> {
+        let variant = match value {
+            Value::Object(object) => match self.heap.get(object) {
+                Object::Variant(variant) => variant,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        match self.heap.get(variant.constructor) {
+            Object::Constructor(constructor)
+                if prelude::variant_type(constructor.type_name.as_str()).is_some() =>
+            {
+                match constructor.name.as_str() {
+                    "Some" | "Ok" => Some(variant.fields.first().copied()),
+                    _ => Some(None),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, op: Op) -> Result<()> {
+        let (lhs, rhs) = (self.peek(1), self.peek(0));
+        let result = match (op, lhs, rhs) {
+            (Op::Add, Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+            (Op::Subtract, Value::Number(a), Value::Number(b)) => Value::Number(a - b),
+            (Op::Multiply, Value::Number(a), Value::Number(b)) => Value::Number(a * b),
+            (Op::Divide, Value::Number(a), Value::Number(b)) => Value::Number(a / b),
+            (Op::Less, Value::Number(a), Value::Number(b)) => Value::Bool(a < b),
+            (Op::Greater, Value::Number(a), Value::Number(b)) => Value::Bool(a > b),
+            _ => return self.overloaded(op, lhs, rhs),
+        };
+        self.pop();
+        self.pop();
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /// Calls the function of the `impl` of the left operand which overloads
+    /// the operator. Without one, values are only compared by contents.
+    fn overloaded(&mut self, op: Op, lhs: Value, rhs: Value) -> Result<()> {
+        let (method, operator) = match op {
+            Op::Add => (sym::ADD, "+"),
+            Op::Subtract => (sym::SUB, "-"),
+            Op::Multiply => (sym::MUL, "*"),
+            Op::Divide => (sym::DIV, "/"),
+            Op::Equal => (sym::EQ, "=="),
+            Op::Less => (sym::LT, "<"),
+            Op::Greater => (sym::LT, ">"),
+            _ => unreachable!(),
+        };
+        let function = self
+            .heap
+            .declared_type(lhs)
+            .and_then(|ty| self.methods.get(&(ty, method)))
+            .copied();
+
+        match function {
+            Some(function) => {
+                let len = self.stack.len();
+                // `a > b` is `lt(b, a)`.
+                if op == Op::Greater {
+                    self.stack.swap(len - 2, len - 1);
+                }
+                self.stack.insert(len - 2, function);
+                self.call_value(2)
+            }
+            None if op == Op::Equal => {
+                let equal = self.heap.equal(lhs, rhs);
+                self.pop();
+                self.pop();
+                self.stack.push(Value::Bool(equal));
+                Ok(())
+            }
+            None => {
+                let culprit = match lhs {
+                    Value::Number(_) => rhs,
+                    _ => lhs,
+                };
+                let ty = self.heap.type_name(culprit);
+                Err(RuntimeError::Operands(operator, ty, self.line()))
+            }
+        }
+    }
+
+    /// Calls the value below the `argc` arguments on top of the stack.
+    fn call_value(&mut self, argc: usize) -> Result<()> {
+        let callee = self.peek(argc);
+        let object = match callee {
+            Value::Object(object) => object,
+            _ => {
+                let ty = self.heap.type_name(callee);
+                return Err(RuntimeError::NotCallable(ty, self.line()));
+            }
+        };
+
+        match self.heap.get(object) {
+            Object::Closure(closure) => {
+                let function = closure.function.clone();
+                if function.arity != argc {
+                    return Err(RuntimeError::Arity(function.arity, argc, self.line()));
+                }
+                // The module itself is the first frame.
+                if self.frames.len() > FRAMES_MAX {
+                    return Err(RuntimeError::StackOverflow(self.line()));
+                }
+                if let Some(max) = self.limits.max_call_depth {
+                    if self.frames.len() > max {
+                        let limit = Limit::CallDepth(max);
+                        return Err(RuntimeError::LimitExceeded(limit, self.line()));
+                    }
+                }
+                let base = self.stack.len() - argc;
+                for _ in argc..function.locals {
+                    self.stack.push(Value::Nil);
+                }
+                self.frames.push(CallFrame {
+                    closure: object,
+                    function,
+                    ip: 0,
+                    base,
+                });
+                Ok(())
+            }
+            Object::Constructor(constructor) => {
+                if constructor.arity != argc {
+                    return Err(RuntimeError::Arity(constructor.arity, argc, self.line()));
+                }
+                let fields = self.stack.split_off(self.stack.len() - argc);
+                self.pop();
+                let variant = Object::Variant(Variant {
+                    constructor: object,
+                    fields,
+                });
+                let variant = self.heap.alloc(variant);
+                self.stack.push(Value::Object(variant));
+                Ok(())
+            }
+            Object::Builtin(builtin) => {
+                let builtin = *builtin;
+                if builtin.arity() != argc {
+                    return Err(RuntimeError::Arity(builtin.arity(), argc, self.line()));
+                }
+                match builtin {
+                    Builtin::Panic => {
+                        let message = match self.pop() {
+                            Value::Object(object) => match self.heap.get(object) {
+                                Object::String(message) => message.clone(),
+                                _ => self.heap.display(Value::Object(object)),
+                            },
+                            value => self.heap.display(value),
+                        };
+                        Err(RuntimeError::Panicked(message, self.line()))
+                    }
+                }
+            }
+            Object::Native(native) => {
+                if native.arity != argc {
+                    return Err(RuntimeError::Arity(native.arity, argc, self.line()));
+                }
+                let (name, function) = (native.name, native.function.clone());
+                let args = self.stack[self.stack.len() - argc..].to_vec();
+                let result = self.call_native(name, &function, &args)?;
+                self.stack.truncate(self.stack.len() - argc - 1);
+                self.stack.push(result);
+                Ok(())
+            }
+            Object::Partial(partial) => {
+                let (function, args) = (partial.function, partial.args.clone());
+                let slot = self.stack.len() - argc - 1;
+                self.stack[slot] = Value::Object(function);
+                self.stack.splice(slot + 1..slot + 1, args.iter().copied());
+                self.call_value(argc + args.len())
+            }
+            _ => {
+                let ty = self.heap.type_name(callee);
+                Err(RuntimeError::NotCallable(ty, self.line()))
+            }
+        }
+    }
+
+    /// Stops the run if it has gone past a limit, or has been cancelled.
+    fn check_limits(&mut self) -> Result<()> {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                let limit = Limit::Fuel(self.limits.fuel.unwrap());
+                return Err(RuntimeError::LimitExceeded(limit, self.line()));
+            }
+            *fuel -= 1;
+        }
+        if let Some(max) = self.limits.max_heap_bytes {
+            if self.heap.stats().bytes > max {
+                self.collect_garbage();
+            }
+            if self.heap.stats().bytes > max {
+                let limit = Limit::HeapBytes(max);
+                return Err(RuntimeError::LimitExceeded(limit, self.line()));
+            }
+        }
+        if self.cancel.is_cancelled() {
+            return Err(RuntimeError::LimitExceeded(Limit::Cancelled, self.line()));
+        }
+        Ok(())
+    }
+
+    /// Calls a function of the host, whose errors are runtime errors
+    /// raised by `name`.
+    fn call_native(&mut self, name: Symbol, function: &NativeFn, args: &[Value]) -> Result<Value> {
+        function(self, args).map_err(|message| RuntimeError::Native(name, message, self.line()))
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> ObjectRef {
+        let heap = &self.heap;
+        let open = self.open_upvalues.iter().find(|upvalue| {
+            matches!(heap.get(**upvalue), Object::Upvalue(Upvalue::Open(s)) if *s == slot)
+        });
+        if let Some(upvalue) = open {
+            return *upvalue;
+        }
+
+        let upvalue = self.heap.alloc(Object::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    /// Moves the values of the upvalues pointing at or above `from` off the
+    /// stack.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        let heap = &mut self.heap;
+        self.open_upvalues
+            .retain(|upvalue| match heap.get_mut(*upvalue) {
+                Object::Upvalue(upvalue) => match *upvalue {
+                    Upvalue::Open(slot) if slot >= from => {
+                        *upvalue = Upvalue::Closed(stack[slot]);
+                        false
+                    }
+                    _ => true,
+                },
+                _ => unreachable!(),
+            });
+    }
+
+    fn match_pattern(&mut self, index: usize, value: Value) -> bool {
+        let function = self.frame().function.clone();
+        self.bind(&function.chunk.patterns[index], value)
+    }
+
+    /// Binds the variables of the pattern if the value matches it. On a
+    /// mismatch some variables may have been bound already.
+    fn bind(&mut self, pattern: &Pattern, value: Value) -> bool {
+        match pattern {
+            Pattern::Bind(target) => {
+                self.store(target, value);
+                true
+            }
+            Pattern::Wildcard => true,
+            Pattern::Literal(constant) => match (constant, value) {
+                (Constant::Nil, Value::Nil) => true,
+                (Constant::Number(a), Value::Number(b)) => *a == b,
+                (Constant::String(a), Value::Object(object)) => {
+                    matches!(self.heap.get(object), Object::String(b) if a == b)
+                }
+                _ => false,
+            },
+            Pattern::Tuple(patterns) => {
+                let elements = match value {
+                    Value::Object(object) => match self.heap.get(object) {
+                        Object::Tuple(elements) if elements.len() == patterns.len() => {
+                            elements.clone()
+                        }
+                        _ => return false,
+                    },
+                    _ => return false,
+                };
+                patterns
+                    .iter()
+                    .zip(elements)
+                    .all(|(pattern, element)| self.bind(pattern, element))
+            }
+            Pattern::Record(fields) => {
+                let record = match value {
+                    Value::Object(object) => match self.heap.get(object) {
+                        Object::Record(record) => record.fields.clone(),
+                        _ => return false,
+                    },
+                    _ => return false,
+                };
+                fields.iter().all(|(name, target)| {
+                    match record.iter().find(|(field, _)| field == name) {
+                        Some((_, value)) => {
+                            self.store(target, *value);
+                            true
+                        }
+                        None => false,
+                    }
+                })
+            }
+            Pattern::List => false,
+            Pattern::Variant(name, patterns) => {
+                let constructor = match self.globals.get(name) {
+                    Some(value) => self.constructor_of(*value),
+                    None => None,
+                };
+                let fields = match value {
+                    Value::Object(object) => match self.heap.get(object) {
+                        Object::Variant(variant)
+                            if Some(variant.constructor) == constructor
+                                && variant.fields.len() == patterns.len() =>
+                        {
+                            variant.fields.clone()
+                        }
+                        _ => return false,
+                    },
+                    _ => return false,
+                };
+                patterns
+                    .iter()
+                    .zip(fields)
+                    .all(|(pattern, field)| self.bind(pattern, field))
+            }
+        }
+    }
+
+    /// The constructor a global holds, which is a variant itself if the
+    /// constructor has no fields.
+    fn constructor_of(&self, value: Value) -> Option<ObjectRef> {
+        match value {
+            Value::Object(object) => match self.heap.get(object) {
+                Object::Constructor(_) => Some(object),
+                Object::Variant(variant) => Some(variant.constructor),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn store(&mut self, target: &Target, value: Value) {
+        match target {
+            Target::Local(slot) => {
+                let base = self.frame().base;
+                self.stack[base + slot] = value;
+            }
+            Target::Global(name) => {
+                self.globals.insert(*name, value);
+            }
+        }
+    }
+
+    fn upvalue(&self, index: usize) -> ObjectRef {
+        match self.heap.get(self.frame().closure) {
+            Object::Closure(closure) => closure.upvalues[index],
+            _ => unreachable!(),
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn name(&self, index: usize) -> Symbol {
+        self.frame().function.chunk.names[index]
+    }
+
+    /// The line of the instruction being run.
+    fn line(&self) -> usize {
+        *self.frame().position().line()
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+}
+
+/// `nil` and `false` are false, everything else is true.
+fn is_truthy(value: Value) -> bool {
+    !matches!(value, Value::Nil | Value::Bool(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::Resolver;
+    use crate::syntax::lexer::Lexer;
+    use crate::syntax::parser::AstParser;
+    use crate::types::checker::TypeChecker;
+    use crate::vm::compiler::Compiler;
+
+    /// Runs the source, and again collecting before every instruction,
+    /// which has to make no difference.
+    fn run(source: &str) -> std::result::Result<String, (String, usize)> {
+        let stress = GcConfig {
+            stress: true,
+            ..GcConfig::default()
+        };
+        let result = run_with(source, GcConfig::default());
+        assert_eq!(result, run_with(source, stress));
+        result
+    }
+
+    fn run_with(source: &str, config: GcConfig) -> std::result::Result<String, (String, usize)> {
+        let mut tokens = Lexer::tokenize(source).unwrap();
+        let module = AstParser::parse(&mut tokens).unwrap();
+        let resolution = Resolver::resolve(&module);
+        assert!(resolution.errors().is_empty(), "{:?}", resolution.errors());
+        // Type errors are left in, to check how the VM reports them.
+        let types = TypeChecker::check(&module);
+
+        let mut vm = Vm::with_gc(config);
+        match vm.run(Compiler::compile(&module, &resolution, &types)) {
+            Ok(value) => Ok(vm.heap().display(value)),
+            Err(err) => Err((err.to_string(), err.line())),
+        }
+    }
+
+    #[test]
+    fn run_expressions() {
+        let source = r#"
+        let x = 2
+        let (a, b) = (x * 3, "six")
+        def double(n)
+            n * 2
+        end
+        (a + 1, b, double(x) |> double, -x, !nil, x == 2, 1 <= 1, (1,).0)
+        "#;
+
+        assert_eq!(
+            Ok(r#"(7, "six", 8, -2, true, true, true, 1)"#.to_string()),
+            run(source)
+        );
+    }
+
+    #[test]
+    fn run_closures() {
+        let source = r#"
+        def counter()
+            let mut count = 0
+            def increment()
+                count = count + 1
+                count
+            end
+            increment
+        end
+        let a = counter()
+        let b = counter()
+        a()
+        a()
+        (a(), b())
+        "#;
+
+        assert_eq!(Ok("(3, 1)".to_string()), run(source));
+    }
+
+    #[test]
+    fn run_match() {
+        let source = r#"
+        type shape =
+            | Circle(radius)
+            | Square(side)
+            | Empty
+        def area(shape)
+            match shape {
+                | Circle(r) => 3 * r * r
+                | Square(side) => side * side
+                | Empty => 0
+            }
+        end
+        def name(pair)
+            let (0, name) = pair else return "none"
+            name
+        end
+        (area(Circle(1)), area(Square(2)), area(Empty), name((0, "a")), name((1, "b")))
+        "#;
+
+        assert_eq!(Ok(r#"(3, 4, 0, "a", "none")"#.to_string()), run(source));
+    }
+
+    #[test]
+    fn run_operators() {
+        let source = r#"
+        type money = { cents }
+        impl money
+            def add(a, b)
+                money { cents: a.cents + b.cents }
+            end
+            def lt(a, b)
+                a.cents < b.cents
+            end
+        end
+        type point = { x, y }
+        let one = money { cents: 1 }
+        let two = one + one
+        (two, one < two, one > two, one <= one, two >= one, point { x: 1, y: 2 } == point { y: 2, x: 1 })
+        "#;
+
+        assert_eq!(
+            Ok("(money { cents: 2 }, true, false, true, true, true)".to_string()),
+            run(source)
+        );
+    }
+
+    #[test]
+    fn run_interfaces() {
+        let source = r#"
+        interface Show
+            def show(value)
+        end
+        type shape =
+            | Circle(r)
+            | Square(side)
+        impl Show for shape
+            def show(s)
+                match s {
+                    | Circle(_) => "circle"
+                    | Square(_) => "square"
+                }
+            end
+        end
+        impl Show for number
+            def show(n)
+                "number"
+            end
+        end
+        def print(x: a) where a: Show
+            show(x)
+        end
+        let s = show
+        (print(Circle(1)), Square(2) |> show, print(3), s(Square(1)), s)
+        "#;
+
+        assert_eq!(
+            Ok(r#"("circle", "square", "number", "square", <def show>)"#.to_string()),
+            run(source)
+        );
+    }
+
+    #[test]
+    fn run_generic_functions() {
+        // Dictionaries are passed on to generic functions, captured by
+        // nested ones, and `Self` need not be the first parameter.
+        let source = r#"
+        interface Show
+            def show(value)
+            def label(prefix: string, value: Self)
+        end
+        type point = { x }
+        impl Show for point
+            def show(p)
+                "point"
+            end
+            def label(prefix, p)
+                (prefix, p.show())
+            end
+        end
+        impl Show for string
+            def show(s)
+                s
+            end
+            def label(prefix, s)
+                (prefix, s)
+            end
+        end
+        def twice(x: a) where a: Show
+            def once()
+                show(x)
+            end
+            (once(), once())
+        end
+        def both(x: a, y: b) where a: Show, b: Show
+            (twice(x), label("y", y))
+        end
+        (both(point { x: 1 }, "s"), both("s", point { x: 1 }))
+        "#;
+
+        assert_eq!(
+            Ok(r#"((("point", "point"), ("y", "s")), (("s", "s"), ("y", "point")))"#.to_string()),
+            run(source)
+        );
+    }
+
+    #[test]
+    fn run_methods() {
+        let source = r#"
+        type point = { x, y }
+        impl point
+            def scale(p, k)
+                point { x: p.x * k, y: p.y * k }
+            end
+        end
+        def double(n)
+            n * 2
+        end
+        let p = point { x: 1, y: 2 }
+        let n = 4
+        (p.scale(3), n.double(), p.scale(2).y.double())
+        "#;
+
+        assert_eq!(Ok("(point { x: 3, y: 6 }, 8, 8)".to_string()), run(source));
+    }
+
+    #[test]
+    fn run_results() {
+        let source = r#"
+        def parse(s)
+            match s {
+                | "one" => Ok(1)
+                | "two" => Ok(2)
+                | _ => Err("not a number")
+            }
+        end
+        def sum(a, b)
+            let x = parse(a)?
+            let y = parse(b)?
+            Ok(x + y)
+        end
+        def first(pair)
+            Some(pair.0? + pair.1)
+        end
+        (sum("one", "two"), sum("one", "six"), first((Some(1), 2)), first((None, 2)))
+        "#;
+
+        assert_eq!(
+            Ok(r#"(Ok(3), Err("not a number"), Some(3), None)"#.to_string()),
+            run(source)
+        );
+    }
+
+    #[test]
+    fn trace_panics() {
+        let source = r#"def check(n)
+    match n {
+        | Ok(v) => v
+        | Err(message) => "failed: " |> panic(message)
+    }
+end
+def total(a, b)
+    check(a) + check(b)
+end
+let t = total(Ok(1), Err("b"))
+"#;
+        let mut tokens = Lexer::tokenize(source).unwrap();
+        let module = AstParser::parse(&mut tokens).unwrap();
+        let resolution = Resolver::resolve(&module);
+        let types = TypeChecker::check(&module);
+
+        let mut vm = Vm::default();
+        let panic = vm
+            .run(Compiler::compile(&module, &resolution, &types))
+            .unwrap_err();
+        assert_eq!(RuntimeError::Arity(1, 2, 4), panic.error);
+        assert_eq!(
+            vec!["check", "total", "<module>"],
+            panic
+                .trace
+                .iter()
+                .map(|frame| frame.function.as_str())
+                .collect::<Vec<_>>()
+        );
+        let expect = r#"main.am:4:41: expected 1 arguments, found 2
+    at check (main.am:4:41)
+        | Err(message) => "failed: " |> panic(message)
+    at total (main.am:8:16)
+        check(a) + check(b)
+    at <module> (main.am:10:9)
+        let t = total(Ok(1), Err("b"))
+"#;
+        assert_eq!(expect, panic.report("main.am", source));
+    }
+
+    #[test]
+    fn collapse_recursion() {
+        let source = r#"def count(n)
+    match n {
+        | 0 => panic("done")
+        | _ => count(n - 1)
+    }
+end
+count(100)
+"#;
+        let mut tokens = Lexer::tokenize(source).unwrap();
+        let module = AstParser::parse(&mut tokens).unwrap();
+        let resolution = Resolver::resolve(&module);
+        let types = TypeChecker::check(&module);
+
+        let mut vm = Vm::default();
+        let panic = vm
+            .run(Compiler::compile(&module, &resolution, &types))
+            .unwrap_err();
+        assert_eq!(102, panic.trace.len());
+        let expect = r#"main.am:3:16: done
+    at count (main.am:3:16)
+        | 0 => panic("done")
+    at count (main.am:4:16)
+        | _ => count(n - 1)
+    ... 99 more calls of count
+    at <module> (main.am:7:1)
+        count(100)
+"#;
+        assert_eq!(expect, panic.report("main.am", source));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let source = "def f(n)\n    f(n + 1)\nend\nf(0)\n";
+        let mut tokens = Lexer::tokenize(source).unwrap();
+        let module = AstParser::parse(&mut tokens).unwrap();
+        let resolution = Resolver::resolve(&module);
+        let types = TypeChecker::check(&module);
+
+        // Without any limits set.
+        let mut vm = Vm::default();
+        let panic = vm
+            .run(Compiler::compile(&module, &resolution, &types))
+            .unwrap_err();
+        assert_eq!(RuntimeError::StackOverflow(2), panic.error);
+        assert_eq!(FRAMES_MAX + 1, panic.trace.len());
+        assert_eq!(
+            "stack overflow, calls nested deeper than 10000",
+            panic.to_string()
+        );
+    }
+
+    #[test]
+    fn free_cycles() {
+        // Every `again` refers to itself through the upvalue it closes
+        // over.
+        let source = r#"
+        def make(n)
+            def again()
+                again
+            end
+            match n {
+                | 0 => 0
+                | _ => make(n - 1)
+            }
+        end
+        make(50)
+        "#;
+        let mut tokens = Lexer::tokenize(source).unwrap();
+        let module = AstParser::parse(&mut tokens).unwrap();
+        let resolution = Resolver::resolve(&module);
+        let types = TypeChecker::check(&module);
+
+        let mut vm = Vm::default();
+        let before = vm.heap().stats().live;
+        vm.run(Compiler::compile(&module, &resolution, &types))
+            .unwrap();
+        vm.collect_garbage();
+
+        let stats = vm.heap().stats();
+        assert_eq!(1, stats.collections);
+        assert!(stats.freed > 100, "{:?}", stats);
+        // Only `make` is left.
+        assert_eq!(before + 1, stats.live);
+    }
+
+    #[test]
+    fn report_runtime_errors() {
+        assert_eq!(
+            Err(("`+` is not defined for tuple".to_string(), 2)),
+            run("let a = 1\nlet b = a + (1, 2)\n")
+        );
+        assert_eq!(
+            Err(("`*` is not defined for point".to_string(), 3)),
+            run("type point = { x }\nlet p = point { x: 1 }\nlet q = p * p\n")
+        );
+        assert_eq!(
+            Err(("expected 1 arguments, found 2".to_string(), 4)),
+            run("def f(x)\n    x\nend\nlet y = f(1, 2)\n")
+        );
+        assert_eq!(
+            Err(("number is not a function".to_string(), 2)),
+            run("let f = 1\nlet x = f()\n")
+        );
+        assert_eq!(
+            Err(("no pattern matches 2".to_string(), 2)),
+            run("let x = 2\nlet y = match x {\n| 1 => 1\n}\n")
+        );
+        assert_eq!(
+            Err(("`g` is used before it is declared".to_string(), 2)),
+            run("def f()\n    g()\nend\nf()\ndef g()\nend\n")
+        );
+        // Reported at the expression, not at the last variable before it.
+        assert_eq!(
+            Err(("`+` is not defined for nil".to_string(), 3)),
+            run("let t = 1\nlet x = t\n(1 + nil, x)\n")
+        );
+        assert_eq!(
+            Err(("no pattern matches 3".to_string(), 2)),
+            run("let n = 3\nlet y = match n {\n| 1 => n\n}\n")
+        );
+        assert_eq!(
+            Err(("number has no method `norm`".to_string(), 2)),
+            run("let n = 1\nlet m = n.norm()\n")
+        );
+        assert_eq!(
+            Err(("string has no method `show`".to_string(), 4)),
+            run("interface Show\n    def show(x)\nend\nlet s = show(\"text\")\n")
+        );
+        assert_eq!(
+            Err(("`?` is not defined for number".to_string(), 2)),
+            run("def f(x)\n    x?\nend\nf(1)\n")
+        );
+        assert_eq!(
+            Err(("expected 1 arguments, found 0".to_string(), 1)),
+            run("let x = panic()\n")
+        );
+        assert_eq!(
+            Err(("`x` of point cannot be assigned".to_string(), 3)),
+            run("type point = { x }\nlet p = point { x: 1 }\np.x = 2\n")
+        );
+    }
+}
@@ -0,0 +1,593 @@
+use crate::syntax::symbol::{sym, Symbol};
+use crate::vm::chunk::Function;
+use crate::vm::Vm;
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::mem;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A value on the stack. Everything larger than a number lives on the heap
+/// and is referred to by handle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Object(ObjectRef),
+}
+
+/// A handle to an object on the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectRef(usize);
+
+#[derive(Debug)]
+pub enum Object {
+    String(String),
+    Tuple(Vec<Value>),
+    Record(Record),
+    Variant(Variant),
+    Constructor(Constructor),
+    Partial(Partial),
+    Builtin(Builtin),
+    Native(Native),
+    Host(HostObject),
+    Closure(Closure),
+    Upvalue(Upvalue),
+}
+
+impl Object {
+    /// An estimate of the bytes the object takes up, which decides when the
+    /// heap is collected.
+    fn size(&self) -> usize {
+        let values = |len: usize| len * mem::size_of::<Value>();
+        mem::size_of::<Object>()
+            + match self {
+                Object::String(s) => s.len(),
+                Object::Tuple(elements) => values(elements.len()),
+                Object::Record(record) => record.fields.len() * mem::size_of::<(Symbol, Value)>(),
+                Object::Variant(variant) => values(variant.fields.len()),
+                Object::Closure(closure) => closure.upvalues.len() * mem::size_of::<ObjectRef>(),
+                Object::Partial(partial) => values(partial.args.len()),
+                Object::Constructor(_)
+                | Object::Builtin(_)
+                | Object::Native(_)
+                | Object::Host(_)
+                | Object::Upvalue(_) => 0,
+            }
+    }
+
+    /// The objects this one refers to.
+    fn children(&self) -> Vec<ObjectRef> {
+        let objects = |values: &[Value]| {
+            values
+                .iter()
+                .filter_map(|value| match value {
+                    Value::Object(object) => Some(*object),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        match self {
+            Object::Tuple(elements) => objects(elements),
+            Object::Record(record) => {
+                let values: Vec<Value> = record.fields.iter().map(|(_, value)| *value).collect();
+                objects(&values)
+            }
+            Object::Variant(variant) => {
+                let mut children = objects(&variant.fields);
+                children.push(variant.constructor);
+                children
+            }
+            Object::Closure(closure) => closure.upvalues.clone(),
+            Object::Partial(partial) => {
+                let mut children = objects(&partial.args);
+                children.push(partial.function);
+                children
+            }
+            Object::Upvalue(Upvalue::Closed(value)) => objects(&[*value]),
+            Object::String(_)
+            | Object::Constructor(_)
+            | Object::Builtin(_)
+            | Object::Native(_)
+            | Object::Host(_)
+            | Object::Upvalue(Upvalue::Open(_)) => vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Record {
+    pub type_name: Symbol,
+    pub fields: Vec<(Symbol, Value)>,
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    pub constructor: ObjectRef,
+    pub fields: Vec<Value>,
+}
+
+/// A case of a variant type. Called with its fields it makes a `Variant`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constructor {
+    pub type_name: Symbol,
+    pub name: Symbol,
+    pub arity: usize,
+}
+
+/// A function applied to its first arguments, which are passed before the
+/// ones it is called with. Generic functions are applied to the
+/// dictionaries of their `where` clause.
+#[derive(Debug)]
+pub struct Partial {
+    pub function: ObjectRef,
+    pub args: Vec<Value>,
+}
+
+/// A function of the prelude which is implemented by the VM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    /// Stops the program with a message.
+    Panic,
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "panic" => Some(Builtin::Panic),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Panic => "panic",
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            Builtin::Panic => 1,
+        }
+    }
+}
+
+/// A function the host defines, called with its arguments. An error stops
+/// the program like any runtime error.
+pub type NativeFn = Rc<dyn Fn(&mut Vm, &[Value]) -> Result<Value, String>>;
+
+/// A function implemented by the host rather than in amber.
+#[derive(Clone)]
+pub struct Native {
+    pub name: Symbol,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Native")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+/// A value of a type the host implements in Rust, which programs can only
+/// use through the methods and properties of its type. Clones share the
+/// value.
+#[derive(Clone)]
+pub struct HostObject {
+    pub type_name: Symbol,
+    value: Rc<RefCell<dyn Any>>,
+}
+
+impl HostObject {
+    pub fn new<T: Any>(type_name: Symbol, value: T) -> Self {
+        HostObject {
+            type_name,
+            value: Rc::new(RefCell::new(value)),
+        }
+    }
+
+    /// The value, if it is a `T` and is not borrowed mutably.
+    pub fn borrow<T: Any>(&self) -> Option<Ref<'_, T>> {
+        let value = self.value.try_borrow().ok()?;
+        Ref::filter_map(value, |value| value.downcast_ref()).ok()
+    }
+
+    /// The value, if it is a `T` and is not borrowed.
+    pub fn borrow_mut<T: Any>(&self) -> Option<RefMut<'_, T>> {
+        let value = self.value.try_borrow_mut().ok()?;
+        RefMut::filter_map(value, |value| value.downcast_mut()).ok()
+    }
+}
+
+/// Objects are equal if they share their value.
+impl PartialEq for HostObject {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl fmt::Debug for HostObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}>", self.type_name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<ObjectRef>,
+}
+
+/// A captured variable. It points into the stack while the variable is in
+/// scope and holds the value itself once the frame has returned.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// When the heap is collected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcConfig {
+    /// The bytes the heap may grow to before it is first collected.
+    pub initial_threshold: usize,
+    /// After a collection, the heap may grow to this multiple of the bytes
+    /// which survived it before the next.
+    pub growth_factor: f64,
+    /// Collects before every instruction, which shakes out objects still
+    /// in use without being reachable from a root.
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            initial_threshold: 1024 * 1024,
+            growth_factor: 2.0,
+            stress: false,
+        }
+    }
+}
+
+/// What the collector has done so far.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    /// The number of objects allocated and freed.
+    pub allocated: usize,
+    pub freed: usize,
+    /// The objects alive, and their bytes, as of the last allocation or
+    /// collection.
+    pub live: usize,
+    pub bytes: usize,
+}
+
+/// An object the host holds on to. It is not collected while the root is
+/// alive, and only the heap which rooted it hands it back.
+#[derive(Debug, PartialEq)]
+pub struct Root {
+    heap: usize,
+    object: ObjectRef,
+}
+
+/// Tells heaps apart, so that a root cannot be used with another one.
+static HEAPS: AtomicUsize = AtomicUsize::new(0);
+
+/// The objects of a VM, freed by a mark and sweep collector. Which objects
+/// are still in use is up to the VM, which passes their roots to `collect`,
+/// and to the host, which roots what it holds.
+#[derive(Debug)]
+pub struct Heap {
+    id: usize,
+    /// `None` for a freed slot, which is reused by the next allocation.
+    objects: Vec<Option<Object>>,
+    marked: Vec<bool>,
+    free: Vec<usize>,
+    /// The objects rooted by the host. Rooting only reads the heap, so
+    /// values can be copied out of it without borrowing it mutably.
+    roots: RefCell<Vec<Weak<Root>>>,
+    /// The bytes at which the heap is collected next.
+    threshold: usize,
+    config: GcConfig,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Heap {
+            id: HEAPS.fetch_add(1, Ordering::Relaxed),
+            objects: vec![],
+            marked: vec![],
+            free: vec![],
+            roots: RefCell::new(vec![]),
+            threshold: config.initial_threshold,
+            config,
+            stats: GcStats::default(),
+        }
+    }
+
+    /// Keeps an object alive until the root is dropped.
+    pub fn root(&self, object: ObjectRef) -> Rc<Root> {
+        let root = Rc::new(Root {
+            heap: self.id,
+            object,
+        });
+        self.roots.borrow_mut().push(Rc::downgrade(&root));
+        root
+    }
+
+    /// The object of a root, if it was rooted by this heap.
+    pub fn rooted(&self, root: &Root) -> Option<ObjectRef> {
+        (root.heap == self.id).then_some(root.object)
+    }
+
+    pub fn alloc(&mut self, object: Object) -> ObjectRef {
+        self.stats.allocated += 1;
+        self.stats.live += 1;
+        self.stats.bytes += object.size();
+
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index] = Some(object);
+                ObjectRef(index)
+            }
+            None => {
+                self.objects.push(Some(object));
+                self.marked.push(false);
+                ObjectRef(self.objects.len() - 1)
+            }
+        }
+    }
+
+    pub fn get(&self, object: ObjectRef) -> &Object {
+        self.objects[object.0]
+            .as_ref()
+            .expect("object was freed while in use")
+    }
+
+    pub fn get_mut(&mut self, object: ObjectRef) -> &mut Object {
+        self.objects[object.0]
+            .as_mut()
+            .expect("object was freed while in use")
+    }
+
+    pub fn stats(&self) -> &GcStats {
+        &self.stats
+    }
+
+    /// Whether enough has been allocated since the last collection for
+    /// another.
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.stats.bytes > self.threshold
+    }
+
+    /// Frees every object which cannot be reached from the roots, or from
+    /// those the host holds.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = ObjectRef>) {
+        let host = self.roots.get_mut();
+        host.retain(|root| root.strong_count() > 0);
+        // Marked objects whose children still have to be marked.
+        let mut gray: Vec<ObjectRef> = roots.into_iter().collect();
+        gray.extend(
+            host.iter()
+                .filter_map(Weak::upgrade)
+                .map(|root| root.object),
+        );
+        while let Some(object) = gray.pop() {
+            if mem::replace(&mut self.marked[object.0], true) {
+                continue;
+            }
+            gray.extend(self.get(object).children());
+        }
+
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            if mem::replace(&mut self.marked[index], false) {
+                continue;
+            }
+            if let Some(object) = slot.take() {
+                self.stats.freed += 1;
+                self.stats.live -= 1;
+                self.stats.bytes -= object.size();
+                self.free.push(index);
+            }
+        }
+
+        self.stats.collections += 1;
+        let grown = self.stats.bytes as f64 * self.config.growth_factor;
+        self.threshold = self.config.initial_threshold.max(grown as usize);
+    }
+
+    /// The declared type of a record or variant, which picks the `impl`
+    /// that operators on it dispatch to.
+    pub fn declared_type(&self, value: Value) -> Option<Symbol> {
+        match value {
+            Value::Object(object) => match self.get(object) {
+                Object::Record(record) => Some(record.type_name),
+                Object::Variant(variant) => match self.get(variant.constructor) {
+                    Object::Constructor(constructor) => Some(constructor.type_name),
+                    _ => None,
+                },
+                Object::Host(host) => Some(host.type_name),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The name of the type of a value, for error messages.
+    pub fn type_name(&self, value: Value) -> Symbol {
+        if let Some(name) = self.declared_type(value) {
+            return name;
+        }
+        match value {
+            Value::Nil => sym::NIL,
+            Value::Bool(_) => sym::BOOL,
+            Value::Number(_) => sym::NUMBER,
+            Value::Object(object) => match self.get(object) {
+                Object::String(_) => sym::STRING,
+                Object::Tuple(_) => sym::TUPLE,
+                Object::Closure(_)
+                | Object::Partial(_)
+                | Object::Constructor(_)
+                | Object::Builtin(_)
+                | Object::Native(_) => sym::FUNCTION,
+                Object::Record(_) | Object::Variant(_) | Object::Host(_) | Object::Upvalue(_) => {
+                    unreachable!()
+                }
+            },
+        }
+    }
+
+    /// Compares values by their contents. Values can be nested however
+    /// deeply, so the pairs still to compare are kept on a stack of their
+    /// own rather than on the native one.
+    pub fn equal(&self, a: Value, b: Value) -> bool {
+        let mut pairs = vec![(a, b)];
+        while let Some(pair) = pairs.pop() {
+            let (a, b) = match pair {
+                (Value::Object(a), Value::Object(b)) if a == b => continue,
+                (Value::Object(a), Value::Object(b)) => (a, b),
+                (a, b) if a == b => continue,
+                _ => return false,
+            };
+            let mut all = |a: &[Value], b: &[Value]| {
+                pairs.extend(a.iter().copied().zip(b.iter().copied()));
+                a.len() == b.len()
+            };
+
+            let is_equal = match (self.get(a), self.get(b)) {
+                (Object::String(a), Object::String(b)) => a == b,
+                (Object::Tuple(a), Object::Tuple(b)) => all(a, b),
+                (Object::Record(a), Object::Record(b)) => {
+                    a.type_name == b.type_name
+                        && a.fields.len() == b.fields.len()
+                        && a.fields.iter().all(|(name, a)| {
+                            match b.fields.iter().find(|(other, _)| name == other) {
+                                Some((_, b)) => {
+                                    pairs.push((*a, *b));
+                                    true
+                                }
+                                None => false,
+                            }
+                        })
+                }
+                (Object::Variant(a), Object::Variant(b)) => {
+                    a.constructor == b.constructor && all(&a.fields, &b.fields)
+                }
+                (Object::Host(a), Object::Host(b)) => a == b,
+                _ => false,
+            };
+            if !is_equal {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Renders a value the way it would be written in amber. Like `equal`,
+    /// it keeps what is still to be written on a stack of its own.
+    pub fn display(&self, value: Value) -> String {
+        enum Piece {
+            Value(Value),
+            Text(String),
+        }
+
+        // Pushes the elements of a list, and the text between and after
+        // them, in reverse, so they are popped in order.
+        fn list(pieces: &mut Vec<Piece>, elements: Vec<(String, Value)>, close: &str) {
+            pieces.push(Piece::Text(close.to_string()));
+            for (i, (prefix, element)) in elements.into_iter().enumerate().rev() {
+                pieces.push(Piece::Value(element));
+                pieces.push(Piece::Text(prefix));
+                if i > 0 {
+                    pieces.push(Piece::Text(", ".to_string()));
+                }
+            }
+        }
+
+        let mut text = String::new();
+        let mut pieces = vec![Piece::Value(value)];
+        while let Some(piece) = pieces.pop() {
+            let value = match piece {
+                Piece::Text(piece) => {
+                    text.push_str(&piece);
+                    continue;
+                }
+                Piece::Value(value) => value,
+            };
+            let object = match value {
+                Value::Nil => {
+                    text.push_str("nil");
+                    continue;
+                }
+                Value::Bool(b) => {
+                    text.push_str(&b.to_string());
+                    continue;
+                }
+                Value::Number(n) => {
+                    text.push_str(&n.to_string());
+                    continue;
+                }
+                Value::Object(object) => object,
+            };
+            let elements = |values: &[Value]| {
+                values
+                    .iter()
+                    .map(|value| (String::new(), *value))
+                    .collect::<Vec<_>>()
+            };
+            match self.get(object) {
+                Object::String(s) => text.push_str(&format!("{:?}", s)),
+                Object::Tuple(elements) if elements.len() == 1 => {
+                    text.push('(');
+                    pieces.push(Piece::Text(",)".to_string()));
+                    pieces.push(Piece::Value(elements[0]));
+                }
+                Object::Tuple(values) => {
+                    text.push('(');
+                    list(&mut pieces, elements(values), ")");
+                }
+                Object::Record(record) => {
+                    text.push_str(&format!("{} {{ ", record.type_name));
+                    let fields = record
+                        .fields
+                        .iter()
+                        .map(|(name, value)| (format!("{}: ", name), *value))
+                        .collect();
+                    list(&mut pieces, fields, " }");
+                }
+                Object::Variant(variant) => {
+                    let name = match self.get(variant.constructor) {
+                        Object::Constructor(constructor) => &constructor.name,
+                        _ => unreachable!(),
+                    };
+                    text.push_str(name.as_str());
+                    if !variant.fields.is_empty() {
+                        text.push('(');
+                        list(&mut pieces, elements(&variant.fields), ")");
+                    }
+                }
+                Object::Constructor(constructor) => {
+                    text.push_str(&format!("<constructor {}>", constructor.name))
+                }
+                Object::Partial(partial) => {
+                    pieces.push(Piece::Value(Value::Object(partial.function)))
+                }
+                Object::Builtin(builtin) => text.push_str(&format!("<def {}>", builtin.name())),
+                Object::Native(native) => text.push_str(&format!("<def {}>", native.name)),
+                Object::Host(host) => text.push_str(&format!("<{}>", host.type_name)),
+                Object::Closure(closure) => {
+                    text.push_str(&format!("<def {}>", closure.function.name))
+                }
+                Object::Upvalue(_) => text.push_str("<upvalue>"),
+            }
+        }
+        text
+    }
+}